@@ -0,0 +1,68 @@
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use std::collections::HashMap;
+
+use simple_transaction_manager::engine::process_transaction;
+use simple_transaction_manager::generate::{Generator, Options};
+use simple_transaction_manager::transaction::Transaction;
+use simple_transaction_manager::transaction_set::{CachedClient, Client, LruCache, MemoryClient};
+
+const ROWS: usize = 10_000;
+
+fn process_all<T: Client>(transactions: Vec<Transaction>, mut tx_record: T) {
+    let mut clients = HashMap::new();
+    for transaction in transactions {
+        let _ = process_transaction(transaction, &mut clients, &mut tx_record);
+    }
+}
+
+fn bench_process(c: &mut Criterion) {
+    let mut group = c.benchmark_group("process_transaction");
+    group.throughput(Throughput::Elements(ROWS as u64));
+
+    for &dispute_rate in &[0.0, 0.1, 0.5] {
+        let transactions = Generator::new(Options {
+            dispute_rate,
+            ..Options::default()
+        })
+        .take(ROWS)
+        .collect::<Vec<_>>();
+
+        group.bench_with_input(
+            BenchmarkId::new("memory", dispute_rate),
+            &transactions,
+            |b, transactions| {
+                b.iter_batched(
+                    || transactions.clone(),
+                    |transactions| process_all(transactions, MemoryClient::default()),
+                    BatchSize::LargeInput,
+                )
+            },
+        );
+
+        for &cache_size in &[10, 100, 1000] {
+            group.bench_with_input(
+                BenchmarkId::new(format!("cached/{}", cache_size), dispute_rate),
+                &transactions,
+                |b, transactions| {
+                    b.iter_batched(
+                        || transactions.clone(),
+                        |transactions| {
+                            process_all(
+                                transactions,
+                                CachedClient::new(
+                                    MemoryClient::default(),
+                                    LruCache::with_capacity(cache_size),
+                                ),
+                            )
+                        },
+                        BatchSize::LargeInput,
+                    )
+                },
+            );
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_process);
+criterion_main!(benches);
@@ -0,0 +1,10 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use simple_transaction_manager::transaction::read_from_csv_reader;
+
+fuzz_target!(|data: &[u8]| {
+    for transaction in read_from_csv_reader(data) {
+        let _ = transaction;
+    }
+});
@@ -0,0 +1,59 @@
+#![no_main]
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use std::collections::HashMap;
+
+use simple_transaction_manager::decimal::Decimal;
+use simple_transaction_manager::engine::Engine;
+use simple_transaction_manager::transaction::{DisputableType, Transaction, Type};
+use simple_transaction_manager::transaction_set::MemoryClient;
+
+#[derive(Arbitrary, Debug)]
+enum FuzzType {
+    Deposit(u32, u16),
+    Withdrawal(u32, u16),
+    Dispute,
+    Resolve,
+    Chargeback,
+}
+
+#[derive(Arbitrary, Debug)]
+struct FuzzTransaction {
+    // Keep ids small so that disputes regularly reference real transactions
+    client_id: u8,
+    transaction_id: u8,
+    type_: FuzzType,
+}
+
+impl From<FuzzTransaction> for Transaction {
+    fn from(t: FuzzTransaction) -> Self {
+        // Dollars are limited to u32 so that sums of a few thousand transactions can't overflow
+        let type_ = match t.type_ {
+            FuzzType::Deposit(dollars, cents) => {
+                Type::Disputable(DisputableType::Deposit(Decimal::new(dollars as u64, cents)))
+            }
+            FuzzType::Withdrawal(dollars, cents) => Type::Disputable(DisputableType::Withdrawal(
+                Decimal::new(dollars as u64, cents),
+            )),
+            FuzzType::Dispute => Type::Dispute(None),
+            FuzzType::Resolve => Type::Resolve(None),
+            FuzzType::Chargeback => Type::Chargeback(None),
+        };
+        Transaction {
+            client_id: t.client_id as u16,
+            transaction_id: t.transaction_id as u32,
+            type_,
+            metadata: HashMap::new(),
+        }
+    }
+}
+
+fuzz_target!(|transactions: Vec<FuzzTransaction>| {
+    let mut engine = Engine::new(MemoryClient::default());
+    for transaction in transactions {
+        let _ = engine.process(transaction.into());
+        if let Err(violation) = engine.check_invariants() {
+            panic!("{}", violation);
+        }
+    }
+});
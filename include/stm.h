@@ -0,0 +1,87 @@
+#ifndef STM_H
+#define STM_H
+
+/* Generated by cbindgen from src/ffi.rs, do not edit */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+enum StmStatus
+#if defined(__cplusplus) || __STDC_VERSION__ >= 202311L
+  : int32_t
+#endif // defined(__cplusplus) || __STDC_VERSION__ >= 202311L
+ {
+  STM_STATUS_OK = 0,
+  /*
+   A null pointer, or a string which isn't UTF-8
+   */
+  STM_STATUS_INVALID_ARGUMENT = 1,
+  /*
+   The JSON wasn't a valid transaction
+   */
+  STM_STATUS_PARSE_ERROR = 2,
+  /*
+   The transaction was valid, but the engine refused to apply it
+   */
+  STM_STATUS_REJECTED = 3,
+};
+#ifndef __cplusplus
+#if __STDC_VERSION__ >= 202311L
+typedef enum StmStatus StmStatus;
+#else
+typedef int32_t StmStatus;
+#endif // __STDC_VERSION__ >= 202311L
+#endif // __cplusplus
+
+/*
+ An engine with its transaction record held in memory, opaque to C
+ */
+typedef struct StmEngine StmEngine;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+struct StmEngine *stm_engine_new(void);
+
+/*
+ Applies a single transaction given as a JSON object with the same fields as a CSV row, e.g.
+ `{"type": "deposit", "client": 1, "tx": 1, "amount": "1.5"}`. Amounts are strings, so they're never rounded through
+ a float.
+
+ If `error` is not null and the transaction couldn't be applied, it receives a description of why.
+
+ # Safety
+ `engine` must come from [`stm_engine_new`], and `json` must be null or a NUL terminated string.
+ */
+StmStatus stm_engine_apply_json(struct StmEngine *engine,
+                                const char *json,
+                                char **error);
+
+/*
+ The accounts report as CSV, ordered by client id, or null if `engine` is null
+
+ # Safety
+ `engine` must be null or come from [`stm_engine_new`].
+ */
+char *stm_engine_report_csv(const struct StmEngine *engine);
+
+/*
+ # Safety
+ `engine` must be null or come from [`stm_engine_new`], and not be used afterwards.
+ */
+void stm_engine_free(struct StmEngine *engine);
+
+/*
+ # Safety
+ `s` must be null or a string returned by this library, and not be used afterwards.
+ */
+void stm_string_free(char *s);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* STM_H */
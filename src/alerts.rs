@@ -0,0 +1,195 @@
+//! Alerts raised when a client's balance crosses a threshold, like going below zero or above a million, so operations
+//! hear about it as it happens rather than by diffing successive reports.
+//!
+//! The balance is the client's available funds less any overdraft in use, so it's negative once a client has
+//! overdrawn. An alert is raised each time a transaction takes the balance across a threshold, in either direction, and
+//! not again until it crosses back.
+
+use serde::{Serialize, Serializer};
+use std::cmp::Ordering;
+use std::fmt;
+use std::num::ParseIntError;
+use std::str::FromStr;
+
+use crate::client::Client;
+use crate::decimal::Decimal;
+
+/// A signed amount of available funds
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Balance {
+    /// Whether `amount` is owed, which is never the case for zero
+    pub overdrawn: bool,
+    pub amount: Decimal,
+}
+
+impl Balance {
+    pub fn new(overdrawn: bool, amount: Decimal) -> Self {
+        Balance {
+            overdrawn: overdrawn && amount != Decimal::zero(),
+            amount,
+        }
+    }
+
+    /// The client's available funds less any overdraft in use
+    pub fn of(client: &Client) -> Self {
+        match client.available() - client.overdraft() {
+            Ok(amount) => Balance::new(false, amount),
+            Err(owed) => Balance::new(true, owed),
+        }
+    }
+}
+
+impl Ord for Balance {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.overdrawn, other.overdrawn) {
+            (false, false) => self.amount.cmp(&other.amount),
+            (true, true) => other.amount.cmp(&self.amount),
+            (true, false) => Ordering::Less,
+            (false, true) => Ordering::Greater,
+        }
+    }
+}
+
+impl PartialOrd for Balance {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for Balance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.overdrawn {
+            f.write_str("-")?;
+        }
+        self.amount.fmt(f)
+    }
+}
+
+impl FromStr for Balance {
+    type Err = ParseIntError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.strip_prefix('-') {
+            Some(amount) => Balance::new(true, amount.parse()?),
+            None => Balance::new(false, s.parse()?),
+        })
+    }
+}
+
+impl Serialize for Balance {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Which way a balance crossed a threshold. Reaching a threshold exactly counts as going above it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Crossing {
+    Below,
+    Above,
+}
+
+/// A client's balance crossing a threshold
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Alert {
+    #[serde(rename = "client")]
+    pub client_id: u16,
+    /// The transaction which took the balance across
+    #[serde(rename = "tx")]
+    pub transaction_id: u32,
+    pub crossing: Crossing,
+    pub threshold: Balance,
+    pub balance: Balance,
+}
+
+impl fmt::Display for Alert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "client {} balance {} after tx {}, {} {}",
+            self.client_id,
+            self.balance,
+            self.transaction_id,
+            match self.crossing {
+                Crossing::Below => "below",
+                Crossing::Above => "above",
+            },
+            self.threshold
+        )
+    }
+}
+
+/// The balances to alert on crossing
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Thresholds {
+    levels: Vec<Balance>,
+}
+
+impl Thresholds {
+    pub fn new(levels: Vec<Balance>) -> Self {
+        Thresholds { levels }
+    }
+
+    /// The thresholds crossed by a balance going from `before` to `after`, and which way
+    pub(crate) fn crossed<'a>(
+        &'a self,
+        before: &'a Balance,
+        after: &'a Balance,
+    ) -> impl Iterator<Item = (Crossing, &'a Balance)> + 'a {
+        self.levels.iter().filter_map(move |level| {
+            if before >= level && after < level {
+                Some((Crossing::Below, level))
+            } else if before < level && after >= level {
+                Some((Crossing::Above, level))
+            } else {
+                None
+            }
+        })
+    }
+}
+
+/// A comma separated list, like `0,1000000`
+impl FromStr for Thresholds {
+    type Err = ParseIntError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Thresholds::new(
+            s.split(',')
+                .map(|level| level.trim().parse())
+                .collect::<Result<_, _>>()?,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn crossings() {
+        let thresholds = "0, 1000".parse::<Thresholds>().unwrap();
+        let balance = |s: &str| s.parse::<Balance>().unwrap();
+        let crossed = |before, after| {
+            thresholds
+                .crossed(&balance(before), &balance(after))
+                .map(|(crossing, level)| (crossing, level.to_string()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(crossed("5", "-1"), [(Crossing::Below, "0.0000".to_owned())]);
+        assert_eq!(crossed("0", "-1"), [(Crossing::Below, "0.0000".to_owned())]);
+        assert_eq!(crossed("-1", "-5"), []);
+        assert_eq!(
+            crossed("-1", "1001"),
+            [
+                (Crossing::Above, "0.0000".to_owned()),
+                (Crossing::Above, "1000.0000".to_owned())
+            ]
+        );
+        assert_eq!(crossed("1000", "1000"), []);
+        assert_eq!(
+            crossed("999", "1000"),
+            [(Crossing::Above, "1000.0000".to_owned())]
+        );
+        assert_eq!(balance("-0"), Balance::default());
+        assert!(balance("-10") < balance("-1"));
+    }
+}
@@ -0,0 +1,130 @@
+//! Reporting large transactions, for AML and CTR style reporting obligations.
+//!
+//! Every applied deposit or withdrawal over the threshold is reported along with the client's cumulative deposits and
+//! withdrawals, counted from when monitoring started and including the reported transaction.
+
+use serde::Serialize;
+use std::collections::HashMap;
+
+use crate::decimal::Decimal;
+use crate::engine::{Engine, Rejection};
+use crate::transaction::{DisputableType, Transaction, TransactionKind, Type};
+use crate::transaction_set::Client as TransactionSetClient;
+
+/// A row of the large transaction report
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct LargeTransaction {
+    pub client: u16,
+    pub tx: u32,
+    #[serde(rename = "type")]
+    pub kind: TransactionKind,
+    pub amount: Decimal,
+    pub cumulative_deposits: Decimal,
+    pub cumulative_withdrawals: Decimal,
+}
+
+#[derive(Clone, Debug)]
+pub struct Monitor {
+    threshold: Decimal,
+    /// Each client's applied deposits and withdrawals
+    cumulative: HashMap<u16, (Decimal, Decimal)>,
+}
+
+impl Monitor {
+    /// Reports deposits and withdrawals of more than `threshold`
+    pub fn new(threshold: Decimal) -> Self {
+        Monitor {
+            threshold,
+            cumulative: HashMap::new(),
+        }
+    }
+
+    /// Processes a transaction, returning its report row if it was a large deposit or withdrawal which was applied
+    pub fn process<T: TransactionSetClient>(
+        &mut self,
+        engine: &mut Engine<T>,
+        transaction: Transaction,
+    ) -> Result<Option<LargeTransaction>, Rejection> {
+        let (client, tx) = (transaction.client_id, transaction.transaction_id);
+        let (kind, amount) = match &transaction.type_ {
+            Type::Disputable(DisputableType::Deposit(amount)) => {
+                (TransactionKind::Deposit, amount.clone())
+            }
+            Type::Disputable(DisputableType::Withdrawal(amount)) => {
+                (TransactionKind::Withdrawal, amount.clone())
+            }
+            _ => return engine.process(transaction).map(|()| None),
+        };
+        engine.process(transaction)?;
+
+        let (deposits, withdrawals) = self.cumulative.entry(client).or_default();
+        match kind {
+            TransactionKind::Deposit => *deposits += amount.clone(),
+            _ => *withdrawals += amount.clone(),
+        }
+        Ok((amount > self.threshold).then(|| LargeTransaction {
+            client,
+            tx,
+            kind,
+            amount,
+            cumulative_deposits: deposits.clone(),
+            cumulative_withdrawals: withdrawals.clone(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::transaction::read_from_csv_reader;
+    use crate::transaction_set::MemoryClient;
+
+    #[test]
+    fn report_large_transactions() {
+        let mut engine = Engine::new(MemoryClient::default());
+        let mut monitor = Monitor::new(Decimal::new(10_000, 0));
+        let reported = read_from_csv_reader(
+            "\
+type,       client,  tx, amount
+deposit,         1,   1, 9000.0
+deposit,         1,   2, 12000.0
+withdrawal,      1,   3, 10000.0
+withdrawal,      1,   4, 10000.01
+withdrawal,      1,   5, 20000.0
+dispute,         1,   2,
+"
+            .as_bytes(),
+        )
+        .map(|t| monitor.process(&mut engine, t.unwrap()))
+        .collect::<Vec<_>>();
+        assert_eq!(
+            reported,
+            vec![
+                Ok(None),
+                Ok(Some(LargeTransaction {
+                    client: 1,
+                    tx: 2,
+                    kind: TransactionKind::Deposit,
+                    amount: Decimal::new(12_000, 0),
+                    cumulative_deposits: Decimal::new(21_000, 0),
+                    cumulative_withdrawals: Decimal::zero(),
+                })),
+                Ok(None),
+                Ok(Some(LargeTransaction {
+                    client: 1,
+                    tx: 4,
+                    kind: TransactionKind::Withdrawal,
+                    amount: Decimal::new(10_000, 100),
+                    cumulative_deposits: Decimal::new(21_000, 0),
+                    cumulative_withdrawals: Decimal::new(20_000, 100),
+                })),
+                Err(Rejection::InsufficientFunds {
+                    client_id: 1,
+                    requested: Decimal::new(20_000, 0),
+                    available: Decimal::new(999, 9900),
+                }),
+                Ok(None),
+            ]
+        );
+    }
+}
@@ -0,0 +1,250 @@
+//! Applying Arrow `RecordBatch`es, for Arrow based ETL pipelines.
+//!
+//! Batches use the same columns as the CSV format, looked up by name:
+//!
+//! | column   | type                               |
+//! |----------|------------------------------------|
+//! | `type`   | `Utf8` or `LargeUtf8`              |
+//! | `client` | `UInt16`                           |
+//! | `tx`     | `UInt32`                           |
+//! | `amount` | nullable `Utf8` or `Decimal128`    |
+
+use arrow_array::cast::AsArray;
+use arrow_array::types::{Decimal128Type, UInt16Type, UInt32Type};
+use arrow_array::{Array, ArrayRef, RecordBatch};
+use arrow_schema::DataType;
+use serde::de::{self, value::StrDeserializer};
+use serde::Deserialize;
+use std::convert::TryFrom;
+use std::fmt;
+
+use crate::decimal::Decimal;
+use crate::engine::{Engine, Rejection};
+use crate::transaction::{CsvTransaction, CsvType, Transaction};
+use crate::transaction_set::Client as TransactionSetClient;
+
+/// The batch as a whole can't be read
+#[derive(Debug, Clone, PartialEq)]
+pub enum SchemaError {
+    MissingColumn(&'static str),
+    WrongType {
+        column: &'static str,
+        found: DataType,
+    },
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SchemaError::MissingColumn(column) => write!(f, "missing column {}", column),
+            SchemaError::WrongType { column, found } => {
+                write!(f, "column {} has unsupported type {}", column, found)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SchemaError {}
+
+/// A single row which couldn't be applied
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RowError {
+    Null(&'static str),
+    BadType(String),
+    BadAmount,
+    MissingAmount,
+    Rejected(Rejection),
+}
+
+impl fmt::Display for RowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RowError::Null(column) => write!(f, "{} is null", column),
+            RowError::BadType(type_) => write!(f, "unknown transaction type {:?}", type_),
+            RowError::BadAmount => write!(f, "amount is negative or too large"),
+            RowError::MissingAmount => write!(f, "Missing amount"),
+            RowError::Rejected(rejection) => rejection.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for RowError {}
+
+impl<T: TransactionSetClient> Engine<T> {
+    /// Applies every row of `batch` in order, returning the index and error of each row which couldn't be applied
+    pub fn apply_record_batch(
+        &mut self,
+        batch: &RecordBatch,
+    ) -> Result<Vec<(usize, RowError)>, SchemaError> {
+        let mut errors = Vec::new();
+        for (row, transaction) in transactions(batch)?.enumerate() {
+            if let Err(e) = transaction.and_then(|t| self.process(t).map_err(RowError::Rejected)) {
+                errors.push((row, e));
+            }
+        }
+        Ok(errors)
+    }
+}
+
+/// The rows of `batch` as transactions. The columns are checked and downcast once up front.
+pub fn transactions<'a>(
+    batch: &'a RecordBatch,
+) -> Result<impl Iterator<Item = Result<Transaction, RowError>> + 'a, SchemaError> {
+    let column = |name: &'static str| {
+        batch
+            .column_by_name(name)
+            .ok_or(SchemaError::MissingColumn(name))
+    };
+    let wrong_type = |column: &'static str, array: &ArrayRef| SchemaError::WrongType {
+        column,
+        found: array.data_type().clone(),
+    };
+
+    let types = column("type")?;
+    let type_at: Box<dyn Fn(usize) -> &'a str + 'a> = match types.data_type() {
+        DataType::Utf8 => {
+            let types = types.as_string::<i32>();
+            Box::new(move |row| types.value(row))
+        }
+        DataType::LargeUtf8 => {
+            let types = types.as_string::<i64>();
+            Box::new(move |row| types.value(row))
+        }
+        _ => return Err(wrong_type("type", types)),
+    };
+    let clients = column("client")?;
+    let clients = clients
+        .as_primitive_opt::<UInt16Type>()
+        .ok_or_else(|| wrong_type("client", clients))?;
+    let txs = column("tx")?;
+    let txs = txs
+        .as_primitive_opt::<UInt32Type>()
+        .ok_or_else(|| wrong_type("tx", txs))?;
+
+    let amounts = column("amount")?;
+    let amount_at: Box<dyn Fn(usize) -> Result<Decimal, RowError> + 'a> = match amounts.data_type()
+    {
+        DataType::Utf8 => {
+            let amounts = amounts.as_string::<i32>();
+            Box::new(move |row| amounts.value(row).parse().map_err(|_| RowError::BadAmount))
+        }
+        &DataType::Decimal128(_, scale) => {
+            let amounts = amounts.as_primitive::<Decimal128Type>();
+            Box::new(move |row| {
+                from_decimal128(amounts.value(row), scale).ok_or(RowError::BadAmount)
+            })
+        }
+        _ => return Err(wrong_type("amount", amounts)),
+    };
+
+    Ok((0..batch.num_rows()).map(move |row| {
+        if types.is_null(row) {
+            return Err(RowError::Null("type"));
+        }
+        if clients.is_null(row) {
+            return Err(RowError::Null("client"));
+        }
+        if txs.is_null(row) {
+            return Err(RowError::Null("tx"));
+        }
+
+        let type_ = type_at(row);
+        let csv = CsvTransaction {
+            type_: CsvType::deserialize(StrDeserializer::<de::value::Error>::new(type_))
+                .map_err(|_| RowError::BadType(type_.to_owned()))?,
+            client: clients.value(row),
+            tx: txs.value(row),
+            amount: match amounts.is_null(row) {
+                true => None,
+                false => Some(amount_at(row)?.into()),
+            },
+            reason: None,
+        };
+        Transaction::try_from(csv).map_err(|_| RowError::MissingAmount)
+    }))
+}
+
+/// Converts an Arrow decimal, truncating any digits beyond our precision just like parsing does
+fn from_decimal128(value: i128, scale: i8) -> Option<Decimal> {
+    const PRECISION: i32 = 4;
+    let value = u128::try_from(value).ok()?;
+    let scale = i32::from(scale);
+
+    let (dollars, fraction) = match scale {
+        s if s <= 0 => (value.checked_mul(10u128.checked_pow((-s) as u32)?)?, 0),
+        s => {
+            let unit = 10u128.pow(s as u32);
+            (value / unit, value % unit)
+        }
+    };
+    let cents = match scale - PRECISION {
+        d if d >= 0 => fraction / 10u128.pow(d as u32),
+        d => fraction * 10u128.pow((-d) as u32),
+    };
+    Some(Decimal::new(u64::try_from(dollars).ok()?, cents as u16))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::transaction_set::MemoryClient;
+    use arrow_array::{Decimal128Array, StringArray, UInt16Array, UInt32Array};
+    use std::sync::Arc;
+
+    #[test]
+    fn apply_batch() {
+        let batch = RecordBatch::try_from_iter(vec![
+            (
+                "type",
+                Arc::new(StringArray::from(vec![
+                    "deposit",
+                    "withdrawal",
+                    "transfer",
+                    "deposit",
+                    "withdrawal",
+                ])) as ArrayRef,
+            ),
+            (
+                "client",
+                Arc::new(UInt16Array::from(vec![1, 1, 1, 2, 1])) as ArrayRef,
+            ),
+            (
+                "tx",
+                Arc::new(UInt32Array::from(vec![1, 2, 3, 4, 5])) as ArrayRef,
+            ),
+            (
+                "amount",
+                Arc::new(
+                    Decimal128Array::from(vec![
+                        Some(1_500_000),
+                        Some(250_000),
+                        None,
+                        None,
+                        Some(9_000_000),
+                    ])
+                    .with_precision_and_scale(10, 6)
+                    .unwrap(),
+                ) as ArrayRef,
+            ),
+        ])
+        .unwrap();
+
+        let mut engine = Engine::new(MemoryClient::default());
+        let errors = engine.apply_record_batch(&batch).unwrap();
+        assert_eq!(errors.len(), 3);
+        assert_eq!(errors[0], (2, RowError::BadType("transfer".to_owned())));
+        assert_eq!(errors[1], (3, RowError::MissingAmount));
+        assert!(matches!(errors[2], (4, RowError::Rejected(_))));
+
+        let client = engine.report().next().unwrap();
+        assert_eq!(client.available, Decimal::new(1, 2500));
+    }
+
+    #[test]
+    fn decimal128_scales() {
+        assert_eq!(from_decimal128(12345, 2), Some(Decimal::new(123, 4500)));
+        assert_eq!(from_decimal128(1234567, 6), Some(Decimal::new(1, 2345)));
+        assert_eq!(from_decimal128(5, -2), Some(Decimal::new(500, 0)));
+        assert_eq!(from_decimal128(-1, 2), None);
+    }
+}
@@ -0,0 +1,437 @@
+//! Reading transactions from Avro object container files.
+//!
+//! Files are expected to be written with this schema, or one which differs only in the order of the enum symbols, in
+//! `int` vs `long` for the ids, or in the order of the `amount` union:
+//!
+//! ```json
+//! {
+//!     "type": "record",
+//!     "name": "Transaction",
+//!     "namespace": "stm",
+//!     "fields": [
+//!         {"name": "type", "type": {"type": "enum", "name": "Type",
+//!             "symbols": ["deposit", "withdrawal", "dispute", "resolve", "chargeback", "fee", "interest", "close",
+//!                 "authorize", "capture", "void", "refund"]}},
+//!         {"name": "client", "type": "int"},
+//!         {"name": "tx", "type": "long"},
+//!         {"name": "amount", "type": ["null", "string"], "default": null}
+//!     ]
+//! }
+//! ```
+//!
+//! `Schema::decode` decodes a single record, so the same decoder can be used on messages which aren't in a container.
+//! Only the `null` codec is supported, and `deflate` too when built with the `gzip` feature.
+
+use serde::de::{self, value::StrDeserializer};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{self, Read};
+
+use crate::transaction::{CsvTransaction, CsvType, Transaction};
+
+const MAGIC: &[u8; 4] = b"Obj\x01";
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    /// The file isn't valid Avro
+    Malformed(&'static str),
+    /// The writer's schema isn't one we can read transactions from
+    Schema(String),
+    /// A record was read, but isn't a valid transaction
+    Invalid(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => e.fmt(f),
+            Error::Malformed(what) => write!(f, "malformed avro: {}", what),
+            Error::Schema(what) => write!(f, "unsupported schema: {}", what),
+            Error::Invalid(what) => f.write_str(what),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::UnexpectedEof => Error::Malformed("unexpected end of file"),
+            _ => Error::Io(e),
+        }
+    }
+}
+
+/// How the writer laid out a transaction record
+#[derive(Clone, Debug)]
+pub struct Schema {
+    // `None` if the type is a string rather than an enum
+    symbols: Option<Vec<CsvType>>,
+    // The union branch holding the amount, the other being null. `None` if the amount isn't a union.
+    amount_branch: Option<i64>,
+}
+
+impl Schema {
+    /// Parses a writer schema, checking that transactions can be read from it
+    pub fn parse(json: &str) -> Result<Self, Error> {
+        let schema: Value = serde_json::from_str(json).map_err(|e| Error::Schema(e.to_string()))?;
+        let unsupported = |what: &str| Error::Schema(what.to_owned());
+
+        let fields = match (schema.get("type"), schema.get("fields")) {
+            (Some(t), Some(Value::Array(fields))) if t == "record" => fields,
+            _ => return Err(unsupported("expected a record")),
+        };
+        let names = fields
+            .iter()
+            .map(|f| f.get("name").and_then(Value::as_str))
+            .collect::<Vec<_>>();
+        if names != [Some("type"), Some("client"), Some("tx"), Some("amount")] {
+            return Err(unsupported(
+                "expected the fields type, client, tx and amount in that order",
+            ));
+        }
+        let types = fields.iter().map(|f| &f["type"]).collect::<Vec<_>>();
+
+        let symbols = match types[0] {
+            Value::String(s) if s == "string" => None,
+            Value::Object(e) if e.get("type").is_some_and(|t| t == "enum") => Some(
+                e.get("symbols")
+                    .and_then(Value::as_array)
+                    .ok_or_else(|| unsupported("enum without symbols"))?
+                    .iter()
+                    .map(|s| {
+                        s.as_str()
+                            .and_then(csv_type)
+                            .ok_or_else(|| Error::Schema(format!("unknown transaction type {}", s)))
+                    })
+                    .collect::<Result<_, _>>()?,
+            ),
+            _ => return Err(unsupported("type must be an enum or a string")),
+        };
+        for (field, type_) in ["client", "tx"].iter().zip(&types[1..3]) {
+            if *type_ != "int" && *type_ != "long" {
+                return Err(Error::Schema(format!("{} must be an int or a long", field)));
+            }
+        }
+        let amount_branch = match types[3] {
+            Value::String(s) if s == "string" => None,
+            Value::Array(branches) => match branches.as_slice() {
+                [a, b] if a == "null" && b == "string" => Some(1),
+                [a, b] if a == "string" && b == "null" => Some(0),
+                _ => return Err(unsupported("amount must be a union of null and string")),
+            },
+            _ => return Err(unsupported("amount must be a string or nullable string")),
+        };
+
+        Ok(Schema {
+            symbols,
+            amount_branch,
+        })
+    }
+
+    /// Decodes a single record from the front of `buf`
+    pub fn decode(&self, buf: &mut &[u8]) -> Result<Transaction, Error> {
+        let type_ = match &self.symbols {
+            Some(symbols) => *usize::try_from(read_long(buf)?)
+                .ok()
+                .and_then(|i| symbols.get(i))
+                .ok_or(Error::Malformed("enum index out of range"))?,
+            None => {
+                let type_ = read_string(buf)?;
+                csv_type(&type_).ok_or_else(|| {
+                    Error::Invalid(format!("unknown transaction type {:?}", type_))
+                })?
+            }
+        };
+        let client = read_long(buf)?;
+        let tx = read_long(buf)?;
+        let amount = match self.amount_branch {
+            Some(branch) if read_long(buf)? != branch => None,
+            _ => Some(read_string(buf)?),
+        };
+
+        let csv = CsvTransaction {
+            type_,
+            client: u16::try_from(client)
+                .map_err(|_| Error::Invalid(format!("client {} out of range", client)))?,
+            tx: u32::try_from(tx).map_err(|_| Error::Invalid(format!("tx {} out of range", tx)))?,
+            amount: amount
+                .map(|a| {
+                    a.parse()
+                        .map_err(|_| Error::Invalid(format!("invalid amount {:?}", a)))
+                })
+                .transpose()?,
+            reason: None,
+        };
+        Transaction::try_from(csv).map_err(|e| Error::Invalid(e.to_string()))
+    }
+}
+
+fn csv_type(s: &str) -> Option<CsvType> {
+    CsvType::deserialize(StrDeserializer::<de::value::Error>::new(s)).ok()
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Codec {
+    Null,
+    #[cfg(feature = "gzip")]
+    Deflate,
+}
+
+/// Transactions from an object container file, one block in memory at a time
+pub struct Reader<R: Read> {
+    rdr: R,
+    schema: Schema,
+    codec: Codec,
+    sync: [u8; 16],
+    // The current block, and how many records remain in it
+    block: Vec<u8>,
+    offset: usize,
+    remaining: i64,
+    failed: bool,
+}
+
+impl<R: Read> Reader<R> {
+    /// Reads the file header
+    pub fn new(mut rdr: R) -> Result<Self, Error> {
+        let mut magic = [0; 4];
+        rdr.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(Error::Malformed("not an object container file"));
+        }
+
+        let mut metadata = HashMap::new();
+        loop {
+            let mut count = read_long_from(&mut rdr)?;
+            if count == 0 {
+                break;
+            }
+            if count < 0 {
+                count = -count;
+                read_long_from(&mut rdr)?; // Block size in bytes
+            }
+            for _ in 0..count {
+                let key = String::from_utf8(read_bytes_from(&mut rdr)?)
+                    .map_err(|_| Error::Malformed("metadata key isn't UTF-8"))?;
+                metadata.insert(key, read_bytes_from(&mut rdr)?);
+            }
+        }
+
+        let schema = metadata
+            .get("avro.schema")
+            .ok_or(Error::Malformed("missing schema"))?;
+        let schema = Schema::parse(
+            std::str::from_utf8(schema).map_err(|_| Error::Malformed("schema isn't UTF-8"))?,
+        )?;
+        let codec = match metadata.get("avro.codec").map(Vec::as_slice) {
+            None | Some(b"null") => Codec::Null,
+            #[cfg(feature = "gzip")]
+            Some(b"deflate") => Codec::Deflate,
+            Some(codec) => {
+                return Err(Error::Schema(format!(
+                    "unsupported codec {}",
+                    String::from_utf8_lossy(codec)
+                )))
+            }
+        };
+
+        let mut sync = [0; 16];
+        rdr.read_exact(&mut sync)?;
+        Ok(Reader {
+            rdr,
+            schema,
+            codec,
+            sync,
+            block: Vec::new(),
+            offset: 0,
+            remaining: 0,
+            failed: false,
+        })
+    }
+
+    pub fn schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    /// Loads the next block, returning false at the end of the file
+    fn next_block(&mut self) -> Result<bool, Error> {
+        let mut first = [0];
+        if self.rdr.read(&mut first)? == 0 {
+            return Ok(false);
+        }
+        let count = read_long_from(&mut (&first[..]).chain(&mut self.rdr))?;
+        let size = usize::try_from(read_long_from(&mut self.rdr)?)
+            .map_err(|_| Error::Malformed("negative block size"))?;
+
+        let mut data = vec![0; size];
+        self.rdr.read_exact(&mut data)?;
+        self.block = match self.codec {
+            Codec::Null => data,
+            #[cfg(feature = "gzip")]
+            Codec::Deflate => {
+                let mut block = Vec::new();
+                flate2::read::DeflateDecoder::new(&data[..]).read_to_end(&mut block)?;
+                block
+            }
+        };
+        self.offset = 0;
+        self.remaining = count;
+
+        let mut sync = [0; 16];
+        self.rdr.read_exact(&mut sync)?;
+        if sync != self.sync {
+            return Err(Error::Malformed("sync marker mismatch"));
+        }
+        Ok(true)
+    }
+}
+
+impl<R: Read> Iterator for Reader<R> {
+    type Item = Result<Transaction, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // A broken block leaves us nowhere to resume from
+        if self.failed {
+            return None;
+        }
+        while self.remaining <= 0 {
+            match self.next_block() {
+                Ok(true) => {}
+                Ok(false) => return None,
+                Err(e) => {
+                    self.failed = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+
+        self.remaining -= 1;
+        let mut buf = &self.block[self.offset..];
+        let before = buf.len();
+        let result = self.schema.decode(&mut buf);
+        self.offset += before - buf.len();
+        if let Err(Error::Malformed(_)) = result {
+            self.failed = true;
+        }
+        Some(result)
+    }
+}
+
+/// A zigzag encoded variable length integer
+fn read_long_from<R: Read>(rdr: &mut R) -> Result<i64, Error> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0];
+        rdr.read_exact(&mut byte)?;
+        value |= u64::from(byte[0] & 0x7f) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok((value >> 1) as i64 ^ -((value & 1) as i64));
+        }
+    }
+    Err(Error::Malformed("integer too long"))
+}
+
+fn read_bytes_from<R: Read>(rdr: &mut R) -> Result<Vec<u8>, Error> {
+    let len =
+        usize::try_from(read_long_from(rdr)?).map_err(|_| Error::Malformed("negative length"))?;
+    let mut bytes = Vec::new();
+    rdr.by_ref().take(len as u64).read_to_end(&mut bytes)?;
+    if bytes.len() != len {
+        return Err(Error::Malformed("unexpected end of file"));
+    }
+    Ok(bytes)
+}
+
+fn read_long(buf: &mut &[u8]) -> Result<i64, Error> {
+    read_long_from(buf)
+}
+
+fn read_string(buf: &mut &[u8]) -> Result<String, Error> {
+    String::from_utf8(read_bytes_from(buf)?).map_err(|_| Error::Malformed("string isn't UTF-8"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::decimal::Decimal;
+    use crate::transaction::{DisputableType::*, Type::*};
+
+    fn long(out: &mut Vec<u8>, v: i64) {
+        let mut v = ((v << 1) ^ (v >> 63)) as u64;
+        while v >= 0x80 {
+            out.push(v as u8 | 0x80);
+            v >>= 7;
+        }
+        out.push(v as u8);
+    }
+
+    fn bytes(out: &mut Vec<u8>, b: &[u8]) {
+        long(out, b.len() as i64);
+        out.extend_from_slice(b);
+    }
+
+    #[test]
+    fn read_container() {
+        // Symbols deliberately out of the documented order
+        let schema = r#"{"type": "record", "name": "Transaction", "fields": [
+            {"name": "type", "type": {"type": "enum", "name": "Type",
+                "symbols": ["withdrawal", "deposit", "dispute", "resolve", "chargeback"]}},
+            {"name": "client", "type": "int"},
+            {"name": "tx", "type": "long"},
+            {"name": "amount", "type": ["null", "string"]}
+        ]}"#;
+        let sync = [7; 16];
+
+        let mut file = MAGIC.to_vec();
+        long(&mut file, 1);
+        bytes(&mut file, b"avro.schema");
+        bytes(&mut file, schema.as_bytes());
+        long(&mut file, 0);
+        file.extend_from_slice(&sync);
+
+        let mut block = Vec::new();
+        for (symbol, client, tx, amount) in &[
+            (1, 1, 1, Some("2.5")),
+            (0, 1, 2, Some("1.0")),
+            (2, 1, 1, None),
+            (1, 2, 3, None),
+        ] {
+            long(&mut block, *symbol);
+            long(&mut block, *client);
+            long(&mut block, *tx);
+            match amount {
+                Some(amount) => {
+                    long(&mut block, 1);
+                    bytes(&mut block, amount.as_bytes());
+                }
+                None => long(&mut block, 0),
+            }
+        }
+        long(&mut file, 4);
+        bytes(&mut file, &block);
+        file.extend_from_slice(&sync);
+
+        let read = Reader::new(&file[..]).unwrap().collect::<Vec<_>>();
+        assert_eq!(read.len(), 4);
+        assert_eq!(
+            read[0].as_ref().unwrap(),
+            &Transaction {
+                client_id: 1,
+                transaction_id: 1,
+                type_: Disputable(Deposit(Decimal::new(2, 5000))),
+                metadata: HashMap::new(),
+            }
+        );
+        assert_eq!(
+            read[1].as_ref().unwrap().type_,
+            Disputable(Withdrawal(Decimal::new(1, 0)))
+        );
+        assert_eq!(read[2].as_ref().unwrap().type_, Dispute(None));
+        assert!(matches!(read[3], Err(Error::Invalid(_))));
+    }
+}
@@ -0,0 +1,124 @@
+//! Importing ISO 20022 camt.053 bank-to-customer statements.
+//!
+//! Only booked entries (`<Sts>` of `BOOK`) are imported, pending and informational ones are skipped. Like the other
+//! statement formats the client is given by the caller, credits become deposits and debits become withdrawals. Entries
+//! are keyed by the bank's `<AcctSvcrRef>`, falling back to `<NtryRef>`, hashed as described in `statement`. Elements
+//! are matched by local name, so any version of the schema is accepted.
+
+use roxmltree::{Document, Node};
+use std::collections::HashMap;
+
+use crate::decimal::Decimal;
+use crate::statement::{transaction_id, Error};
+use crate::transaction::{DisputableType, Transaction, Type};
+
+/// The booked `<Ntry>`s of every `<Stmt>` in the document, or an error if it isn't XML at all
+pub fn read_camt053(
+    s: &str,
+    client_id: u16,
+) -> Result<Vec<Result<Transaction, Error>>, roxmltree::Error> {
+    let document = Document::parse(s)?;
+    Ok(document
+        .descendants()
+        .filter(|node| node.has_tag_name("Ntry"))
+        .filter(|entry| status(*entry).as_deref() == Some("BOOK"))
+        .map(|entry| read_entry(entry, client_id))
+        .collect())
+}
+
+fn read_entry(entry: Node, client_id: u16) -> Result<Transaction, Error> {
+    let amount = text(entry, "Amt").ok_or(Error::Missing("Amt"))?;
+    let amount = amount
+        .parse::<Decimal>()
+        .map_err(|_| Error::BadAmount(amount.to_owned()))?;
+    let type_ = match text(entry, "CdtDbtInd") {
+        Some("CRDT") => DisputableType::Deposit(amount),
+        Some("DBIT") => DisputableType::Withdrawal(amount),
+        _ => return Err(Error::Missing("CdtDbtInd")),
+    };
+    let reference = text(entry, "AcctSvcrRef")
+        .or_else(|| text(entry, "NtryRef"))
+        .ok_or(Error::Missing("AcctSvcrRef"))?;
+    Ok(Transaction {
+        client_id,
+        transaction_id: transaction_id(reference),
+        type_: Type::Disputable(type_),
+        metadata: HashMap::new(),
+    })
+}
+
+/// `<Sts>BOOK</Sts>` in older versions of the schema, `<Sts><Cd>BOOK</Cd></Sts>` in newer ones
+fn status(entry: Node) -> Option<String> {
+    let status = child(entry, "Sts")?;
+    let code = child(status, "Cd").unwrap_or(status);
+    Some(code.text()?.trim().to_owned())
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children().find(|child| child.has_tag_name(name))
+}
+
+/// The trimmed text of a direct child
+fn text<'a>(node: Node<'a, '_>, name: &str) -> Option<&'a str> {
+    Some(child(node, name)?.text()?.trim()).filter(|text| !text.is_empty())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn read_booked_entries() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Document xmlns="urn:iso:std:iso:20022:tech:xsd:camt.053.001.08">
+  <BkToCstmrStmt>
+    <Stmt>
+      <Ntry>
+        <Amt Ccy="EUR">250.00</Amt>
+        <CdtDbtInd>CRDT</CdtDbtInd>
+        <Sts><Cd>BOOK</Cd></Sts>
+        <AcctSvcrRef>1001</AcctSvcrRef>
+      </Ntry>
+      <Ntry>
+        <Amt Ccy="EUR">12.5</Amt>
+        <CdtDbtInd>DBIT</CdtDbtInd>
+        <Sts>BOOK</Sts>
+        <NtryRef>REF-2021-06-02</NtryRef>
+      </Ntry>
+      <Ntry>
+        <Amt Ccy="EUR">99.00</Amt>
+        <CdtDbtInd>CRDT</CdtDbtInd>
+        <Sts><Cd>PDNG</Cd></Sts>
+        <AcctSvcrRef>1003</AcctSvcrRef>
+      </Ntry>
+      <Ntry>
+        <Amt Ccy="EUR">-1.00</Amt>
+        <CdtDbtInd>DBIT</CdtDbtInd>
+        <Sts>BOOK</Sts>
+        <AcctSvcrRef>1004</AcctSvcrRef>
+      </Ntry>
+    </Stmt>
+  </BkToCstmrStmt>
+</Document>"#;
+        let transactions = read_camt053(xml, 3).unwrap();
+        assert_eq!(
+            transactions,
+            vec![
+                Ok(Transaction {
+                    client_id: 3,
+                    transaction_id: 1001,
+                    type_: Type::Disputable(DisputableType::Deposit(Decimal::new(250, 0))),
+                    metadata: HashMap::new(),
+                }),
+                Ok(Transaction {
+                    client_id: 3,
+                    transaction_id: transaction_id("REF-2021-06-02"),
+                    type_: Type::Disputable(DisputableType::Withdrawal(Decimal::new(12, 5000))),
+                    metadata: HashMap::new(),
+                }),
+                Err(Error::BadAmount("-1.00".to_owned())),
+            ]
+        );
+        assert!(read_camt053("<Document>", 3).is_err());
+    }
+}
@@ -0,0 +1,243 @@
+//! A tamper-evident hash chain over the ledger of applied transactions.
+//!
+//! The ledger is written as the transaction's CSV columns followed by a `hash` column, the SHA-256 of the previous
+//! row's hash and the transaction's own columns. The first row chains from all zeroes. Changing, removing or reordering
+//! any row changes every hash after it, so the final digest proves the whole ledger unmodified. Transactions are
+//! hashed as written, and metadata isn't written, so it's dropped from the ledger and isn't covered.
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest as _, Sha256};
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+
+use crate::transaction::{CsvAmount, CsvTransaction, CsvType, Transaction};
+
+/// A SHA-256 hash, written as lowercase hex
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Digest(pub [u8; 32]);
+
+impl fmt::Display for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in &self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Digest {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        from_hex(s)
+            .map(Digest)
+            .ok_or_else(|| format!("invalid digest {:?}, expected 64 hex digits", s))
+    }
+}
+
+/// Exactly `N` bytes written as hex, in either case
+pub(crate) fn from_hex<const N: usize>(s: &str) -> Option<[u8; N]> {
+    if s.len() != 2 * N || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    let mut bytes = [0; N];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&s[2 * i..2 * i + 2], 16).ok()?;
+    }
+    Some(bytes)
+}
+
+impl Serialize for Digest {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Digest {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = <&str>::deserialize(deserializer)?;
+        Digest::from_str(s).map_err(de::Error::custom)
+    }
+}
+
+/// A row of the ledger
+#[derive(Serialize, Deserialize)]
+struct Row {
+    #[serde(rename = "type")]
+    type_: CsvType,
+    client: u16,
+    tx: u32,
+    amount: Option<CsvAmount>,
+    reason: Option<String>,
+    hash: Digest,
+}
+
+/// The hash of the last transaction appended
+#[derive(Clone, Debug, Default)]
+pub struct Chain {
+    head: Digest,
+}
+
+impl Chain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Chains a transaction onto the head, returning the new head
+    pub fn append(&mut self, transaction: &Transaction) -> Digest {
+        let mut hasher = Sha256::new();
+        hasher.update(self.head.0);
+        hasher.update(transaction.to_csv_row());
+        self.head = Digest(hasher.finalize().into());
+        self.head
+    }
+
+    pub fn head(&self) -> Digest {
+        self.head
+    }
+}
+
+/// Writes the ledger as CSV, chaining each transaction as it's written
+pub struct Writer<W: Write> {
+    chain: Chain,
+    writer: csv::Writer<W>,
+}
+
+impl<W: Write> Writer<W> {
+    pub fn new(w: W) -> Self {
+        Writer {
+            chain: Chain::new(),
+            writer: csv::Writer::from_writer(w),
+        }
+    }
+
+    pub fn append(&mut self, transaction: &Transaction) -> csv::Result<()> {
+        let hash = self.chain.append(transaction);
+        let CsvTransaction {
+            type_,
+            client,
+            tx,
+            amount,
+            reason,
+        } = CsvTransaction::from(transaction.clone());
+        self.writer.serialize(Row {
+            type_,
+            client,
+            tx,
+            amount,
+            reason,
+            hash,
+        })
+    }
+
+    /// Flushes the ledger, returning the final digest
+    pub fn finish(mut self) -> io::Result<Digest> {
+        self.writer.flush()?;
+        Ok(self.chain.head())
+    }
+}
+
+/// Why a ledger didn't verify
+#[derive(Debug)]
+pub enum Error {
+    Csv(csv::Error),
+    /// The row for `tx`, counting from 1, wasn't chained from the rows before it
+    Broken {
+        row: u64,
+        tx: u32,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Csv(e) => e.fmt(f),
+            Error::Broken { row, tx } => {
+                write!(f, "chain broken at row {} (tx {})", row, tx)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<csv::Error> for Error {
+    fn from(e: csv::Error) -> Self {
+        Error::Csv(e)
+    }
+}
+
+/// Checks every row of a ledger is chained from the rows before it, returning the final digest
+pub fn verify<R: Read>(rdr: R) -> Result<Digest, Error> {
+    let mut chain = Chain::new();
+    let rows = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(rdr)
+        .into_deserialize::<Row>();
+    for (i, row) in rows.enumerate() {
+        let Row {
+            type_,
+            client,
+            tx,
+            amount,
+            reason,
+            hash,
+        } = row?;
+        let broken = || Error::Broken {
+            row: i as u64 + 1,
+            tx,
+        };
+        // Anything we wouldn't have written can't have been chained
+        let transaction = Transaction::try_from(CsvTransaction {
+            type_,
+            client,
+            tx,
+            amount,
+            reason,
+        })
+        .map_err(|_| broken())?;
+        if chain.append(&transaction) != hash {
+            return Err(broken());
+        }
+    }
+    Ok(chain.head())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::transaction::read_from_csv_reader;
+
+    #[test]
+    fn verify_chain() {
+        let mut writer = Writer::new(Vec::new());
+        for transaction in read_from_csv_reader(
+            "\
+type,       client,  tx, amount, reason
+deposit,         1,   1,    5.0,
+dispute,         1,   1,       , 10.4
+withdrawal,      2,   2,    1.5,
+"
+            .as_bytes(),
+        ) {
+            writer.append(&transaction.unwrap()).unwrap();
+        }
+        let digest = writer.chain.head();
+        let ledger = String::from_utf8(writer.writer.into_inner().unwrap()).unwrap();
+        assert_eq!(verify(ledger.as_bytes()).unwrap(), digest);
+        assert_eq!(Digest::from_str(&digest.to_string()), Ok(digest));
+
+        let tampered = ledger.replacen("1.5000", "0.5000", 1);
+        assert!(matches!(
+            verify(tampered.as_bytes()),
+            Err(Error::Broken { row: 3, tx: 2 })
+        ));
+        let mut rows = ledger.lines().collect::<Vec<_>>();
+        rows.remove(1);
+        assert!(matches!(
+            verify(rows.join("\n").as_bytes()),
+            Err(Error::Broken { row: 1, tx: 1 })
+        ));
+    }
+}
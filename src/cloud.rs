@@ -0,0 +1,103 @@
+//! Streaming input from object stores: `s3://`, `gs://` and `az://` URLs.
+//!
+//! The object is read chunk by chunk as it arrives rather than downloaded first, on a runtime of the reader's own so
+//! it can be read like any other file. Credentials and regions come from the usual environment variables, like
+//! `AWS_ACCESS_KEY_ID` or `GOOGLE_SERVICE_ACCOUNT`.
+
+use bytes::Bytes;
+use futures_util::stream::{BoxStream, StreamExt};
+use object_store::{path::Path, ObjectStore, ObjectStoreExt};
+use std::io::{self, Read};
+use tokio::runtime::Runtime;
+use url::Url;
+
+pub fn is_url(path: &str) -> bool {
+    ["s3://", "gs://", "az://"]
+        .iter()
+        .any(|scheme| path.starts_with(scheme))
+}
+
+pub struct ObjectReader {
+    runtime: Runtime,
+    body: BoxStream<'static, object_store::Result<Bytes>>,
+    /// What's left of the last chunk to arrive
+    chunk: Bytes,
+}
+
+impl ObjectReader {
+    pub fn open(url: &str) -> io::Result<Self> {
+        let url = Url::parse(url).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let (store, path) = object_store::parse_url_opts(&url, std::env::vars()).map_err(error)?;
+        Self::from_store(&*store, &path)
+    }
+
+    /// Reads the object at `path` in `store`
+    pub fn from_store<S: ObjectStore + ?Sized>(store: &S, path: &Path) -> io::Result<Self> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let body = runtime
+            .block_on(store.get(path))
+            .map_err(error)?
+            .into_stream();
+        Ok(ObjectReader {
+            runtime,
+            body,
+            chunk: Bytes::new(),
+        })
+    }
+}
+
+impl Read for ObjectReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.chunk.is_empty() {
+            match self.runtime.block_on(self.body.next()) {
+                Some(chunk) => self.chunk = chunk.map_err(error)?,
+                None => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.chunk.len());
+        buf[..n].copy_from_slice(&self.chunk.split_to(n));
+        Ok(n)
+    }
+}
+
+fn error(e: object_store::Error) -> io::Error {
+    match e {
+        object_store::Error::NotFound { .. } => io::Error::new(io::ErrorKind::NotFound, e),
+        e => io::Error::other(e),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use object_store::memory::InMemory;
+
+    #[test]
+    fn read_object() {
+        let store = InMemory::new();
+        let path = Path::from("feeds/transactions.csv");
+        let body = "type,client,tx,amount\n".repeat(1000);
+        tokio::runtime::Runtime::new()
+            .unwrap()
+            .block_on(store.put(&path, body.clone().into()))
+            .unwrap();
+
+        let mut reader = ObjectReader::from_store(&store, &path).unwrap();
+        // Smaller reads than the chunks arrive in
+        let mut read = Vec::new();
+        let mut buf = [0; 7];
+        loop {
+            match reader.read(&mut buf).unwrap() {
+                0 => break,
+                n => read.extend_from_slice(&buf[..n]),
+            }
+        }
+        assert_eq!(read, body.as_bytes());
+
+        let missing = ObjectReader::from_store(&store, &Path::from("missing.csv"));
+        assert_eq!(missing.err().unwrap().kind(), io::ErrorKind::NotFound);
+        assert!(is_url("s3://bucket/transactions.csv") && !is_url("transactions.csv"));
+    }
+}
@@ -0,0 +1,145 @@
+//! Decompressing input files on the fly, so archived dumps don't need unpacking to disk first, and compressing the
+//! records stores keep on disk
+
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    /// Needs the `gzip` feature
+    Gzip,
+    /// Needs the `zstd` feature
+    Zstd,
+}
+
+impl Compression {
+    /// Guesses from the file extension, `.gz` or `.zst`
+    pub fn detect<P: AsRef<Path>>(path: P) -> Self {
+        match path.as_ref().extension().and_then(|e| e.to_str()) {
+            Some("gz") => Compression::Gzip,
+            Some("zst") => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+
+    /// Wraps `rdr` to decompress it
+    pub fn decoder<'a, R: io::Read + 'a>(self, rdr: R) -> io::Result<Box<dyn io::Read + 'a>> {
+        match self {
+            Compression::None => Ok(Box::new(rdr)),
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => Ok(Box::new(flate2::read::MultiGzDecoder::new(rdr))),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => Ok(Box::new(zstd::Decoder::new(rdr)?)),
+            #[allow(unreachable_patterns)]
+            _ => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("built without {} support", self),
+            )),
+        }
+    }
+
+    /// Compresses `data` on its own, for formats compressing parts of a file rather than all of it
+    pub fn compress(self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Compression::None => Ok(data.to_vec()),
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => {
+                use std::io::Write;
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => zstd::encode_all(data, 0),
+            #[allow(unreachable_patterns)]
+            _ => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("built without {} support", self),
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Compression::None => "none",
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct UnknownCompression(String);
+
+impl fmt::Display for UnknownCompression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "unknown compression {:?}, expected none, gzip or zstd",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for UnknownCompression {}
+
+impl FromStr for Compression {
+    type Err = UnknownCompression;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Compression::None),
+            "gzip" | "gz" => Ok(Compression::Gzip),
+            "zstd" | "zst" => Ok(Compression::Zstd),
+            _ => Err(UnknownCompression(s.to_owned())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn detect_and_decode() {
+        assert_eq!(Compression::detect("dump.csv.gz"), Compression::Gzip);
+        assert_eq!(Compression::detect("dump.csv.zst"), Compression::Zstd);
+        assert_eq!(Compression::detect("dump.csv"), Compression::None);
+
+        let data = b"type,client,tx,amount\ndeposit,1,1,1.0\n";
+        let mut compressed = Vec::new();
+        for compression in [Compression::None, Compression::Gzip, Compression::Zstd] {
+            match compression {
+                Compression::None => compressed = data.to_vec(),
+                #[cfg(feature = "gzip")]
+                Compression::Gzip => {
+                    use std::io::Write;
+                    let mut encoder =
+                        flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                    encoder.write_all(data).unwrap();
+                    compressed = encoder.finish().unwrap();
+                }
+                #[cfg(feature = "zstd")]
+                Compression::Zstd => compressed = zstd::encode_all(&data[..], 0).unwrap(),
+                #[allow(unreachable_patterns)]
+                _ => {
+                    assert!(compression.decoder(&compressed[..]).is_err());
+                    continue;
+                }
+            }
+            let mut decoded = Vec::new();
+            compression
+                .decoder(&compressed[..])
+                .unwrap()
+                .read_to_end(&mut decoded)
+                .unwrap();
+            assert_eq!(decoded, data, "{}", compression);
+        }
+    }
+}
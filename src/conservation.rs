@@ -0,0 +1,108 @@
+//! A debugging aid checking that money is never created or destroyed by the engine
+
+use std::fmt;
+
+use crate::decimal::Decimal;
+use crate::posting::{Account, Postings};
+use crate::transaction::{DisputableType, DisputableType::*, Type, Type::*};
+
+/// Tracks every movement of funds into and out of the clients, so that the sum of all client totals can be checked
+/// against it.
+///
+/// Funds come in through deposits, interest and chargebacks of withdrawals, and leave through withdrawals, refunds and
+/// chargebacks of deposits or as fees. Overdrawn withdrawals bring in the credit they draw on, which deposits pay back
+/// out, and captures bring in authorized funds. Fees overdrawing available funds draw on credit which never reaches
+/// them. Withdrawals disputed while disputed withdrawals are held bring their amount back in until they're resolved.
+/// Nothing else should change the sum of client totals.
+#[derive(Clone, Debug, Default)]
+pub struct Conservation {
+    credits: Decimal,
+    debits: Decimal,
+    totals: Decimal,
+}
+
+/// The ledger no longer balances
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Violation {
+    pub credits: Decimal,
+    pub debits: Decimal,
+    pub totals: Decimal,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "deposits, interest and charged back withdrawals {} != \
+             withdrawals, fees and charged back deposits {} + client totals {}",
+            self.credits, self.debits, self.totals
+        )
+    }
+}
+
+impl Conservation {
+    /// Starts from the sum of the existing client totals, treating it as having been deposited
+    pub fn new(totals: Decimal) -> Self {
+        Conservation {
+            credits: totals.clone(),
+            debits: Decimal::zero(),
+            totals,
+        }
+    }
+
+    /// Records the effect of a single transaction.
+    ///
+    /// `charged_back` is the transaction being charged back, if this is a chargeback, `postings` are the postings it
+    /// made if it was applied, and `before` and `after` are the total of the transaction's client either side of
+    /// processing it.
+    pub fn record(
+        &mut self,
+        type_: &Type,
+        charged_back: Option<DisputableType>,
+        postings: Option<&Postings>,
+        before: Decimal,
+        after: Decimal,
+    ) -> Result<(), Violation> {
+        if let Some(postings) = postings {
+            // Fees, overdrafts, adjustments and captures move funds between the client's total and accounts of our own,
+            // and so do disputes of withdrawals, which are held or reserved
+            for posting in postings.iter() {
+                match (type_, posting.from, posting.to) {
+                    // Credit drawn on straight out as an overdrawn fee never reaches the client's total
+                    (_, Account::Overdraft, Account::Fees) => {}
+                    (_, _, Account::Fees | Account::Overdraft | Account::Adjustments)
+                    | (Resolve(_), Account::Held, Account::External) => {
+                        self.debits += posting.amount.clone()
+                    }
+                    (_, Account::Overdraft | Account::Adjustments, _)
+                    | (_, Account::Pending, Account::Available)
+                    | (Dispute(_), Account::External, Account::Held)
+                    | (Chargeback(_), Account::Reserve, Account::Available) => {
+                        self.credits += posting.amount.clone()
+                    }
+                    _ => {}
+                }
+            }
+            match (type_, charged_back) {
+                (Disputable(Deposit(amount)), _) => self.credits += amount.clone(),
+                (Disputable(Withdrawal(amount)), _) => self.debits += amount.clone(),
+                (Chargeback(_), Some(Deposit(amount))) => self.debits += amount,
+                (Refund(amount), _) => self.debits += amount.clone(),
+                (Interest(amount), _) => self.credits += amount.clone(),
+                _ => {}
+            }
+        }
+
+        // The client's total is always part of `totals`, so this can't underflow
+        self.totals = (self.totals.clone() - before).unwrap_or_else(|_| Decimal::zero()) + after;
+
+        if self.credits != self.debits.clone() + self.totals.clone() {
+            return Err(Violation {
+                credits: self.credits.clone(),
+                debits: self.debits.clone(),
+                totals: self.totals.clone(),
+            });
+        }
+        Ok(())
+    }
+}
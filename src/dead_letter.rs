@@ -0,0 +1,153 @@
+//! Keeping rejected transactions with why they were rejected, so they can be retried once whatever rejected them is
+//! fixed, like a dispute arriving before the deposit it's for.
+//!
+//! Dead letters are written as a transaction file with extra `code` and `rejection` columns, so they can be read back
+//! like any other. Only the standard columns are kept, so metadata like signatures is lost.
+
+use serde::Serialize;
+use std::io::{self, Read, Write};
+
+use crate::engine::{Engine, Rejection};
+use crate::transaction::{read_from_csv_reader, CsvAmount, CsvTransaction, CsvType, Transaction};
+use crate::transaction_set::Client as TransactionSetClient;
+
+/// A row of a dead letter file
+#[derive(Serialize)]
+struct Row {
+    #[serde(rename = "type")]
+    type_: CsvType,
+    client: u16,
+    tx: u32,
+    amount: Option<CsvAmount>,
+    reason: Option<String>,
+    code: &'static str,
+    rejection: String,
+}
+
+pub struct Writer<W: Write> {
+    writer: csv::Writer<W>,
+}
+
+impl<W: Write> Writer<W> {
+    pub fn new(w: W) -> Self {
+        Writer {
+            writer: csv::Writer::from_writer(w),
+        }
+    }
+
+    pub fn append(&mut self, transaction: &Transaction, rejection: &Rejection) -> csv::Result<()> {
+        let CsvTransaction {
+            type_,
+            client,
+            tx,
+            amount,
+            reason,
+        } = CsvTransaction::from(transaction.clone());
+        self.writer.serialize(Row {
+            type_,
+            client,
+            tx,
+            amount,
+            reason,
+            code: rejection.code(),
+            rejection: rejection.to_string(),
+        })
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    /// Flushes, and returns what was written to
+    pub fn into_inner(self) -> io::Result<W> {
+        self.writer.into_inner().map_err(|e| e.into_error())
+    }
+}
+
+/// What came of retrying a dead letter file
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Retried {
+    pub applied: u64,
+    /// Rejected again, and written to the new dead letter file
+    pub rejected: u64,
+}
+
+/// Processes every transaction in a dead letter file again, in order, writing those which are still rejected to
+/// `dead_letters` with their new rejections
+pub fn retry<T: TransactionSetClient, R: Read, W: Write>(
+    engine: &mut Engine<T>,
+    rdr: R,
+    dead_letters: &mut Writer<W>,
+) -> csv::Result<Retried> {
+    let mut retried = Retried::default();
+    for transaction in read_from_csv_reader(rdr) {
+        let mut transaction = transaction?;
+        transaction.metadata.remove("code");
+        transaction.metadata.remove("rejection");
+        match engine.process(transaction.clone()) {
+            Ok(()) => retried.applied += 1,
+            Err(rejection) => {
+                dead_letters.append(&transaction, &rejection)?;
+                retried.rejected += 1;
+            }
+        }
+    }
+    Ok(retried)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::transaction_set::MemoryClient;
+
+    #[test]
+    fn retry_once_fixed() {
+        let mut engine = Engine::new(MemoryClient::default());
+        let mut dead_letters = Writer::new(Vec::new());
+        for transaction in read_from_csv_reader(
+            "\
+type,       client,  tx, amount, reason
+withdrawal,      1,   1,    2.0,
+dispute,         1,   2,       , fraud
+deposit,         1,   2,    1.0,
+"
+            .as_bytes(),
+        ) {
+            let transaction = transaction.unwrap();
+            if let Err(rejection) = engine.process(transaction.clone()) {
+                dead_letters.append(&transaction, &rejection).unwrap();
+            }
+        }
+        let file = dead_letters.into_inner().unwrap();
+        assert_eq!(
+            String::from_utf8(file.clone()).unwrap(),
+            "\
+type,client,tx,amount,reason,code,rejection
+withdrawal,1,1,2.0000,,E_INSUFFICIENT_FUNDS,Failed to withdraw 2.0000 from client 1. Only 0.0000 funds present
+dispute,1,2,,fraud,E_TX_NOT_FOUND,Failed to dispute transaction: Not found.
+"
+        );
+
+        // The disputed deposit has arrived, but there's still not enough for the withdrawal
+        let mut dead_letters = Writer::new(Vec::new());
+        let retried = retry(&mut engine, file.as_slice(), &mut dead_letters).unwrap();
+        assert_eq!(
+            retried,
+            Retried {
+                applied: 1,
+                rejected: 1
+            }
+        );
+        assert_eq!(engine.client(1).unwrap().disputes(), 1);
+        let file = dead_letters.into_inner().unwrap();
+        let still_rejected = read_from_csv_reader(file.as_slice())
+            .map(Result::unwrap)
+            .collect::<Vec<_>>();
+        assert_eq!(still_rejected.len(), 1);
+        assert_eq!(still_rejected[0].transaction_id, 1);
+        assert_eq!(
+            still_rejected[0].metadata["rejection"],
+            "Failed to withdraw 2.0000 from client 1. Only 1.0000 funds present"
+        );
+    }
+}
@@ -0,0 +1,161 @@
+//! Parsing the `client`, `tx` and `amount` columns straight from their bytes, eight digits at a time.
+//!
+//! Runs of digits are parsed SWAR (SIMD within a register) style: eight ASCII digits are loaded into a `u64`, checked
+//! and combined with three multiplications, rather than one digit at a time. Anything other than plain digits, like a
+//! sign or more digits than fit, falls back to the standard parsers, so the results are always the same as theirs.
+
+use std::convert::TryFrom;
+use std::str::{self, FromStr};
+
+use crate::decimal::Decimal;
+use crate::transaction::CsvAmount;
+
+/// The fractional digits a `Decimal` keeps
+const PRECISION: usize = 4;
+
+/// Any unsigned integer, as `FromStr` would parse it
+pub fn parse_int<T: FromStr + TryFrom<u64>>(field: &[u8]) -> Option<T> {
+    match digits(field) {
+        Some(n) => T::try_from(n).ok(),
+        None => str::from_utf8(field).ok()?.parse().ok(),
+    }
+}
+
+/// An amount, as `Decimal::from_str` would parse it
+pub fn parse_decimal(field: &[u8]) -> Option<Decimal> {
+    let fallback = || str::from_utf8(field).ok()?.parse().ok();
+    let (whole, fraction) = match field.iter().position(|&b| b == b'.') {
+        Some(dot) => (&field[..dot], &field[dot + 1..]),
+        None => (field, &[][..]),
+    };
+    if field.is_empty() || fraction.len() > PRECISION {
+        return fallback();
+    }
+    let dollars = match whole.is_empty() {
+        true => 0,
+        false => match digits(whole) {
+            Some(dollars) => dollars,
+            None => return fallback(),
+        },
+    };
+    let cents = match fraction.is_empty() {
+        true => 0,
+        false => match digits(fraction) {
+            Some(cents) => cents * 10u64.pow((PRECISION - fraction.len()) as u32),
+            None => return fallback(),
+        },
+    };
+    Some(Decimal::new(dollars, cents as u16))
+}
+
+/// An amount column, as `CsvAmount::from_str` would parse it
+pub(crate) fn parse_amount(field: &[u8]) -> Option<CsvAmount> {
+    Some(match field.split_first() {
+        Some((b'-', magnitude)) => CsvAmount {
+            negative: true,
+            magnitude: parse_decimal(magnitude)?,
+        },
+        Some((b'+', magnitude)) => CsvAmount::from(parse_decimal(magnitude)?),
+        _ => CsvAmount::from(parse_decimal(field)?),
+    })
+}
+
+/// Up to 19 ASCII digits, which always fit in a `u64`
+fn digits(bytes: &[u8]) -> Option<u64> {
+    if bytes.is_empty() || bytes.len() > 19 {
+        return None;
+    }
+    // The first chunk is padded with leading zeroes to a full eight digits
+    let head = match bytes.len() % 8 {
+        0 => 8,
+        n => n,
+    };
+    let mut chunk = [b'0'; 8];
+    chunk[8 - head..].copy_from_slice(&bytes[..head]);
+    let mut n = eight_digits(chunk)?;
+    for chunk in bytes[head..].chunks_exact(8) {
+        n = n * 100_000_000 + eight_digits(<[u8; 8]>::try_from(chunk).expect("chunks of eight"))?;
+    }
+    Some(n)
+}
+
+/// Eight ASCII digits, most significant first
+fn eight_digits(chunk: [u8; 8]) -> Option<u64> {
+    let v = u64::from_le_bytes(chunk);
+    // Every high nibble is 3, and every low nibble is at most 9 so adding 6 doesn't carry into the high nibble
+    if v & 0xf0f0_f0f0_f0f0_f0f0 != 0x3030_3030_3030_3030
+        || v.wrapping_add(0x0606_0606_0606_0606) & 0xf0f0_f0f0_f0f0_f0f0 != 0x3030_3030_3030_3030
+    {
+        return None;
+    }
+    let v = v - 0x3030_3030_3030_3030;
+    // Pairs of digits, then fours, then all eight
+    let v = v * 10 + (v >> 8);
+    let v = ((v & 0x0000_00ff_0000_00ff).wrapping_mul(100 + (1_000_000 << 32))
+        + ((v >> 16) & 0x0000_00ff_0000_00ff).wrapping_mul(1 + (10_000 << 32)))
+        >> 32;
+    Some(v)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::prelude::*;
+
+    /// Every string of up to `len` characters from `alphabet`
+    fn strings(alphabet: &[u8], len: usize) -> Vec<Vec<u8>> {
+        let mut all = vec![Vec::new()];
+        let mut last = vec![Vec::new()];
+        for _ in 0..len {
+            last = last
+                .iter()
+                .flat_map(|s: &Vec<u8>| {
+                    alphabet.iter().map(move |&c| {
+                        let mut s = s.clone();
+                        s.push(c);
+                        s
+                    })
+                })
+                .collect();
+            all.extend(last.iter().cloned());
+        }
+        all
+    }
+
+    #[test]
+    fn same_as_from_str() {
+        let standard = |s: &[u8]| str::from_utf8(s).unwrap().parse::<Decimal>().ok();
+        for s in strings(b"0159.+-x", 6) {
+            assert_eq!(parse_decimal(&s), standard(&s), "{:?}", str::from_utf8(&s));
+            assert_eq!(
+                parse_amount(&s),
+                str::from_utf8(&s).unwrap().parse::<CsvAmount>().ok()
+            );
+            assert_eq!(parse_int::<u16>(&s), standard_int(&s));
+        }
+
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..100_000 {
+            let len = rng.gen_range(1..=24);
+            let s = (0..len)
+                .map(|_| match rng.gen_range(0..20) {
+                    0 => b'.',
+                    _ => b'0' + rng.gen_range(0..10),
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(parse_decimal(&s), standard(&s), "{:?}", str::from_utf8(&s));
+            assert_eq!(parse_int::<u32>(&s), standard_int(&s));
+            assert_eq!(parse_int::<u64>(&s), standard_int(&s));
+        }
+        for n in (0..=70_000u64).chain([u32::MAX as u64, u32::MAX as u64 + 1, u64::MAX]) {
+            let s = n.to_string();
+            assert_eq!(parse_int::<u16>(s.as_bytes()), u16::try_from(n).ok());
+            assert_eq!(parse_int::<u32>(s.as_bytes()), u32::try_from(n).ok());
+            assert_eq!(parse_int::<u64>(s.as_bytes()), Some(n));
+        }
+    }
+
+    fn standard_int<T: FromStr>(s: &[u8]) -> Option<T> {
+        str::from_utf8(s).unwrap().parse().ok()
+    }
+}
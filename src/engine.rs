@@ -0,0 +1,2885 @@
+#[cfg(feature = "testing")]
+use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::sync::mpsc;
+use std::thread;
+
+use crate::alerts::{Alert, Balance, Thresholds};
+use crate::client::{Client, ClientOutput};
+use crate::conservation::Conservation;
+use crate::decimal::Decimal;
+use crate::fees::{Fee, FeeSchedule};
+use crate::idempotency::Deduplication;
+use crate::kyc::{Kyc, Status};
+use crate::limits::{AmountLimits, Limit};
+use crate::lock_policy::{AutoLock, LockPolicy};
+use crate::observer::EventObserver;
+use crate::overdraft::OverdraftLimits;
+use crate::period::{DuplicatePeriod, Period, Periods};
+use crate::posting::Postings;
+use crate::rate_limit::RateLimit;
+use crate::record;
+use crate::report::{self, Mismatch};
+use crate::risk::{Flag, RiskRule, Verdict};
+#[cfg(feature = "testing")]
+use crate::testing::InvariantViolation;
+use crate::tiers::Tiers;
+use crate::transaction::{
+    read_from_csv_reader, DisputableTransaction, DisputableType::*, Transaction, TransactionKind,
+    Type, Type::*,
+};
+use crate::transaction_set::{
+    Client as TransactionSetClient, MemoryClient, State, State::*, StoreStats, UpdateFailure,
+    UpdateFailure::*,
+};
+use crate::view::{View, Views};
+
+/// A step in the dispute process, in settling an authorization, or a refund
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Step {
+    Dispute,
+    Resolve,
+    Chargeback,
+    Capture,
+    Void,
+    Refund,
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Step::Dispute => write!(f, "dispute"),
+            Step::Resolve => write!(f, "resolve"),
+            Step::Chargeback => write!(f, "chargeback"),
+            Step::Capture => write!(f, "capture"),
+            Step::Void => write!(f, "void"),
+            Step::Refund => write!(f, "refund"),
+        }
+    }
+}
+
+/// Why a transaction was not applied
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Rejection {
+    /// A withdrawal of more than the available funds
+    InsufficientFunds {
+        client_id: u16,
+        requested: Decimal,
+        available: Decimal,
+    },
+    /// A withdrawal from a locked client
+    Frozen { client_id: u16, requested: Decimal },
+    /// A dispute step for a transaction that was never stored
+    NotFound(Step),
+    /// A dispute step for a transaction that isn't in a state to take it
+    WrongState(Step, State),
+    /// A resolve or chargeback of more funds than are held for it
+    InsufficientHeld {
+        step: Step,
+        requested: Decimal,
+        available: Decimal,
+    },
+    /// A refund of more than is left of the original deposit, which is nothing for withdrawals
+    ExcessRefund {
+        requested: Decimal,
+        refundable: Decimal,
+    },
+    /// A refund of another client's deposit
+    NotOwner { client_id: u16, transaction_id: u32 },
+    /// Any transaction for a client whose account has been closed
+    AccountClosed { client_id: u16 },
+    /// A close of an account with funds still held or reserved for disputes, or pending capture
+    Unsettled { client_id: u16, held: Decimal },
+    /// Any transaction a risk rule rejected
+    Risk { client_id: u16, reason: String },
+    /// Any transaction for a client which has used up its rate limit
+    RateLimited { client_id: u16 },
+    /// A transaction of an amount outside the limit for its kind
+    LimitExceeded {
+        client_id: u16,
+        kind: TransactionKind,
+        amount: Decimal,
+        limit: Limit,
+    },
+    /// A dispute of a transaction applied longer ago than the client's tier allows
+    DisputeWindowClosed { client_id: u16, transaction_id: u32 },
+    /// A transaction which moves money for a suspended client
+    Suspended {
+        client_id: u16,
+        kind: TransactionKind,
+    },
+    /// A withdrawal or authorization for a client which hasn't been verified
+    Unverified {
+        client_id: u16,
+        kind: TransactionKind,
+    },
+    /// A deposit which would take an unverified client's total over the cap
+    DepositCapExceeded {
+        client_id: u16,
+        requested: Decimal,
+        cap: Decimal,
+    },
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rejection::InsufficientFunds {
+                client_id,
+                requested,
+                available,
+            } => write!(
+                f,
+                "Failed to withdraw {} from client {}. Only {} funds present",
+                requested, client_id, available
+            ),
+            Rejection::Frozen {
+                client_id,
+                requested,
+            } => write!(
+                f,
+                "Failed to withdraw {} from client {}. Client frozen.",
+                requested, client_id
+            ),
+            Rejection::NotFound(step) => write!(f, "Failed to {} transaction: Not found.", step),
+            Rejection::WrongState(step, s) => {
+                write!(f, "Failed to {} transaction: Wrong state {:?}.", step, s)
+            }
+            Rejection::InsufficientHeld {
+                step,
+                requested,
+                available,
+            } => write!(
+                f,
+                "Failed to {} transaction: Requested {} funds, only {} available.",
+                step, requested, available
+            ),
+            Rejection::ExcessRefund {
+                requested,
+                refundable,
+            } => write!(
+                f,
+                "Failed to refund transaction: Requested {} funds, only {} refundable.",
+                requested, refundable
+            ),
+            Rejection::NotOwner {
+                client_id,
+                transaction_id,
+            } => write!(
+                f,
+                "Failed to refund transaction {} for client {}. It belongs to another client.",
+                transaction_id, client_id
+            ),
+            Rejection::AccountClosed { client_id } => {
+                write!(f, "Client {} account closed.", client_id)
+            }
+            Rejection::Unsettled { client_id, held } => write!(
+                f,
+                "Failed to close client {}. {} funds still disputed or pending.",
+                client_id, held
+            ),
+            Rejection::Risk { client_id, reason } => {
+                write!(
+                    f,
+                    "Rejected for client {} by risk rule: {}.",
+                    client_id, reason
+                )
+            }
+            Rejection::RateLimited { client_id } => {
+                write!(f, "Client {} is over its rate limit.", client_id)
+            }
+            Rejection::LimitExceeded {
+                client_id,
+                kind,
+                amount,
+                limit,
+            } => write!(
+                f,
+                "Failed to process {} of {} for client {}. Must be {}.",
+                kind, amount, client_id, limit
+            ),
+            Rejection::DisputeWindowClosed {
+                client_id,
+                transaction_id,
+            } => write!(
+                f,
+                "Failed to dispute transaction {} for client {}. Too late to dispute.",
+                transaction_id, client_id
+            ),
+            Rejection::Suspended { client_id, kind } => write!(
+                f,
+                "Failed to process {} for client {}. Client suspended.",
+                kind, client_id
+            ),
+            Rejection::Unverified { client_id, kind } => write!(
+                f,
+                "Failed to process {} for client {}. Client unverified.",
+                kind, client_id
+            ),
+            Rejection::DepositCapExceeded {
+                client_id,
+                requested,
+                cap,
+            } => write!(
+                f,
+                "Failed to deposit {} for client {}. Unverified clients may only hold {}.",
+                requested, client_id, cap
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Rejection {}
+
+impl Rejection {
+    /// A stable code for the kind of rejection, for automation to match on instead of the message
+    pub fn code(&self) -> &'static str {
+        match self {
+            Rejection::InsufficientFunds { .. } => "E_INSUFFICIENT_FUNDS",
+            Rejection::Frozen { .. } => "E_FROZEN",
+            Rejection::NotFound(_) => "E_TX_NOT_FOUND",
+            Rejection::WrongState(..) => "E_WRONG_STATE",
+            Rejection::InsufficientHeld { .. } => "E_INSUFFICIENT_HELD",
+            Rejection::ExcessRefund { .. } => "E_EXCESS_REFUND",
+            Rejection::NotOwner { .. } => "E_NOT_OWNER",
+            Rejection::AccountClosed { .. } => "E_ACCOUNT_CLOSED",
+            Rejection::Unsettled { .. } => "E_UNSETTLED",
+            Rejection::Risk { .. } => "E_RISK",
+            Rejection::RateLimited { .. } => "E_RATE_LIMITED",
+            Rejection::LimitExceeded { .. } => "E_LIMIT_EXCEEDED",
+            Rejection::DisputeWindowClosed { .. } => "E_DISPUTE_WINDOW_CLOSED",
+            Rejection::Suspended { .. } => "E_SUSPENDED",
+            Rejection::Unverified { .. } => "E_UNVERIFIED",
+            Rejection::DepositCapExceeded { .. } => "E_DEPOSIT_CAP",
+        }
+    }
+}
+
+/// Why two clients couldn't be merged
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MergeError {
+    SameClient,
+    NotFound(u16),
+    Closed(u16),
+}
+
+impl fmt::Display for MergeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MergeError::SameClient => write!(f, "Can't merge a client into itself."),
+            MergeError::NotFound(client_id) => write!(f, "Client {} not found.", client_id),
+            MergeError::Closed(client_id) => write!(f, "Client {} account closed.", client_id),
+        }
+    }
+}
+
+impl std::error::Error for MergeError {}
+
+/// Applies a transaction, returning the postings it made to the client's accounts.
+///
+/// The transaction is applied to a copy of the client, which only replaces it once the store has taken every change to
+/// the transaction's record too, so a rejection never leaves a transaction partly applied.
+pub fn process_transaction<T: TransactionSetClient>(
+    transaction: Transaction,
+    clients: &mut HashMap<u16, Client>,
+    tx_record: &mut T,
+) -> Result<Postings, Rejection> {
+    let client = clients
+        .entry(transaction.client_id)
+        .or_insert(Client::new(transaction.client_id));
+    if client.is_closed() {
+        return Err(Rejection::AccountClosed {
+            client_id: transaction.client_id,
+        });
+    }
+    let mut staged = client.clone();
+    let result = stage_transaction(transaction, &mut staged, tx_record);
+    match result {
+        // A chargeback which reaches the client locks it, and is counted, even if there isn't enough held for it
+        Ok(_)
+        | Err(Rejection::InsufficientHeld {
+            step: Step::Chargeback,
+            ..
+        }) => *client = staged,
+        Err(_) => {}
+    }
+    result
+}
+
+/// The client's tier's overdraft allowance, or otherwise its overdraft limit, if either is set
+fn overdraft_limit(
+    overdrafts: Option<&OverdraftLimits>,
+    tiers: Option<&Tiers>,
+    client_id: u16,
+) -> Option<Decimal> {
+    tiers
+        .and_then(|tiers| tiers.tier(client_id)?.overdraft.clone())
+        .or_else(|| Some(overdrafts?.limit(client_id)))
+}
+
+fn update_failure(step: Step) -> impl Fn(UpdateFailure) -> Rejection {
+    move |e| match e {
+        NotFound => Rejection::NotFound(step),
+        WrongState(s) => Rejection::WrongState(step, s),
+    }
+}
+
+/// Moves a transaction which was in `from` through `states`, or if the store refuses any of them, or moved it from
+/// another state than expected, like one another engine sharing the store moved it to since, puts it back as it was
+fn transition<T: TransactionSetClient>(
+    tx_record: &mut T,
+    step: Step,
+    original: DisputableTransaction,
+    from: State,
+    states: &[State],
+) -> Result<(), Rejection> {
+    let mut expected = from;
+    for &state in states {
+        let rejection = match tx_record.transition(original.transaction_id, state) {
+            Ok(t) if t.from == expected => {
+                expected = state;
+                continue;
+            }
+            Ok(t) => Rejection::WrongState(step, t.from),
+            Err(e) => update_failure(step)(e),
+        };
+        tx_record.restore(original, from);
+        return Err(rejection);
+    }
+    Ok(())
+}
+
+/// Applies a transaction to the client and the store, leaving the store as it was if it's rejected
+fn stage_transaction<T: TransactionSetClient>(
+    transaction: Transaction,
+    client: &mut Client,
+    tx_record: &mut T,
+) -> Result<Postings, Rejection> {
+    let transaction_id = transaction.transaction_id;
+    // Finds the transaction a step is for, if it's in the state the step starts from
+    let expect_state = |tx_record: &mut T, step, from| match tx_record.access(transaction_id) {
+        None => Err(Rejection::NotFound(step)),
+        Some((original, state)) if state == from => Ok(original),
+        Some((_, state)) => Err(Rejection::WrongState(step, state)),
+    };
+    let client_id = transaction.client_id;
+    let withdrawal_failure = |requested: &Decimal| {
+        let requested = requested.clone();
+        move |e| match e {
+            Some(present) => Rejection::InsufficientFunds {
+                client_id,
+                requested,
+                available: present,
+            },
+            None => Rejection::Frozen {
+                client_id,
+                requested,
+            },
+        }
+    };
+    Ok(match transaction.type_ {
+        Disputable(Deposit(ref deposit)) => {
+            let postings = client.deposit(deposit.clone());
+            tx_record.store(DisputableTransaction {
+                transaction_id: transaction.transaction_id,
+                client_id: transaction.client_id,
+                type_: Deposit(deposit.clone()),
+                metadata: transaction.metadata.clone(),
+                history: Vec::new(),
+            });
+            postings
+        }
+        Disputable(Withdrawal(ref withdrawal)) => {
+            let postings = client
+                .withdraw(withdrawal.clone())
+                .map_err(withdrawal_failure(withdrawal))?;
+            tx_record.store(DisputableTransaction {
+                transaction_id: transaction.transaction_id,
+                client_id: transaction.client_id,
+                type_: Withdrawal(withdrawal.clone()),
+                metadata: transaction.metadata.clone(),
+                history: Vec::new(),
+            });
+            postings
+        }
+        Refund(ref refund) => {
+            let original = expect_state(tx_record, Step::Refund, Committed)?;
+            if original.client_id != transaction.client_id {
+                return Err(Rejection::NotOwner {
+                    client_id: transaction.client_id,
+                    transaction_id,
+                });
+            }
+            let refundable = match original.type_ {
+                Deposit(value) => value,
+                Withdrawal(_) => Decimal::zero(),
+            };
+            if *refund > refundable {
+                return Err(Rejection::ExcessRefund {
+                    requested: refund.clone(),
+                    refundable,
+                });
+            }
+            let postings = client
+                .refund(refund.clone())
+                .map_err(withdrawal_failure(refund))?;
+            tx_record
+                .refund(transaction_id, refund.clone())
+                .map_err(update_failure(Step::Refund))?;
+            postings
+        }
+        Fee(ref fee) => {
+            client
+                .charge_fee(fee.clone())
+                .map_err(|available| Rejection::InsufficientFunds {
+                    client_id: transaction.client_id,
+                    requested: fee.clone(),
+                    available,
+                })?
+        }
+        Interest(ref interest) => client.accrue_interest(interest.clone()),
+        Adjustment(ref adjustment) => client
+            .adjust(adjustment.debit, adjustment.amount.clone())
+            .map_err(|available| Rejection::InsufficientFunds {
+                client_id: transaction.client_id,
+                requested: adjustment.amount.clone(),
+                available,
+            })?,
+        Close => {
+            client.close().map_err(|held| Rejection::Unsettled {
+                client_id: transaction.client_id,
+                held,
+            })?;
+            Postings::default()
+        }
+        // A client's status is kept by the engine
+        Status(_) => Postings::default(),
+        Authorize(ref amount) => {
+            let postings = client.authorize(amount.clone());
+            tx_record.restore(
+                DisputableTransaction {
+                    transaction_id: transaction.transaction_id,
+                    client_id: transaction.client_id,
+                    type_: Deposit(amount.clone()),
+                    metadata: transaction.metadata.clone(),
+                    history: Vec::new(),
+                },
+                Authorized,
+            );
+            postings
+        }
+        Capture | Void => {
+            let (step, state) = match transaction.type_ {
+                Capture => (Step::Capture, Committed),
+                _ => (Step::Void, Voided),
+            };
+            let authorized = expect_state(tx_record, step, Authorized)?;
+            let (Deposit(ref value) | Withdrawal(ref value)) = authorized.type_;
+            let postings = match step {
+                Step::Capture => client.capture(value.clone()),
+                _ => client.void(value.clone()),
+            }
+            .map_err(|pending| Rejection::InsufficientHeld {
+                step,
+                requested: value.clone(),
+                available: pending,
+            })?;
+            transition(tx_record, step, authorized, Authorized, &[state])?;
+            postings
+        }
+        Dispute(ref reason) => {
+            let disputed = tx_record
+                .update(transaction.transaction_id, Disputed)
+                .map_err(update_failure(Step::Dispute))?;
+            let postings = match disputed.type_ {
+                Deposit(value) => client.dispute_deposit(value),
+                Withdrawal(value) => client.dispute_withdrawal(value),
+            };
+            tx_record.record(
+                transaction.transaction_id,
+                TransactionKind::Dispute,
+                reason.clone(),
+            );
+            postings
+        }
+        // A resolve or chargeback without enough funds held for it is rejected, leaving the transaction disputed, so it
+        // can be settled once there are. A chargeback still locks the client.
+        Resolve(ref reason) | Chargeback(ref reason) => {
+            let (step, kind, states) = match transaction.type_ {
+                Resolve(_) => (
+                    Step::Resolve,
+                    TransactionKind::Resolve,
+                    [Resolved, Committed],
+                ),
+                _ => (
+                    Step::Chargeback,
+                    TransactionKind::Chargeback,
+                    [ChargedBack, ChargedBackFinal],
+                ),
+            };
+            let disputed = expect_state(tx_record, step, Disputed)?;
+            let (Deposit(ref value) | Withdrawal(ref value)) = disputed.type_;
+            let postings = match (step, disputed.type_.clone()) {
+                (Step::Resolve, Deposit(value)) => client.resolve_deposit(value),
+                (Step::Resolve, Withdrawal(value)) => client.resolve_withdrawal(value),
+                (_, Deposit(value)) => client.chargeback_deposit(value),
+                (_, Withdrawal(value)) => client.chargeback_withdrawal(value),
+            }
+            .map_err(|held| Rejection::InsufficientHeld {
+                step,
+                requested: value.clone(),
+                available: held,
+            })?;
+            transition(tx_record, step, disputed, Disputed, &states)?;
+            tx_record.record(transaction_id, kind, reason.clone());
+            postings
+        }
+    })
+}
+
+/// Replacements for recorded transactions during a replay, keyed by transaction id and the recorded type.
+///
+/// A replacement of `None` drops the transaction entirely. For example, mapping `(7, Chargeback)` to `Some(Resolve)`
+/// treats the chargeback of transaction 7 as a resolve instead.
+pub type Overrides = HashMap<(u32, Type), Option<Type>>;
+
+/// What a recording engine keeps to replay
+struct Log {
+    /// The engine as it was before the first transaction logged, configured the same
+    start: Option<Box<Engine<MemoryClient>>>,
+    /// Every transaction which has passed the checks since
+    transactions: Vec<Transaction>,
+}
+
+/// Owns the client accounts and the record of disputable transactions, applying transactions to both
+pub struct Engine<T: TransactionSetClient> {
+    clients: HashMap<u16, Client>,
+    tx_record: T,
+    log: Option<Log>,
+    conservation: Option<Conservation>,
+    fees: Option<FeeSchedule>,
+    chargeback_fee: Option<Fee>,
+    // The fees charged for chargebacks since they were last taken
+    chargeback_fees: Vec<Transaction>,
+    overdrafts: Option<OverdraftLimits>,
+    tiers: Option<Tiers>,
+    kyc: Kyc,
+    hold_disputed_withdrawals: bool,
+    periods: Option<Periods>,
+    risk_rules: Vec<Box<dyn RiskRule>>,
+    flags: Vec<Flag>,
+    lock_policy: Option<LockPolicy>,
+    auto_locks: Vec<AutoLock>,
+    thresholds: Option<Thresholds>,
+    alerts: Vec<Alert>,
+    rate_limit: Option<RateLimit>,
+    amount_limits: Option<AmountLimits>,
+    // Timestamps each client's activity, if anything does
+    activity_clock: Option<Box<dyn Fn() -> u64>>,
+    observers: Vec<Box<dyn EventObserver>>,
+    deduplication: Option<Deduplication>,
+    // Each client's open disputes by transaction id, with the reason given for each
+    open_disputes: HashMap<u16, BTreeMap<u32, Option<String>>>,
+    // The latest view given and the clients changed since, once one has been
+    views: Option<Views>,
+    // Every client a chargeback has been applied to, or which started out or was automatically locked
+    #[cfg(feature = "testing")]
+    charged_back: HashSet<u16>,
+}
+
+impl<T: TransactionSetClient> Engine<T> {
+    pub fn new(tx_record: T) -> Self {
+        Self::with_clients(HashMap::new(), tx_record)
+    }
+
+    /// Starts from an existing set of client accounts, keyed by client id
+    pub fn with_clients(clients: HashMap<u16, Client>, tx_record: T) -> Self {
+        Engine {
+            #[cfg(feature = "testing")]
+            charged_back: clients
+                .values()
+                .filter(|c| c.is_locked())
+                .map(Client::id)
+                .collect(),
+            clients,
+            tx_record,
+            log: None,
+            conservation: None,
+            fees: None,
+            chargeback_fee: None,
+            chargeback_fees: Vec::new(),
+            overdrafts: None,
+            tiers: None,
+            kyc: Kyc::default(),
+            hold_disputed_withdrawals: false,
+            periods: None,
+            risk_rules: Vec::new(),
+            flags: Vec::new(),
+            lock_policy: None,
+            auto_locks: Vec::new(),
+            thresholds: None,
+            alerts: Vec::new(),
+            rate_limit: None,
+            amount_limits: None,
+            activity_clock: None,
+            observers: Vec::new(),
+            deduplication: None,
+            open_disputes: HashMap::new(),
+            views: None,
+        }
+    }
+
+    /// Records every transaction processed from now on, so that they can be replayed later with the same
+    /// configuration. Only transactions which pass the rate limit, amount limits, tiers, KYC and risk rules are
+    /// recorded, so those aren't checked again.
+    pub fn recording(mut self) -> Self {
+        self.log = Some(Log {
+            start: None,
+            transactions: Vec::new(),
+        });
+        self
+    }
+
+    /// An engine starting from `clients` which applies transactions as this one does, with none of its rate limit,
+    /// risk rules, observers or anything else which only checks or reports on transactions
+    fn configured_like<U: TransactionSetClient>(
+        &self,
+        clients: HashMap<u16, Client>,
+        tx_record: U,
+    ) -> Engine<U> {
+        Engine {
+            fees: self.fees.clone(),
+            chargeback_fee: self.chargeback_fee.clone(),
+            overdrafts: self.overdrafts.clone(),
+            tiers: self.tiers.clone(),
+            kyc: self.kyc.clone(),
+            hold_disputed_withdrawals: self.hold_disputed_withdrawals,
+            lock_policy: self.lock_policy.clone(),
+            ..Engine::with_clients(clients, tx_record)
+        }
+    }
+
+    /// Panics after any transaction which changes the sum of client totals by anything other than the funds moved
+    /// into or out of the system, for debugging the engine
+    pub fn checking_conservation(mut self) -> Self {
+        let totals = self
+            .clients
+            .keys()
+            .fold(Decimal::zero(), |sum, &id| sum + self.total(id));
+        self.conservation = Some(Conservation::new(totals));
+        self
+    }
+
+    /// Charges the client a fee from `schedule` after every applied transaction. A fee larger than the client's
+    /// available funds takes whatever is available, as the transaction has already gone through.
+    pub fn charging_fees(mut self, schedule: FeeSchedule) -> Self {
+        self.fees = Some(schedule);
+        self
+    }
+
+    /// Charges the client `fee` for every chargeback, on top of any in a fee schedule, as acquirers bill merchants.
+    /// Unlike other fees, a chargeback fee larger than the client's available funds overdraws them, past any overdraft
+    /// limit. Each is also taken as a `fee` transaction with the charged back transaction's id, to be recorded with it.
+    pub fn charging_chargeback_fees(mut self, fee: Fee) -> Self {
+        self.chargeback_fee = Some(fee);
+        self
+    }
+
+    /// Lets withdrawals overdraw each client's available funds up to their limit, for existing clients as well as new
+    /// ones
+    pub fn allowing_overdrafts(mut self, limits: OverdraftLimits) -> Self {
+        self.overdrafts = Some(limits);
+        self.refresh_overdraft_limits();
+        self
+    }
+
+    /// Holds each client in `tiers` to its tier's withdrawal limit and dispute window, and lets it overdraw by its
+    /// tier's allowance instead of any other overdraft limit, for existing clients as well as new ones
+    pub fn tiering(mut self, tiers: Tiers) -> Self {
+        self.tiers = Some(tiers);
+        self.refresh_overdraft_limits();
+        self
+    }
+
+    /// Gates what clients can do on their statuses in `kyc`, rather than treating every client as verified. Status
+    /// transactions change them either way.
+    pub fn verifying(mut self, kyc: Kyc) -> Self {
+        self.kyc = kyc;
+        self
+    }
+
+    fn refresh_overdraft_limits(&mut self) {
+        self.views = None;
+        let (overdrafts, tiers) = (self.overdrafts.as_ref(), self.tiers.as_ref());
+        for client in self.clients.values_mut() {
+            if let Some(limit) = overdraft_limit(overdrafts, tiers, client.id()) {
+                *client = client.clone().with_overdraft_limit(limit);
+            }
+        }
+    }
+
+    /// Holds the amount of every disputed withdrawal until the dispute is settled, so it's reported in held funds and
+    /// the total, for existing clients as well as new ones. Must be the same whenever the engine is restored while any
+    /// withdrawal is disputed.
+    pub fn holding_disputed_withdrawals(mut self) -> Self {
+        self.views = None;
+        for client in self.clients.values_mut() {
+            *client = client.clone().with_disputed_withdrawals_held();
+        }
+        self.hold_disputed_withdrawals = true;
+        self
+    }
+
+    /// Keeps the ledger in segments, one for each business period, which are closed with `close_period`. Disputes
+    /// opened before this aren't rolled forward from period to period.
+    pub fn closing_periods(mut self) -> Self {
+        self.periods = Some(Periods::default());
+        self
+    }
+
+    /// Evaluates `rule` before applying every transaction, after any rules already added
+    pub fn with_risk_rule<R: RiskRule + 'static>(mut self, rule: R) -> Self {
+        self.risk_rules.push(Box::new(rule));
+        self
+    }
+
+    /// Locks clients which reach one of `policy`'s dispute or chargeback thresholds
+    pub fn locking_automatically(mut self, policy: LockPolicy) -> Self {
+        self.lock_policy = Some(policy);
+        self
+    }
+
+    /// Rejects every transaction for a client over `limit`, before anything else sees it
+    pub fn limiting_rate(mut self, limit: RateLimit) -> Self {
+        self.rate_limit = Some(limit);
+        self
+    }
+
+    /// Rejects every transaction of an amount outside `limits` for its kind, before anything but the rate limit sees it
+    pub fn limiting_amounts(mut self, limits: AmountLimits) -> Self {
+        self.amount_limits = Some(limits);
+        self
+    }
+
+    /// Records when each client was first and last active, by `clock` as each transaction for them is applied, e.g. in
+    /// unix seconds
+    pub fn timestamping_activity<C: Fn() -> u64 + 'static>(mut self, clock: C) -> Self {
+        self.activity_clock = Some(Box::new(clock));
+        self
+    }
+
+    /// Treats every transaction with the same idempotency key as one of the last `window` keys seen as a retry of it,
+    /// returning the original outcome instead of applying it again. Retries aren't recorded, logged or observed.
+    pub fn deduplicating(mut self, window: usize) -> Self {
+        self.deduplication = Some(Deduplication::new(window));
+        self
+    }
+
+    /// Raises an alert whenever a transaction takes a client's balance across one of `thresholds`
+    pub fn alerting(mut self, thresholds: Thresholds) -> Self {
+        self.thresholds = Some(thresholds);
+        self
+    }
+
+    /// Calls `observer` as every transaction is processed, after any observers already added
+    pub fn with_observer<O: EventObserver + 'static>(mut self, observer: O) -> Self {
+        self.observers.push(Box::new(observer));
+        self
+    }
+
+    pub fn process(&mut self, transaction: Transaction) -> Result<(), Rejection> {
+        self.process_with_postings(transaction).map(drop)
+    }
+
+    /// Like `process`, but also returning the postings the transaction made to its client's accounts
+    pub fn process_with_postings(
+        &mut self,
+        transaction: Transaction,
+    ) -> Result<Postings, Rejection> {
+        let key = match &self.deduplication {
+            Some(deduplication) => match deduplication.outcome(&transaction) {
+                Some(outcome) => return outcome.clone(),
+                None => Deduplication::key(&transaction).map(str::to_owned),
+            },
+            None => None,
+        };
+        let result = self.observe(transaction);
+        if let (Some(deduplication), Some(key)) = (&mut self.deduplication, key) {
+            deduplication.record(key, &result);
+        }
+        result
+    }
+
+    /// The outcome of the original submission, if deduplicating and the transaction is a retry of one. Processing it
+    /// would return the same outcome without applying anything.
+    pub fn retried(&self, transaction: &Transaction) -> Option<&Result<Postings, Rejection>> {
+        self.deduplication.as_ref()?.outcome(transaction)
+    }
+
+    fn observe(&mut self, transaction: Transaction) -> Result<Postings, Rejection> {
+        if self.observers.is_empty() {
+            return self.apply(transaction);
+        }
+        let observed = transaction.clone();
+        let client_id = transaction.client_id;
+        let was_locked = self.clients.get(&client_id).is_some_and(Client::is_locked);
+        let (flagged, alerted) = (self.flags.len(), self.alerts.len());
+        let result = self.apply(transaction);
+
+        let locked = !was_locked && self.clients.get(&client_id).is_some_and(Client::is_locked);
+        for observer in &mut self.observers {
+            for flag in &self.flags[flagged..] {
+                observer.on_flag(flag);
+            }
+            match &result {
+                Ok(postings) => observer.on_applied(&observed, postings),
+                Err(rejection) => observer.on_rejected(&observed, rejection),
+            }
+            match (&result, &observed.type_) {
+                (Ok(_), Dispute(_)) => observer.on_dispute_opened(&observed),
+                (Ok(_), Resolve(_) | Chargeback(_)) => observer.on_dispute_closed(&observed),
+                _ => {}
+            }
+            if locked {
+                observer.on_lock(client_id);
+            }
+            for alert in &self.alerts[alerted..] {
+                observer.on_alert(alert);
+            }
+        }
+        result
+    }
+
+    fn apply(&mut self, transaction: Transaction) -> Result<Postings, Rejection> {
+        if let Some(Log { start: None, .. }) = self.log {
+            let start = self.configured_like(self.clients.clone(), MemoryClient::default());
+            self.log = Some(Log {
+                start: Some(Box::new(start)),
+                transactions: Vec::new(),
+            });
+        }
+        let (transaction_id, client_id) = (transaction.transaction_id, transaction.client_id);
+        let kind = transaction.type_.kind();
+        let reason = transaction.type_.reason().map(str::to_owned);
+        if let Some(limit) = &mut self.rate_limit {
+            if !limit.take(client_id) {
+                return Err(Rejection::RateLimited { client_id });
+            }
+        }
+        if let (Some(limits), Some(amount)) = (&self.amount_limits, transaction.type_.amount()) {
+            match limits.get(kind) {
+                Some(limit) if !limit.allows(amount) => {
+                    return Err(Rejection::LimitExceeded {
+                        client_id,
+                        kind,
+                        amount: amount.clone(),
+                        limit: limit.clone(),
+                    })
+                }
+                _ => {}
+            }
+        }
+        if let Some(tiers) = &self.tiers {
+            tiers.check(&transaction)?;
+        }
+        self.kyc.check(&transaction, self.total(client_id))?;
+        let status = match transaction.type_ {
+            Status(status) => Some(status),
+            _ => None,
+        };
+
+        // Looked up before processing, which changes the state of the charged back transaction
+        let charged_back = match transaction.type_ {
+            Chargeback(_) if cfg!(feature = "testing") || self.conservation.is_some() => {
+                match self.tx_record.access(transaction_id) {
+                    Some((disputed, Disputed)) => Some(disputed.type_),
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+        // A chargeback locks the account once it reaches the client, even if there weren't enough held funds for it
+        // to complete
+        #[cfg(feature = "testing")]
+        if charged_back.is_some() {
+            self.charged_back.insert(client_id);
+        }
+
+        let (overdrafts, tiers) = (self.overdrafts.as_ref(), self.tiers.as_ref());
+        let hold = self.hold_disputed_withdrawals;
+        let new_client = || {
+            let mut client = Client::new(client_id);
+            if let Some(limit) = overdraft_limit(overdrafts, tiers, client_id) {
+                client = client.with_overdraft_limit(limit);
+            }
+            if hold {
+                client = client.with_disputed_withdrawals_held();
+            }
+            client
+        };
+
+        if !self.risk_rules.is_empty() {
+            let new;
+            let client = match self.clients.get(&client_id) {
+                Some(client) => client,
+                None => {
+                    new = new_client();
+                    &new
+                }
+            };
+            for rule in &self.risk_rules {
+                match rule.evaluate(&transaction, client) {
+                    Verdict::Accept => {}
+                    Verdict::Flag(reason) => self.flags.push(Flag {
+                        client_id,
+                        transaction_id,
+                        reason,
+                    }),
+                    Verdict::Reject(reason) => return Err(Rejection::Risk { client_id, reason }),
+                }
+            }
+        }
+        if let Some(log) = &mut self.log {
+            log.transactions.push(transaction.clone());
+        }
+
+        // Only added once the transaction has passed the checks, so a rejected one leaves nothing to replay
+        if overdrafts.is_some() || tiers.is_some() || hold {
+            self.clients.entry(client_id).or_insert_with(new_client);
+        }
+        if let Some(views) = &mut self.views {
+            views.change(client_id);
+        }
+
+        let (fees, tx_record) = (&self.fees, &mut self.tx_record);
+        let fee = fees.as_ref().and_then(|fees| {
+            let fee = fees.get(transaction.type_.kind())?;
+            let amount = match &transaction.type_ {
+                Disputable(Deposit(amount) | Withdrawal(amount))
+                | Fee(amount)
+                | Interest(amount)
+                | Authorize(amount)
+                | Refund(amount) => amount.clone(),
+                Adjustment(adjustment) => adjustment.amount.clone(),
+                Dispute(_) | Resolve(_) | Chargeback(_) | Capture | Void => {
+                    match tx_record.access(transaction_id) {
+                        Some((disputed, _)) => match disputed.type_ {
+                            Deposit(amount) | Withdrawal(amount) => amount,
+                        },
+                        None => Decimal::zero(),
+                    }
+                }
+                Close | Status(_) => Decimal::zero(),
+            };
+            fee.for_amount(&amount)
+        });
+        let chargeback_fee = match (&transaction.type_, &self.chargeback_fee) {
+            (Chargeback(_), Some(fee)) => match tx_record.access(transaction_id) {
+                Some((disputed, Disputed)) => match disputed.type_ {
+                    Deposit(amount) | Withdrawal(amount) => fee.for_amount(&amount),
+                },
+                _ => None,
+            },
+            _ => None,
+        };
+
+        let check = self
+            .conservation
+            .is_some()
+            .then(|| (transaction.type_.clone(), self.total(client_id)));
+        let balance = self.thresholds.is_some().then(|| self.balance(client_id));
+        let mut result = process_transaction(transaction, &mut self.clients, &mut self.tx_record);
+        if let (Ok(postings), Some(fee)) = (&mut result, fee) {
+            let client = self.clients.get_mut(&client_id).expect("just processed");
+            let charged = client
+                .charge_fee(fee)
+                .or_else(|available| client.charge_fee(available))
+                .expect("available funds can always be charged");
+            postings.extend(charged);
+        }
+        if let (Ok(postings), Some(fee)) = (&mut result, chargeback_fee) {
+            let client = self.clients.get_mut(&client_id).expect("just processed");
+            postings.extend(client.charge_fee_overdrawing(fee.clone()));
+            self.chargeback_fees.push(Transaction {
+                client_id,
+                transaction_id,
+                type_: Fee(fee),
+                metadata: HashMap::new(),
+            });
+        }
+        if let (Ok(_), Some(thresholds), Some(before)) = (&result, &self.thresholds, balance) {
+            let after = self.balance(client_id);
+            for (crossing, threshold) in thresholds.crossed(&before, &after) {
+                self.alerts.push(Alert {
+                    client_id,
+                    transaction_id,
+                    crossing,
+                    threshold: threshold.clone(),
+                    balance: after.clone(),
+                });
+            }
+        }
+        if let (Ok(_), Some(status)) = (&result, status) {
+            self.kyc.set(client_id, status);
+        }
+        if let (Ok(_), Some(tiers)) = (&result, &mut self.tiers) {
+            tiers.record(client_id, transaction_id, kind);
+        }
+        if let (Ok(_), Some(clock)) = (&result, &self.activity_clock) {
+            let client = self.clients.get_mut(&client_id).expect("just processed");
+            client.record_activity(clock());
+        }
+        if let (Ok(_), Some(policy)) = (&result, &mut self.lock_policy) {
+            let client = self.clients.get_mut(&client_id).expect("just processed");
+            if let Some(lock) = policy.record(kind, client) {
+                #[cfg(feature = "testing")]
+                self.charged_back.insert(client_id);
+                self.auto_locks.push(lock);
+            }
+        }
+        if result.is_ok() {
+            match kind {
+                TransactionKind::Dispute => {
+                    self.open_disputes
+                        .entry(client_id)
+                        .or_default()
+                        .insert(transaction_id, reason.clone());
+                }
+                TransactionKind::Resolve | TransactionKind::Chargeback => {
+                    if let Some(disputes) = self.open_disputes.get_mut(&client_id) {
+                        disputes.remove(&transaction_id);
+                        if disputes.is_empty() {
+                            self.open_disputes.remove(&client_id);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        if let (Ok(postings), Some(periods)) = (&result, &mut self.periods) {
+            periods.record(kind, transaction_id, reason, postings);
+        }
+        if let Some((type_, before)) = check {
+            let after = self.total(client_id);
+            let conservation = self.conservation.as_mut().expect("checked above");
+            if let Err(violation) =
+                conservation.record(&type_, charged_back, result.as_ref().ok(), before, after)
+            {
+                panic!(
+                    "tx {}: conservation violated: {}",
+                    transaction_id, violation
+                );
+            }
+        }
+        result
+    }
+
+    /// Closes the current business period as `id`, snapshotting every client's balances, and starts the next.
+    ///
+    /// # Panics
+    /// If the engine isn't closing periods.
+    pub fn close_period<S: Into<String>>(&mut self, id: S) -> Result<&Period, DuplicatePeriod> {
+        let balances = self.report().collect();
+        self.periods
+            .as_mut()
+            .expect("engine is not closing periods")
+            .close(id.into(), balances)
+    }
+
+    /// Takes the transactions flagged by risk rules since the last call
+    pub fn take_flags(&mut self) -> Vec<Flag> {
+        std::mem::take(&mut self.flags)
+    }
+
+    /// Takes the `fee` transactions charged for chargebacks since the last call
+    pub fn take_chargeback_fees(&mut self) -> Vec<Transaction> {
+        std::mem::take(&mut self.chargeback_fees)
+    }
+
+    /// Takes the alerts raised since the last call
+    pub fn take_alerts(&mut self) -> Vec<Alert> {
+        std::mem::take(&mut self.alerts)
+    }
+
+    /// Takes the clients locked by the lock policy since the last call
+    pub fn take_auto_locks(&mut self) -> Vec<AutoLock> {
+        std::mem::take(&mut self.auto_locks)
+    }
+
+    /// Every closed period, if the engine is closing periods
+    pub fn periods(&self) -> Option<&Periods> {
+        self.periods.as_ref()
+    }
+
+    /// The accounts report as of the close of period `id`, in client order
+    pub fn report_as_of(&self, id: &str) -> Option<&[ClientOutput]> {
+        Some(&self.periods.as_ref()?.get(id)?.balances)
+    }
+
+    /// Merges client `from` into client `into` for cleaning up duplicate accounts: `into` takes all of `from`'s funds
+    /// and disputable transactions, and `from` is closed. Merges aren't recorded, so a replay won't include them.
+    pub fn merge_clients(&mut self, into: u16, from: u16) -> Result<(), MergeError> {
+        if into == from {
+            return Err(MergeError::SameClient);
+        }
+        for id in [into, from] {
+            match self.clients.get(&id) {
+                None => return Err(MergeError::NotFound(id)),
+                Some(client) if client.is_closed() => return Err(MergeError::Closed(id)),
+                Some(_) => {}
+            }
+        }
+
+        let mut merged = self.clients.remove(&from).expect("checked above");
+        #[cfg(feature = "testing")]
+        if merged.is_locked() {
+            self.charged_back.insert(into);
+        }
+        let client = self.clients.get_mut(&into).expect("checked above");
+        client.merge(&mut merged);
+        self.clients.insert(from, merged);
+        self.tx_record.reassign(from, into);
+        if let Some(views) = &mut self.views {
+            views.change(into);
+            views.change(from);
+        }
+        if let Some(disputes) = self.open_disputes.remove(&from) {
+            self.open_disputes.entry(into).or_default().extend(disputes);
+        }
+        Ok(())
+    }
+
+    /// The client's open disputes in transaction id order, with the reason given for each. Disputes opened before the
+    /// engine was created aren't known.
+    pub fn open_disputes(&self, client_id: u16) -> impl Iterator<Item = (u32, Option<&str>)> {
+        self.open_disputes
+            .get(&client_id)
+            .into_iter()
+            .flatten()
+            .map(|(&transaction_id, reason)| (transaction_id, reason.as_deref()))
+    }
+
+    /// A view of every client and their open disputes as they are now, which isn't affected by anything applied after
+    /// it, for reading on another thread. Only the clients changed since the last view are copied.
+    pub fn view(&mut self) -> View {
+        let clients = &self.clients;
+        let views = self.views.get_or_insert_with(|| Views::new(clients));
+        views.refresh(clients, &self.open_disputes)
+    }
+
+    /// The reported total of a client, or zero if there is no such client
+    fn total(&self, client_id: u16) -> Decimal {
+        self.clients
+            .get(&client_id)
+            .map_or_else(Decimal::zero, Client::total)
+    }
+
+    fn balance(&self, client_id: u16) -> Balance {
+        self.clients
+            .get(&client_id)
+            .map_or_else(Balance::default, Balance::of)
+    }
+
+    /// Checks every client's invariants, and that only clients which have had a chargeback applied, or were locked by
+    /// the lock policy, are locked
+    #[cfg(feature = "testing")]
+    pub fn check_invariants(&self) -> Result<(), InvariantViolation> {
+        for client in self.clients.values() {
+            client.check_invariants()?;
+            if client.is_locked() && !self.charged_back.contains(&client.id()) {
+                return Err(InvariantViolation {
+                    client: client.id(),
+                    description: "locked without a chargeback".to_owned(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    pub fn client(&self, client_id: u16) -> Option<&Client> {
+        self.clients.get(&client_id)
+    }
+
+    pub fn clients(&self) -> impl Iterator<Item = &Client> {
+        self.clients.values()
+    }
+
+    /// The client's KYC status
+    pub fn status(&self, client_id: u16) -> Status {
+        self.kyc.status(client_id)
+    }
+
+    pub fn into_clients(self) -> HashMap<u16, Client> {
+        self.clients
+    }
+
+    /// What the transaction store holds, and roughly what it takes to hold it
+    pub fn store_stats(&self) -> StoreStats {
+        self.tx_record.stats()
+    }
+
+    /// The accounts report for the current state of every client
+    pub fn report(&self) -> impl Iterator<Item = ClientOutput> + '_ {
+        self.clients().cloned().map(ClientOutput::from)
+    }
+
+    /// Writes every client, stored transaction and open dispute as a file of records, which `restore` can start
+    /// another engine from, whatever transaction store either uses. Configuration, like fees and risk rules, isn't
+    /// included.
+    pub fn snapshot<W: Write>(&self, w: W) -> io::Result<()> {
+        let mut writer = record::Writer::new(w)?;
+        for client in self.clients.values() {
+            writer.write(record::Kind::Client, &record::encode_client(client))?;
+        }
+        for (transaction, state) in self.tx_record.entries() {
+            writer.write(
+                record::Kind::Transaction,
+                &record::encode_transaction(&transaction, state),
+            )?;
+        }
+        for (&client_id, disputes) in &self.open_disputes {
+            for (&transaction_id, reason) in disputes {
+                writer.write(
+                    record::Kind::Dispute,
+                    &record::encode_dispute(client_id, transaction_id, reason.as_deref()),
+                )?;
+            }
+        }
+        writer.into_inner().flush()
+    }
+
+    /// Starts from a snapshot, storing its transactions in `tx_record`, which should be empty
+    pub fn restore<R: Read>(r: R, mut tx_record: T) -> io::Result<Self> {
+        let invalid = |e| io::Error::new(io::ErrorKind::InvalidData, e);
+        let mut clients = HashMap::new();
+        let mut open_disputes: HashMap<u16, BTreeMap<_, _>> = HashMap::new();
+        for entry in record::Reader::new(r)? {
+            let (kind, bytes) = entry?;
+            match kind {
+                record::Kind::Client => {
+                    let client = record::decode_client(&bytes).map_err(invalid)?;
+                    clients.insert(client.id(), client);
+                }
+                record::Kind::Transaction => {
+                    let (transaction, state) =
+                        record::decode_transaction(&bytes).map_err(invalid)?;
+                    tx_record.restore(transaction, state);
+                }
+                record::Kind::Dispute => {
+                    let (client_id, transaction_id, reason) =
+                        record::decode_dispute(&bytes).map_err(invalid)?;
+                    open_disputes
+                        .entry(client_id)
+                        .or_default()
+                        .insert(transaction_id, reason);
+                }
+            }
+        }
+        let mut engine = Engine::with_clients(clients, tx_record);
+        engine.open_disputes = open_disputes;
+        Ok(engine)
+    }
+
+    /// Re-runs the recorded log from the state and configuration it started with, replacing transactions as specified
+    /// in `overrides`, and returns how the resulting accounts differ from the current ones.
+    ///
+    /// # Panics
+    /// If the engine isn't recording.
+    pub fn replay_with(&self, overrides: &Overrides) -> Vec<Mismatch> {
+        let log = self.log.as_ref().expect("engine is not recording");
+        let start = match &log.start {
+            Some(start) => start,
+            None => return Vec::new(),
+        };
+
+        let mut replay = start.configured_like(start.clients.clone(), MemoryClient::default());
+        for transaction in &log.transactions {
+            let type_ =
+                match overrides.get(&(transaction.transaction_id, transaction.type_.clone())) {
+                    Some(Some(type_)) => type_.clone(),
+                    Some(None) => continue,
+                    None => transaction.type_.clone(),
+                };
+            let _ = replay.process(Transaction {
+                type_,
+                ..transaction.clone()
+            });
+        }
+        report::diff(self.report(), replay.report())
+    }
+}
+
+/// How many transactions can wait for each shard before reading blocks
+const SHARD_QUEUE: usize = 1024;
+
+/// What `Engine::process_parallel` made of a transaction file
+pub struct ParallelRun {
+    pub engine: Engine<MemoryClient>,
+    /// Every row which couldn't be read, in file order
+    pub malformed: Vec<csv::Error>,
+    /// Every transaction which wasn't applied, by id. Each client's are in file order, but not between clients.
+    pub rejections: Vec<(u32, Rejection)>,
+}
+
+impl Engine<MemoryClient> {
+    /// Processes a transaction file on `num_shards` worker threads, partitioned by client id, and merges the shards
+    /// into one engine once the file is done. Each shard has its own clients and transaction store.
+    ///
+    /// Each client's transactions are applied in file order, so the result is the same as processing the file in one
+    /// engine, except that a dispute step naming another client's transaction is only found if both clients are on
+    /// the same shard.
+    ///
+    /// # Panics
+    /// If `num_shards` is zero.
+    pub fn process_parallel<R: Read>(rdr: R, num_shards: usize) -> ParallelRun {
+        assert!(num_shards > 0, "can't process on zero shards");
+        let mut malformed = Vec::new();
+        let shards = thread::scope(|scope| {
+            let (senders, workers): (Vec<_>, Vec<_>) = (0..num_shards)
+                .map(|_| {
+                    let (sender, receiver) = mpsc::sync_channel::<Transaction>(SHARD_QUEUE);
+                    // Engines aren't `Send`, so each shard only sends back what it's made
+                    let worker = scope.spawn(move || {
+                        let mut engine = Engine::new(MemoryClient::default());
+                        let rejections = receiver
+                            .into_iter()
+                            .filter_map(|transaction| {
+                                let transaction_id = transaction.transaction_id;
+                                engine
+                                    .process(transaction)
+                                    .err()
+                                    .map(|e| (transaction_id, e))
+                            })
+                            .collect::<Vec<_>>();
+                        let Engine {
+                            clients,
+                            tx_record,
+                            open_disputes,
+                            ..
+                        } = engine;
+                        (clients, tx_record, open_disputes, rejections)
+                    });
+                    (sender, worker)
+                })
+                .unzip();
+            for transaction in read_from_csv_reader(rdr) {
+                match transaction {
+                    Ok(transaction) => senders[transaction.client_id as usize % num_shards]
+                        .send(transaction)
+                        .expect("shard worker panicked"),
+                    Err(e) => malformed.push(e),
+                }
+            }
+            drop(senders);
+            workers
+                .into_iter()
+                .map(|worker| worker.join().expect("shard worker panicked"))
+                .collect::<Vec<_>>()
+        });
+
+        let mut clients = HashMap::new();
+        let mut tx_record = MemoryClient::default();
+        let mut open_disputes = HashMap::new();
+        let mut rejections = Vec::new();
+        for shard in shards {
+            clients.extend(shard.0);
+            tx_record.absorb(shard.1);
+            open_disputes.extend(shard.2);
+            rejections.extend(shard.3);
+        }
+        let mut engine = Engine::with_clients(clients, tx_record);
+        engine.open_disputes = open_disputes;
+        ParallelRun {
+            engine,
+            malformed,
+            rejections,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::generate::{Generator, Options};
+    use crate::posting::Account;
+    use crate::transaction::read_from_csv_reader;
+    use crate::transaction_set::{CachedClient, LruCache, Transition};
+    use csv::{ReaderBuilder, Trim};
+    use rand::prelude::*;
+
+    const CACHE_SIZE: usize = 10;
+
+    #[test]
+    fn basic_process_test() {
+        let mut clients = HashMap::new();
+        let mut tx_record =
+            CachedClient::new(MemoryClient::default(), LruCache::with_capacity(CACHE_SIZE));
+
+        let data = "\
+type,       client,  tx, amount
+deposit,         1,   1,    1.0
+chargeback,         10,   21,
+";
+
+        let mut rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+
+        for transaction in rdr.deserialize() {
+            let transaction = match transaction {
+                Ok(transaction) => transaction,
+                Err(e) => {
+                    eprintln!("failed to parse transaction: {}", e);
+                    continue;
+                }
+            };
+            let _ = process_transaction(transaction, &mut clients, &mut tx_record);
+        }
+    }
+
+    #[test]
+    fn random_process_test() {
+        let mut clients = HashMap::new();
+        let mut tx_record =
+            CachedClient::new(MemoryClient::default(), LruCache::with_capacity(CACHE_SIZE));
+
+        let generator = Generator::new(Options {
+            seed: thread_rng().gen(),
+            ..Options::default()
+        });
+        for transaction in generator.take(1000 * 1000) {
+            let _ = process_transaction(transaction, &mut clients, &mut tx_record);
+        }
+    }
+
+    #[test]
+    fn process_in_parallel() {
+        let transactions = Generator::new(Options {
+            seed: thread_rng().gen(),
+            ..Options::default()
+        })
+        .take(10_000)
+        .collect::<Vec<_>>();
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        let mut engine = Engine::new(MemoryClient::default());
+        for transaction in transactions {
+            writer.serialize(&transaction).unwrap();
+            let _ = engine.process(transaction);
+        }
+        let file = writer.into_inner().unwrap();
+
+        let run = Engine::process_parallel(file.as_slice(), 4);
+        assert!(run.malformed.is_empty());
+        assert_eq!(report::diff(engine.report(), run.engine.report()), vec![]);
+    }
+
+    #[test]
+    fn replay_with_overrides() {
+        let data = "\
+type,       client,  tx, amount
+deposit,         1,   1,    5.0
+deposit,         1,   2,    2.0
+dispute,         1,   1,
+chargeback,      1,   1,
+deposit,         2,   3,    1.0
+";
+        let mut engine = Engine::new(MemoryClient::default()).recording();
+        for transaction in read_from_csv_reader(data.as_bytes()) {
+            let _ = engine.process(transaction.unwrap());
+        }
+
+        assert_eq!(engine.replay_with(&Overrides::new()), vec![]);
+
+        let mut overrides = Overrides::new();
+        overrides.insert((1, Chargeback(None)), Some(Resolve(None)));
+        overrides.insert((3, Disputable(Deposit(Decimal::new(1, 0)))), None);
+        assert_eq!(
+            engine.replay_with(&overrides),
+            vec![
+                Mismatch::Differs {
+                    expected: ClientOutput {
+                        client: 1,
+                        available: Decimal::new(2, 0),
+                        held: Decimal::zero(),
+                        total: Decimal::new(2, 0),
+                        locked: true,
+                        overdraft: Decimal::zero(),
+                        closed: false,
+                        pending: Decimal::zero(),
+                    },
+                    actual: ClientOutput {
+                        client: 1,
+                        available: Decimal::new(7, 0),
+                        held: Decimal::zero(),
+                        total: Decimal::new(7, 0),
+                        locked: false,
+                        overdraft: Decimal::zero(),
+                        closed: false,
+                        pending: Decimal::zero(),
+                    },
+                },
+                Mismatch::Missing(ClientOutput {
+                    client: 2,
+                    available: Decimal::new(1, 0),
+                    held: Decimal::zero(),
+                    total: Decimal::new(1, 0),
+                    locked: false,
+                    overdraft: Decimal::zero(),
+                    closed: false,
+                    pending: Decimal::zero(),
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn replay_configured() {
+        let large = Decimal::new(100, 0);
+        let fee = Fee {
+            flat: Decimal::new(1, 0),
+            percent: Decimal::zero(),
+        };
+        let mut engine = Engine::new(MemoryClient::default())
+            .charging_fees(FeeSchedule::new().with(TransactionKind::Withdrawal, fee.clone()))
+            .charging_chargeback_fees(fee)
+            .allowing_overdrafts(OverdraftLimits::new(Decimal::new(10, 0)))
+            .limiting_rate(RateLimit::new(0.0, 3).with_clock(|| 0))
+            .with_risk_rule(move |t: &Transaction, _: &Client| match &t.type_ {
+                Disputable(Withdrawal(amount)) if *amount > large => {
+                    Verdict::Reject("large withdrawal".to_owned())
+                }
+                _ => Verdict::Accept,
+            })
+            .recording();
+        process_all(
+            &mut engine,
+            "\
+type,       client,  tx, amount
+deposit,         1,   1,    5.0
+withdrawal,      1,   2,   12.0
+withdrawal,      1,   3,    1.0
+deposit,         1,   4,    3.0
+deposit,         2,   5,   20.0
+dispute,         2,   5,
+chargeback,      2,   5,
+withdrawal,      3,   6,  150.0
+",
+        );
+
+        assert_eq!(engine.replay_with(&Overrides::new()), vec![]);
+    }
+
+    fn process_all<T: TransactionSetClient>(engine: &mut Engine<T>, data: &str) {
+        for transaction in read_from_csv_reader(data.as_bytes()) {
+            let _ = engine.process(transaction.unwrap());
+        }
+    }
+
+    #[test]
+    fn conservation_holds() {
+        let mut engine = Engine::new(MemoryClient::default()).checking_conservation();
+        process_all(
+            &mut engine,
+            "\
+type,       client,  tx, amount
+deposit,         1,   1,    5.0
+deposit,         1,   4,    3.0
+withdrawal,      1,   2,    2.0
+withdrawal,      1,   3,    9.0
+dispute,         1,   4,
+resolve,         1,   4,
+dispute,         1,   2,
+chargeback,      1,   2,
+dispute,         1,   1,
+chargeback,      1,   1,
+",
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "tx 1: conservation violated")]
+    fn conservation_catches_overdrawn_dispute() {
+        let mut engine = Engine::new(MemoryClient::default()).checking_conservation();
+        // Disputing more than is available creates funds out of nowhere
+        process_all(
+            &mut engine,
+            "\
+type,       client,  tx, amount
+deposit,         1,   1,    5.0
+withdrawal,      1,   2,    2.0
+dispute,         1,   1,
+",
+        );
+    }
+
+    #[test]
+    fn postings_per_transaction() {
+        use crate::posting::Account::*;
+
+        let mut engine = Engine::new(MemoryClient::default());
+        let postings = read_from_csv_reader(
+            "\
+type,       client,  tx, amount
+deposit,         1,   1,    5.0
+withdrawal,      1,   2,    2.0
+dispute,         1,   1,
+chargeback,      1,   1,
+"
+            .as_bytes(),
+        )
+        .map(|t| {
+            engine
+                .process_with_postings(t.unwrap())
+                .unwrap()
+                .iter()
+                .map(|p| (p.from, p.to, p.amount.to_string()))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+        let posting =
+            |from, to, amount: &str| (from, to, amount.parse::<Decimal>().unwrap().to_string());
+        assert_eq!(
+            postings,
+            vec![
+                vec![posting(External, Available, "5")],
+                vec![posting(Available, External, "2")],
+                vec![
+                    posting(Available, Held, "3"),
+                    posting(External, HeldReserve, "2")
+                ],
+                vec![
+                    posting(HeldReserve, ChargebackWriteoff, "2"),
+                    posting(Held, ChargebackWriteoff, "3"),
+                ],
+            ]
+        );
+    }
+
+    #[test]
+    fn fee_schedule() {
+        use crate::fees::Fee;
+        use crate::transaction::TransactionKind;
+
+        let schedule = FeeSchedule::new()
+            .with(
+                TransactionKind::Withdrawal,
+                Fee {
+                    flat: Decimal::new(1, 0),
+                    percent: Decimal::new(10, 0),
+                },
+            )
+            .with(
+                TransactionKind::Chargeback,
+                Fee {
+                    flat: Decimal::new(15, 0),
+                    percent: Decimal::zero(),
+                },
+            );
+        let mut engine = Engine::new(MemoryClient::default())
+            .checking_conservation()
+            .charging_fees(schedule);
+        process_all(
+            &mut engine,
+            "\
+type,       client,  tx, amount
+deposit,         1,   1,   20.0
+withdrawal,      1,   2,   10.0
+fee,             1,   3,    2.0
+fee,             1,   4,   99.0
+deposit,         2,   5,   10.0
+deposit,         2,   6,    5.0
+dispute,         2,   6,
+chargeback,      2,   6,
+",
+        );
+        // 20 - 10 - (1 + 10% of 10) - 2
+        assert_eq!(engine.total(1), Decimal::new(6, 0));
+        // The chargeback fee takes everything that's left
+        assert_eq!(engine.total(2), Decimal::zero());
+    }
+
+    #[test]
+    fn chargeback_fees() {
+        use crate::fees::Fee;
+
+        let mut engine = Engine::new(MemoryClient::default())
+            .checking_conservation()
+            .charging_chargeback_fees(Fee {
+                flat: Decimal::new(100, 0),
+                percent: Decimal::new(1, 0),
+            });
+        process_all(
+            &mut engine,
+            "\
+type,       client,  tx, amount
+deposit,         1,   1,  100.0
+deposit,         1,   2,  500.0
+dispute,         1,   2,
+chargeback,      1,   2,
+",
+        );
+        // 100 - (100 + 1% of 500), leaving the client owing 5
+        let client = engine.client(1).unwrap();
+        assert_eq!(client.available(), Decimal::zero());
+        assert_eq!(client.overdraft(), Decimal::new(5, 0));
+        assert_eq!(
+            engine.take_chargeback_fees(),
+            [Transaction {
+                client_id: 1,
+                transaction_id: 2,
+                type_: Type::Fee(Decimal::new(105, 0)),
+                metadata: HashMap::new(),
+            }]
+        );
+        assert_eq!(engine.take_chargeback_fees(), []);
+    }
+
+    #[test]
+    fn overdraft_limits() {
+        let mut engine = Engine::new(MemoryClient::default())
+            .checking_conservation()
+            .allowing_overdrafts(
+                OverdraftLimits::new(Decimal::new(10, 0)).with(2, Decimal::zero()),
+            );
+        let mut process = |data: &str| {
+            read_from_csv_reader(format!("type,client,tx,amount\n{}", data).as_bytes())
+                .map(|t| engine.process(t.unwrap()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            process("deposit,1,1,5.0\nwithdrawal,1,2,12.0\nwithdrawal,1,3,4.0\ndeposit,2,4,1.0\nwithdrawal,2,5,2.0"),
+            vec![
+                Ok(()),
+                Ok(()),
+                Err(Rejection::InsufficientFunds {
+                    client_id: 1,
+                    requested: Decimal::new(4, 0),
+                    available: Decimal::new(3, 0),
+                }),
+                Ok(()),
+                Err(Rejection::InsufficientFunds {
+                    client_id: 2,
+                    requested: Decimal::new(2, 0),
+                    available: Decimal::new(1, 0),
+                }),
+            ]
+        );
+        let overdraft = |engine: &Engine<_>| {
+            let c = ClientOutput::from(engine.client(1).unwrap().clone());
+            (c.available, c.overdraft)
+        };
+        assert_eq!(overdraft(&engine), (Decimal::zero(), Decimal::new(7, 0)));
+
+        // Deposits pay back the overdraft first
+        let _ = engine.process(Transaction {
+            client_id: 1,
+            transaction_id: 6,
+            type_: Disputable(Deposit(Decimal::new(10, 0))),
+            metadata: HashMap::new(),
+        });
+        assert_eq!(overdraft(&engine), (Decimal::new(3, 0), Decimal::zero()));
+    }
+
+    #[test]
+    fn held_withdrawal_disputes() {
+        let mut existing = Client::new(1);
+        existing.deposit(Decimal::new(10, 0));
+        let mut engine =
+            Engine::with_clients(HashMap::from([(1, existing)]), MemoryClient::default())
+                .holding_disputed_withdrawals()
+                .checking_conservation();
+        process_all(
+            &mut engine,
+            "\
+type,       client,  tx, amount
+withdrawal,      1,   1,    4.0
+dispute,         1,   1,
+deposit,         2,   2,    5.0
+withdrawal,      2,   3,    5.0
+dispute,         2,   3,
+",
+        );
+        let balances = |engine: &Engine<_>, id| {
+            let c = engine.client(id).unwrap();
+            (c.available(), c.held(), c.total())
+        };
+        assert_eq!(
+            balances(&engine, 1),
+            (Decimal::new(6, 0), Decimal::new(4, 0), Decimal::new(10, 0))
+        );
+        assert_eq!(
+            balances(&engine, 2),
+            (Decimal::zero(), Decimal::new(5, 0), Decimal::new(5, 0))
+        );
+
+        process_all(
+            &mut engine,
+            "\
+type,       client,  tx, amount
+resolve,         1,   1,
+chargeback,      2,   3,
+",
+        );
+        assert_eq!(
+            balances(&engine, 1),
+            (Decimal::new(6, 0), Decimal::zero(), Decimal::new(6, 0))
+        );
+        assert_eq!(
+            balances(&engine, 2),
+            (Decimal::new(5, 0), Decimal::zero(), Decimal::new(5, 0))
+        );
+    }
+
+    #[test]
+    fn close_account() {
+        let mut engine = Engine::new(MemoryClient::default()).checking_conservation();
+        let results = read_from_csv_reader(
+            "\
+type,       client,  tx, amount
+deposit,         1,   1,    5.0
+dispute,         1,   1,
+close,           1,   2,
+resolve,         1,   1,
+close,           1,   3,
+deposit,         1,   4,    1.0
+resolve,         1,   1,
+"
+            .as_bytes(),
+        )
+        .map(|t| engine.process(t.unwrap()))
+        .collect::<Vec<_>>();
+        let closed = Err(Rejection::AccountClosed { client_id: 1 });
+        assert_eq!(
+            results,
+            vec![
+                Ok(()),
+                Ok(()),
+                Err(Rejection::Unsettled {
+                    client_id: 1,
+                    held: Decimal::new(5, 0),
+                }),
+                Ok(()),
+                Ok(()),
+                closed.clone(),
+                closed,
+            ]
+        );
+        let report = engine.report().collect::<Vec<_>>();
+        assert!(report[0].closed);
+        assert_eq!(report[0].total, Decimal::new(5, 0));
+    }
+
+    #[test]
+    fn merge_clients() {
+        let mut engine = Engine::new(MemoryClient::default()).checking_conservation();
+        process_all(
+            &mut engine,
+            "\
+type,       client,  tx, amount
+deposit,         1,   1,    5.0
+deposit,         2,   2,    3.0
+dispute,         2,   2,
+",
+        );
+        assert_eq!(engine.merge_clients(1, 1), Err(MergeError::SameClient));
+        assert_eq!(engine.merge_clients(1, 3), Err(MergeError::NotFound(3)));
+        assert_eq!(engine.merge_clients(1, 2), Ok(()));
+        assert_eq!(engine.merge_clients(1, 2), Err(MergeError::Closed(2)));
+
+        // The dispute carries over, and can be resolved on the merged client
+        assert_eq!(
+            engine.process(Transaction {
+                client_id: 1,
+                transaction_id: 2,
+                type_: Resolve(None),
+                metadata: HashMap::new(),
+            }),
+            Ok(())
+        );
+        assert_eq!(engine.tx_record.access(2).unwrap().0.client_id, 1);
+        let client = |id| ClientOutput::from(engine.client(id).unwrap().clone());
+        assert_eq!(client(1).available, Decimal::new(8, 0));
+        assert_eq!(client(2).total, Decimal::zero());
+        assert!(client(2).closed);
+    }
+
+    #[test]
+    fn authorize_and_capture() {
+        let mut engine = Engine::new(MemoryClient::default()).checking_conservation();
+        let results = read_from_csv_reader(
+            "\
+type,       client,  tx, amount
+authorize,       1,   1,    5.0
+authorize,       1,   2,    3.0
+dispute,         1,   1,
+withdrawal,      1,   3,    1.0
+capture,         1,   1,
+void,            1,   2,
+capture,         1,   2,
+dispute,         1,   1,
+"
+            .as_bytes(),
+        )
+        .map(|t| engine.process(t.unwrap()))
+        .collect::<Vec<_>>();
+        assert_eq!(
+            results,
+            vec![
+                Ok(()),
+                Ok(()),
+                Err(Rejection::WrongState(Step::Dispute, Authorized)),
+                Err(Rejection::InsufficientFunds {
+                    client_id: 1,
+                    requested: Decimal::new(1, 0),
+                    available: Decimal::zero(),
+                }),
+                Ok(()),
+                Ok(()),
+                Err(Rejection::WrongState(Step::Capture, State::Voided)),
+                // Captured authorizations are disputed like deposits
+                Ok(()),
+            ]
+        );
+        let client = ClientOutput::from(engine.client(1).unwrap().clone());
+        assert_eq!(client.held, Decimal::new(5, 0));
+        assert_eq!(client.pending, Decimal::zero());
+    }
+
+    #[test]
+    fn settling_undisputed() {
+        let mut engine = Engine::new(MemoryClient::default()).checking_conservation();
+        let results = read_from_csv_reader(
+            "\
+type,       client,  tx, amount
+deposit,         1,   1,    5.0
+deposit,         1,   2,    5.0
+dispute,         1,   1,
+resolve,         1,   2,
+chargeback,      1,   2,
+dispute,         1,   1,
+resolve,         1,   1,
+resolve,         1,   1,
+"
+            .as_bytes(),
+        )
+        .map(|t| engine.process(t.unwrap()))
+        .collect::<Vec<_>>();
+        assert_eq!(
+            results,
+            vec![
+                Ok(()),
+                Ok(()),
+                Ok(()),
+                // Rather than releasing what's held for the other deposit
+                Err(Rejection::WrongState(Step::Resolve, Committed)),
+                Err(Rejection::WrongState(Step::Chargeback, Committed)),
+                // Rather than holding it twice
+                Err(Rejection::WrongState(Step::Dispute, Disputed)),
+                Ok(()),
+                Err(Rejection::WrongState(Step::Resolve, Committed)),
+            ]
+        );
+        let client = engine.client(1).unwrap();
+        assert_eq!(
+            (client.available(), client.held(), client.is_locked()),
+            (Decimal::new(10, 0), Decimal::zero(), false)
+        );
+    }
+
+    #[test]
+    fn failed_settlements() {
+        let stored = |transaction_id, amount| DisputableTransaction {
+            transaction_id,
+            client_id: 1,
+            type_: Deposit(Decimal::new(amount, 0)),
+            metadata: HashMap::new(),
+            history: Vec::new(),
+        };
+        // Nothing is actually held or pending for them, as if the client had been seeded from a report
+        let mut tx_record = MemoryClient::default();
+        tx_record.restore(stored(1, 5), Disputed);
+        tx_record.restore(stored(2, 3), Authorized);
+        let mut engine = Engine::with_clients(HashMap::from([(1, Client::new(1))]), tx_record);
+        let results = read_from_csv_reader(
+            "\
+type,       client,  tx, amount
+resolve,         1,   1,
+chargeback,      1,   1,
+resolve,         1,   1,
+capture,         1,   2,
+void,            1,   2,
+"
+            .as_bytes(),
+        )
+        .map(|t| engine.process(t.unwrap()))
+        .collect::<Vec<_>>();
+        let insufficient = |step, requested| {
+            Err(Rejection::InsufficientHeld {
+                step,
+                requested: Decimal::new(requested, 0),
+                available: Decimal::zero(),
+            })
+        };
+        // Each failure leaves the transaction where it was, so the same step fails the same way again
+        assert_eq!(
+            results,
+            vec![
+                insufficient(Step::Resolve, 5),
+                insufficient(Step::Chargeback, 5),
+                insufficient(Step::Resolve, 5),
+                insufficient(Step::Capture, 3),
+                insufficient(Step::Void, 3),
+            ]
+        );
+        assert_eq!(
+            engine.tx_record.access(1).unwrap(),
+            (stored(1, 5), Disputed)
+        );
+        assert_eq!(
+            engine.tx_record.access(2).unwrap(),
+            (stored(2, 3), Authorized)
+        );
+        let client = engine.client(1).unwrap();
+        assert_eq!(client.total(), Decimal::zero());
+        // The chargeback still reached the client
+        assert!(client.is_locked());
+        assert_eq!(client.chargebacks(), 1);
+    }
+
+    /// Refuses every transition to one state, like a store which has failed part way through
+    struct Refusing {
+        client: MemoryClient,
+        refuse: Option<State>,
+    }
+
+    impl TransactionSetClient for Refusing {
+        fn store(&mut self, t: DisputableTransaction) {
+            self.client.store(t)
+        }
+        fn access(&mut self, id: u32) -> Option<(DisputableTransaction, State)> {
+            self.client.access(id)
+        }
+        fn update(
+            &mut self,
+            id: u32,
+            state: State,
+        ) -> Result<DisputableTransaction, UpdateFailure> {
+            match self.refuse {
+                Some(refused) if refused == state => {
+                    Err(WrongState(self.client.access(id).ok_or(NotFound)?.1))
+                }
+                _ => self.client.update(id, state),
+            }
+        }
+        fn reassign(&mut self, from: u16, to: u16) {
+            self.client.reassign(from, to)
+        }
+        fn refund(
+            &mut self,
+            id: u32,
+            amount: Decimal,
+        ) -> Result<DisputableTransaction, UpdateFailure> {
+            self.client.refund(id, amount)
+        }
+        fn record(&mut self, id: u32, kind: TransactionKind, reason: Option<String>) {
+            self.client.record(id, kind, reason)
+        }
+        fn restore(&mut self, t: DisputableTransaction, state: State) {
+            self.client.restore(t, state)
+        }
+        fn entries(&self) -> Box<dyn Iterator<Item = (DisputableTransaction, State)> + '_> {
+            self.client.entries()
+        }
+    }
+
+    #[test]
+    fn rolled_back_when_the_store_refuses() {
+        let mut engine = Engine::new(Refusing {
+            client: MemoryClient::default(),
+            refuse: Some(ChargedBackFinal),
+        })
+        .checking_conservation();
+        process_all(
+            &mut engine,
+            "\
+type,       client,  tx, amount
+deposit,         1,   1,    5.0
+dispute,         1,   1,
+",
+        );
+        let chargeback = Transaction {
+            client_id: 1,
+            transaction_id: 1,
+            type_: Chargeback(None),
+            metadata: HashMap::new(),
+        };
+        // Refused after the transaction was charged back, but before it was final
+        assert_eq!(
+            engine.process(chargeback.clone()),
+            Err(Rejection::WrongState(Step::Chargeback, ChargedBack))
+        );
+        assert_eq!(engine.tx_record.access(1).unwrap().1, Disputed);
+        let client = engine.client(1).unwrap();
+        assert_eq!(
+            (client.held(), client.total(), client.is_locked()),
+            (Decimal::new(5, 0), Decimal::new(5, 0), false)
+        );
+
+        engine.tx_record.refuse = None;
+        assert_eq!(engine.process(chargeback), Ok(()));
+        assert_eq!(engine.tx_record.access(1).unwrap().1, ChargedBackFinal);
+        assert_eq!(engine.total(1), Decimal::zero());
+    }
+
+    /// Reports every transition as from `Resolved`, like a store another engine moved the transaction in between this
+    /// one reading and moving it
+    struct Moved(MemoryClient);
+
+    impl TransactionSetClient for Moved {
+        fn store(&mut self, t: DisputableTransaction) {
+            self.0.store(t)
+        }
+        fn access(&mut self, id: u32) -> Option<(DisputableTransaction, State)> {
+            self.0.access(id)
+        }
+        fn update(
+            &mut self,
+            id: u32,
+            state: State,
+        ) -> Result<DisputableTransaction, UpdateFailure> {
+            self.0.update(id, state)
+        }
+        fn transition(&mut self, id: u32, state: State) -> Result<Transition, UpdateFailure> {
+            let transition = self.0.transition(id, state)?;
+            Ok(Transition {
+                from: Resolved,
+                ..transition
+            })
+        }
+        fn reassign(&mut self, from: u16, to: u16) {
+            self.0.reassign(from, to)
+        }
+        fn refund(
+            &mut self,
+            id: u32,
+            amount: Decimal,
+        ) -> Result<DisputableTransaction, UpdateFailure> {
+            self.0.refund(id, amount)
+        }
+        fn record(&mut self, id: u32, kind: TransactionKind, reason: Option<String>) {
+            self.0.record(id, kind, reason)
+        }
+        fn restore(&mut self, t: DisputableTransaction, state: State) {
+            self.0.restore(t, state)
+        }
+        fn entries(&self) -> Box<dyn Iterator<Item = (DisputableTransaction, State)> + '_> {
+            self.0.entries()
+        }
+    }
+
+    #[test]
+    fn rolled_back_when_moved_from_elsewhere() {
+        let mut engine = Engine::new(Moved(MemoryClient::default())).checking_conservation();
+        process_all(
+            &mut engine,
+            "\
+type,       client,  tx, amount
+deposit,         1,   1,   10.0
+authorize,       1,   2,    4.0
+",
+        );
+        let capture = Transaction {
+            client_id: 1,
+            transaction_id: 2,
+            type_: Capture,
+            metadata: HashMap::new(),
+        };
+        // Committed, but from the resolved deposit it had become rather than the authorization this one captured
+        assert_eq!(
+            engine.process(capture),
+            Err(Rejection::WrongState(Step::Capture, Resolved))
+        );
+        assert_eq!(engine.tx_record.access(2).unwrap().1, Authorized);
+        let client = ClientOutput::from(engine.client(1).unwrap().clone());
+        assert_eq!(client.pending, Decimal::new(4, 0));
+    }
+
+    #[test]
+    fn retries_deduplicated() {
+        let mut engine = Engine::new(MemoryClient::default()).deduplicating(10);
+        let results = read_from_csv_reader(
+            "\
+type,       client,  tx, amount, idempotency_key
+withdrawal,      1,   1,    2.0, a
+deposit,         1,   2,    5.0, b
+deposit,         1,   2,    5.0, b
+withdrawal,      1,   1,    2.0, a
+deposit,         1,   3,    1.0,
+deposit,         1,   3,    1.0,
+"
+            .as_bytes(),
+        )
+        .map(|t| engine.process(t.unwrap()))
+        .collect::<Vec<_>>();
+        let rejected = Err(Rejection::InsufficientFunds {
+            client_id: 1,
+            requested: Decimal::new(2, 0),
+            available: Decimal::zero(),
+        });
+        // A retry gets the original outcome, even when applying it now would go differently
+        assert_eq!(
+            results,
+            vec![rejected.clone(), Ok(()), Ok(()), rejected, Ok(()), Ok(())]
+        );
+        // Deposits without a key are applied every time
+        assert_eq!(engine.total(1), Decimal::new(7, 0));
+    }
+
+    #[test]
+    fn amount_limits() {
+        let mut engine =
+            Engine::new(MemoryClient::default()).limiting_amounts(AmountLimits::new().with(
+                TransactionKind::Deposit,
+                Limit {
+                    min: Some(Decimal::new(0, 100)),
+                    max: Some(Decimal::new(1000, 0)),
+                },
+            ));
+        let results = read_from_csv_reader(
+            "\
+type,       client,  tx, amount
+deposit,         1,   1, 1000000000.0
+deposit,         1,   2, 0.0001
+deposit,         1,   3, 1000.0
+withdrawal,      1,   4, 2000.0
+dispute,         1,   3,
+"
+            .as_bytes(),
+        )
+        .map(|t| engine.process(t.unwrap()))
+        .collect::<Vec<_>>();
+        let exceeded = |amount| {
+            Err(Rejection::LimitExceeded {
+                client_id: 1,
+                kind: TransactionKind::Deposit,
+                amount,
+                limit: Limit {
+                    min: Some(Decimal::new(0, 100)),
+                    max: Some(Decimal::new(1000, 0)),
+                },
+            })
+        };
+        assert_eq!(
+            results[..3],
+            [
+                exceeded(Decimal::new(1_000_000_000, 0)),
+                exceeded(Decimal::new(0, 1)),
+                Ok(())
+            ]
+        );
+        assert_eq!(
+            results[0].as_ref().unwrap_err().to_string(),
+            "Failed to process deposit of 1000000000.0000 for client 1. Must be between 0.0100 and 1000.0000."
+        );
+        // Only deposits are limited, and disputes never are
+        assert!(matches!(
+            results[3],
+            Err(Rejection::InsufficientFunds { .. })
+        ));
+        assert_eq!(results[4], Ok(()));
+    }
+
+    #[test]
+    fn tiers() {
+        use crate::tiers::{Tier, Tiers};
+
+        let clock = std::rc::Rc::new(std::cell::Cell::new(0));
+        let tiers = Tiers::new()
+            .with_tier(
+                "retail",
+                Tier {
+                    max_withdrawal: Some(Decimal::new(100, 0)),
+                    overdraft: None,
+                    dispute_window: Some(60 * 60),
+                },
+            )
+            .with_tier(
+                "enterprise",
+                Tier {
+                    overdraft: Some(Decimal::new(500, 0)),
+                    ..Tier::default()
+                },
+            )
+            .with_client(1, "retail")
+            .with_client(2, "enterprise")
+            .with_clock({
+                let clock = clock.clone();
+                move || clock.get()
+            });
+        let mut engine = Engine::new(MemoryClient::default())
+            .allowing_overdrafts(OverdraftLimits::new(Decimal::new(10, 0)))
+            .tiering(tiers);
+        let mut process = |data: &str| {
+            read_from_csv_reader(format!("type,client,tx,amount\n{}", data).as_bytes())
+                .map(|t| engine.process(t.unwrap()))
+                .collect::<Vec<_>>()
+        };
+        let results = process(
+            "\
+deposit,1,1,1000.0
+withdrawal,1,2,200.0
+withdrawal,1,3,50.0
+withdrawal,2,4,400.0
+withdrawal,3,5,10.0
+dispute,1,1,
+",
+        );
+        assert_eq!(
+            results,
+            [
+                Ok(()),
+                Err(Rejection::LimitExceeded {
+                    client_id: 1,
+                    kind: TransactionKind::Withdrawal,
+                    amount: Decimal::new(200, 0),
+                    limit: Limit {
+                        min: None,
+                        max: Some(Decimal::new(100, 0)),
+                    },
+                }),
+                Ok(()),
+                // Enterprise clients overdraw by their tier's allowance, and clients without a tier by the default
+                Ok(()),
+                Ok(()),
+                Ok(()),
+            ]
+        );
+
+        // Withdrawal 3 can't be disputed once the window has passed
+        clock.set(60 * 60);
+        assert_eq!(
+            process("dispute,1,3,"),
+            [Err(Rejection::DisputeWindowClosed {
+                client_id: 1,
+                transaction_id: 3
+            })]
+        );
+    }
+
+    #[test]
+    fn kyc_statuses() {
+        let mut engine = Engine::new(MemoryClient::default()).verifying(
+            Kyc::new(Status::Unverified)
+                .with(2, Status::Verified)
+                .capping_deposits(Decimal::new(100, 0)),
+        );
+        let results = read_from_csv_reader(
+            "\
+type,       client,  tx, amount, reason
+deposit,         1,   1,   60.0,
+deposit,         1,   2,   60.0,
+withdrawal,      1,   3,   10.0,
+status,          1,   4,       , verified
+withdrawal,      1,   5,   10.0,
+deposit,         2,   6,  500.0,
+status,          2,   7,       , suspended
+withdrawal,      2,   8,   10.0,
+dispute,         2,   6,       ,
+"
+            .as_bytes(),
+        )
+        .map(|t| engine.process(t.unwrap()))
+        .collect::<Vec<_>>();
+        assert_eq!(
+            results,
+            [
+                Ok(()),
+                Err(Rejection::DepositCapExceeded {
+                    client_id: 1,
+                    requested: Decimal::new(60, 0),
+                    cap: Decimal::new(100, 0),
+                }),
+                Err(Rejection::Unverified {
+                    client_id: 1,
+                    kind: TransactionKind::Withdrawal,
+                }),
+                Ok(()),
+                Ok(()),
+                Ok(()),
+                Ok(()),
+                Err(Rejection::Suspended {
+                    client_id: 2,
+                    kind: TransactionKind::Withdrawal,
+                }),
+                // Disputes come from outside, so still reach suspended clients
+                Ok(()),
+            ]
+        );
+        assert_eq!(engine.status(1), Status::Verified);
+        assert_eq!(engine.status(3), Status::Unverified);
+    }
+
+    #[test]
+    fn balance_alerts() {
+        use crate::alerts::Crossing;
+
+        let mut engine = Engine::new(MemoryClient::default())
+            .allowing_overdrafts(OverdraftLimits::new(Decimal::new(50, 0)))
+            .alerting("0,1000".parse().unwrap());
+        for transaction in read_from_csv_reader(
+            "\
+type,       client,  tx, amount
+deposit,         1,   1,   10.0
+withdrawal,      1,   2,   30.0
+withdrawal,      1,   3,   10.0
+deposit,         1,   4, 1500.0
+withdrawal,      1,   5,  500.0
+"
+            .as_bytes(),
+        ) {
+            engine.process(transaction.unwrap()).unwrap();
+        }
+        let alert = |transaction_id, crossing, threshold: &str, balance: &str| Alert {
+            client_id: 1,
+            transaction_id,
+            crossing,
+            threshold: threshold.parse().unwrap(),
+            balance: balance.parse().unwrap(),
+        };
+        assert_eq!(
+            engine.take_alerts(),
+            [
+                alert(2, Crossing::Below, "0", "-20"),
+                // Going further below zero with tx 3 raises nothing
+                alert(4, Crossing::Above, "0", "1470"),
+                alert(4, Crossing::Above, "1000", "1470"),
+                alert(5, Crossing::Below, "1000", "970"),
+            ][..]
+        );
+        assert_eq!(engine.take_alerts(), []);
+    }
+
+    #[test]
+    fn refunds() {
+        let mut engine = Engine::new(MemoryClient::default()).checking_conservation();
+        let results = read_from_csv_reader(
+            "\
+type,       client,  tx, amount
+deposit,         1,   1,   10.0
+withdrawal,      1,   2,    1.0
+refund,          1,   1,    4.0
+refund,          1,   1,    7.0
+refund,          1,   2,    1.0
+refund,          1,   3,    1.0
+deposit,         1,   4,    1.0
+dispute,         1,   1,
+refund,          1,   1,    1.0
+"
+            .as_bytes(),
+        )
+        .map(|t| engine.process(t.unwrap()))
+        .collect::<Vec<_>>();
+        assert_eq!(
+            results,
+            vec![
+                Ok(()),
+                Ok(()),
+                Ok(()),
+                Err(Rejection::ExcessRefund {
+                    requested: Decimal::new(7, 0),
+                    refundable: Decimal::new(6, 0),
+                }),
+                Err(Rejection::ExcessRefund {
+                    requested: Decimal::new(1, 0),
+                    refundable: Decimal::zero(),
+                }),
+                Err(Rejection::NotFound(Step::Refund)),
+                Ok(()),
+                Ok(()),
+                Err(Rejection::WrongState(Step::Refund, Disputed)),
+            ]
+        );
+        // Only what's left of the deposit is disputed
+        let client = ClientOutput::from(engine.client(1).unwrap().clone());
+        assert_eq!(client.held, Decimal::new(6, 0));
+        assert_eq!(client.available, Decimal::zero());
+    }
+
+    #[test]
+    fn refund_of_another_clients_deposit() {
+        let mut engine = Engine::new(MemoryClient::default()).checking_conservation();
+        let results = read_from_csv_reader(
+            "\
+type,       client,  tx, amount
+deposit,         1,   1,   10.0
+deposit,         2,   2,   10.0
+refund,          2,   1,    5.0
+refund,          1,   1,   10.0
+"
+            .as_bytes(),
+        )
+        .map(|t| engine.process(t.unwrap()))
+        .collect::<Vec<_>>();
+        let rejection = Rejection::NotOwner {
+            client_id: 2,
+            transaction_id: 1,
+        };
+        assert_eq!(rejection.code(), "E_NOT_OWNER");
+        assert_eq!(results, vec![Ok(()), Ok(()), Err(rejection), Ok(())]);
+        // Client 2 paid nothing, and client 1's deposit could still be refunded in full
+        assert_eq!(engine.client(2).unwrap().total(), Decimal::new(10, 0));
+        assert_eq!(engine.client(1).unwrap().total(), Decimal::zero());
+    }
+
+    #[test]
+    fn activity_timestamps() {
+        use std::cell::Cell;
+        use std::rc::Rc;
+
+        let now = Rc::new(Cell::new(0));
+        let clock = now.clone();
+        let mut engine =
+            Engine::new(MemoryClient::default()).timestamping_activity(move || clock.get());
+        for (at, transaction) in read_from_csv_reader(
+            "\
+type,       client,  tx, amount
+deposit,         1,   1,   10.0
+deposit,         2,   2,    1.0
+withdrawal,      1,   3,    4.0
+withdrawal,      1,   4,  100.0
+"
+            .as_bytes(),
+        )
+        .enumerate()
+        {
+            now.set(100 * (at as u64 + 1));
+            let _ = engine.process(transaction.unwrap());
+        }
+        // The rejected withdrawal isn't activity
+        let activity = |id| {
+            let lifetime = engine.client(id).unwrap().lifetime();
+            (lifetime.first_activity, lifetime.last_activity)
+        };
+        assert_eq!(activity(1), (Some(100), Some(300)));
+        assert_eq!(activity(2), (Some(200), Some(200)));
+    }
+
+    #[test]
+    fn adjustments() {
+        let mut engine = Engine::new(MemoryClient::default()).checking_conservation();
+        let results = read_from_csv_reader(
+            "\
+type,       client,  tx, amount, reason
+deposit,         1,   1,   10.0,
+adjustment,      1,   2,   -4.0, duplicate-deposit
+adjustment,      1,   3,   -7.0, duplicate-deposit
+adjustment,      1,   4,    1.5, missed-deposit
+dispute,         1,   4,       ,
+"
+            .as_bytes(),
+        )
+        .map(|t| engine.process(t.unwrap()))
+        .collect::<Vec<_>>();
+        assert_eq!(
+            results,
+            vec![
+                Ok(()),
+                Ok(()),
+                Err(Rejection::InsufficientFunds {
+                    client_id: 1,
+                    requested: Decimal::new(7, 0),
+                    available: Decimal::new(6, 0),
+                }),
+                Ok(()),
+                Err(Rejection::NotFound(Step::Dispute)),
+            ]
+        );
+        let client = ClientOutput::from(engine.client(1).unwrap().clone());
+        assert_eq!(client.available, Decimal::new(7, 5000));
+
+        let errors = read_from_csv_reader(
+            "type,client,tx,amount,reason\nadjustment,1,5,1.0,\ndeposit,1,6,-1.0,\n".as_bytes(),
+        )
+        .map(|t| t.unwrap_err().to_string())
+        .collect::<Vec<_>>();
+        assert!(errors[0].contains("Missing reason"), "{}", errors[0]);
+        assert!(errors[1].contains("Negative amount"), "{}", errors[1]);
+    }
+
+    #[test]
+    fn dispute_reasons() {
+        let mut engine = Engine::new(MemoryClient::default());
+        for transaction in read_from_csv_reader(
+            "\
+type,       client,  tx, amount, reason
+deposit,         1,   1,    5.0,
+dispute,         1,   1,       , 10.4
+resolve,         1,   1,       ,
+dispute,         1,   1,       , 13.1
+chargeback,      1,   1,       , 13.1
+"
+            .as_bytes(),
+        ) {
+            engine.process(transaction.unwrap()).unwrap();
+        }
+        let reason = |reason: &str| Some(reason.to_owned());
+        assert_eq!(
+            engine.tx_record.access(1).unwrap().0.history,
+            vec![
+                (TransactionKind::Dispute, reason("10.4")),
+                (TransactionKind::Resolve, None),
+                (TransactionKind::Dispute, reason("13.1")),
+                (TransactionKind::Chargeback, reason("13.1")),
+            ]
+        );
+    }
+
+    #[test]
+    fn open_disputes() {
+        let mut engine = Engine::new(MemoryClient::default());
+        for transaction in read_from_csv_reader(
+            "\
+type,       client,  tx, amount, reason
+deposit,         1,   1,    5.0,
+deposit,         1,   2,    5.0,
+deposit,         2,   3,    5.0,
+dispute,         1,   2,       , 10.4
+dispute,         1,   1,       ,
+dispute,         2,   3,       , 13.1
+resolve,         1,   1,       ,
+"
+            .as_bytes(),
+        ) {
+            engine.process(transaction.unwrap()).unwrap();
+        }
+        assert_eq!(
+            engine.open_disputes(1).collect::<Vec<_>>(),
+            vec![(2, Some("10.4"))]
+        );
+        assert_eq!(engine.open_disputes(3).count(), 0);
+
+        engine.merge_clients(1, 2).unwrap();
+        assert_eq!(
+            engine.open_disputes(1).collect::<Vec<_>>(),
+            vec![(2, Some("10.4")), (3, Some("13.1"))]
+        );
+        assert_eq!(engine.open_disputes(2).count(), 0);
+    }
+
+    #[test]
+    fn snapshot_and_restore() {
+        let mut engine = Engine::new(MemoryClient::default());
+        for transaction in read_from_csv_reader(
+            "\
+type,       client,  tx, amount, reason
+deposit,         1,   1,    5.0,
+deposit,         1,   2,    5.0,
+deposit,         2,   3,    5.0,
+dispute,         1,   2,       , fraud
+dispute,         2,   3,       ,
+chargeback,      2,   3,       ,
+"
+            .as_bytes(),
+        ) {
+            engine.process(transaction.unwrap()).unwrap();
+        }
+        let mut snapshot = Vec::new();
+        engine.snapshot(&mut snapshot).unwrap();
+
+        let mut restored = Engine::restore(
+            snapshot.as_slice(),
+            CachedClient::new(MemoryClient::default(), LruCache::with_capacity(CACHE_SIZE)),
+        )
+        .unwrap();
+        let sorted = |mut report: Vec<ClientOutput>| {
+            report.sort_by_key(|c| c.client);
+            report
+        };
+        assert_eq!(
+            sorted(restored.report().collect()),
+            sorted(engine.report().collect())
+        );
+        assert_eq!(
+            restored.open_disputes(1).collect::<Vec<_>>(),
+            vec![(2, Some("fraud"))]
+        );
+        assert_eq!(restored.client(2).unwrap().chargebacks(), 1);
+
+        // The restored transactions carry on through the dispute process
+        for transaction in read_from_csv_reader(
+            "\
+type,       client,  tx, amount
+resolve,         1,   2,
+dispute,         1,   1,
+"
+            .as_bytes(),
+        ) {
+            restored.process(transaction.unwrap()).unwrap();
+        }
+        assert_eq!(restored.client(1).unwrap().disputes(), 2);
+        assert_eq!(
+            restored.open_disputes(1).collect::<Vec<_>>(),
+            vec![(1, None)]
+        );
+    }
+
+    #[test]
+    fn close_periods() {
+        let mut engine = Engine::new(MemoryClient::default()).closing_periods();
+        let process = |engine: &mut Engine<_>, data: &str| {
+            for transaction in read_from_csv_reader(data.as_bytes()) {
+                let _ = engine.process(transaction.unwrap());
+            }
+        };
+        process(
+            &mut engine,
+            "\
+type,       client,  tx, amount, reason
+deposit,         2,   1,   10.0,
+deposit,         1,   2,    5.0,
+dispute,         1,   2,       ,   10.4
+",
+        );
+        let day_1 = engine.close_period("2021-06-01").unwrap().clone();
+        assert_eq!(
+            day_1.balances.iter().map(|c| c.client).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        assert_eq!(
+            day_1.open_disputes.iter().collect::<Vec<_>>(),
+            vec![(&2, &Some("10.4".to_owned()))]
+        );
+        assert_eq!(
+            day_1.ledger[&Account::External],
+            (Decimal::zero(), Decimal::new(15, 0))
+        );
+
+        process(
+            &mut engine,
+            "\
+type,       client,  tx, amount
+resolve,         1,   2,
+withdrawal,      2,   3,    4.0
+",
+        );
+        assert_eq!(
+            engine.close_period("2021-06-01"),
+            Err(DuplicatePeriod("2021-06-01".to_owned()))
+        );
+        let day_2 = engine.close_period("2021-06-02").unwrap();
+        assert!(day_2.open_disputes.is_empty());
+        assert_eq!(
+            day_2.ledger[&Account::External],
+            (Decimal::new(4, 0), Decimal::zero())
+        );
+
+        // Reports as of the first day are unchanged by the second
+        let as_of = engine.report_as_of("2021-06-01").unwrap();
+        assert_eq!(as_of[0].held, Decimal::new(5, 0));
+        assert_eq!(as_of[1].available, Decimal::new(10, 0));
+        assert_eq!(engine.report_as_of("2021-06-03"), None);
+    }
+
+    #[test]
+    fn risk_rules() {
+        let large = Decimal::new(100, 0);
+        let mut engine = Engine::new(MemoryClient::default())
+            .with_risk_rule(|t: &Transaction, client: &Client| match &t.type_ {
+                Disputable(Deposit(_)) if client.total() == Decimal::zero() => {
+                    Verdict::Flag("first deposit".to_owned())
+                }
+                _ => Verdict::Accept,
+            })
+            .with_risk_rule(move |t: &Transaction, _: &Client| match &t.type_ {
+                Disputable(Withdrawal(amount)) if *amount > large => {
+                    Verdict::Reject("large withdrawal".to_owned())
+                }
+                _ => Verdict::Accept,
+            });
+        let results = read_from_csv_reader(
+            "\
+type,       client,  tx, amount
+deposit,         1,   1,  500.0
+deposit,         1,   2,  500.0
+withdrawal,      1,   3,  150.0
+withdrawal,      1,   4,   50.0
+"
+            .as_bytes(),
+        )
+        .map(|t| engine.process(t.unwrap()))
+        .collect::<Vec<_>>();
+        assert_eq!(
+            results,
+            vec![
+                Ok(()),
+                Ok(()),
+                Err(Rejection::Risk {
+                    client_id: 1,
+                    reason: "large withdrawal".to_owned()
+                }),
+                Ok(()),
+            ]
+        );
+        assert_eq!(
+            engine.take_flags(),
+            vec![Flag {
+                client_id: 1,
+                transaction_id: 1,
+                reason: "first deposit".to_owned()
+            }]
+        );
+        assert_eq!(engine.take_flags(), vec![]);
+        let client = ClientOutput::from(engine.client(1).unwrap().clone());
+        assert_eq!(client.available, Decimal::new(950, 0));
+    }
+
+    #[test]
+    fn lock_on_dispute_rate() {
+        let clock = std::rc::Rc::new(std::cell::Cell::new(0));
+        let policy = LockPolicy::new(Some(2), Some(3))
+            .within(30 * 24 * 60 * 60)
+            .with_clock({
+                let clock = clock.clone();
+                move || clock.get()
+            });
+        let mut engine = Engine::new(MemoryClient::default()).locking_automatically(policy);
+        let process = |engine: &mut Engine<_>, data: &str| {
+            for transaction in read_from_csv_reader(data.as_bytes()) {
+                engine.process(transaction.unwrap()).unwrap();
+                clock.set(clock.get() + 10 * 24 * 60 * 60);
+            }
+        };
+        process(
+            &mut engine,
+            "\
+type,       client,  tx, amount
+deposit,         1,   1,    1.0
+deposit,         1,   2,    1.0
+deposit,         1,   3,    1.0
+dispute,         1,   1,
+resolve,         1,   1,
+deposit,         1,   4,    1.0
+deposit,         1,   5,    1.0
+dispute,         1,   2,
+resolve,         1,   2,
+",
+        );
+        // 40 days apart, then 20
+        assert_eq!(engine.take_auto_locks(), vec![]);
+        assert!(!engine.client(1).unwrap().is_locked());
+        assert_eq!(engine.client(1).unwrap().disputes(), 2);
+
+        process(&mut engine, "type,client,tx,amount\ndispute,1,3,\n");
+        assert_eq!(
+            engine.take_auto_locks(),
+            vec![AutoLock {
+                client_id: 1,
+                kind: TransactionKind::Dispute,
+                count: 2,
+            }]
+        );
+        assert!(engine.client(1).unwrap().is_locked());
+    }
+}
@@ -0,0 +1,406 @@
+//! Exporting the journal of applied transactions for plain-text accounting tools, to cross-check balances.
+//!
+//! Each client's available funds are the asset `Assets:Client:<id>`, and held funds are owed back to someone until the
+//! dispute is settled, so are the liability `Liabilities:Held`. Funds moving into or out of the system are balanced
+//! against `Equity:Transfers`, except for chargebacks which are balanced against `Expenses:Chargebacks` and
+//! adjustments which are balanced against `Equity:Adjustments`.
+//!
+//! For a general ledger, `GlBatch` totals the engine's own postings per account instead, under the codes given by a
+//! `ChartOfAccounts`.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use crate::client::{Client, ClientOutput};
+use crate::decimal::Decimal;
+use crate::engine::{Engine, Rejection};
+use crate::posting::{Account, Postings};
+use crate::transaction::{Transaction, Type};
+use crate::transaction_set::Client as TransactionSetClient;
+
+/// A movement of funds into an account, or out of it if `negative`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Posting {
+    pub account: String,
+    pub amount: Decimal,
+    pub negative: bool,
+}
+
+impl Posting {
+    /// The change from `before` to `after`, or `None` if there wasn't one
+    fn change(account: String, before: Decimal, after: Decimal) -> Option<Self> {
+        let (amount, negative) = match after - before {
+            Ok(amount) => (amount, false),
+            Err(amount) => (amount, true),
+        };
+        (amount != Decimal::zero()).then_some(Posting {
+            account,
+            amount,
+            negative,
+        })
+    }
+}
+
+/// A single applied transaction and the postings it made, which always balance
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub transaction: Transaction,
+    pub postings: Vec<Posting>,
+}
+
+/// Every applied transaction, in order
+#[derive(Clone, Debug, Default)]
+pub struct Journal {
+    entries: Vec<Entry>,
+}
+
+impl Journal {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Processes a transaction, recording it if it was applied. Transactions which didn't change the client's
+    /// balances, like disputes of withdrawals, aren't recorded.
+    pub fn process<T: TransactionSetClient>(
+        &mut self,
+        engine: &mut Engine<T>,
+        transaction: Transaction,
+    ) -> Result<(), Rejection> {
+        let client_id = transaction.client_id;
+        let state = |engine: &Engine<T>| {
+            ClientOutput::from(
+                engine
+                    .client(client_id)
+                    .cloned()
+                    .unwrap_or_else(|| Client::new(client_id)),
+            )
+        };
+        let before = state(engine);
+        engine.process(transaction.clone())?;
+        self.record(transaction, &before, &state(engine));
+        Ok(())
+    }
+
+    fn record(&mut self, transaction: Transaction, before: &ClientOutput, after: &ClientOutput) {
+        let client = transaction.client_id;
+        let mut postings = Vec::new();
+        postings.extend(Posting::change(
+            format!("Assets:Client:{}", client),
+            before.available.clone(),
+            after.available.clone(),
+        ));
+        postings.extend(Posting::change(
+            "Liabilities:Held".to_owned(),
+            before.held.clone(),
+            after.held.clone(),
+        ));
+        let counterpart = match transaction.type_ {
+            Type::Chargeback(_) => "Expenses:Chargebacks",
+            Type::Fee(_) => "Income:Fees",
+            Type::Interest(_) => "Expenses:Interest",
+            Type::Adjustment(_) => "Equity:Adjustments",
+            _ => "Equity:Transfers",
+        };
+        // Balances out the change in the client's total
+        if let Some(Posting {
+            amount, negative, ..
+        }) = Posting::change(String::new(), before.total.clone(), after.total.clone())
+        {
+            postings.push(Posting {
+                account: counterpart.to_owned(),
+                amount,
+                negative: !negative,
+            });
+        }
+        if !postings.is_empty() {
+            self.entries.push(Entry {
+                transaction,
+                postings,
+            });
+        }
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// Writes the journal in beancount syntax, with every entry on `date` in `currency`. Each transaction's metadata
+    /// is written as entry metadata.
+    pub fn write_beancount<W: Write>(
+        &self,
+        mut w: W,
+        date: &str,
+        currency: &str,
+    ) -> io::Result<()> {
+        let accounts = self
+            .entries
+            .iter()
+            .flat_map(|e| &e.postings)
+            .map(|p| p.account.as_str())
+            .collect::<BTreeSet<_>>();
+        for account in &accounts {
+            writeln!(w, "{} open {} {}", date, account, currency)?;
+        }
+
+        for Entry {
+            transaction,
+            postings,
+        } in &self.entries
+        {
+            writeln!(w)?;
+            writeln!(
+                w,
+                "{} * \"client {}\" \"{} of tx {}\"",
+                date,
+                transaction.client_id,
+                transaction.type_.kind(),
+                transaction.transaction_id
+            )?;
+            let metadata = transaction
+                .metadata
+                .iter()
+                .map(|(key, value)| (beancount_key(key), value))
+                .collect::<BTreeMap<_, _>>();
+            for (key, value) in metadata {
+                writeln!(w, "  {}: {:?}", key, value)?;
+            }
+            for posting in postings {
+                let sign = if posting.negative { "-" } else { "" };
+                writeln!(
+                    w,
+                    "  {:<32} {}{} {}",
+                    posting.account, sign, posting.amount, currency
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A metadata key beancount accepts, which must start with a lowercase letter and can't contain spaces or most
+/// punctuation
+fn beancount_key(key: &str) -> String {
+    let key = key
+        .chars()
+        .map(|c| match c {
+            'A'..='Z' => c.to_ascii_lowercase(),
+            'a'..='z' | '0'..='9' | '-' | '_' => c,
+            _ => '-',
+        })
+        .collect::<String>();
+    match key.starts_with(|c: char| c.is_ascii_lowercase()) {
+        true => key,
+        false => format!("x{}", key),
+    }
+}
+
+/// General ledger account codes for the engine's internal accounts
+#[derive(Clone, Debug, Default)]
+pub struct ChartOfAccounts {
+    codes: HashMap<Account, String>,
+}
+
+#[derive(Debug)]
+pub enum ChartError {
+    Csv(csv::Error),
+    UnknownAccount(String),
+}
+
+impl fmt::Display for ChartError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChartError::Csv(e) => e.fmt(f),
+            ChartError::UnknownAccount(account) => write!(f, "unknown account {:?}", account),
+        }
+    }
+}
+
+impl std::error::Error for ChartError {}
+
+impl From<csv::Error> for ChartError {
+    fn from(e: csv::Error) -> Self {
+        ChartError::Csv(e)
+    }
+}
+
+impl ChartOfAccounts {
+    /// Reads `account,code` rows, where `account` is an internal account name like `available`
+    pub fn from_csv_reader<R: Read>(rdr: R) -> Result<Self, ChartError> {
+        #[derive(Deserialize)]
+        struct Row {
+            account: String,
+            code: String,
+        }
+
+        let mut chart = ChartOfAccounts::default();
+        for row in csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(rdr)
+            .into_deserialize()
+        {
+            let Row { account, code } = row?;
+            let account = Account::ALL
+                .iter()
+                .copied()
+                .find(|a| a.name() == account)
+                .ok_or(ChartError::UnknownAccount(account))?;
+            chart.codes.insert(account, code);
+        }
+        Ok(chart)
+    }
+
+    /// The code for `account`, or its internal name if it isn't mapped
+    pub fn code(&self, account: Account) -> &str {
+        self.codes
+            .get(&account)
+            .map_or(account.name(), String::as_str)
+    }
+}
+
+/// A single row of a GL batch file
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct GlLine {
+    pub date: String,
+    pub account: String,
+    pub debit: Decimal,
+    pub credit: Decimal,
+}
+
+/// The total debits and credits to each GL account over a run. Funds moving into an account debit it, and funds
+/// moving out credit it, so the batch always balances.
+#[derive(Clone, Debug)]
+pub struct GlBatch {
+    chart: ChartOfAccounts,
+    totals: BTreeMap<String, (Decimal, Decimal)>,
+}
+
+impl GlBatch {
+    pub fn new(chart: ChartOfAccounts) -> Self {
+        GlBatch {
+            chart,
+            totals: BTreeMap::new(),
+        }
+    }
+
+    pub fn record(&mut self, postings: &Postings) {
+        for posting in postings.iter() {
+            let from = self.chart.code(posting.from).to_owned();
+            let to = self.chart.code(posting.to).to_owned();
+            self.totals.entry(to).or_default().0 += posting.amount.clone();
+            self.totals.entry(from).or_default().1 += posting.amount.clone();
+        }
+    }
+
+    /// One line per account, in code order
+    pub fn lines(&self, date: &str) -> impl Iterator<Item = GlLine> + '_ {
+        let date = date.to_owned();
+        self.totals
+            .iter()
+            .map(move |(account, (debit, credit))| GlLine {
+                date: date.clone(),
+                account: account.clone(),
+                debit: debit.clone(),
+                credit: credit.clone(),
+            })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::transaction::read_from_csv_reader;
+    use crate::transaction_set::MemoryClient;
+
+    #[test]
+    fn beancount_journal() {
+        let data = "\
+type,       client,  tx, amount, memo,   Merchant ID
+deposit,         1,   1,    5.0, rent,   ACME \"UK\"
+withdrawal,      1,   2,    9.0,     ,
+dispute,         1,   1,       ,     ,
+chargeback,      1,   1,       ,     ,
+";
+        let mut engine = Engine::new(MemoryClient::default());
+        let mut journal = Journal::new();
+        for transaction in read_from_csv_reader(data.as_bytes()) {
+            let _ = journal.process(&mut engine, transaction.unwrap());
+        }
+
+        assert_eq!(journal.entries().len(), 3);
+        let mut out = Vec::new();
+        journal
+            .write_beancount(&mut out, "2021-06-01", "USD")
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\
+2021-06-01 open Assets:Client:1 USD
+2021-06-01 open Equity:Transfers USD
+2021-06-01 open Expenses:Chargebacks USD
+2021-06-01 open Liabilities:Held USD
+
+2021-06-01 * \"client 1\" \"deposit of tx 1\"
+  memo: \"rent\"
+  merchant-id: \"ACME \\\"UK\\\"\"
+  Assets:Client:1                  5.0000 USD
+  Equity:Transfers                 -5.0000 USD
+
+2021-06-01 * \"client 1\" \"dispute of tx 1\"
+  Assets:Client:1                  -5.0000 USD
+  Liabilities:Held                 5.0000 USD
+
+2021-06-01 * \"client 1\" \"chargeback of tx 1\"
+  Liabilities:Held                 -5.0000 USD
+  Expenses:Chargebacks             5.0000 USD
+"
+        );
+    }
+
+    #[test]
+    fn gl_batch() {
+        let chart = ChartOfAccounts::from_csv_reader(
+            "account,code\nexternal,1000\navailable,2100\nheld,2200\nheld_reserve,2200\n"
+                .as_bytes(),
+        )
+        .unwrap();
+        assert!(matches!(
+            ChartOfAccounts::from_csv_reader("account,code\ncash,1000\n".as_bytes()),
+            Err(ChartError::UnknownAccount(_))
+        ));
+
+        let data = "\
+type,       client,  tx, amount
+deposit,         1,   1,    5.0
+withdrawal,      1,   2,    2.0
+dispute,         1,   1,
+chargeback,      1,   1,
+";
+        let mut engine = Engine::new(MemoryClient::default());
+        let mut batch = GlBatch::new(chart);
+        for transaction in read_from_csv_reader(data.as_bytes()) {
+            batch.record(&engine.process_with_postings(transaction.unwrap()).unwrap());
+        }
+        let lines = batch
+            .lines("2021-06-30")
+            .map(|l| (l.account, l.debit.to_string(), l.credit.to_string()))
+            .collect::<Vec<_>>();
+        let line = |account: &str, debit: u64, credit: u64| {
+            (
+                account.to_owned(),
+                Decimal::new(debit, 0).to_string(),
+                Decimal::new(credit, 0).to_string(),
+            )
+        };
+        assert_eq!(
+            lines,
+            vec![
+                line("1000", 2, 7),
+                line("2100", 5, 5),
+                line("2200", 5, 5),
+                line("chargeback_writeoff", 5, 0),
+            ]
+        );
+    }
+}
@@ -0,0 +1,311 @@
+//! A faster path for reading large, clean CSV files in the standard dialect.
+//!
+//! Rows are read into a single reused `ByteRecord` and each field is parsed straight from its bytes by `digits`,
+//! rather than deserialized through serde, so a row only allocates for a reason or any columns we don't understand.
+//! Amounts must be strict, and the same transactions and metadata come out as from
+//! `read_from_csv_reader_with_metadata`.
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::io;
+use std::str;
+
+use csv::ByteRecord;
+
+use crate::digits;
+use crate::transaction::{
+    self, AmountRules, CsvOptions, CsvTransaction, CsvType, Metadata, Transaction,
+};
+
+/// Why a row couldn't be read
+#[derive(Debug)]
+pub enum Error {
+    Csv(csv::Error),
+    /// The header has no such column, so no row can be read
+    MissingColumn(&'static str),
+    /// A field on line `line` which couldn't be parsed
+    InvalidField {
+        line: u64,
+        column: &'static str,
+    },
+    Transaction {
+        line: u64,
+        error: transaction::Error,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Csv(e) => e.fmt(f),
+            Error::MissingColumn(column) => write!(f, "missing column {:?}", column),
+            Error::InvalidField { line, column } => write!(f, "line {}: invalid {}", line, column),
+            Error::Transaction { line, error } => write!(f, "line {}: {}", line, error),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl Error {
+    /// A stable code for the error, like `Rejection::code`
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::Csv(_) => "E_PARSE",
+            Error::MissingColumn(_) => "E_MISSING_COLUMN",
+            Error::InvalidField { column, .. } => match *column {
+                "type" => "E_PARSE_TYPE",
+                "client" => "E_PARSE_CLIENT",
+                "tx" => "E_PARSE_TX",
+                "amount" => "E_PARSE_AMOUNT",
+                _ => "E_PARSE",
+            },
+            Error::Transaction { error, .. } => error.code(),
+        }
+    }
+}
+
+impl From<csv::Error> for Error {
+    fn from(e: csv::Error) -> Self {
+        Error::Csv(e)
+    }
+}
+
+/// Where each column we understand is, found from the header
+#[derive(Clone)]
+pub(crate) struct Columns {
+    type_: usize,
+    client: usize,
+    tx: usize,
+    amount: Option<usize>,
+    reason: Option<usize>,
+    /// Every other column, with its header
+    unknown: Vec<(usize, String)>,
+    /// How many columns the header has, past which columns are named by their index
+    width: usize,
+}
+
+impl Columns {
+    pub(crate) fn find(headers: &ByteRecord) -> Result<Self, Error> {
+        let position = |name: &str| headers.iter().position(|h| h == name.as_bytes());
+        let required = |name| position(name).ok_or(Error::MissingColumn(name));
+        Ok(Columns {
+            type_: required("type")?,
+            client: required("client")?,
+            tx: required("tx")?,
+            amount: position("amount"),
+            reason: position("reason"),
+            unknown: headers
+                .iter()
+                .enumerate()
+                .filter(|&(_, h)| {
+                    !transaction::Dialect::Standard
+                        .columns()
+                        .iter()
+                        .any(|c| c.as_bytes() == h)
+                })
+                .map(|(i, h)| (i, String::from_utf8_lossy(h).into_owned()))
+                .collect(),
+            width: headers.len(),
+        })
+    }
+}
+
+pub struct Reader<R> {
+    rdr: csv::Reader<R>,
+    record: ByteRecord,
+    /// The columns, or why they couldn't be found until that's been returned
+    columns: Option<Result<Columns, Error>>,
+    amounts: AmountRules,
+}
+
+/// Reads a CSV file in the standard dialect with `options`' delimiter, quote and amount rules. Its dialect and lenient
+/// amounts are ignored.
+pub fn read<R: io::Read>(rdr: R, options: &CsvOptions) -> Reader<R> {
+    let mut rdr = options.reader_builder().from_reader(rdr);
+    let columns = rdr
+        .byte_headers()
+        .map_err(Error::from)
+        .and_then(Columns::find);
+    Reader {
+        rdr,
+        record: ByteRecord::new(),
+        columns: Some(columns),
+        amounts: options.amounts.clone(),
+    }
+}
+
+/// Reads headerless rows laid out as in `columns`
+#[cfg(feature = "mmap")]
+pub(crate) fn read_rows<R: io::Read>(rdr: R, options: &CsvOptions, columns: Columns) -> Reader<R> {
+    Reader {
+        rdr: options.reader_builder().has_headers(false).from_reader(rdr),
+        record: ByteRecord::new(),
+        columns: Some(Ok(columns)),
+        amounts: options.amounts.clone(),
+    }
+}
+
+impl Error {
+    /// Moves the line an error is on down by `lines`, for rows read after the start of the file
+    #[cfg(feature = "mmap")]
+    pub(crate) fn offset(mut self, lines: u64) -> Self {
+        match &mut self {
+            Error::InvalidField { line, .. } | Error::Transaction { line, .. } => *line += lines,
+            Error::Csv(_) | Error::MissingColumn(_) => {}
+        }
+        self
+    }
+}
+
+impl<R: io::Read> Reader<R> {
+    /// The columns found from the header, without reading any rows
+    #[cfg(feature = "mmap")]
+    pub(crate) fn columns(mut self) -> Result<Columns, Error> {
+        self.columns.take().expect("no rows read yet")
+    }
+
+    fn parse(&self, columns: &Columns) -> Result<(Transaction, Metadata), Error> {
+        let record = &self.record;
+        let line = record.position().map_or(0, |p| p.line());
+        let field = |i: usize| record.get(i).unwrap_or_default();
+        let invalid = |column| Error::InvalidField { line, column };
+        let optional = |i: Option<usize>| i.map(field).filter(|f| !f.is_empty());
+
+        let type_ = parse_type(field(columns.type_)).ok_or_else(|| invalid("type"))?;
+        let client = digits::parse_int(field(columns.client)).ok_or_else(|| invalid("client"))?;
+        let tx = digits::parse_int(field(columns.tx)).ok_or_else(|| invalid("tx"))?;
+        let amount = match optional(columns.amount) {
+            Some(amount) => Some(digits::parse_amount(amount).ok_or_else(|| invalid("amount"))?),
+            None => None,
+        };
+        let reason = match optional(columns.reason) {
+            Some(reason) => Some(
+                str::from_utf8(reason)
+                    .map_err(|_| invalid("reason"))?
+                    .to_owned(),
+            ),
+            None => None,
+        };
+
+        let mut transaction = Transaction::try_from(CsvTransaction {
+            type_,
+            client,
+            tx,
+            amount,
+            reason,
+        })
+        .map_err(|error| Error::Transaction { line, error })?;
+        self.amounts
+            .check(&transaction.type_)
+            .map_err(|error| Error::Transaction { line, error })?;
+        let metadata = Metadata(
+            columns
+                .unknown
+                .iter()
+                .map(|(i, header)| (*i, Some(header)))
+                .chain((columns.width..record.len()).map(|i| (i, None)))
+                .filter(|&(i, _)| !field(i).is_empty())
+                .map(|(i, header)| {
+                    (
+                        header.map_or_else(|| i.to_string(), String::clone),
+                        String::from_utf8_lossy(field(i)).into_owned(),
+                    )
+                })
+                .collect(),
+        );
+        transaction.metadata = metadata.0.iter().cloned().collect();
+        Ok((transaction, metadata))
+    }
+}
+
+impl<R: io::Read> Iterator for Reader<R> {
+    type Item = Result<(Transaction, Metadata), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let columns = match self.columns.take()? {
+            Ok(columns) => columns,
+            Err(e) => return Some(Err(e)),
+        };
+        let result = match self.rdr.read_byte_record(&mut self.record) {
+            Ok(true) => Some(self.parse(&columns)),
+            Ok(false) => None,
+            Err(e) => Some(Err(e.into())),
+        };
+        self.columns = Some(Ok(columns));
+        result
+    }
+}
+
+fn parse_type(field: &[u8]) -> Option<CsvType> {
+    Some(match field {
+        b"deposit" => CsvType::Deposit,
+        b"withdrawal" => CsvType::Withdrawal,
+        b"dispute" => CsvType::Dispute,
+        b"resolve" => CsvType::Resolve,
+        b"chargeback" => CsvType::Chargeback,
+        b"fee" => CsvType::Fee,
+        b"interest" => CsvType::Interest,
+        b"close" => CsvType::Close,
+        b"authorize" => CsvType::Authorize,
+        b"capture" => CsvType::Capture,
+        b"void" => CsvType::Void,
+        b"refund" => CsvType::Refund,
+        b"adjustment" => CsvType::Adjustment,
+        b"status" => CsvType::Status,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::transaction::read_from_csv_reader_with_metadata;
+
+    #[test]
+    fn same_as_serde() {
+        let data = "\
+type,       client,  tx, amount, memo, reason
+deposit,         1,   1,    1.5,  rent,
+withdrawal,      2,   2,   0.25,      ,
+dispute,         1,   1,       ,      , fraud
+adjustment,      2,   3,   -1.0,      , duplicate-deposit
+adjustment,      2,   4,   -1.0,      ,
+withdrawal,      2,   5,   -1.0,      ,
+deposit,         1,   8,    0.0,      ,
+chargeback,      1,   1,       ,      ,       , extra
+deposit,    100000,   6,    1.0,      ,
+transfer,        1,   7,    1.0,      ,
+";
+        let options = CsvOptions::default();
+        let fast = read(data.as_bytes(), &options).collect::<Vec<_>>();
+        let serde =
+            read_from_csv_reader_with_metadata(data.as_bytes(), &options).collect::<Vec<_>>();
+        assert_eq!(fast.len(), serde.len());
+        assert_eq!(
+            fast.iter()
+                .filter_map(|t| t.as_ref().err())
+                .map(Error::code)
+                .collect::<Vec<_>>(),
+            [
+                "E_MISSING_REASON",
+                "E_NEGATIVE_AMOUNT",
+                "E_ZERO_AMOUNT",
+                "E_PARSE_CLIENT",
+                "E_PARSE_TYPE"
+            ]
+        );
+        for (fast, serde) in fast.into_iter().zip(serde) {
+            match (fast, serde) {
+                (Ok(fast), Ok(serde)) => assert_eq!(fast, serde),
+                (Err(_), Err(_)) => {}
+                (fast, serde) => panic!("{:?} != {:?}", fast, serde),
+            }
+        }
+
+        assert!(matches!(
+            read("client,tx,amount\n1,1,1.0\n".as_bytes(), &options).collect::<Vec<_>>()[..],
+            [Err(Error::MissingColumn("type"))]
+        ));
+    }
+}
@@ -0,0 +1,112 @@
+//! Fees charged automatically by the engine after applying transactions
+
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::io::Read;
+
+use crate::decimal::Decimal;
+use crate::transaction::TransactionKind;
+
+/// A flat amount plus a percentage of the transaction's amount
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Fee {
+    pub flat: Decimal,
+    /// e.g. `1.5` for 1.5%
+    pub percent: Decimal,
+}
+
+impl Fee {
+    /// The fee for a transaction of `amount`, truncated to our precision, or `None` if it overflows
+    pub fn for_amount(&self, amount: &Decimal) -> Option<Decimal> {
+        Some(self.flat.clone() + amount.checked_percent(&self.percent)?)
+    }
+}
+
+/// The fee for each kind of transaction.
+///
+/// Percentages are of the transaction's amount, or of the disputed transaction's amount for disputes, resolves and
+/// chargebacks. Fees themselves are never charged a fee.
+#[derive(Clone, Debug, Default)]
+pub struct FeeSchedule {
+    fees: HashMap<TransactionKind, Fee>,
+}
+
+impl FeeSchedule {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, kind: TransactionKind, fee: Fee) -> Self {
+        self.fees.insert(kind, fee);
+        self
+    }
+
+    /// Reads `type,flat,percent` rows, where either amount may be left empty
+    pub fn from_csv_reader<R: Read>(rdr: R) -> csv::Result<Self> {
+        #[derive(Deserialize)]
+        struct Row {
+            #[serde(rename = "type")]
+            kind: TransactionKind,
+            flat: Option<Decimal>,
+            percent: Option<Decimal>,
+        }
+
+        let mut schedule = FeeSchedule::new();
+        for row in csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(rdr)
+            .into_deserialize()
+        {
+            let row: Row = row?;
+            schedule = schedule.with(
+                row.kind,
+                Fee {
+                    flat: row.flat.unwrap_or_default(),
+                    percent: row.percent.unwrap_or_default(),
+                },
+            );
+        }
+        Ok(schedule)
+    }
+
+    pub fn get(&self, kind: TransactionKind) -> Option<&Fee> {
+        match kind {
+            TransactionKind::Fee => None,
+            kind => self.fees.get(&kind),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn read_schedule() {
+        let schedule = FeeSchedule::from_csv_reader(
+            "\
+type,       flat,  percent
+withdrawal,     ,      1.5
+chargeback, 15.0,
+fee,         1.0,
+"
+            .as_bytes(),
+        )
+        .unwrap();
+        let fee = |kind, amount| {
+            schedule
+                .get(kind)
+                .and_then(|f: &Fee| f.for_amount(&Decimal::new(amount, 0)))
+        };
+        assert_eq!(
+            fee(TransactionKind::Withdrawal, 200),
+            Some(Decimal::new(3, 0))
+        );
+        assert_eq!(
+            fee(TransactionKind::Chargeback, 200),
+            Some(Decimal::new(15, 0))
+        );
+        assert_eq!(fee(TransactionKind::Deposit, 200), None);
+        assert_eq!(fee(TransactionKind::Fee, 200), None);
+    }
+}
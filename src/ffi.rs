@@ -0,0 +1,186 @@
+//! A C ABI for embedding the engine, declared in `include/stm.h`.
+//!
+//! Strings returned to the caller are owned by them and must be released with [`stm_string_free`].
+
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use std::ptr;
+
+use crate::engine::Engine;
+use crate::report::Row;
+use crate::transaction::Transaction;
+use crate::transaction_set::MemoryClient;
+
+/// An engine with its transaction record held in memory, opaque to C
+pub struct StmEngine(Engine<MemoryClient>);
+
+#[repr(i32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StmStatus {
+    Ok = 0,
+    /// A null pointer, or a string which isn't UTF-8
+    InvalidArgument = 1,
+    /// The JSON wasn't a valid transaction
+    ParseError = 2,
+    /// The transaction was valid, but the engine refused to apply it
+    Rejected = 3,
+}
+
+#[no_mangle]
+pub extern "C" fn stm_engine_new() -> *mut StmEngine {
+    Box::into_raw(Box::new(StmEngine(Engine::new(MemoryClient::default()))))
+}
+
+/// Applies a single transaction given as a JSON object with the same fields as a CSV row, e.g.
+/// `{"type": "deposit", "client": 1, "tx": 1, "amount": "1.5"}`. Amounts are strings, so they're never rounded through
+/// a float.
+///
+/// If `error` is not null and the transaction couldn't be applied, it receives a description of why with a code for
+/// automation to match on, like `Rejection::code`, e.g. `tx 2: E_INSUFFICIENT_FUNDS: ...` or `E_PARSE: ...`.
+///
+/// # Safety
+/// `engine` must come from [`stm_engine_new`], and `json` must be null or a NUL terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn stm_engine_apply_json(
+    engine: *mut StmEngine,
+    json: *const c_char,
+    error: *mut *mut c_char,
+) -> StmStatus {
+    let (status, message) = match apply_json(engine, json) {
+        Ok(()) => return StmStatus::Ok,
+        Err(e) => e,
+    };
+    if !error.is_null() {
+        *error = into_c_string(message);
+    }
+    status
+}
+
+unsafe fn apply_json(
+    engine: *mut StmEngine,
+    json: *const c_char,
+) -> Result<(), (StmStatus, String)> {
+    let invalid = |what: &str| {
+        (
+            StmStatus::InvalidArgument,
+            format!("E_INVALID_ARGUMENT: {}", what),
+        )
+    };
+    let engine = engine.as_mut().ok_or_else(|| invalid("engine is null"))?;
+    if json.is_null() {
+        return Err(invalid("json is null"));
+    }
+    let json = CStr::from_ptr(json)
+        .to_str()
+        .map_err(|_| invalid("json is not UTF-8"))?;
+
+    let transaction = serde_json::from_str::<Transaction>(json)
+        .map_err(|e| (StmStatus::ParseError, format!("E_PARSE: {}", e)))?;
+    let transaction_id = transaction.transaction_id;
+    engine.0.process(transaction).map_err(|e| {
+        (
+            StmStatus::Rejected,
+            format!("tx {}: {}: {}", transaction_id, e.code(), e),
+        )
+    })
+}
+
+/// The accounts report as CSV, ordered by client id, or null if `engine` is null
+///
+/// # Safety
+/// `engine` must be null or come from [`stm_engine_new`].
+#[no_mangle]
+pub unsafe extern "C" fn stm_engine_report_csv(engine: *const StmEngine) -> *mut c_char {
+    let engine = match engine.as_ref() {
+        Some(engine) => engine,
+        None => return ptr::null_mut(),
+    };
+    let mut report = engine.0.report().collect::<Vec<_>>();
+    report.sort_by_key(|c| c.client);
+
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    for client in report {
+        writer
+            .serialize(Row::new(client, false))
+            .expect("writing to memory can't fail");
+    }
+    let csv = writer.into_inner().expect("writing to memory can't fail");
+    into_c_string(String::from_utf8(csv).expect("reports are ASCII"))
+}
+
+/// # Safety
+/// `engine` must be null or come from [`stm_engine_new`], and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn stm_engine_free(engine: *mut StmEngine) {
+    if !engine.is_null() {
+        drop(Box::from_raw(engine));
+    }
+}
+
+/// # Safety
+/// `s` must be null or a string returned by this library, and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn stm_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+fn into_c_string(s: String) -> *mut c_char {
+    // None of our messages contain NULs, but serde_json may quote the input back at us
+    CString::new(s.replace('\0', "\\0"))
+        .expect("NULs were replaced")
+        .into_raw()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn apply_and_report() {
+        let apply = |engine, json: &str| unsafe {
+            let json = CString::new(json).unwrap();
+            let mut error = ptr::null_mut();
+            let status = stm_engine_apply_json(engine, json.as_ptr(), &mut error);
+            if !error.is_null() {
+                stm_string_free(error);
+            }
+            status
+        };
+
+        let engine = stm_engine_new();
+        assert_eq!(
+            apply(
+                engine,
+                r#"{"type": "deposit", "client": 2, "tx": 1, "amount": "1.5"}"#
+            ),
+            StmStatus::Ok
+        );
+        assert_eq!(
+            apply(
+                engine,
+                r#"{"type": "withdrawal", "client": 2, "tx": 2, "amount": "2.0"}"#
+            ),
+            StmStatus::Rejected
+        );
+        assert_eq!(
+            apply(engine, r#"{"type": "deposit", "client": 1, "tx": 3}"#),
+            StmStatus::ParseError
+        );
+        assert_eq!(
+            unsafe { stm_engine_apply_json(engine, ptr::null(), ptr::null_mut()) },
+            StmStatus::InvalidArgument
+        );
+
+        unsafe {
+            let report = stm_engine_report_csv(engine);
+            assert_eq!(
+                CStr::from_ptr(report).to_str().unwrap(),
+                "client,available,held,total,locked\n2,1.5000,0.0000,1.5000,false\n"
+            );
+            stm_string_free(report);
+            stm_engine_free(engine);
+        }
+    }
+}
@@ -0,0 +1,111 @@
+//! Reproducible synthetic transactions, for load testing and benchmarking backends
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::HashMap;
+
+use crate::decimal::Decimal;
+use crate::transaction::{DisputableType::*, Transaction, Type::*};
+
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// Client ids are drawn uniformly from `0..clients`
+    pub clients: u16,
+    pub seed: u64,
+    /// Chance of each row being a dispute, resolve or chargeback of an earlier deposit or withdrawal
+    pub dispute_rate: f64,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            clients: 500,
+            seed: 0,
+            dispute_rate: 0.1,
+        }
+    }
+}
+
+/// An endless stream of transactions. The same options always produce the same stream.
+///
+/// Deposits and withdrawals are equally likely and get sequential transaction ids. Dispute steps pick uniformly from
+/// the earlier deposits and withdrawals, so they may well be invalid for the state that transaction is in.
+pub struct Generator {
+    rng: StdRng,
+    options: Options,
+    // Every deposit or withdrawal generated so far, as (transaction id, client id)
+    issued: Vec<(u32, u16)>,
+}
+
+impl Generator {
+    pub fn new(options: Options) -> Self {
+        Generator {
+            rng: StdRng::seed_from_u64(options.seed),
+            options,
+            issued: Vec::new(),
+        }
+    }
+}
+
+impl Iterator for Generator {
+    type Item = Transaction;
+
+    fn next(&mut self) -> Option<Transaction> {
+        if !self.issued.is_empty() && self.rng.gen_bool(self.options.dispute_rate) {
+            let (transaction_id, client_id) = self.issued[self.rng.gen_range(0..self.issued.len())];
+            let type_ = match self.rng.gen_range(0..3) {
+                0 => Dispute(None),
+                1 => Resolve(None),
+                _ => Chargeback(None),
+            };
+            return Some(Transaction {
+                client_id,
+                transaction_id,
+                type_,
+                metadata: HashMap::new(),
+            });
+        }
+
+        let transaction_id = self.issued.len() as u32 + 1;
+        let client_id = self.rng.gen_range(0..self.options.clients.max(1));
+        let type_ = match self.rng.gen() {
+            false => Deposit(Decimal::new(
+                self.rng.gen_range(0..65000),
+                self.rng.gen_range(0..10000),
+            )),
+            true => Withdrawal(Decimal::new(
+                self.rng.gen_range(0..1000),
+                self.rng.gen_range(0..10000),
+            )),
+        };
+        self.issued.push((transaction_id, client_id));
+        Some(Transaction {
+            client_id,
+            transaction_id,
+            type_: Disputable(type_),
+            metadata: HashMap::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reproducible_generation() {
+        let options = Options {
+            clients: 10,
+            seed: 42,
+            dispute_rate: 0.5,
+        };
+        let a = Generator::new(options.clone())
+            .take(1000)
+            .collect::<Vec<_>>();
+        let b = Generator::new(options).take(1000).collect::<Vec<_>>();
+        assert_eq!(a, b);
+
+        assert!(a.iter().all(|t| t.client_id < 10));
+        assert!(a.iter().any(|t| matches!(t.type_, Chargeback(_))));
+    }
+}
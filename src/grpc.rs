@@ -0,0 +1,665 @@
+//! Keeping an engine's disputable transactions in a central store over gRPC, so the engine process itself can be
+//! stateless.
+//!
+//! `RemoteClient` is a transaction store which calls the `TransactionStore` service in `proto/store.proto`, and
+//! `StoreServer` serves that service from any store of its own which threads can share. The transaction store
+//! interface can't fail, so a call the service doesn't answer panics rather than leave the engine acting on a state it
+//! doesn't know.
+
+use std::convert::{Infallible, TryFrom};
+use std::io;
+use std::net::TcpListener;
+use tokio::runtime::{self, Runtime};
+use tonic::body::BoxBody;
+use tonic::codec::ProstCodec;
+use tonic::codegen::http::{self, uri::PathAndQuery};
+use tonic::codegen::{BoxFuture, Context, Poll, Service};
+use tonic::server::NamedService;
+use tonic::transport::server::TcpIncoming;
+use tonic::transport::{Channel, Endpoint, Server};
+use tonic::{Request, Response, Status};
+
+use crate::decimal::Decimal;
+use crate::protobuf::pb::Type;
+use crate::transaction::{DisputableTransaction, DisputableType, TransactionKind};
+use crate::transaction_set::{Client, State, StoreStats, Transition, UpdateFailure};
+
+/// The generated types, kept in step with `proto/store.proto` by hand
+pub mod pb {
+    use crate::protobuf::pb::Type;
+    use std::collections::HashMap;
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct HistoryEntry {
+        #[prost(enumeration = "Type", tag = "1")]
+        pub r#type: i32,
+        #[prost(string, optional, tag = "2")]
+        pub reason: Option<String>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Entry {
+        #[prost(uint32, tag = "1")]
+        pub client: u32,
+        #[prost(uint32, tag = "2")]
+        pub tx: u32,
+        #[prost(enumeration = "Type", tag = "3")]
+        pub r#type: i32,
+        #[prost(string, tag = "4")]
+        pub amount: String,
+        #[prost(map = "string, string", tag = "5")]
+        pub metadata: HashMap<String, String>,
+        #[prost(message, repeated, tag = "6")]
+        pub history: Vec<HistoryEntry>,
+        #[prost(enumeration = "State", tag = "7")]
+        pub state: i32,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Empty {}
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct AccessRequest {
+        #[prost(uint32, tag = "1")]
+        pub tx: u32,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct AccessResponse {
+        #[prost(message, optional, tag = "1")]
+        pub entry: Option<Entry>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct UpdateRequest {
+        #[prost(uint32, tag = "1")]
+        pub tx: u32,
+        #[prost(enumeration = "State", tag = "2")]
+        pub state: i32,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct RefundRequest {
+        #[prost(uint32, tag = "1")]
+        pub tx: u32,
+        #[prost(string, tag = "2")]
+        pub amount: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct UpdateResponse {
+        #[prost(oneof = "update_response::Result", tags = "1, 2, 3")]
+        pub result: Option<update_response::Result>,
+        #[prost(enumeration = "State", tag = "4")]
+        pub previous: i32,
+    }
+
+    pub mod update_response {
+        #[derive(Clone, PartialEq, prost::Oneof)]
+        pub enum Result {
+            #[prost(message, tag = "1")]
+            Entry(super::Entry),
+            #[prost(bool, tag = "2")]
+            NotFound(bool),
+            #[prost(enumeration = "super::State", tag = "3")]
+            WrongState(i32),
+        }
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ReassignRequest {
+        #[prost(uint32, tag = "1")]
+        pub from: u32,
+        #[prost(uint32, tag = "2")]
+        pub to: u32,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct RecordRequest {
+        #[prost(uint32, tag = "1")]
+        pub tx: u32,
+        #[prost(enumeration = "Type", tag = "2")]
+        pub r#type: i32,
+        #[prost(string, optional, tag = "3")]
+        pub reason: Option<String>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct StateCount {
+        #[prost(enumeration = "State", tag = "1")]
+        pub state: i32,
+        #[prost(uint64, tag = "2")]
+        pub count: u64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct StatsResponse {
+        #[prost(message, repeated, tag = "1")]
+        pub states: Vec<StateCount>,
+        #[prost(uint64, tag = "2")]
+        pub memory_bytes: u64,
+        #[prost(uint64, tag = "3")]
+        pub disk_bytes: u64,
+        #[prost(uint32, optional, tag = "4")]
+        pub oldest_disputed: Option<u32>,
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+    #[repr(i32)]
+    pub enum State {
+        Unspecified = 0,
+        Committed = 1,
+        Resolved = 2,
+        Disputed = 3,
+        ChargedBack = 4,
+        ChargedBackFinal = 5,
+        Authorized = 6,
+        Voided = 7,
+    }
+}
+
+const SERVICE: &str = "stm.store.TransactionStore";
+const STORE: &str = "/stm.store.TransactionStore/Store";
+const ACCESS: &str = "/stm.store.TransactionStore/Access";
+const UPDATE: &str = "/stm.store.TransactionStore/Update";
+const REFUND: &str = "/stm.store.TransactionStore/Refund";
+const REASSIGN: &str = "/stm.store.TransactionStore/Reassign";
+const RECORD: &str = "/stm.store.TransactionStore/Record";
+const STATS: &str = "/stm.store.TransactionStore/Stats";
+
+impl From<State> for pb::State {
+    fn from(state: State) -> Self {
+        match state {
+            State::Committed => pb::State::Committed,
+            State::Resolved => pb::State::Resolved,
+            State::Disputed => pb::State::Disputed,
+            State::ChargedBack => pb::State::ChargedBack,
+            State::ChargedBackFinal => pb::State::ChargedBackFinal,
+            State::Authorized => pb::State::Authorized,
+            State::Voided => pb::State::Voided,
+        }
+    }
+}
+
+fn state(state: i32) -> Result<State, String> {
+    match pb::State::try_from(state) {
+        Ok(pb::State::Committed) => Ok(State::Committed),
+        Ok(pb::State::Resolved) => Ok(State::Resolved),
+        Ok(pb::State::Disputed) => Ok(State::Disputed),
+        Ok(pb::State::ChargedBack) => Ok(State::ChargedBack),
+        Ok(pb::State::ChargedBackFinal) => Ok(State::ChargedBackFinal),
+        Ok(pb::State::Authorized) => Ok(State::Authorized),
+        Ok(pb::State::Voided) => Ok(State::Voided),
+        Ok(pb::State::Unspecified) | Err(_) => Err(format!("unknown state {}", state)),
+    }
+}
+
+fn kind(type_: i32) -> Result<TransactionKind, String> {
+    Type::try_from(type_)
+        .ok()
+        .and_then(|type_| TransactionKind::try_from(type_).ok())
+        .ok_or_else(|| format!("unknown transaction type {}", type_))
+}
+
+fn amount(amount: &str) -> Result<Decimal, String> {
+    amount
+        .parse()
+        .map_err(|_| format!("invalid amount {:?}", amount))
+}
+
+fn entry(t: DisputableTransaction, state: State) -> pb::Entry {
+    let (type_, amount) = match t.type_ {
+        DisputableType::Deposit(amount) => (Type::Deposit, amount),
+        DisputableType::Withdrawal(amount) => (Type::Withdrawal, amount),
+    };
+    pb::Entry {
+        client: t.client_id.into(),
+        tx: t.transaction_id,
+        r#type: type_ as i32,
+        amount: amount.to_string(),
+        metadata: t.metadata,
+        history: t
+            .history
+            .into_iter()
+            .map(|(kind, reason)| pb::HistoryEntry {
+                r#type: Type::from(kind) as i32,
+                reason,
+            })
+            .collect(),
+        state: pb::State::from(state) as i32,
+    }
+}
+
+impl TryFrom<pb::Entry> for (DisputableTransaction, State) {
+    type Error = String;
+    fn try_from(e: pb::Entry) -> Result<Self, String> {
+        let type_ = match Type::try_from(e.r#type) {
+            Ok(Type::Deposit) => DisputableType::Deposit(amount(&e.amount)?),
+            Ok(Type::Withdrawal) => DisputableType::Withdrawal(amount(&e.amount)?),
+            _ => return Err(format!("{} isn't a deposit or withdrawal", e.r#type)),
+        };
+        let transaction = DisputableTransaction {
+            client_id: u16::try_from(e.client)
+                .map_err(|_| format!("client {} out of range", e.client))?,
+            transaction_id: e.tx,
+            type_,
+            metadata: e.metadata,
+            history: e
+                .history
+                .into_iter()
+                .map(|h| Ok((kind(h.r#type)?, h.reason)))
+                .collect::<Result<_, String>>()?,
+        };
+        Ok((transaction, state(e.state)?))
+    }
+}
+
+impl From<StoreStats> for pb::StatsResponse {
+    fn from(stats: StoreStats) -> Self {
+        pb::StatsResponse {
+            states: stats
+                .states
+                .into_iter()
+                .map(|(state, count)| pb::StateCount {
+                    state: pb::State::from(state) as i32,
+                    count,
+                })
+                .collect(),
+            memory_bytes: stats.memory_bytes,
+            disk_bytes: stats.disk_bytes,
+            oldest_disputed: stats.oldest_disputed,
+        }
+    }
+}
+
+impl TryFrom<pb::StatsResponse> for StoreStats {
+    type Error = String;
+    fn try_from(s: pb::StatsResponse) -> Result<Self, String> {
+        Ok(StoreStats {
+            states: s
+                .states
+                .into_iter()
+                .map(|c| Ok((state(c.state)?, c.count)))
+                .collect::<Result<_, String>>()?,
+            memory_bytes: s.memory_bytes,
+            disk_bytes: s.disk_bytes,
+            oldest_disputed: s.oldest_disputed,
+        })
+    }
+}
+
+fn update_response(result: Result<Transition, UpdateFailure>) -> pb::UpdateResponse {
+    use pb::update_response::Result as R;
+    match result {
+        Ok(t) => pb::UpdateResponse {
+            result: Some(R::Entry(entry(t.transaction, t.to))),
+            previous: pb::State::from(t.from) as i32,
+        },
+        Err(e) => pb::UpdateResponse {
+            result: Some(match e {
+                UpdateFailure::NotFound => R::NotFound(true),
+                UpdateFailure::WrongState(s) => R::WrongState(pb::State::from(s) as i32),
+            }),
+            previous: pb::State::Unspecified as i32,
+        },
+    }
+}
+
+/// A transaction store kept by the `TransactionStore` service.
+///
+/// The transactions aren't listed by `entries`, as they stay with the service, so a snapshot of an engine using one
+/// only has its clients, and should be restored with the same service behind it.
+pub struct RemoteClient {
+    runtime: Runtime,
+    grpc: tonic::client::Grpc<Channel>,
+}
+
+impl RemoteClient {
+    /// Connects to the service at `url`, like `http://store.internal:50051`
+    pub fn connect(url: &str) -> io::Result<Self> {
+        let runtime = runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let endpoint = Endpoint::from_shared(url.to_owned()).map_err(io::Error::other)?;
+        let channel = runtime
+            .block_on(endpoint.connect())
+            .map_err(io::Error::other)?;
+        Ok(RemoteClient {
+            runtime,
+            grpc: tonic::client::Grpc::new(channel),
+        })
+    }
+
+    fn call<Req, Resp>(&self, method: &'static str, request: Req) -> Resp
+    where
+        Req: prost::Message + Send + Sync + 'static,
+        Resp: prost::Message + Default + Send + Sync + 'static,
+    {
+        // Cloning a channel is cheap, and lets `stats` call through `&self`
+        let mut grpc = self.grpc.clone();
+        let response = self.runtime.block_on(async {
+            grpc.ready()
+                .await
+                .map_err(|e| Status::unavailable(e.to_string()))?;
+            grpc.unary(
+                Request::new(request),
+                PathAndQuery::from_static(method),
+                ProstCodec::default(),
+            )
+            .await
+        });
+        match response {
+            Ok(response) => response.into_inner(),
+            Err(status) => panic!("transaction store {} failed: {}", method, status),
+        }
+    }
+
+    fn update_call<Req>(
+        &mut self,
+        method: &'static str,
+        request: Req,
+    ) -> Result<Transition, UpdateFailure>
+    where
+        Req: prost::Message + Send + Sync + 'static,
+    {
+        use pb::update_response::Result as R;
+        let response: pb::UpdateResponse = self.call(method, request);
+        let invalid = |e| panic!("transaction store {} answered badly: {}", method, e);
+        match response.result {
+            Some(R::Entry(e)) => {
+                let entry = <(DisputableTransaction, State)>::try_from(e);
+                let previous = response.previous;
+                match entry.and_then(|entry| Ok((entry, state(previous)?))) {
+                    Ok(((transaction, to), from)) => Ok(Transition {
+                        transaction,
+                        from,
+                        to,
+                    }),
+                    Err(e) => invalid(e),
+                }
+            }
+            Some(R::NotFound(_)) => Err(UpdateFailure::NotFound),
+            Some(R::WrongState(s)) => match state(s) {
+                Ok(s) => Err(UpdateFailure::WrongState(s)),
+                Err(e) => invalid(e),
+            },
+            None => invalid("no result".to_owned()),
+        }
+    }
+}
+
+impl Client for RemoteClient {
+    fn store(&mut self, t: DisputableTransaction) {
+        self.restore(t, State::Committed);
+    }
+
+    fn access(&mut self, id: u32) -> Option<(DisputableTransaction, State)> {
+        let response: pb::AccessResponse = self.call(ACCESS, pb::AccessRequest { tx: id });
+        response.entry.map(|e| {
+            <(DisputableTransaction, State)>::try_from(e)
+                .unwrap_or_else(|e| panic!("transaction store {} answered badly: {}", ACCESS, e))
+        })
+    }
+
+    fn update(&mut self, id: u32, state: State) -> Result<DisputableTransaction, UpdateFailure> {
+        self.transition(id, state).map(|t| t.transaction)
+    }
+
+    fn transition(&mut self, id: u32, state: State) -> Result<Transition, UpdateFailure> {
+        let request = pb::UpdateRequest {
+            tx: id,
+            state: pb::State::from(state) as i32,
+        };
+        self.update_call(UPDATE, request)
+    }
+
+    fn reassign(&mut self, from: u16, to: u16) {
+        let request = pb::ReassignRequest {
+            from: from.into(),
+            to: to.into(),
+        };
+        let pb::Empty {} = self.call(REASSIGN, request);
+    }
+
+    fn refund(&mut self, id: u32, amount: Decimal) -> Result<DisputableTransaction, UpdateFailure> {
+        let request = pb::RefundRequest {
+            tx: id,
+            amount: amount.to_string(),
+        };
+        self.update_call(REFUND, request).map(|t| t.transaction)
+    }
+
+    fn record(&mut self, id: u32, kind: TransactionKind, reason: Option<String>) {
+        let request = pb::RecordRequest {
+            tx: id,
+            r#type: Type::from(kind) as i32,
+            reason,
+        };
+        let pb::Empty {} = self.call(RECORD, request);
+    }
+
+    fn restore(&mut self, t: DisputableTransaction, state: State) {
+        let pb::Empty {} = self.call(STORE, entry(t, state));
+    }
+
+    fn entries(&self) -> Box<dyn Iterator<Item = (DisputableTransaction, State)> + '_> {
+        Box::new(std::iter::empty())
+    }
+
+    fn stats(&self) -> StoreStats {
+        let response: pb::StatsResponse = self.call(STATS, pb::Empty {});
+        StoreStats::try_from(response)
+            .unwrap_or_else(|e| panic!("transaction store {} answered badly: {}", STATS, e))
+    }
+}
+
+/// Serves the `TransactionStore` service from a store of our own whose clones share its transactions, like a
+/// `ShardedClient`, or any other behind a `Locked`. Each call gets its own clone, so calls only wait for each other as
+/// much as the store makes them.
+#[derive(Clone)]
+pub struct StoreServer<C> {
+    client: C,
+}
+
+impl<C> StoreServer<C> {
+    pub fn new(client: C) -> Self {
+        StoreServer { client }
+    }
+}
+
+impl<C> NamedService for StoreServer<C> {
+    const NAME: &'static str = SERVICE;
+}
+
+/// One of the service's methods, answered by `f` from the store. `f` fails with why the request is invalid.
+struct Method<C, F> {
+    client: C,
+    f: F,
+}
+
+impl<C, F, Req, Resp> Service<Request<Req>> for Method<C, F>
+where
+    F: FnMut(&mut C, Req) -> Result<Resp, String>,
+{
+    type Response = Response<Resp>;
+    type Error = Status;
+    type Future = std::future::Ready<Result<Response<Resp>, Status>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Status>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request<Req>) -> Self::Future {
+        std::future::ready(
+            (self.f)(&mut self.client, request.into_inner())
+                .map(Response::new)
+                .map_err(Status::invalid_argument),
+        )
+    }
+}
+
+fn unary<C, F, Req, Resp>(
+    client: C,
+    request: http::Request<BoxBody>,
+    f: F,
+) -> BoxFuture<http::Response<BoxBody>, Infallible>
+where
+    C: Send + 'static,
+    F: FnMut(&mut C, Req) -> Result<Resp, String> + Send + 'static,
+    Req: prost::Message + Default + Send + 'static,
+    Resp: prost::Message + Send + 'static,
+{
+    Box::pin(async move {
+        let mut grpc = tonic::server::Grpc::new(ProstCodec::default());
+        Ok(grpc.unary(Method { client, f }, request).await)
+    })
+}
+
+impl<C: Client + Clone + Send + 'static> Service<http::Request<BoxBody>> for StoreServer<C> {
+    type Response = http::Response<BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response, Infallible>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: http::Request<BoxBody>) -> Self::Future {
+        let client = self.client.clone();
+        match request.uri().path() {
+            STORE => unary(client, request, |client: &mut C, e: pb::Entry| {
+                let (t, state) = <(DisputableTransaction, State)>::try_from(e)?;
+                client.restore(t, state);
+                Ok(pb::Empty {})
+            }),
+            ACCESS => unary(client, request, |client: &mut C, r: pb::AccessRequest| {
+                Ok(pb::AccessResponse {
+                    entry: client.access(r.tx).map(|(t, s)| entry(t, s)),
+                })
+            }),
+            UPDATE => unary(client, request, |client: &mut C, r: pb::UpdateRequest| {
+                Ok(update_response(client.transition(r.tx, state(r.state)?)))
+            }),
+            REFUND => unary(client, request, |client: &mut C, r: pb::RefundRequest| {
+                // Only committed deposits can be refunded, and they stay committed
+                let refund = client.refund(r.tx, amount(&r.amount)?).map(|t| Transition {
+                    transaction: t,
+                    from: State::Committed,
+                    to: State::Committed,
+                });
+                Ok(update_response(refund))
+            }),
+            REASSIGN => unary(client, request, |client: &mut C, r: pb::ReassignRequest| {
+                let id =
+                    |id: u32| u16::try_from(id).map_err(|_| format!("client {} out of range", id));
+                client.reassign(id(r.from)?, id(r.to)?);
+                Ok(pb::Empty {})
+            }),
+            RECORD => unary(client, request, |client: &mut C, r: pb::RecordRequest| {
+                client.record(r.tx, kind(r.r#type)?, r.reason);
+                Ok(pb::Empty {})
+            }),
+            STATS => unary(client, request, |client: &mut C, pb::Empty {}| {
+                Ok(pb::StatsResponse::from(client.stats()))
+            }),
+            path => {
+                let status = Status::unimplemented(format!("no method {}", path));
+                Box::pin(async move { Ok(status.into_http()) })
+            }
+        }
+    }
+}
+
+/// Serves `client` as the `TransactionStore` service to connections on `listener` from a thread per core, until the
+/// process exits
+pub fn serve<C>(client: C, listener: TcpListener) -> io::Result<()>
+where
+    C: Client + Clone + Send + 'static,
+{
+    listener.set_nonblocking(true)?;
+    let runtime = runtime::Builder::new_multi_thread().enable_all().build()?;
+    runtime.block_on(async {
+        let listener = tokio::net::TcpListener::from_std(listener)?;
+        let incoming =
+            TcpIncoming::from_listener(listener, true, None).map_err(io::Error::other)?;
+        Server::builder()
+            .add_service(StoreServer::new(client))
+            .serve_with_incoming(incoming)
+            .await
+            .map_err(io::Error::other)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::client::ClientOutput;
+    use crate::engine::Engine;
+    use crate::transaction::read_from_csv_reader;
+    use crate::transaction_set::{MemoryClient, ShardedClient};
+
+    #[test]
+    fn remote_store() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        std::thread::spawn(move || serve(ShardedClient::new(4), listener));
+
+        let data = "\
+type,       client,  tx, amount, reason
+deposit,         1,   1,   10.0,
+deposit,         1,   2,    5.0,
+withdrawal,      2,   3,    1.0,
+deposit,         2,   4,    3.0,
+dispute,         1,   1,       , stolen card
+resolve,         1,   1,       ,
+dispute,         1,   2,       ,
+chargeback,      1,   2,       ,
+refund,          2,   4,    1.0,
+dispute,         2,   4,       ,
+";
+        let mut remote = Engine::new(RemoteClient::connect(&url).unwrap());
+        let mut local = Engine::new(MemoryClient::default());
+        for transaction in read_from_csv_reader(data.as_bytes()) {
+            let transaction = transaction.unwrap();
+            assert_eq!(
+                remote.process(transaction.clone()),
+                local.process(transaction)
+            );
+        }
+        let sorted = |mut report: Vec<ClientOutput>| {
+            report.sort_by_key(|client| client.client);
+            report
+        };
+        assert_eq!(
+            sorted(remote.report().collect()),
+            sorted(local.report().collect())
+        );
+        assert_eq!(remote.store_stats(), local.store_stats());
+        assert_eq!(remote.store_stats().oldest_disputed, Some(4));
+
+        let mut store = RemoteClient::connect(&url).unwrap();
+        let (deposit, state) = store.access(1).unwrap();
+        assert_eq!(state, State::Committed);
+        assert_eq!(
+            deposit.history,
+            [
+                (TransactionKind::Dispute, Some("stolen card".to_owned())),
+                (TransactionKind::Resolve, None)
+            ]
+        );
+        assert_eq!(
+            store.update(2, State::Disputed),
+            Err(UpdateFailure::WrongState(State::ChargedBackFinal))
+        );
+        assert_eq!(
+            store.update(99, State::Disputed),
+            Err(UpdateFailure::NotFound)
+        );
+        let transition = store.transition(1, State::Disputed).unwrap();
+        assert_eq!(
+            (transition.from, transition.to),
+            (State::Committed, State::Disputed)
+        );
+        assert_eq!(store.access(99), None);
+    }
+}
@@ -0,0 +1,124 @@
+//! Streaming input from `http://` and `https://` URLs.
+//!
+//! The body is read as it arrives rather than downloaded first. If the connection drops part way through, the rest is
+//! fetched with a range request starting where reading stopped, a few times over, so long as the server supports them.
+
+use std::io::{self, Read};
+
+/// How many times a dropped download is resumed before giving up
+const RETRIES: u32 = 3;
+
+pub fn is_url(path: &str) -> bool {
+    path.starts_with("http://") || path.starts_with("https://")
+}
+
+pub struct UrlReader {
+    url: String,
+    body: Box<dyn Read + Send + Sync>,
+    /// The length of the whole body, if the server gave one
+    len: Option<u64>,
+    /// How much of the body has been read
+    offset: u64,
+    retries: u32,
+}
+
+impl UrlReader {
+    pub fn open(url: &str) -> io::Result<Self> {
+        let response = ureq::get(url).call().map_err(error)?;
+        Ok(UrlReader {
+            url: url.to_owned(),
+            len: response
+                .header("Content-Length")
+                .and_then(|len| len.parse().ok()),
+            body: response.into_reader(),
+            offset: 0,
+            retries: RETRIES,
+        })
+    }
+
+    /// Fetches the rest of the body, from where reading stopped
+    fn resume(&mut self) -> io::Result<()> {
+        let response = ureq::get(&self.url)
+            .set("Range", &format!("bytes={}-", self.offset))
+            .call()
+            .map_err(error)?;
+        // Anything else would be the whole body again
+        if response.status() != 206 {
+            return Err(io::Error::other(format!(
+                "{} can't resume downloads",
+                self.url
+            )));
+        }
+        self.body = response.into_reader();
+        Ok(())
+    }
+}
+
+impl Read for UrlReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let dropped = match self.body.read(buf) {
+                Ok(0) if !buf.is_empty() && self.len.is_some_and(|len| self.offset < len) => {
+                    io::Error::from(io::ErrorKind::UnexpectedEof)
+                }
+                Ok(n) => {
+                    self.offset += n as u64;
+                    return Ok(n);
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => e,
+            };
+            if self.retries == 0 {
+                return Err(dropped);
+            }
+            self.retries -= 1;
+            self.resume()?;
+        }
+    }
+}
+
+fn error(e: ureq::Error) -> io::Error {
+    io::Error::other(e)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn resume_dropped_download() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/transactions.csv", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let mut requests = Vec::new();
+            for response in [
+                // Drops the connection halfway through the body
+                "HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n01234",
+                "HTTP/1.1 206 Partial Content\r\nContent-Length: 5\r\nContent-Range: bytes 5-9/10\r\n\r\n56789",
+            ] {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = Vec::new();
+                while !request.ends_with(b"\r\n\r\n") {
+                    let mut byte = [0];
+                    stream.read_exact(&mut byte).unwrap();
+                    request.push(byte[0]);
+                }
+                requests.push(String::from_utf8(request).unwrap());
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+            requests
+        });
+
+        let mut body = String::new();
+        UrlReader::open(&url)
+            .unwrap()
+            .read_to_string(&mut body)
+            .unwrap();
+        assert_eq!(body, "0123456789");
+        let requests = server.join().unwrap();
+        assert!(requests[1].to_lowercase().contains("range: bytes=5-"));
+    }
+}
@@ -0,0 +1,103 @@
+//! Recognising resubmissions of a transaction by an idempotency key, so an upstream which retries a submission it never
+//! heard back about gets the original outcome, instead of the transaction being applied twice.
+//!
+//! Keys are given in an `idempotency_key` column, and only the most recent are remembered, in a window of a fixed
+//! number of keys. Transactions without a key are never treated as retries.
+
+use std::collections::{HashMap, VecDeque};
+
+use crate::engine::Rejection;
+use crate::posting::Postings;
+use crate::transaction::Transaction;
+
+/// The column a transaction's idempotency key is read from
+pub const COLUMN: &str = "idempotency_key";
+
+/// What came of a transaction's first submission
+pub type Outcome = Result<Postings, Rejection>;
+
+pub struct Deduplication {
+    window: usize,
+    outcomes: HashMap<String, Outcome>,
+    // Oldest first, to forget once there are more than `window`
+    keys: VecDeque<String>,
+}
+
+impl Deduplication {
+    /// Remembers the outcomes of the last `window` keys
+    pub fn new(window: usize) -> Self {
+        Deduplication {
+            window,
+            outcomes: HashMap::new(),
+            keys: VecDeque::new(),
+        }
+    }
+
+    /// The transaction's idempotency key, if it has one
+    pub fn key(transaction: &Transaction) -> Option<&str> {
+        transaction.metadata.get(COLUMN).map(String::as_str)
+    }
+
+    /// The outcome of the first submission with the transaction's key, if it's a retry of one still in the window
+    pub fn outcome(&self, transaction: &Transaction) -> Option<&Outcome> {
+        self.outcomes.get(Self::key(transaction)?)
+    }
+
+    /// Remembers the outcome of the first submission with `key`, forgetting the oldest key if the window is full.
+    /// Rate limiting isn't remembered, as it's what a retry is meant to get past.
+    pub fn record(&mut self, key: String, outcome: &Outcome) {
+        if let Err(Rejection::RateLimited { .. }) = outcome {
+            return;
+        }
+        if self.outcomes.insert(key.clone(), outcome.clone()).is_none() {
+            self.keys.push_back(key);
+        }
+        while self.keys.len() > self.window {
+            if let Some(oldest) = self.keys.pop_front() {
+                self.outcomes.remove(&oldest);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::decimal::Decimal;
+    use crate::transaction::{DisputableType::*, Type::*};
+
+    #[test]
+    fn window() {
+        let transaction = |key: &str| Transaction {
+            client_id: 1,
+            transaction_id: 1,
+            type_: Disputable(Deposit(Decimal::new(1, 0))),
+            metadata: [(COLUMN.to_owned(), key.to_owned())].into(),
+        };
+        let mut deduplication = Deduplication::new(2);
+        deduplication.record("a".to_owned(), &Ok(Postings::default()));
+        deduplication.record(
+            "b".to_owned(),
+            &Err(Rejection::NotFound(crate::engine::Step::Dispute)),
+        );
+        deduplication.record(
+            "c".to_owned(),
+            &Err(Rejection::RateLimited { client_id: 1 }),
+        );
+        assert_eq!(
+            deduplication.outcome(&transaction("a")),
+            Some(&Ok(Postings::default()))
+        );
+        assert!(deduplication.outcome(&transaction("b")).unwrap().is_err());
+        assert_eq!(deduplication.outcome(&transaction("c")), None);
+
+        // The oldest key is forgotten, so it's no longer a retry
+        deduplication.record("d".to_owned(), &Ok(Postings::default()));
+        assert_eq!(deduplication.outcome(&transaction("a")), None);
+        assert!(deduplication.outcome(&transaction("d")).is_some());
+
+        let mut unkeyed = transaction("a");
+        unkeyed.metadata.clear();
+        assert_eq!(deduplication.outcome(&unkeyed), None);
+    }
+}
@@ -0,0 +1,108 @@
+//! Accruing interest on client balances over a period.
+//!
+//! Interest is simple, at an annual rate prorated by the length of the period in seconds, and is paid on the client's
+//! reported total, so held funds earn interest as well as available ones. It's paid out as `interest` transactions,
+//! which the engine applies like deposits but which can't be disputed.
+
+use std::collections::HashMap;
+
+use crate::client::ClientOutput;
+use crate::decimal::Decimal;
+use crate::transaction::{Transaction, Type};
+
+const SECONDS_PER_YEAR: u64 = 365 * 24 * 60 * 60;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Accrual {
+    /// e.g. `4.5` for 4.5% a year
+    pub rate: Decimal,
+}
+
+impl Accrual {
+    pub fn new(rate: Decimal) -> Self {
+        Accrual { rate }
+    }
+
+    /// The interest on `balance` between two unix timestamps, truncated to our precision, or `None` if it overflows
+    pub fn interest(&self, balance: &Decimal, from: u64, to: u64) -> Option<Decimal> {
+        balance
+            .checked_percent(&self.rate)?
+            .checked_ratio(to.saturating_sub(from), SECONDS_PER_YEAR)
+    }
+
+    /// An `interest` transaction for every client which earned any, in client order, numbered from `first_id`
+    pub fn transactions<I: IntoIterator<Item = ClientOutput>>(
+        &self,
+        clients: I,
+        from: u64,
+        to: u64,
+        first_id: u32,
+    ) -> Vec<Transaction> {
+        let mut clients = clients.into_iter().collect::<Vec<_>>();
+        clients.sort_by_key(|c| c.client);
+        clients
+            .into_iter()
+            .filter_map(|c| {
+                let interest = self
+                    .interest(&c.total, from, to)
+                    .filter(|i| *i != Decimal::zero())?;
+                Some((c.client, interest))
+            })
+            .zip(first_id..)
+            .map(|((client_id, interest), transaction_id)| Transaction {
+                client_id,
+                transaction_id,
+                type_: Type::Interest(interest),
+                metadata: HashMap::new(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn accrue_interest() {
+        let client = |client, available: u64, held: u64| ClientOutput {
+            client,
+            available: Decimal::new(available, 0),
+            held: Decimal::new(held, 0),
+            total: Decimal::new(available + held, 0),
+            locked: false,
+            overdraft: Decimal::zero(),
+            closed: false,
+            pending: Decimal::zero(),
+        };
+        let accrual = Accrual::new(Decimal::new(5, 0));
+        // Half a year
+        let (from, to) = (1_600_000_000, 1_600_000_000 + SECONDS_PER_YEAR / 2);
+        assert_eq!(
+            accrual.transactions(
+                vec![client(2, 0, 0), client(3, 100, 20), client(1, 40, 0)],
+                from,
+                to,
+                1000
+            ),
+            vec![
+                Transaction {
+                    client_id: 1,
+                    transaction_id: 1000,
+                    type_: Type::Interest(Decimal::new(1, 0)),
+                    metadata: HashMap::new(),
+                },
+                Transaction {
+                    client_id: 3,
+                    transaction_id: 1001,
+                    type_: Type::Interest(Decimal::new(3, 0)),
+                    metadata: HashMap::new(),
+                },
+            ]
+        );
+        assert_eq!(
+            accrual.interest(&Decimal::new(100, 0), to, from),
+            Some(Decimal::zero())
+        );
+    }
+}
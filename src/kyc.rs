@@ -0,0 +1,182 @@
+//! Gating what clients can do on their know-your-customer status, so onboarding rules are enforced by the engine.
+//!
+//! Unverified clients can't withdraw or authorize, and may only deposit up to a cap on their total. Suspended clients
+//! can't move money at all, though disputes, fees, interest and adjustments still reach them like a locked client.
+//! Statuses are read from a file, and changed by `status` transactions giving the new status as their reason.
+
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Read;
+use std::str::FromStr;
+
+use crate::decimal::Decimal;
+use crate::engine::Rejection;
+use crate::transaction::{DisputableType, Transaction, Type};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Unverified,
+    #[default]
+    Verified,
+    Suspended,
+}
+
+impl Status {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Status::Unverified => "unverified",
+            Status::Verified => "verified",
+            Status::Suspended => "suspended",
+        }
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug)]
+pub struct UnknownStatus(String);
+
+impl fmt::Display for UnknownStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "unknown status {:?}, expected unverified, verified or suspended",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for UnknownStatus {}
+
+impl FromStr for Status {
+    type Err = UnknownStatus;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "unverified" => Ok(Status::Unverified),
+            "verified" => Ok(Status::Verified),
+            "suspended" => Ok(Status::Suspended),
+            _ => Err(UnknownStatus(s.to_owned())),
+        }
+    }
+}
+
+/// Each client's status, and the cap on unverified clients' deposits
+#[derive(Clone, Debug, Default)]
+pub struct Kyc {
+    /// The status of clients without one of their own
+    default: Status,
+    statuses: HashMap<u16, Status>,
+    /// The most an unverified client's total may reach by depositing
+    deposit_cap: Option<Decimal>,
+}
+
+impl Kyc {
+    pub fn new(default: Status) -> Self {
+        Kyc {
+            default,
+            ..Kyc::default()
+        }
+    }
+
+    pub fn with(mut self, client: u16, status: Status) -> Self {
+        self.set(client, status);
+        self
+    }
+
+    /// Only lets unverified clients deposit while their total stays within `cap`
+    pub fn capping_deposits(mut self, cap: Decimal) -> Self {
+        self.deposit_cap = Some(cap);
+        self
+    }
+
+    /// Reads `client,status` rows overriding `default`
+    pub fn from_csv_reader<R: Read>(rdr: R, default: Status) -> csv::Result<Self> {
+        #[derive(Deserialize)]
+        struct Row {
+            client: u16,
+            status: Status,
+        }
+
+        let mut kyc = Kyc::new(default);
+        for row in csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(rdr)
+            .into_deserialize()
+        {
+            let Row { client, status } = row?;
+            kyc = kyc.with(client, status);
+        }
+        Ok(kyc)
+    }
+
+    pub fn status(&self, client: u16) -> Status {
+        *self.statuses.get(&client).unwrap_or(&self.default)
+    }
+
+    pub(crate) fn set(&mut self, client: u16, status: Status) {
+        self.statuses.insert(client, status);
+    }
+
+    /// Rejects the transaction if the client's status doesn't allow it, given the client's `total` before it
+    pub(crate) fn check(&self, transaction: &Transaction, total: Decimal) -> Result<(), Rejection> {
+        let client_id = transaction.client_id;
+        let kind = transaction.type_.kind();
+        match (self.status(client_id), &transaction.type_) {
+            (Status::Verified, _) => Ok(()),
+            (
+                Status::Suspended,
+                Type::Disputable(_)
+                | Type::Authorize(_)
+                | Type::Capture
+                | Type::Refund(_)
+                | Type::Close,
+            ) => Err(Rejection::Suspended { client_id, kind }),
+            (
+                Status::Unverified,
+                Type::Disputable(DisputableType::Withdrawal(_)) | Type::Authorize(_),
+            ) => Err(Rejection::Unverified { client_id, kind }),
+            (Status::Unverified, Type::Disputable(DisputableType::Deposit(amount))) => {
+                match &self.deposit_cap {
+                    Some(cap) if total + amount.clone() > *cap => {
+                        Err(Rejection::DepositCapExceeded {
+                            client_id,
+                            requested: amount.clone(),
+                            cap: cap.clone(),
+                        })
+                    }
+                    _ => Ok(()),
+                }
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn read_statuses() {
+        let kyc = Kyc::from_csv_reader(
+            "client, status\n1, verified\n2, suspended\n".as_bytes(),
+            Status::Unverified,
+        )
+        .unwrap();
+        assert_eq!(
+            [1, 2, 3].map(|client| kyc.status(client)),
+            [Status::Verified, Status::Suspended, Status::Unverified]
+        );
+        assert!(
+            Kyc::from_csv_reader("client,status\n1,pending\n".as_bytes(), Status::Verified)
+                .is_err()
+        );
+        assert_eq!("suspended".parse::<Status>().unwrap(), Status::Suspended);
+    }
+}
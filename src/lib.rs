@@ -0,0 +1,64 @@
+pub use stm_core::{client, decimal, posting};
+
+pub mod alerts;
+pub mod aml;
+#[cfg(feature = "arrow")]
+pub mod arrow;
+#[cfg(feature = "avro")]
+pub mod avro;
+#[cfg(feature = "camt053")]
+pub mod camt;
+pub mod chain;
+#[cfg(feature = "object-store")]
+pub mod cloud;
+pub mod compression;
+pub mod conservation;
+pub mod dead_letter;
+pub mod digits;
+pub mod engine;
+pub mod export;
+pub mod fast_parse;
+pub mod fees;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod generate;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+#[cfg(feature = "http")]
+pub mod http;
+pub mod idempotency;
+pub mod interest;
+pub mod kyc;
+pub mod limits;
+pub mod lock_policy;
+#[cfg(feature = "mmap")]
+pub mod mmap;
+pub mod observer;
+pub mod overdraft;
+pub mod period;
+#[cfg(feature = "protobuf")]
+pub mod protobuf;
+pub mod query;
+pub mod queue;
+pub mod rate_limit;
+pub mod record;
+pub mod report;
+pub mod risk;
+pub mod schedule;
+#[cfg(feature = "signatures")]
+pub mod signature;
+pub mod statement;
+pub mod stats;
+pub mod tenant;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod tiers;
+pub mod transaction;
+pub mod transaction_set;
+#[cfg(feature = "tui")]
+pub mod tui;
+pub mod validate;
+pub mod velocity;
+pub mod view;
+#[cfg(feature = "wasm")]
+pub mod wasm;
@@ -0,0 +1,115 @@
+//! Limits on the amount of each kind of transaction, so a fat-fingered amount is rejected before it reaches a client's
+//! balances
+
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Read;
+
+use crate::decimal::Decimal;
+use crate::transaction::TransactionKind;
+
+/// The smallest and largest amounts allowed, either of which may be left unbounded
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Limit {
+    pub min: Option<Decimal>,
+    pub max: Option<Decimal>,
+}
+
+impl Limit {
+    pub fn allows(&self, amount: &Decimal) -> bool {
+        self.min.as_ref().is_none_or(|min| amount >= min)
+            && self.max.as_ref().is_none_or(|max| amount <= max)
+    }
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.min, &self.max) {
+            (Some(min), Some(max)) => write!(f, "between {} and {}", min, max),
+            (Some(min), None) => write!(f, "at least {}", min),
+            (None, Some(max)) => write!(f, "at most {}", max),
+            (None, None) => write!(f, "any amount"),
+        }
+    }
+}
+
+/// The limit for each kind of transaction.
+///
+/// Only transactions which give an amount are limited, so disputes, resolves, chargebacks, captures and voids aren't.
+#[derive(Clone, Debug, Default)]
+pub struct AmountLimits {
+    limits: HashMap<TransactionKind, Limit>,
+}
+
+impl AmountLimits {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, kind: TransactionKind, limit: Limit) -> Self {
+        self.limits.insert(kind, limit);
+        self
+    }
+
+    /// Reads `type,min,max` rows, where either amount may be left empty
+    pub fn from_csv_reader<R: Read>(rdr: R) -> csv::Result<Self> {
+        #[derive(Deserialize)]
+        struct Row {
+            #[serde(rename = "type")]
+            kind: TransactionKind,
+            min: Option<Decimal>,
+            max: Option<Decimal>,
+        }
+
+        let mut limits = AmountLimits::new();
+        for row in csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(rdr)
+            .into_deserialize()
+        {
+            let row: Row = row?;
+            limits = limits.with(
+                row.kind,
+                Limit {
+                    min: row.min,
+                    max: row.max,
+                },
+            );
+        }
+        Ok(limits)
+    }
+
+    pub fn get(&self, kind: TransactionKind) -> Option<&Limit> {
+        self.limits.get(&kind)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn read_limits() {
+        let limits = AmountLimits::from_csv_reader(
+            "\
+type,        min,      max
+deposit,    0.01, 100000.0
+withdrawal,     ,   5000.0
+"
+            .as_bytes(),
+        )
+        .unwrap();
+        let allows = |kind, amount| limits.get(kind).is_none_or(|limit| limit.allows(&amount));
+        assert!(allows(TransactionKind::Deposit, Decimal::new(100_000, 0)));
+        assert!(!allows(TransactionKind::Deposit, Decimal::new(100_001, 0)));
+        assert!(!allows(TransactionKind::Deposit, Decimal::zero()));
+        assert!(allows(TransactionKind::Withdrawal, Decimal::zero()));
+        assert!(!allows(TransactionKind::Withdrawal, Decimal::new(5_001, 0)));
+        assert!(allows(TransactionKind::Fee, Decimal::new(1_000_000, 0)));
+        assert_eq!(
+            limits.get(TransactionKind::Deposit).unwrap().to_string(),
+            "between 0.0100 and 100000.0000"
+        );
+    }
+}
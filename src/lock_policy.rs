@@ -0,0 +1,106 @@
+//! Locking clients automatically once they've had too many disputes or chargebacks.
+//!
+//! Without a window the thresholds are on the client's lifetime counts. With one, disputes and chargebacks are
+//! timestamped by a clock as they're applied, the system clock by default, and only those within the window count.
+//! Chargebacks already lock the client they're applied to, so reaching a chargeback threshold only raises the event.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::rc::Rc;
+
+use crate::client::Client;
+use crate::transaction::TransactionKind;
+use crate::velocity::now;
+
+/// A client reaching one of the policy's thresholds
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AutoLock {
+    pub client_id: u16,
+    pub kind: TransactionKind,
+    pub count: u32,
+}
+
+impl fmt::Display for AutoLock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "client {} locked automatically after {} {}s",
+            self.client_id, self.count, self.kind
+        )
+    }
+}
+
+#[derive(Clone)]
+pub struct LockPolicy {
+    pub disputes: Option<u32>,
+    pub chargebacks: Option<u32>,
+    /// In seconds
+    pub window: Option<u64>,
+    clock: Rc<dyn Fn() -> u64>,
+    /// Each client's recent disputes and chargebacks, oldest first
+    history: HashMap<u16, VecDeque<(u64, TransactionKind)>>,
+}
+
+impl LockPolicy {
+    /// Locks clients after `disputes` disputes or `chargebacks` chargebacks, ever
+    pub fn new(disputes: Option<u32>, chargebacks: Option<u32>) -> Self {
+        LockPolicy {
+            disputes,
+            chargebacks,
+            window: None,
+            clock: Rc::new(now),
+            history: HashMap::new(),
+        }
+    }
+
+    /// Only counts disputes and chargebacks within `window` seconds
+    pub fn within(mut self, window: u64) -> Self {
+        self.window = Some(window);
+        self
+    }
+
+    /// Timestamps disputes and chargebacks with `clock`, in unix seconds, instead of the system clock
+    pub fn with_clock<C: Fn() -> u64 + 'static>(mut self, clock: C) -> Self {
+        self.clock = Rc::new(clock);
+        self
+    }
+
+    /// Records a dispute or chargeback which has just been applied to `client`, locking it if that reaches a
+    /// threshold
+    pub(crate) fn record(
+        &mut self,
+        kind: TransactionKind,
+        client: &mut Client,
+    ) -> Option<AutoLock> {
+        let (threshold, lifetime) = match kind {
+            TransactionKind::Dispute => (self.disputes?, client.disputes()),
+            TransactionKind::Chargeback => (self.chargebacks?, client.chargebacks()),
+            _ => return None,
+        };
+        let count = match self.window {
+            None => lifetime,
+            Some(window) => {
+                let now = (self.clock)();
+                let recent = self.history.entry(client.id()).or_default();
+                while recent
+                    .front()
+                    .is_some_and(|&(at, _)| now.saturating_sub(at) >= window)
+                {
+                    recent.pop_front();
+                }
+                recent.push_back((now, kind));
+                recent.iter().filter(|&&(_, k)| k == kind).count() as u32
+            }
+        };
+        // Only raised as the threshold is reached, not again for every one after
+        if count != threshold {
+            return None;
+        }
+        client.lock();
+        Some(AutoLock {
+            client_id: client.id(),
+            kind,
+            count,
+        })
+    }
+}
@@ -1,120 +1,700 @@
-use cached::SizedCache;
-use client::Client;
-use std::collections::HashMap;
-use std::env::args_os;
-use transaction::{
-    read_from_csv_file, DisputableTransaction, DisputableType::*, Transaction, Type::*,
+use signal_hook::consts::{SIGINT, SIGTERM};
+use simple_transaction_manager::alerts::Thresholds;
+#[cfg(feature = "avro")]
+use simple_transaction_manager::avro;
+#[cfg(feature = "camt053")]
+use simple_transaction_manager::camt;
+use simple_transaction_manager::client::{Client, ClientRecord};
+#[cfg(feature = "object-store")]
+use simple_transaction_manager::cloud;
+use simple_transaction_manager::compression::Compression;
+use simple_transaction_manager::decimal::{Decimal, Locale};
+use simple_transaction_manager::engine::Engine;
+use simple_transaction_manager::fees::{Fee, FeeSchedule};
+#[cfg(feature = "grpc")]
+use simple_transaction_manager::grpc;
+#[cfg(feature = "http")]
+use simple_transaction_manager::http;
+use simple_transaction_manager::kyc::{Kyc, Status};
+use simple_transaction_manager::limits::AmountLimits;
+use simple_transaction_manager::lock_policy::LockPolicy;
+#[cfg(feature = "mmap")]
+use simple_transaction_manager::mmap;
+use simple_transaction_manager::overdraft::OverdraftLimits;
+#[cfg(feature = "protobuf")]
+use simple_transaction_manager::protobuf;
+use simple_transaction_manager::query;
+use simple_transaction_manager::queue::{self, Overflow};
+use simple_transaction_manager::rate_limit::RateLimit;
+use simple_transaction_manager::tiers::Tiers;
+use simple_transaction_manager::transaction::{
+    self, read_from_csv_reader_with_metadata, AmountRules, CsvOptions, Dialect, Metadata,
+    Transaction,
 };
-use transaction_set::{
-    CachedClient, Client as TransactionSetClient, MemoryClient, State::*, UpdateFailure::*,
+use simple_transaction_manager::transaction_set::{
+    self as transaction_set, CachedClient, Client as TransactionSetClient, FileClient, LruCache,
+    MemoryClient,
 };
-
-mod client;
-pub mod decimal;
-mod transaction;
-mod transaction_set;
+use simple_transaction_manager::velocity::{self, Velocity};
+use simple_transaction_manager::{
+    aml, chain, dead_letter, export, fast_parse, generate, interest, record, report, schedule,
+    statement, stats, tenant, validate,
+};
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::env::args_os;
+use std::error::Error;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, OnceLock};
+use std::thread;
+use std::time::Instant;
 
 const CACHE_SIZE: usize = 10;
+/// How many shards `serve-store` splits its transactions across
+#[cfg(feature = "grpc")]
+const STORE_SHARDS: usize = 64;
+/// How many transactions are read between views of the engine published to `--query`
+const QUERY_REFRESH: u64 = 1000;
 
-pub fn process_transaction<T: TransactionSetClient>(
-    transaction: Transaction,
-    clients: &mut HashMap<u16, Client>,
-    tx_record: &mut T,
-) {
-    let client = clients
-        .entry(transaction.client_id)
-        .or_insert(Client::new(transaction.client_id));
-    match transaction.type_ {
-        Disputable(Deposit(ref deposit)) => {
-            client.deposit(deposit.clone());
-            tx_record.store(DisputableTransaction {
-                transaction_id: transaction.transaction_id,
-                client_id: transaction.client_id,
-                type_: Deposit(deposit.clone()),
-            });
-        }
-        Disputable(Withdrawal(ref withdrawal)) => match client.withdraw(withdrawal.clone()) {
-            Err(Some(present)) => eprintln!(
-                "tx {}: Failed to withdraw {} from client {}. Only {} funds present",
-                transaction.transaction_id, withdrawal, transaction.client_id, present
-            ),
-            Err(None) => eprintln!(
-                "tx {}: Failed to withdraw {} from client {}. Client frozen.",
-                transaction.transaction_id, withdrawal, transaction.client_id
-            ),
-            Ok(()) => tx_record.store(DisputableTransaction {
-                transaction_id: transaction.transaction_id,
-                client_id: transaction.client_id,
-                type_: Withdrawal(withdrawal.clone()),
-            }),
-        },
-        Dispute => match tx_record.update(transaction.transaction_id, Disputed) {
-            Err(NotFound) => eprintln!(
-                "tx {}: Failed to dispute transaction: Not found.",
-                transaction.transaction_id
-            ),
-            Err(WrongState(s)) => eprintln!(
-                "tx {}: Failed to dispute transaction: Wrong state {:?}.",
-                transaction.transaction_id, s
-            ),
-            Ok(disputed) => match disputed.type_ {
-                Deposit(value) => client.dispute_deposit(value),
-                Withdrawal(value) => client.dispute_withdrawal(value),
-            },
-        },
-        Resolve => match tx_record.update(transaction.transaction_id, Resolved) {
-            Err(NotFound) => eprintln!(
-                "tx {}: Failed to resolve transaction: Not found.",
-                transaction.transaction_id
-            ),
-            Err(WrongState(s)) => eprintln!(
-                "tx {}: Failed to resolve transaction: Wrong state {:?}.",
-                transaction.transaction_id, s
-            ),
-            Ok(disputed) => match match disputed.type_ {
-                Deposit(value) => (value.clone(), client.resolve_deposit(value)),
-                Withdrawal(value) => (value.clone(), client.resolve_withdrawal(value)),
-            } {
-                (_, Ok(_)) => {
-                    // TODO: error handle?
-                    let _ = tx_record.update(transaction.transaction_id, Committed);
+type TxRecord = CachedClient<MemoryClient, LruCache>;
+
+/// The transaction file format
+#[derive(Clone, Copy, Default)]
+enum Format {
+    #[default]
+    Csv,
+    /// Needs the `avro` feature, and can only be read
+    Avro,
+    /// Length delimited, needs the `protobuf` feature
+    Protobuf,
+    /// Bank statements, for a single `--client`. Can only be read
+    Ofx,
+    Qif,
+    /// Needs the `camt053` feature
+    Camt053,
+}
+
+impl FromStr for Format {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Format::Csv),
+            "avro" => Ok(Format::Avro),
+            "protobuf" => Ok(Format::Protobuf),
+            "ofx" => Ok(Format::Ofx),
+            "qif" => Ok(Format::Qif),
+            "camt053" => Ok(Format::Camt053),
+            _ => Err(format!("unknown format {:?}", s)),
+        }
+    }
+}
+
+/// For formats left out of this build
+#[allow(dead_code)]
+fn unsupported(what: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("built without {} support", what),
+    )
+}
+
+/// Each transaction comes with any columns we don't understand, which only CSV files can have
+type Transactions =
+    Box<dyn Iterator<Item = Result<(Transaction, Metadata), Box<dyn Error + Send + Sync>>>>;
+
+/// How the default command processes its input
+#[derive(Clone, Default)]
+struct Options {
+    format: Format,
+    csv: CsvOptions,
+    /// Parse CSV rows straight from their bytes, for large clean files
+    fast_parse: bool,
+    /// Parse a memory mapped CSV file on this many threads, or one per CPU if zero
+    parse_threads: Option<usize>,
+    /// Guessed from the file extension if not given
+    compression: Option<Compression>,
+    /// Whose statement an OFX or QIF file is
+    client: Option<u16>,
+    check_conservation: bool,
+    /// The fee schedule to charge, if any
+    fees: Option<OsString>,
+    /// The flat fee charged for each chargeback, even if it overdraws the client
+    chargeback_fee: Option<Decimal>,
+    /// The default overdraft limit, zero if not given
+    overdraft_limit: Option<Decimal>,
+    /// Per-client overdraft limits
+    overdraft_limits: Option<OsString>,
+    /// Report disputed withdrawals in held funds, instead of reserving them
+    hold_disputed_withdrawals: bool,
+    /// The tiers' limits and policies, and which tier each client is in
+    tiers: Option<OsString>,
+    client_tiers: Option<OsString>,
+    /// Each client's KYC status, the status of any other client, verified if not given, and the most an unverified
+    /// client may hold
+    kyc: Option<OsString>,
+    kyc_default: Option<Status>,
+    unverified_deposit_cap: Option<Decimal>,
+    /// Velocity limits on each client's deposits and withdrawals
+    velocity: Option<OsString>,
+    /// How many disputes or chargebacks lock a client automatically
+    lock_disputes: Option<u32>,
+    lock_chargebacks: Option<u32>,
+    /// The window they're counted in, in seconds, or forever if not given
+    lock_window: Option<u64>,
+    /// The smallest and largest amount of each kind of transaction
+    amount_limits: Option<OsString>,
+    /// How many idempotency keys to remember, if retries are deduplicated
+    idempotency_window: Option<usize>,
+    /// Transactions a second allowed per client
+    rate_limit: Option<f64>,
+    /// How many a client can make at once, the rate rounded up if not given
+    rate_burst: Option<u32>,
+    /// Where to report large deposits and withdrawals
+    aml_report: Option<OsString>,
+    /// What counts as large, 10,000 if not given
+    aml_threshold: Option<Decimal>,
+    /// The balances to alert on clients crossing, and where to report the alerts as well as `STDERR`
+    alert_thresholds: Option<Thresholds>,
+    alerts: Option<OsString>,
+    /// Where to write the hash chained ledger of applied transactions
+    ledger: Option<OsString>,
+    /// Where to write a snapshot of the engine once processing stops
+    checkpoint: Option<OsString>,
+    /// A snapshot to start from, instead of a seed
+    resume: Option<OsString>,
+    /// Where to write rejected transactions, for retrying later
+    dead_letters: Option<OsString>,
+    /// Where to write a summary of the run, with the busiest clients
+    stats: Option<OsString>,
+    /// How many clients to list in each breakdown of the summary
+    stats_top: usize,
+    /// Add each client's lifetime statistics to the report
+    lifetime_stats: bool,
+    /// Add the overdraft, closed and pending columns to the report
+    all_columns: bool,
+    /// Only report the clients whose accounts changed during the run
+    output_delta: bool,
+    /// The address to serve a read-only view of the engine on while processing
+    query: Option<OsString>,
+    /// Read transactions on another thread, queueing up to this many for processing
+    queue: Option<usize>,
+    /// What to do with transactions read while the queue is full
+    overflow: Overflow,
+    /// Standing orders to make before the file's transactions
+    schedule: Option<OsString>,
+    /// The unix timestamps between which standing orders are made
+    period: (u64, u64),
+    /// The transaction id of the first standing order made
+    first_id: u32,
+    /// Public keys trusted to sign transactions, if signatures are required
+    #[cfg(feature = "signatures")]
+    trusted_keys: Option<OsString>,
+    #[cfg(feature = "tui")]
+    tui: bool,
+}
+
+/// Opens a local file, or a URL if built with the `http` or `object-store` feature
+fn open(path: &Path) -> io::Result<Box<dyn Read>> {
+    #[cfg(feature = "http")]
+    if let Some(url) = path.to_str().filter(|path| http::is_url(path)) {
+        return Ok(Box::new(http::UrlReader::open(url)?));
+    }
+    #[cfg(feature = "object-store")]
+    if let Some(url) = path.to_str().filter(|path| cloud::is_url(path)) {
+        return Ok(Box::new(cloud::ObjectReader::open(url)?));
+    }
+    Ok(Box::new(File::open(path)?))
+}
+
+fn read_transactions<P: AsRef<Path>>(path: P, options: &Options) -> io::Result<Transactions> {
+    let compression = options
+        .compression
+        .unwrap_or_else(|| Compression::detect(&path));
+    let rdr = compression.decoder(open(path.as_ref())?)?;
+    let scheduled = match &options.schedule {
+        Some(schedule) => {
+            let (from, to) = options.period;
+            schedule::Schedule::from_csv_reader(File::open(schedule)?)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+                .transactions(from, to, options.first_id)
+        }
+        None => Vec::new(),
+    };
+    let transactions: Transactions = match options.format {
+        Format::Csv if options.fast_parse || options.parse_threads.is_some() => {
+            if options.csv.dialect != Dialect::Standard || options.csv.lenient_amounts.is_some() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "--fast-parse only reads the standard dialect with strict amounts",
+                ));
+            }
+            match options.parse_threads {
+                #[cfg(feature = "mmap")]
+                Some(threads) if compression == Compression::None => {
+                    Box::new(mmap::read(path, &options.csv, threads)?.map(|t| t.map_err(Box::from)))
                 }
-                (value, Err(resolveable)) => {
-                    eprintln!("tx {}: Failed to resolve transaction: Requested {} funds, only {} available.", transaction.transaction_id, value, resolveable);
-                    // TODO: error handle?
-                    let _ = tx_record.update(transaction.transaction_id, Disputed);
+                #[cfg(feature = "mmap")]
+                Some(_) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "--parse-threads can't read compressed files",
+                    ))
                 }
-            },
-        },
-        Chargeback => match tx_record.update(transaction.transaction_id, ChargedBack) {
-            Err(NotFound) => eprintln!(
-                "tx {}: Failed to chargeback transaction: Not found.",
-                transaction.transaction_id
-            ),
-            Err(WrongState(s)) => eprintln!(
-                "tx {}: Failed to chargeback transaction: Wrong state {:?}.",
-                transaction.transaction_id, s
-            ),
-            Ok(disputed) => match match disputed.type_ {
-                Deposit(value) => (value.clone(), client.chargeback_deposit(value)),
-                Withdrawal(value) => (value.clone(), client.chargeback_withdrawal(value)),
-            } {
-                (_, Ok(_)) => {
-                    // TODO: error handle?
-                    let _ = tx_record.update(transaction.transaction_id, ChargedBackFinal);
+                #[cfg(not(feature = "mmap"))]
+                Some(_) => return Err(unsupported("mmap")),
+                None => Box::new(fast_parse::read(rdr, &options.csv).map(|t| t.map_err(Box::from))),
+            }
+        }
+        Format::Csv => Box::new(
+            read_from_csv_reader_with_metadata(rdr, &options.csv).map(|t| t.map_err(Box::from)),
+        ),
+        #[cfg(feature = "avro")]
+        Format::Avro => Box::new(
+            avro::Reader::new(rdr)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+                .map(|t| Ok((t?, Metadata::default()))),
+        ),
+        #[cfg(not(feature = "avro"))]
+        Format::Avro => return Err(unsupported("avro")),
+        #[cfg(feature = "protobuf")]
+        Format::Protobuf => {
+            Box::new(protobuf::Reader::new(rdr).map(|t| Ok((t?, Metadata::default()))))
+        }
+        #[cfg(not(feature = "protobuf"))]
+        Format::Protobuf => return Err(unsupported("protobuf")),
+        Format::Ofx => read_statement(rdr, options.client, |s, client| {
+            Ok(statement::read_ofx(s, client))
+        })?,
+        Format::Qif => read_statement(rdr, options.client, |s, client| {
+            Ok(statement::read_qif(s, client))
+        })?,
+        #[cfg(feature = "camt053")]
+        Format::Camt053 => read_statement(rdr, options.client, |s, client| {
+            camt::read_camt053(s, client).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        })?,
+        #[cfg(not(feature = "camt053"))]
+        Format::Camt053 => return Err(unsupported("camt053")),
+    };
+    // Only CSV is checked as it's read, so the rules are applied here to whatever the input is
+    let amounts = options.csv.amounts.clone();
+    Ok(Box::new(
+        scheduled
+            .into_iter()
+            .map(|t| Ok((t, Metadata::default())))
+            .chain(transactions)
+            .map(move |t| {
+                let (transaction, metadata) = t?;
+                amounts.check(&transaction.type_)?;
+                Ok((transaction, metadata))
+            }),
+    ))
+}
+
+/// The code for why a transaction couldn't be read, which is only more specific than `E_PARSE` with `--fast-parse`, or
+/// for amounts the amount rules don't allow
+fn parse_code(e: &(dyn Error + Send + Sync + 'static)) -> &'static str {
+    if let Some(e) = e.downcast_ref::<fast_parse::Error>() {
+        return e.code();
+    }
+    if let Some(e) = e.downcast_ref::<transaction::Error>() {
+        return e.code();
+    }
+    match e.downcast_ref::<csv::Error>().map(csv::Error::kind) {
+        Some(csv::ErrorKind::Io(e)) => e
+            .get_ref()
+            .and_then(|e| e.downcast_ref::<transaction::Error>())
+            .map_or("E_PARSE", transaction::Error::code),
+        _ => "E_PARSE",
+    }
+}
+
+/// Reads transactions on another thread, queueing up to `capacity` of them for processing
+fn queue_transactions(
+    path: PathBuf,
+    options: Options,
+    capacity: usize,
+) -> io::Result<(Transactions, Arc<queue::Metrics>)> {
+    let (sender, transactions) = queue::bounded(capacity, options.overflow);
+    let metrics = transactions.metrics();
+    // Readers can't be sent between threads, so are opened on the reading thread
+    let (opened, ready) = mpsc::channel();
+    thread::spawn(move || {
+        let read = match read_transactions(path, &options) {
+            Ok(read) => read,
+            Err(e) => return opened.send(Err(e)),
+        };
+        opened.send(Ok(()))?;
+        for transaction in read {
+            match sender.send(transaction) {
+                Ok(()) => {}
+                Err(queue::SendError::Full(Ok((transaction, _)))) => {
+                    eprintln!(
+                        "tx {}: E_SHED: shed, ingestion queue full",
+                        transaction.transaction_id
+                    )
                 }
-                (value, Err(chargeable)) => {
-                    eprintln!("tx {}: Failed to chargeback transaction: Requested {} funds, only {} available.", transaction.transaction_id, value, chargeable);
-                    // TODO: error handle?
-                    let _ = tx_record.update(transaction.transaction_id, Disputed);
+                Err(queue::SendError::Full(Err(e))) => {
+                    eprintln!(
+                        "failed to parse transaction: {}: {} (shed)",
+                        parse_code(&*e),
+                        e
+                    )
                 }
+                // Processing has stopped
+                Err(queue::SendError::Closed(_)) => break,
+            }
+        }
+        Ok(())
+    });
+    ready
+        .recv()
+        .map_err(|_| io::Error::other("transaction reader panicked"))??;
+    Ok((Box::new(transactions), metrics))
+}
+
+/// Statements are small, so are read whole
+fn read_statement<R: Read>(
+    mut rdr: R,
+    client: Option<u16>,
+    read: impl FnOnce(&str, u16) -> io::Result<Vec<Result<Transaction, statement::Error>>>,
+) -> io::Result<Transactions> {
+    let client = client
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "statements need a --client"))?;
+    let mut s = String::new();
+    rdr.read_to_string(&mut s)?;
+    Ok(Box::new(
+        read(&s, client)?
+            .into_iter()
+            .map(|t| Ok((t?, Metadata::default()))),
+    ))
+}
+
+/// Applies deduplication, rate and amount limits, the fee schedule and chargeback fees, overdraft limits, tiers,
+/// withdrawal disputes, KYC statuses, velocity limits, lock policy and balance alerts from the options
+fn configure<T: TransactionSetClient>(
+    mut engine: Engine<T>,
+    options: &Options,
+) -> io::Result<Engine<T>> {
+    let invalid = |e| io::Error::new(io::ErrorKind::InvalidData, e);
+    if let Some(window) = options.idempotency_window {
+        engine = engine.deduplicating(window);
+    }
+    if let Some(rate) = options.rate_limit {
+        let burst = options.rate_burst.unwrap_or(rate.ceil().max(1.0) as u32);
+        engine = engine.limiting_rate(RateLimit::new(rate, burst));
+    }
+    if let Some(path) = &options.amount_limits {
+        engine = engine
+            .limiting_amounts(AmountLimits::from_csv_reader(File::open(path)?).map_err(invalid)?);
+    }
+    // Checked before any other rule sees the transaction
+    #[cfg(feature = "signatures")]
+    if let Some(path) = &options.trusted_keys {
+        engine = engine.with_risk_rule(
+            simple_transaction_manager::signature::Signatures::from_csv_reader(File::open(path)?)
+                .map_err(invalid)?,
+        );
+    }
+    if let Some(path) = &options.fees {
+        engine =
+            engine.charging_fees(FeeSchedule::from_csv_reader(File::open(path)?).map_err(invalid)?);
+    }
+    if let Some(flat) = &options.chargeback_fee {
+        engine = engine.charging_chargeback_fees(Fee {
+            flat: flat.clone(),
+            percent: Decimal::zero(),
+        });
+    }
+    let default = options.overdraft_limit.clone().unwrap_or_default();
+    engine = match &options.overdraft_limits {
+        Some(path) => engine.allowing_overdrafts(
+            OverdraftLimits::from_csv_reader(File::open(path)?, default).map_err(invalid)?,
+        ),
+        None if default != Decimal::zero() => {
+            engine.allowing_overdrafts(OverdraftLimits::new(default))
+        }
+        None => engine,
+    };
+    if let Some(path) = &options.tiers {
+        let clients = options.client_tiers.as_ref().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "--tiers needs --client-tiers")
+        })?;
+        engine = engine.tiering(
+            Tiers::from_csv_readers(File::open(path)?, File::open(clients)?).map_err(invalid)?,
+        );
+    }
+    if options.hold_disputed_withdrawals {
+        engine = engine.holding_disputed_withdrawals();
+    }
+    if options.kyc.is_some()
+        || options.kyc_default.is_some()
+        || options.unverified_deposit_cap.is_some()
+    {
+        let default = options.kyc_default.unwrap_or_default();
+        let mut kyc = match &options.kyc {
+            Some(path) => Kyc::from_csv_reader(File::open(path)?, default).map_err(invalid)?,
+            None => Kyc::new(default),
+        };
+        if let Some(cap) = &options.unverified_deposit_cap {
+            kyc = kyc.capping_deposits(cap.clone());
+        }
+        engine = engine.verifying(kyc);
+    }
+    if let Some(path) = &options.velocity {
+        engine =
+            engine.with_risk_rule(Velocity::from_csv_reader(File::open(path)?).map_err(invalid)?);
+    }
+    if options.lock_disputes.is_some() || options.lock_chargebacks.is_some() {
+        let policy = LockPolicy::new(options.lock_disputes, options.lock_chargebacks);
+        engine = engine.locking_automatically(match options.lock_window {
+            Some(window) => policy.within(window),
+            None => policy,
+        });
+    }
+    if let Some(thresholds) = &options.alert_thresholds {
+        engine = engine.alerting(thresholds.clone());
+    }
+    Ok(engine)
+}
+
+fn tx_record() -> TxRecord {
+    CachedClient::new(MemoryClient::default(), LruCache::with_capacity(CACHE_SIZE))
+}
+
+/// Starts from a snapshot written with `--checkpoint`
+fn restore<P: AsRef<Path>>(snapshot: P) -> io::Result<Engine<TxRecord>> {
+    Engine::restore(io::BufReader::new(File::open(snapshot)?), tx_record())
+}
+
+fn process_file<P: AsRef<Path>>(
+    path: P,
+    mut engine: Engine<TxRecord>,
+    options: &Options,
+) -> io::Result<Engine<TxRecord>> {
+    if options.check_conservation {
+        engine = engine.checking_conservation();
+    }
+    engine = configure(engine, options)?.timestamping_activity(unix_now);
+    #[cfg(feature = "tui")]
+    let mut dashboard = match options.tui {
+        true => Some(simple_transaction_manager::tui::Dashboard::new()?),
+        false => None,
+    };
+    // The dashboard shows rejections itself, and anything else written to `STDERR` would garble it
+    #[cfg(feature = "tui")]
+    let quiet = dashboard.is_some();
+    #[cfg(not(feature = "tui"))]
+    let quiet = false;
+
+    let mut large_transactions = match &options.aml_report {
+        Some(path) => Some((
+            aml::Monitor::new(
+                options
+                    .aml_threshold
+                    .clone()
+                    .unwrap_or_else(|| Decimal::new(10_000, 0)),
+            ),
+            csv::Writer::from_path(path)?,
+        )),
+        None => None,
+    };
+    let mut ledger = match &options.ledger {
+        Some(path) => Some(chain::Writer::new(File::create(path)?)),
+        None => None,
+    };
+    let mut dead_letters = match &options.dead_letters {
+        Some(path) => Some(dead_letter::Writer::new(File::create(path)?)),
+        None => None,
+    };
+    let mut alerts = match &options.alerts {
+        Some(path) => Some(csv::Writer::from_path(path)?),
+        None => None,
+    };
+    let mut stats = options.stats.as_ref().map(|_| stats::Stats::default());
+
+    let (transactions, queue) = match options.queue {
+        Some(capacity) => {
+            let (transactions, metrics) =
+                queue_transactions(path.as_ref().to_owned(), options.clone(), capacity)?;
+            (transactions, Some(metrics))
+        }
+        None => (read_transactions(path, options)?, None),
+    };
+    let published = match &options.query {
+        Some(address) => {
+            let listener = TcpListener::bind(address.to_string_lossy().as_ref())?;
+            let published = query::Published::default();
+            let server = published.clone();
+            thread::spawn(move || query::serve(listener, server));
+            Some(published)
+        }
+        None => None,
+    };
+    let shutdown = shutdown();
+    let mut read = 0;
+    for transaction in transactions {
+        if shutdown.load(Ordering::Relaxed) {
+            eprintln!("interrupted, no more transactions will be read");
+            break;
+        }
+        if let Some(published) = published.as_ref().filter(|_| read % QUERY_REFRESH == 0) {
+            published.publish(read, engine.view());
+        }
+        read += 1;
+        let (transaction, metadata) = match transaction {
+            Ok(transaction) => transaction,
+            Err(e) => {
+                eprintln!("failed to parse transaction: {}: {}", parse_code(&*e), e);
+                continue;
+            }
+        };
+        let transaction_id = transaction.transaction_id;
+        if let Some(outcome) = engine.retried(&transaction) {
+            match outcome {
+                _ if quiet => {}
+                Ok(_) => eprintln!("tx {}: retry, already applied", transaction_id),
+                Err(e) => eprintln!(
+                    "tx {}: retry, already rejected: {}: {}",
+                    transaction_id,
+                    e.code(),
+                    e
+                ),
+            }
+            continue;
+        }
+        #[cfg(feature = "tui")]
+        let before = dashboard
+            .as_ref()
+            .map(|dashboard| dashboard.before(&engine, &transaction));
+        let kept = (ledger.is_some() || dead_letters.is_some() || stats.is_some())
+            .then(|| transaction.clone());
+        let started = Instant::now();
+        let result = match &mut large_transactions {
+            Some((monitor, writer)) => match monitor.process(&mut engine, transaction) {
+                Ok(Some(large)) => Ok(writer.serialize(large)?),
+                result => result.map(drop),
             },
-        },
+            None => engine.process(transaction),
+        };
+        if let (Some(stats), Some(processed)) = (&mut stats, &kept) {
+            stats.record(processed, result.as_ref().map(drop), started.elapsed());
+        }
+        if let (Err(e), Some(dead_letters), Some(rejected)) = (&result, &mut dead_letters, &kept) {
+            dead_letters.append(rejected, e)?;
+        }
+        #[cfg(feature = "tui")]
+        if let (Some(dashboard), Some(before)) = (&mut dashboard, before) {
+            dashboard.record(&engine, before, result.as_ref().map(drop));
+        }
+        match result {
+            Err(_) if quiet => {}
+            Err(e) if metadata.is_empty() => {
+                eprintln!("tx {}: {}: {}", transaction_id, e.code(), e)
+            }
+            Err(e) => eprintln!("tx {}: {}: {} ({})", transaction_id, e.code(), e, metadata),
+            Ok(()) => {
+                if let (Some(ledger), Some(applied)) = (&mut ledger, &kept) {
+                    ledger.append(applied)?;
+                }
+            }
+        }
+        for flag in engine.take_flags() {
+            if !quiet {
+                eprintln!("{}", flag);
+            }
+        }
+        for lock in engine.take_auto_locks() {
+            if !quiet {
+                eprintln!("{}", lock);
+            }
+        }
+        for fee in engine.take_chargeback_fees() {
+            if !quiet {
+                eprintln!(
+                    "tx {}: chargeback fee of {} charged to client {}",
+                    fee.transaction_id,
+                    fee.type_.amount().expect("fees have an amount"),
+                    fee.client_id
+                );
+            }
+            if let Some(ledger) = &mut ledger {
+                ledger.append(&fee)?;
+            }
+        }
+        for alert in engine.take_alerts() {
+            if !quiet {
+                eprintln!("{}", alert);
+            }
+            if let Some(writer) = &mut alerts {
+                writer.serialize(alert)?;
+            }
+        }
+        #[cfg(feature = "tui")]
+        if let Some(dashboard) = &mut dashboard {
+            dashboard.draw(&engine)?;
+        }
+    }
+    if let Some((_, mut writer)) = large_transactions {
+        writer.flush()?;
+    }
+    if let Some(writer) = &mut alerts {
+        writer.flush()?;
+    }
+    if let Some(ledger) = ledger {
+        eprintln!("ledger digest {}", ledger.finish()?);
+    }
+    if let Some(dead_letters) = &mut dead_letters {
+        dead_letters.flush()?;
     }
+    if let Some(queue) = queue {
+        eprintln!(
+            "ingestion queue peaked at {} transactions, {} shed",
+            queue.peak(),
+            queue.shed()
+        );
+    }
+    if let Some(published) = &published {
+        published.publish(read, engine.view());
+    }
+    if let (Some(stats), Some(path)) = (stats, &options.stats) {
+        let mut writer = io::BufWriter::new(File::create(path)?);
+        let mut summary = stats.summary(options.stats_top);
+        summary.store = Some(engine.store_stats());
+        serde_json::to_writer_pretty(&mut writer, &summary)?;
+        writer.flush()?;
+    }
+    #[cfg(feature = "tui")]
+    if let Some(dashboard) = dashboard {
+        dashboard.finish(&engine)?;
+    }
+    Ok(engine)
 }
 
-fn main() -> std::io::Result<()> {
-    let path = args_os().skip(1).next().expect("missing filename");
+/// Set by the first SIGINT or SIGTERM, after which no more transactions are read and whatever was processed is
+/// reported. A second exits straight away, in case reading is stuck.
+fn shutdown() -> &'static Arc<AtomicBool> {
+    static SHUTDOWN: OnceLock<Arc<AtomicBool>> = OnceLock::new();
+    SHUTDOWN.get_or_init(|| {
+        let requested = Arc::new(AtomicBool::new(false));
+        for signal in [SIGINT, SIGTERM] {
+            // Registered first, so it only sees the flag set by an earlier signal
+            signal_hook::flag::register_conditional_shutdown(signal, 130, requested.clone())
+                .and_then(|_| signal_hook::flag::register(signal, requested.clone()))
+                .expect("can't handle signals");
+        }
+        requested
+    })
+}
+
+/// Loads the starting client state from a previously written report
+fn read_seed<P: AsRef<Path>>(path: Option<P>) -> io::Result<HashMap<u16, Client>> {
     // Optimization: use hashset
     // Blocked by https://github.com/rust-lang/rust/issues/60896
     //
@@ -122,98 +702,1031 @@ fn main() -> std::io::Result<()> {
     // If client count isn't actually that limited, if it got big enough we'd eventually want to move the data out
     // of RAM and onto disk, possibly even remotely in a distributed KVP datastore using an interface similar to `TransactionSet`
     let mut clients = HashMap::new();
+    if let Some(path) = path {
+        for row in report::read_from_csv_file(path)? {
+            let record = ClientRecord::try_from(row?)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+            let client = Client::from(record);
+            clients.insert(client.id(), client);
+        }
+    }
+    Ok(clients)
+}
+
+/// `stm [--seed <accounts.csv>] [--dry-run] [--output-delta] [--lifetime-stats] [--all-columns] [--check-conservation]
+/// [--fees <fees.csv>] [--chargeback-fee <amount>] [--overdraft-limit <amount>] [--overdraft-limits <limits.csv>]
+/// [--tiers <tiers.csv> --client-tiers <clients.csv>] [--hold-disputed-withdrawals] [--kyc <statuses.csv>]
+/// [--kyc-default <status>] [--unverified-deposit-cap <amount>] [--velocity <velocity.csv>] [--lock-disputes <N>]
+/// [--lock-chargebacks <N>] [--lock-window <window>] [--idempotency-window <N>] [--amount-limits <limits.csv>]
+/// [--rate-limit <per second> [--rate-burst <N>]] [--aml-report <large.csv> [--aml-threshold <amount>]]
+/// [--alert-thresholds <amounts> [--alerts <alerts.csv>]] [--ledger <ledger.csv>] [--checkpoint <snapshot>]
+/// [--resume <snapshot>] [--dead-letters <rejected.csv>] [--stats <stats.json> [--stats-top <N>]] [--query <address>]
+/// [--queue <N> [--overflow <block|shed>]]
+/// [--schedule <schedule.csv> --from <unix time> --to <unix time> [--first-id <N>]] [--trusted-keys <keys.csv>] [--tui]
+/// [--compression <none|gzip|zstd>] [--format <csv|avro|protobuf|ofx|qif|camt053>] [--client <id>] [--delimiter <c>]
+/// [--quote <c>] [--dialect <standard|debit-credit>] [--allow-zero-amounts] [--max-amount <amount>] [--fast-parse]
+/// [--parse-threads <N>] <transactions.csv>`: writes the accounts report to `STDOUT`
+///
+/// The transactions can be an `http://` or `https://` URL if built with the `http` feature, which is streamed rather
+/// than downloaded first, and an `s3://`, `gs://` or `az://` URL if built with the `object-store` feature, which is
+/// too. `.gz` and `.zst` inputs are decompressed on the fly, `--compression` overrides the guess from the extension.
+/// `--delimiter '\t'` reads TSV, and `--dialect debit-credit` reads bank exports with `debit` and `credit` columns.
+/// Amounts of zero are rejected unless `--allow-zero-amounts` is given, and `--max-amount` rejects any larger amount.
+/// OFX, QIF and camt.053 statements are for a single account, so need `--client`. `--fast-parse` reads large, clean
+/// CSV files in the standard dialect faster, by parsing rows straight from their bytes, and `--parse-threads` does so
+/// on that many threads (one per CPU if 0) over a memory mapped file, if built with the `mmap` feature.
+/// With `--dry-run`, only the clients whose accounts would change are described instead, and none of the files below
+/// are written. `--output-delta` only reports the clients whose balances or status changed since the seed or snapshot
+/// the run started from, for runs whose report is upserted downstream. `--lifetime-stats` adds each client's lifetime
+/// counts and volumes of deposits, withdrawals, disputes and chargebacks, and when it was first and last active in unix
+/// seconds, to the report. `--all-columns` adds the `overdraft`, `closed` and `pending` columns to the report, which
+/// only has `client,available,held,total,locked` otherwise. `--check-conservation` aborts as soon as funds are created
+/// or destroyed by the engine. `--fees` charges fees from a `type,flat,percent` schedule after each applied
+/// transaction. `--chargeback-fee` also charges that amount for every chargeback, overdrawing the client if they can't
+/// cover it, and writes it to `STDERR`, and to the ledger, as a `fee` transaction with the chargeback's id.
+/// `--overdraft-limit` lets every client overdraw by up to that amount, and `--overdraft-limits` overrides it for the
+/// clients in a `client,limit` file. `--tiers` holds the clients in a `client,tier` file given by `--client-tiers` to
+/// the tiers in a `tier,max_withdrawal,overdraft,dispute_window` file, which cap single withdrawals, override overdraft
+/// limits and reject late disputes. `--hold-disputed-withdrawals` reports the amount of each disputed withdrawal in
+/// held funds and the total until the dispute is settled. `--kyc` gates what clients can do on their status in a
+/// `client,status` file, or `--kyc-default` for clients it doesn't list: unverified clients can't withdraw or
+/// authorize, or deposit more than `--unverified-deposit-cap` in total, and suspended clients can't move money at all.
+/// `--velocity` rejects or flags deposits and withdrawals over the per-client limits in a
+/// `type,window,count,volume,action` file, writing flags to `STDERR`. `--lock-disputes` and `--lock-chargebacks` lock
+/// clients once they've had that many disputes or chargebacks, within `--lock-window` (e.g. `30d`) if given, writing
+/// each automatic lock to `STDERR`. `--idempotency-window` treats a transaction with the same `idempotency_key` as one
+/// of the last that many keys as a retry, which isn't applied again. `--amount-limits` rejects transactions of less
+/// than the `min` or more than the `max` for their type in a `type,min,max` file. `--rate-limit` rejects a client's
+/// transactions once they're coming in faster than that many a second, after a burst of `--rate-burst` (the rate
+/// rounded up by default), which is for input streamed as it's made. `--aml-report` writes every applied deposit or
+/// withdrawal of more than `--aml-threshold` (10,000 by default) to a separate report, with the client's cumulative
+/// totals. `--alert-thresholds` (e.g. `0,1000000`) writes an alert to `STDERR` whenever a transaction takes a client's
+/// available balance, less any overdraft in use, across one of those amounts, and `--alerts` also writes them to a
+/// separate report. `--ledger` writes every applied transaction to a hash chained ledger, and its final digest to
+/// `STDERR`. `--checkpoint` writes a snapshot of the engine, for `Engine::restore`, once processing stops, unless it's
+/// a dry run, and `--resume` starts from one instead of `--seed`. SIGINT or SIGTERM stops reading transactions, and
+/// everything above is still written for those already read before exiting with status 130. `--dead-letters` writes
+/// every rejected transaction, with why, to a file which `dlq retry` processes again. `--stats` writes a JSON summary
+/// of the run, with latency histograms for each type of transaction, the `--stats-top` (10 by default) clients by
+/// volume, disputes and rejections, and how many stored transactions are in each state. `--query` serves the clients'
+/// balances and open disputes as JSON over HTTP on that address (e.g. `127.0.0.1:8080`) while processing, from a view
+/// of the engine refreshed every thousand transactions, at `/status`, `/clients` and `/clients/<id>`. `--queue` reads
+/// transactions on another thread, queueing up to that many for processing. Reading waits for room once the queue is
+/// full, or with `--overflow shed` rejects transactions until there is some, and the most queued at once and how many
+/// were shed are written to `STDERR`. `--schedule` makes the standing orders in a
+/// `client,type,amount,start,every,count` file which fall due between `--from` and `--to` before the file's own
+/// transactions, numbered from `--first-id` (0 by default). `--trusted-keys` rejects every transaction without an
+/// Ed25519 signature by one of the keys in a `key` file, if built with the `signatures` feature. `--tui` shows a live
+/// dashboard on `STDERR` while processing, if built with the `tui` feature.
+fn run<P: AsRef<Path>, W: Write>(
+    path: P,
+    seed: HashMap<u16, Client>,
+    dry_run: bool,
+    options: &Options,
+    mut out: W,
+) -> io::Result<()> {
+    let engine = match &options.resume {
+        Some(snapshot) => restore(snapshot)?,
+        None => Engine::with_clients(seed, tx_record()),
+    };
+    let before = engine.report().collect::<Vec<_>>();
+    // Nothing is committed by a dry run, so nothing which records what was processed is written either
+    let dry = dry_run.then(|| Options {
+        ledger: None,
+        dead_letters: None,
+        stats: None,
+        aml_report: None,
+        alerts: None,
+        ..options.clone()
+    });
+    let engine = process_file(path, engine, dry.as_ref().unwrap_or(options))?;
+
+    if dry_run {
+        for change in report::diff(before, engine.report()) {
+            writeln!(out, "{}", report::Change(&change))?;
+        }
+    } else {
+        if let Some(path) = &options.checkpoint {
+            engine.snapshot(io::BufWriter::new(File::create(path)?))?;
+        }
+        let changed = options.output_delta.then(|| {
+            report::diff(before, engine.report())
+                .iter()
+                .map(report::Mismatch::client)
+                .collect::<HashSet<_>>()
+        });
+        write_report(&engine, changed.as_ref(), options, out)?;
+    }
+    // The report is only of the transactions read before the interruption
+    if shutdown().load(Ordering::Relaxed) {
+        exit(130);
+    }
+    Ok(())
+}
 
-    let mut tx_record =
-        CachedClient::new(MemoryClient::default(), SizedCache::with_size(CACHE_SIZE));
+/// `stm verify [options] <transactions.csv> <report.csv>`: reprocesses the transactions and compares the result
+/// against the report, exiting non-zero if they disagree. Takes the same options as `run` for reading the transactions.
+/// Only the columns the report has by default are compared, unless it was written with `--all-columns`.
+fn verify<P: AsRef<Path>, Q: AsRef<Path>>(
+    path: P,
+    report_path: Q,
+    options: &Options,
+) -> io::Result<()> {
+    let engine = process_file(path, Engine::new(tx_record()), options)?;
+    let expected = report::read_from_csv_file(report_path)?.collect::<csv::Result<Vec<_>>>()?;
+
+    let mismatches = match options.all_columns {
+        true => report::diff(expected, engine.report()),
+        false => report::diff(expected, engine.report().map(report::baseline)),
+    };
+    for mismatch in &mismatches {
+        println!("{}", mismatch);
+    }
+    if !mismatches.is_empty() {
+        eprintln!(
+            "verification failed: {} mismatched clients",
+            mismatches.len()
+        );
+        exit(1);
+    }
+    Ok(())
+}
 
-    for transaction in read_from_csv_file(path)? {
+/// `stm verify-chain [--digest <hex>] <ledger.csv>`: checks every row of a ledger written with `--ledger` is chained
+/// from the rows before it, and that the final digest is `--digest` if given, exiting non-zero if not. Prints the
+/// final digest to `STDOUT`.
+fn verify_chain<P: AsRef<Path>>(path: P, expected: Option<chain::Digest>) -> io::Result<()> {
+    let digest = match chain::verify(File::open(path)?) {
+        Ok(digest) => digest,
+        Err(e) => {
+            eprintln!("verification failed: {}", e);
+            exit(1);
+        }
+    };
+    println!("{}", digest);
+    if expected.is_some_and(|expected| expected != digest) {
+        eprintln!("verification failed: digest doesn't match");
+        exit(1);
+    }
+    Ok(())
+}
+
+/// `stm validate [--delimiter <c>] [--quote <c>] <transactions.csv>`: lists every problem found in the file as CSV on
+/// `STDOUT`, exiting non-zero if there were any
+fn validate<P: AsRef<Path>>(path: P, options: &CsvOptions) -> io::Result<()> {
+    let problems = validate::validate_reader_with(File::open(path)?, options)?;
+
+    let mut writer = csv::Writer::from_writer(io::stdout());
+    for problem in &problems {
+        writer.serialize(problem)?;
+    }
+    writer.flush()?;
+    if !problems.is_empty() {
+        exit(1);
+    }
+    Ok(())
+}
+
+/// The format `stm export` writes
+enum ExportFormat {
+    Beancount { currency: String },
+    Gl(export::ChartOfAccounts),
+}
+
+/// `stm export --format beancount [--currency <C>] [--date <YYYY-MM-DD>] [options] <transactions.csv>`: writes the
+/// journal of applied transactions to `STDOUT` as double-entry postings.
+///
+/// `stm export --format gl [--chart <chart.csv>] [--date <YYYY-MM-DD>] [options] <transactions.csv>`: writes a GL batch
+/// to `STDOUT`, totalling the debits and credits to each account under the codes in the chart of accounts.
+///
+/// Everything is dated `--date`, today by default, as transactions aren't dated. Takes the same options as `run` for
+/// reading the transactions.
+fn export<P: AsRef<Path>>(
+    path: P,
+    format: ExportFormat,
+    date: &str,
+    options: &Options,
+) -> io::Result<()> {
+    let mut engine = configure(Engine::new(MemoryClient::default()), options)?;
+    let mut journal = export::Journal::new();
+    let mut batch = match &format {
+        ExportFormat::Gl(chart) => Some(export::GlBatch::new(chart.clone())),
+        ExportFormat::Beancount { .. } => None,
+    };
+    for transaction in read_transactions(path, options)? {
         let transaction = match transaction {
-            Ok(transaction) => transaction,
+            Ok((transaction, _)) => transaction,
             Err(e) => {
-                eprintln!("failed to parse transaction: {}", e);
+                eprintln!("failed to parse transaction: {}: {}", parse_code(&*e), e);
                 continue;
             }
         };
-        process_transaction(transaction, &mut clients, &mut tx_record);
+        match &mut batch {
+            Some(batch) => {
+                if let Ok(postings) = engine.process_with_postings(transaction) {
+                    batch.record(&postings);
+                }
+            }
+            None => {
+                let _ = journal.process(&mut engine, transaction);
+            }
+        }
     }
 
-    let mut writer = csv::Writer::from_writer(std::io::stdout());
-    for client in clients.values() {
-        writer.serialize(client)?;
+    match (format, batch) {
+        (ExportFormat::Beancount { currency }, _) => {
+            journal.write_beancount(io::BufWriter::new(io::stdout()), date, &currency)
+        }
+        (ExportFormat::Gl(_), batch) => {
+            let mut writer = csv::Writer::from_writer(io::stdout());
+            for line in batch.expect("created above").lines(date) {
+                writer.serialize(line)?;
+            }
+            Ok(writer.flush()?)
+        }
+    }
+}
+
+/// The current unix time, in seconds
+fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Today's UTC date as `YYYY-MM-DD`
+fn today() -> String {
+    let days = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() / 86400) as i64;
+    // Howard Hinnant's `civil_from_days`
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Minimal argument parsing, flags may appear anywhere amongst the positional arguments
+struct Args(Vec<OsString>);
+
+impl Args {
+    /// Removes `--name`, returning whether it was present
+    fn flag(&mut self, name: &str) -> bool {
+        match self.0.iter().position(|arg| arg == name) {
+            Some(i) => {
+                self.0.remove(i);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes `--name <value>`, returning the value if present
+    fn value(&mut self, name: &str) -> Option<OsString> {
+        let i = self.0.iter().position(|arg| arg == name)?;
+        self.0.remove(i);
+        if i == self.0.len() {
+            panic!("missing value for {}", name);
+        }
+        Some(self.0.remove(i))
+    }
+
+    /// Removes `--name <value>`, parsing the value if present
+    fn parsed<T: FromStr>(&mut self, name: &str) -> Option<T> {
+        let value = self.value(name)?;
+        match value.to_str().map(T::from_str) {
+            Some(Ok(value)) => Some(value),
+            _ => panic!("invalid value for {}: {:?}", name, value),
+        }
+    }
+
+    /// Removes the next positional argument
+    fn next(&mut self, what: &str) -> OsString {
+        if self.0.is_empty() {
+            panic!("missing {}", what);
+        }
+        self.0.remove(0)
+    }
+}
+
+/// A single byte CSV setting, written as the character itself or `\t`
+struct CsvByte(u8);
+
+impl FromStr for CsvByte {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.as_bytes() {
+            b"\\t" | b"tab" => Ok(CsvByte(b'\t')),
+            &[byte] if byte.is_ascii() => Ok(CsvByte(byte)),
+            _ => Err(format!("expected a single ASCII character, not {:?}", s)),
+        }
+    }
+}
+
+/// The locale for `--lenient-amounts`, named by its decimal separator
+struct AmountLocale(Locale);
+
+impl FromStr for AmountLocale {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "point" | "." => Ok(AmountLocale(Locale::DECIMAL_POINT)),
+            "comma" | "," => Ok(AmountLocale(Locale::DECIMAL_COMMA)),
+            _ => Err(format!("expected point or comma, not {:?}", s)),
+        }
+    }
+}
+
+/// `--delimiter <c>`, `--quote <c>`, `--lenient-amounts <point|comma>`, `--dialect <standard|debit-credit>`,
+/// `--allow-zero-amounts` and `--max-amount <amount>`, which are accepted wherever a transaction file is read
+fn csv_options(args: &mut Args) -> CsvOptions {
+    let default = CsvOptions::default();
+    CsvOptions {
+        delimiter: args
+            .parsed("--delimiter")
+            .map_or(default.delimiter, |CsvByte(b)| b),
+        quote: args.parsed("--quote").map_or(default.quote, |CsvByte(b)| b),
+        lenient_amounts: args
+            .parsed("--lenient-amounts")
+            .map(|AmountLocale(locale)| locale),
+        dialect: args.parsed("--dialect").unwrap_or_default(),
+        amounts: AmountRules {
+            allow_zero: args.flag("--allow-zero-amounts"),
+            max: args.parsed("--max-amount"),
+        },
+    }
+}
+
+/// The options for reading and processing a transaction file
+fn options(args: &mut Args) -> Options {
+    let schedule = args.value("--schedule");
+    Options {
+        format: args.parsed("--format").unwrap_or_default(),
+        csv: csv_options(args),
+        fast_parse: args.flag("--fast-parse"),
+        parse_threads: args.parsed("--parse-threads"),
+        compression: args.parsed("--compression"),
+        client: args.parsed("--client"),
+        check_conservation: args.flag("--check-conservation"),
+        fees: args.value("--fees"),
+        chargeback_fee: args.parsed("--chargeback-fee"),
+        overdraft_limit: args.parsed("--overdraft-limit"),
+        overdraft_limits: args.value("--overdraft-limits"),
+        hold_disputed_withdrawals: args.flag("--hold-disputed-withdrawals"),
+        tiers: args.value("--tiers"),
+        client_tiers: args.value("--client-tiers"),
+        kyc: args.value("--kyc"),
+        kyc_default: args.parsed("--kyc-default"),
+        unverified_deposit_cap: args.parsed("--unverified-deposit-cap"),
+        period: match &schedule {
+            Some(_) => (
+                args.parsed("--from").expect("missing --from"),
+                args.parsed("--to").expect("missing --to"),
+            ),
+            None => (0, 0),
+        },
+        first_id: args.parsed("--first-id").unwrap_or_default(),
+        velocity: args.value("--velocity"),
+        aml_report: args.value("--aml-report"),
+        aml_threshold: args.parsed("--aml-threshold"),
+        alert_thresholds: args.parsed("--alert-thresholds"),
+        alerts: args.value("--alerts"),
+        ledger: args.value("--ledger"),
+        checkpoint: args.value("--checkpoint"),
+        resume: args.value("--resume"),
+        dead_letters: args.value("--dead-letters"),
+        stats: args.value("--stats"),
+        stats_top: args.parsed("--stats-top").unwrap_or(10),
+        lifetime_stats: args.flag("--lifetime-stats"),
+        all_columns: args.flag("--all-columns"),
+        output_delta: args.flag("--output-delta"),
+        query: args.value("--query"),
+        queue: args.parsed("--queue"),
+        overflow: args.parsed("--overflow").unwrap_or_default(),
+        amount_limits: args.value("--amount-limits"),
+        idempotency_window: args.parsed("--idempotency-window"),
+        lock_disputes: args.parsed("--lock-disputes"),
+        lock_chargebacks: args.parsed("--lock-chargebacks"),
+        lock_window: args
+            .parsed::<String>("--lock-window")
+            .map(|window| velocity::parse_window(&window).unwrap_or_else(|e| panic!("{}", e))),
+        rate_limit: args.parsed("--rate-limit"),
+        rate_burst: args.parsed("--rate-burst"),
+        schedule,
+        #[cfg(feature = "signatures")]
+        trusted_keys: args.value("--trusted-keys"),
+        #[cfg(feature = "tui")]
+        tui: args.flag("--tui"),
+    }
+}
+
+/// `stm generate --rows <N> [--clients <K>] [--seed <S>] [--dispute-rate <p>] [--format <csv|protobuf>]`: writes a
+/// synthetic transaction file to `STDOUT`
+fn generate(rows: usize, options: generate::Options, format: Format) -> io::Result<()> {
+    let transactions = generate::Generator::new(options).take(rows);
+    match format {
+        Format::Csv => {
+            let mut writer = csv::Writer::from_writer(io::stdout());
+            for transaction in transactions {
+                writer.serialize(transaction)?;
+            }
+        }
+        Format::Avro => return Err(unsupported("avro output")),
+        Format::Ofx | Format::Qif | Format::Camt053 => return Err(unsupported("statement output")),
+        #[cfg(feature = "protobuf")]
+        Format::Protobuf => {
+            let mut writer = io::BufWriter::new(io::stdout());
+            for transaction in transactions {
+                protobuf::write_length_delimited(&mut writer, transaction)?;
+            }
+        }
+        #[cfg(not(feature = "protobuf"))]
+        Format::Protobuf => return Err(unsupported("protobuf")),
+    }
+    Ok(())
+}
+
+/// `stm simulate --ops <N> [--clients <K>] [--seed <S>] [--dispute-rate <p>]`: processes generated transactions and
+/// prints a digest of the final state, which is identical across builds for the same options
+fn simulate(ops: usize, options: generate::Options) -> io::Result<()> {
+    let seed = options.seed;
+    let mut engine = Engine::new(tx_record());
+    for transaction in generate::Generator::new(options).take(ops) {
+        let _ = engine.process(transaction);
+    }
+    println!(
+        "seed {} ops {} clients {} digest {:016x}",
+        seed,
+        ops,
+        engine.clients().count(),
+        report::digest(engine.report())
+    );
+    Ok(())
+}
+
+/// `stm merge [--all-columns] <into> <from> <accounts.csv>`: merges client `from` into client `into` in an accounts
+/// report, writing the new report to `STDOUT`. `from` is kept in the report, closed and empty.
+fn merge<P: AsRef<Path>>(into: u16, from: u16, path: P, all_columns: bool) -> io::Result<()> {
+    let mut engine = Engine::with_clients(read_seed(Some(path))?, MemoryClient::default());
+    engine
+        .merge_clients(into, from)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let mut writer = csv::Writer::from_writer(io::stdout());
+    for client in engine.clients() {
+        writer.serialize(report::Row::new(client.clone().into(), all_columns))?;
     }
     Ok(())
 }
 
+/// `stm merge-reports [--sum-duplicates] [--all-columns] <report.csv>...`: combines the accounts reports of shards,
+/// such as tenants or partitions processed separately, into one report on `STDOUT` in client order. Fails if a client
+/// is in more than one report, unless `--sum-duplicates` sums their rows.
+fn merge_reports<W: Write>(
+    paths: Vec<OsString>,
+    sum_duplicates: bool,
+    all_columns: bool,
+    out: W,
+) -> io::Result<()> {
+    if paths.is_empty() {
+        panic!("missing report filenames");
+    }
+    let mut rows = Vec::new();
+    for path in paths {
+        for row in report::read_from_csv_file(path)? {
+            rows.push(row?);
+        }
+    }
+    let merged = report::merge(rows, sum_duplicates)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let mut writer = csv::Writer::from_writer(out);
+    for row in merged {
+        writer.serialize(report::Row::new(row, all_columns))?;
+    }
+    writer.flush()
+}
+
+/// `stm tenants [options] <transactions.csv> <reports dir>`: processes the feeds of several tenants, named in a
+/// `tenant` column, in one engine, writing each tenant's accounts report to `<tenant>.csv` in the reports directory.
+/// Each tenant has its own client and transaction ids. Takes the same options as `run` for reading and processing the
+/// transactions.
+fn tenants<P: AsRef<Path>, Q: AsRef<Path>>(path: P, dir: Q, options: &Options) -> io::Result<()> {
+    let engine = configure(Engine::new(tx_record()), options)?;
+    let mut tenants = tenant::Tenants::new(engine);
+    for transaction in read_transactions(path, options)? {
+        let transaction = match transaction {
+            Ok((transaction, _)) => transaction,
+            Err(e) => {
+                eprintln!("failed to parse transaction: {}: {}", parse_code(&*e), e);
+                continue;
+            }
+        };
+        let transaction_id = transaction.transaction_id;
+        // Tenants name report files, so can't name anything else
+        let tenant = match transaction.metadata.get("tenant") {
+            Some(tenant)
+                if !tenant.is_empty()
+                    && tenant
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') =>
+            {
+                tenant.clone()
+            }
+            Some(tenant) => {
+                eprintln!(
+                    "tx {}: E_INVALID_TENANT: invalid tenant {:?}",
+                    transaction_id, tenant
+                );
+                continue;
+            }
+            None => {
+                eprintln!("tx {}: E_MISSING_TENANT: missing tenant", transaction_id);
+                continue;
+            }
+        };
+        if let Err(e) = tenants.process(&tenant, transaction) {
+            eprintln!(
+                "tenant {} tx {}: {}: {}",
+                tenant,
+                transaction_id,
+                e.code(),
+                e
+            );
+        }
+        for flag in tenants.engine_mut().take_flags() {
+            eprintln!("{}", flag);
+        }
+        for lock in tenants.engine_mut().take_auto_locks() {
+            eprintln!("{}", lock);
+        }
+        for fee in tenants.engine_mut().take_chargeback_fees() {
+            eprintln!(
+                "tenant {} tx {}: chargeback fee of {} charged",
+                tenant,
+                fee.transaction_id,
+                fee.type_.amount().expect("fees have an amount")
+            );
+        }
+        for alert in tenants.engine_mut().take_alerts() {
+            eprintln!("{}", alert);
+        }
+    }
+    for tenant in tenants.tenants() {
+        let mut writer = csv::Writer::from_path(dir.as_ref().join(format!("{}.csv", tenant)))?;
+        for client in tenants.report(tenant) {
+            writer.serialize(report::Row::new(client, options.all_columns))?;
+        }
+    }
+    Ok(())
+}
+
+/// `stm serve-store <address>`: serves a central store of disputable transactions over gRPC on the address, like
+/// `0.0.0.0:50051`, for engines to keep theirs in with `grpc::RemoteClient`, if built with the `grpc` feature. The
+/// transactions are kept in memory, so only last as long as the server, sharded so calls for different transactions
+/// needn't wait for each other.
+#[cfg(feature = "grpc")]
+fn serve_store(address: OsString) -> io::Result<()> {
+    let address = address
+        .to_str()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid address"))?;
+    grpc::serve(
+        simple_transaction_manager::transaction_set::ShardedClient::new(STORE_SHARDS),
+        std::net::TcpListener::bind(address)?,
+    )
+}
+
+#[cfg(not(feature = "grpc"))]
+fn serve_store(_address: OsString) -> io::Result<()> {
+    Err(unsupported("grpc"))
+}
+
+/// `stm accrue --rate <percent> --from <unix time> --to <unix time> [--first-id <N>] <accounts.csv>`: writes an
+/// `interest` transaction to `STDOUT` for every client in the accounts report, for the interest earned on their total
+/// over the period at the annual rate. The transactions are numbered from `--first-id`, 0 by default.
+fn accrue<P: AsRef<Path>>(
+    path: P,
+    accrual: interest::Accrual,
+    (from, to): (u64, u64),
+    first_id: u32,
+) -> io::Result<()> {
+    let clients = report::read_from_csv_file(path)?.collect::<csv::Result<Vec<_>>>()?;
+    let mut writer = csv::Writer::from_writer(io::stdout());
+    for transaction in accrual.transactions(clients, from, to, first_id) {
+        writer.serialize(transaction)?;
+    }
+    Ok(())
+}
+
+/// `stm migrate <store>`: rewrites a file of records, like a snapshot, with every record in the current format
+/// version. The file is only replaced once every record has been read, and is left alone if none needed upgrading.
+fn migrate<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    let path = path.as_ref();
+    let mut migrating = path.as_os_str().to_owned();
+    migrating.push(".migrating");
+    let upgraded = match record::migrate(
+        io::BufReader::new(File::open(path)?),
+        io::BufWriter::new(File::create(&migrating)?),
+    ) {
+        Ok(upgraded) => upgraded,
+        Err(e) => {
+            std::fs::remove_file(&migrating)?;
+            return Err(e);
+        }
+    };
+    match upgraded {
+        0 => std::fs::remove_file(&migrating)?,
+        _ => std::fs::rename(&migrating, path)?,
+    }
+    eprintln!(
+        "upgraded {} records to version {}",
+        upgraded,
+        record::FORMAT_VERSION
+    );
+    Ok(())
+}
+
+/// `stm export-journal <store>`: writes transactions which re-derive what a store of transactions, or a snapshot,
+/// holds to `STDOUT` as CSV that can be processed like any other input. Deposits and withdrawals come first, in
+/// transaction id order, and then the steps of every dispute, as the order they were interleaved in isn't kept.
+fn export_journal<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    let store = FileClient::open(path)?;
+    let mut writer = csv::Writer::from_writer(io::stdout());
+    for transaction in transaction_set::journal(&store) {
+        writer.serialize(transaction)?;
+    }
+    writer.flush()
+}
+
+/// `stm dlq retry [options] <snapshot> <dead-letters.csv>`: starts from a snapshot written with `--checkpoint`, and
+/// processes every transaction in a dead letter file written with `--dead-letters` again, writing the accounts report
+/// to `STDOUT`. The snapshot is updated, and the dead letter file rewritten with only the transactions rejected again.
+/// Takes the same options as `run` for processing the transactions.
+fn retry_dead_letters<P: AsRef<Path>, Q: AsRef<Path>>(
+    snapshot: P,
+    path: Q,
+    options: &Options,
+) -> io::Result<()> {
+    let (snapshot, path) = (snapshot.as_ref(), path.as_ref());
+    let mut engine = configure(restore(snapshot)?, options)?.timestamping_activity(unix_now);
+    let retrying = |path: &Path| {
+        let mut retrying = path.as_os_str().to_owned();
+        retrying.push(".retrying");
+        PathBuf::from(retrying)
+    };
+
+    // Both files are only replaced once both have been written
+    let mut dead_letters = dead_letter::Writer::new(File::create(retrying(path))?);
+    let retried = dead_letter::retry(&mut engine, File::open(path)?, &mut dead_letters)?;
+    dead_letters.flush()?;
+    engine.snapshot(io::BufWriter::new(File::create(retrying(snapshot))?))?;
+    std::fs::rename(retrying(snapshot), snapshot)?;
+    std::fs::rename(retrying(path), path)?;
+    eprintln!(
+        "{} dead letters applied, {} rejected again",
+        retried.applied, retried.rejected
+    );
+
+    write_report(&engine, None, options, io::stdout())
+}
+
+/// Writes the accounts report to `out`, of `only` the given clients if any are, with lifetime statistics if asked for
+fn write_report<W: Write>(
+    engine: &Engine<TxRecord>,
+    only: Option<&HashSet<u16>>,
+    options: &Options,
+    out: W,
+) -> io::Result<()> {
+    let mut writer = csv::Writer::from_writer(out);
+    let clients = engine
+        .clients()
+        .filter(|client| only.is_none_or(|only| only.contains(&client.id())));
+    for client in clients {
+        match options.lifetime_stats {
+            true => writer.serialize(report::LifetimeRow::from(client.state()))?,
+            false => {
+                writer.serialize(report::Row::new(client.clone().into(), options.all_columns))?
+            }
+        }
+    }
+    writer.flush()
+}
+
+/// The options shared by `generate` and `simulate`
+fn generate_options(args: &mut Args) -> generate::Options {
+    let default = generate::Options::default();
+    generate::Options {
+        clients: args.parsed("--clients").unwrap_or(default.clients),
+        seed: args.parsed("--seed").unwrap_or(default.seed),
+        dispute_rate: args
+            .parsed("--dispute-rate")
+            .unwrap_or(default.dispute_rate),
+    }
+}
+
+fn main() -> io::Result<()> {
+    let mut args = Args(args_os().skip(1).collect());
+    match args.0.first().and_then(|arg| arg.to_str()) {
+        Some("verify") => {
+            args.next("command");
+            let options = options(&mut args);
+            let path = args.next("transactions filename");
+            let report_path = args.next("report filename");
+            verify(path, report_path, &options)
+        }
+        Some("verify-chain") => {
+            args.next("command");
+            let digest = args.parsed("--digest");
+            verify_chain(args.next("ledger filename"), digest)
+        }
+        Some("validate") => {
+            args.next("command");
+            let options = csv_options(&mut args);
+            validate(args.next("transactions filename"), &options)
+        }
+        Some("export") => {
+            args.next("command");
+            let format = match args.parsed::<String>("--format").as_deref() {
+                Some("beancount") => ExportFormat::Beancount {
+                    currency: args
+                        .parsed("--currency")
+                        .unwrap_or_else(|| "USD".to_owned()),
+                },
+                Some("gl") => ExportFormat::Gl(match args.value("--chart") {
+                    Some(path) => export::ChartOfAccounts::from_csv_reader(File::open(path)?)
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+                    None => export::ChartOfAccounts::default(),
+                }),
+                format => panic!("unknown export format {:?}", format),
+            };
+            let date = args.parsed("--date").unwrap_or_else(today);
+            let options = options(&mut args);
+            export(args.next("transactions filename"), format, &date, &options)
+        }
+        Some("generate") => {
+            args.next("command");
+            let rows = args.parsed("--rows").expect("missing --rows");
+            let format = args.parsed("--format").unwrap_or_default();
+            let options = generate_options(&mut args);
+            generate(rows, options, format)
+        }
+        Some("merge") => {
+            args.next("command");
+            if args.flag("--sum-duplicates") {
+                panic!("--sum-duplicates is for merge-reports, not merging clients");
+            }
+            let all_columns = args.flag("--all-columns");
+            let parse = |arg: OsString| {
+                arg.to_str()
+                    .and_then(|arg| arg.parse().ok())
+                    .unwrap_or_else(|| panic!("invalid client id {:?}", arg))
+            };
+            let into = parse(args.next("client to merge into"));
+            let from = parse(args.next("client to merge from"));
+            merge(into, from, args.next("accounts filename"), all_columns)
+        }
+        Some("merge-reports") => {
+            args.next("command");
+            let sum_duplicates = args.flag("--sum-duplicates");
+            let all_columns = args.flag("--all-columns");
+            let paths = std::mem::take(&mut args.0);
+            merge_reports(paths, sum_duplicates, all_columns, io::stdout())
+        }
+        Some("tenants") => {
+            args.next("command");
+            let options = options(&mut args);
+            let path = args.next("transactions filename");
+            tenants(path, args.next("reports directory"), &options)
+        }
+        Some("dlq") => {
+            args.next("command");
+            match args.next("dlq command").to_str() {
+                Some("retry") => {}
+                command => panic!("unknown dlq command {:?}", command),
+            }
+            let options = options(&mut args);
+            let snapshot = args.next("snapshot filename");
+            retry_dead_letters(snapshot, args.next("dead letters filename"), &options)
+        }
+        Some("serve-store") => {
+            args.next("command");
+            serve_store(args.next("address"))
+        }
+        Some("migrate") => {
+            args.next("command");
+            migrate(args.next("store filename"))
+        }
+        Some("export-journal") => {
+            args.next("command");
+            export_journal(args.next("store filename"))
+        }
+        Some("accrue") => {
+            args.next("command");
+            let rate = args.parsed("--rate").expect("missing --rate");
+            let from = args.parsed("--from").expect("missing --from");
+            let to = args.parsed("--to").expect("missing --to");
+            let first_id = args.parsed("--first-id").unwrap_or_default();
+            accrue(
+                args.next("accounts filename"),
+                interest::Accrual::new(rate),
+                (from, to),
+                first_id,
+            )
+        }
+        Some("simulate") => {
+            args.next("command");
+            let ops = args.parsed("--ops").expect("missing --ops");
+            let options = generate_options(&mut args);
+            simulate(ops, options)
+        }
+        _ => {
+            let seed = read_seed(args.value("--seed"))?;
+            let dry_run = args.flag("--dry-run");
+            let options = options(&mut args);
+            run(args.next("filename"), seed, dry_run, &options, io::stdout())
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
-    use csv::{ReaderBuilder, Trim};
-    use decimal::Decimal;
-    use rand::prelude::*;
-    use std::collections::HashMap;
-    use transaction_set::{CachedClient, MemoryClient};
+
+    /// Writes `contents` to a file named for the test, returning its path
+    fn temp_file(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("stm-{}-{}", std::process::id(), name));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
 
     #[test]
-    fn basic_process_test() {
-        let mut clients = HashMap::new();
-        let mut tx_record =
-            CachedClient::new(MemoryClient::default(), SizedCache::with_size(CACHE_SIZE));
+    fn amount_rules_apply_to_every_format() {
+        let statement = temp_file(
+            "amounts.qif",
+            "\
+!Type:Bank
+D06/01/2021
+T0.00
+^
+D06/02/2021
+T250.00
+^
+D06/03/2021
+T50.00
+^
+",
+        );
+        let schedule = temp_file(
+            "amounts-schedule.csv",
+            "client,type,amount,start,every,count\n1,deposit,500.0,0,1d,1\n",
+        );
+        let mut options = Options {
+            format: Format::Qif,
+            client: Some(1),
+            schedule: Some(schedule.clone().into()),
+            period: (0, 10),
+            ..Options::default()
+        };
+        options.csv.amounts.max = Some(Decimal::new(100, 0));
+
+        let codes = read_transactions(&statement, &options)
+            .unwrap()
+            .map(|t| t.map(|_| "OK").unwrap_or_else(|e| parse_code(&*e)))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            codes,
+            [
+                "E_AMOUNT_OUT_OF_RANGE",
+                "E_ZERO_AMOUNT",
+                "E_AMOUNT_OUT_OF_RANGE",
+                "OK"
+            ]
+        );
+        std::fs::remove_file(statement).unwrap();
+        std::fs::remove_file(schedule).unwrap();
+    }
 
-        let data = "\
+    #[test]
+    fn output_delta() {
+        let seed = temp_file(
+            "delta-seed.csv",
+            "\
+client,available,held,total,locked
+1,1.0,0.0,1.0,false
+2,2.0,0.0,2.0,false
+3,3.0,0.0,3.0,false
+",
+        );
+        let transactions = temp_file(
+            "delta.csv",
+            "\
 type,       client,  tx, amount
-deposit,         1,   1,    1.0
-chargeback,         10,   21,
-";
-
-        let mut rdr = ReaderBuilder::new()
-            .trim(Trim::All)
-            .from_reader(data.as_bytes());
-
-        for transaction in rdr.deserialize() {
-            let transaction = match transaction {
-                Ok(transaction) => transaction,
-                Err(e) => {
-                    eprintln!("failed to parse transaction: {}", e);
-                    continue;
-                }
-            };
-            process_transaction(transaction, &mut clients, &mut tx_record);
-        }
+deposit,         2,   1,    5.0
+withdrawal,      3,   2,   10.0
+",
+        );
+        let options = Options {
+            output_delta: true,
+            ..Options::default()
+        };
+
+        // The withdrawal is rejected, so only client 2 changed
+        let mut out = Vec::new();
+        let seeded = read_seed(Some(&seed)).unwrap();
+        run(&transactions, seeded, false, &options, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "client,available,held,total,locked\n2,7.0000,0.0000,7.0000,false\n"
+        );
+        std::fs::remove_file(seed).unwrap();
+        std::fs::remove_file(transactions).unwrap();
     }
 
     #[test]
-    fn random_process_test() {
-        let mut clients = HashMap::new();
-        let mut tx_record =
-            CachedClient::new(MemoryClient::default(), SizedCache::with_size(CACHE_SIZE));
-
-        fn generate_transaction<F: FnOnce(u32) -> bool>(exists: F) -> Transaction {
-            let mut rng = thread_rng();
-            let transaction_id = rng.gen();
-            let type_ = if !exists(transaction_id) {
-                match rng.gen() {
-                    false => Disputable(Deposit(Decimal::new(rng.gen_range(0..65000), rng.gen()))),
-                    true => Disputable(Withdrawal(Decimal::new(rng.gen_range(0..1000), rng.gen()))),
-                }
-            } else {
-                match rng.gen_range(0..3) {
-                    0 => Dispute,
-                    1 => Resolve,
-                    _ => Chargeback,
-                }
-            };
-            Transaction {
-                client_id: rng.gen_range(0..500),
-                transaction_id: transaction_id,
-                type_: type_,
-            }
-        }
-        for _ in 0..1000 * 1000 {
-            process_transaction(
-                generate_transaction(|x| tx_record.access(x).is_some()),
-                &mut clients,
-                &mut tx_record,
-            );
+    fn dry_run() {
+        let seed = temp_file(
+            "dry-run-seed.csv",
+            "\
+client,available,held,total,locked
+1,1.0,0.0,1.0,false
+2,2.0,0.0,2.0,false
+3,3.0,0.0,3.0,false
+",
+        );
+        let transactions = temp_file(
+            "dry-run.csv",
+            "\
+type,       client,  tx, amount
+deposit,         1,   1,    5.0
+deposit,         2,   2,    4.0
+dispute,         2,   2,
+chargeback,      2,   2,
+withdrawal,      3,   3,   10.0
+",
+        );
+        let [checkpoint, ledger, dead_letters, stats, aml_report, alerts] = [
+            "snapshot.stmr",
+            "ledger.csv",
+            "rejected.csv",
+            "stats.json",
+            "large.csv",
+            "alerts.csv",
+        ]
+        .map(|name| {
+            std::env::temp_dir().join(format!("stm-{}-dry-run-{}", std::process::id(), name))
+        });
+        let options = Options {
+            checkpoint: Some(checkpoint.clone().into()),
+            ledger: Some(ledger.clone().into()),
+            dead_letters: Some(dead_letters.clone().into()),
+            stats: Some(stats.clone().into()),
+            aml_report: Some(aml_report.clone().into()),
+            alerts: Some(alerts.clone().into()),
+            ..Options::default()
+        };
+
+        // Client 2's balances are back where they started, but it's locked, and client 3's withdrawal is rejected
+        let mut out = Vec::new();
+        let seeded = read_seed(Some(&seed)).unwrap();
+        run(&transactions, seeded, true, &options, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\
+client 1: available 1.0000 -> 6.0000; total 1.0000 -> 6.0000
+client 2: becomes locked
+"
+        );
+        for output in [checkpoint, ledger, dead_letters, stats, aml_report, alerts] {
+            assert!(!output.exists(), "{:?} was written", output);
         }
+        std::fs::remove_file(seed).unwrap();
+        std::fs::remove_file(transactions).unwrap();
+    }
+
+    #[test]
+    fn merge_numbered_reports() {
+        // Report files named like client ids are still reports
+        let dir = std::env::temp_dir().join(format!("stm-{}-shards", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let header = "client,available,held,total,locked\n";
+        std::fs::write(dir.join("1"), format!("{}2,2.0,0.0,2.0,false\n", header)).unwrap();
+        std::fs::write(dir.join("2"), format!("{}1,1.0,0.0,1.0,true\n", header)).unwrap();
+        let paths = vec![dir.join("1").into(), dir.join("2").into()];
+
+        let mut out = Vec::new();
+        merge_reports(paths, false, false, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\
+client,available,held,total,locked
+1,1.0000,0.0000,1.0000,true
+2,2.0000,0.0000,2.0000,false
+"
+        );
+        std::fs::remove_dir_all(dir).unwrap();
     }
 }
@@ -0,0 +1,159 @@
+//! Reading large CSV files on several threads, by memory mapping them and parsing line-aligned chunks in parallel.
+//!
+//! Chunks are parsed by `fast_parse` on a pool of threads and handed back in file order, so transactions still come
+//! out in file order for a single engine to apply. Chunks are split at line ends, so quoted fields can't contain
+//! newlines.
+
+use memmap2::Mmap;
+use std::fs::File;
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::vec;
+
+use crate::fast_parse::{self, Error};
+use crate::transaction::{CsvOptions, Metadata, Transaction};
+
+/// Roughly how much of the file each thread parses at a time
+const CHUNK_SIZE: usize = 1 << 20;
+
+type Row = Result<(Transaction, Metadata), Error>;
+
+/// A chunk's rows, and how many lines it spanned
+type Chunk = (Vec<Row>, u64);
+
+pub struct Reader {
+    /// Where each chunk will be sent once it's parsed, in file order
+    chunks: Receiver<Receiver<Chunk>>,
+    rows: vec::IntoIter<Row>,
+    /// The lines before the current chunk
+    offset: u64,
+    /// The lines before the next chunk
+    next_offset: u64,
+}
+
+/// Reads a CSV file in the standard dialect on `threads` threads, or one per CPU if zero.
+///
+/// The file mustn't be changed while it's read, or the rows read are meaningless.
+pub fn read<P: AsRef<Path>>(path: P, options: &CsvOptions, threads: usize) -> io::Result<Reader> {
+    let file = File::open(path)?;
+    // Safety: only sound as long as nothing else changes the file, which the caller promises
+    let mmap = Arc::new(unsafe { Mmap::map(&file)? });
+    let header_end = line_end(&mmap, 0);
+    let columns = fast_parse::read(&mmap[..header_end], options)
+        .columns()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let threads = match threads {
+        0 => thread::available_parallelism().map_or(1, |n| n.get()),
+        threads => threads,
+    };
+
+    let (work, jobs) = mpsc::sync_channel::<(Range<usize>, SyncSender<Chunk>)>(threads);
+    let jobs = Arc::new(Mutex::new(jobs));
+    for _ in 0..threads {
+        let (mmap, jobs, options, columns) =
+            (mmap.clone(), jobs.clone(), options.clone(), columns.clone());
+        thread::spawn(move || loop {
+            let job = jobs
+                .lock()
+                .expect("no parser panics holding the queue")
+                .recv();
+            let Ok((range, parsed)) = job else { return };
+            let chunk = &mmap[range];
+            let rows = fast_parse::read_rows(chunk, &options, columns.clone()).collect();
+            let lines = chunk.iter().filter(|&&b| b == b'\n').count() as u64;
+            let _ = parsed.send((rows, lines));
+        });
+    }
+
+    // Queues chunks until the reader is dropped or the file is done, a few ahead of the one being applied
+    let (order, chunks) = mpsc::sync_channel(threads * 2);
+    thread::spawn(move || {
+        let mut start = header_end;
+        while start < mmap.len() {
+            let end = line_end(&mmap, (start + CHUNK_SIZE).min(mmap.len()));
+            let (parsed, chunk) = mpsc::sync_channel(1);
+            if order.send(chunk).is_err() || work.send((start..end, parsed)).is_err() {
+                return;
+            }
+            start = end;
+        }
+    });
+
+    Ok(Reader {
+        chunks,
+        rows: Vec::new().into_iter(),
+        offset: 0,
+        next_offset: 1,
+    })
+}
+
+/// Just past the first line end at or after `from`, or the end of the file
+fn line_end(bytes: &[u8], from: usize) -> usize {
+    bytes[from..]
+        .iter()
+        .position(|&b| b == b'\n')
+        .map_or(bytes.len(), |i| from + i + 1)
+}
+
+impl Iterator for Reader {
+    type Item = Row;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(row) = self.rows.next() {
+                return Some(row.map_err(|e| e.offset(self.offset)));
+            }
+            let (rows, lines) = self
+                .chunks
+                .recv()
+                .ok()?
+                .recv()
+                .expect("chunk parser panicked");
+            self.rows = rows.into_iter();
+            self.offset = self.next_offset;
+            self.next_offset += lines;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::generate::{Generator, Options};
+    use std::io::Write;
+
+    #[test]
+    fn same_as_sequential() {
+        let mut file = csv::Writer::from_writer(Vec::new());
+        for transaction in Generator::new(Options::default()).take(100_000) {
+            file.serialize(transaction).unwrap();
+        }
+        let mut data = file.into_inner().unwrap();
+        data.extend_from_slice(b"deposit,1,100001,oops,\n");
+        let path = std::env::temp_dir().join(format!("stm-mmap-{}.csv", std::process::id()));
+        File::create(&path).unwrap().write_all(&data).unwrap();
+
+        let options = CsvOptions::default();
+        let parallel = read(&path, &options, 4).unwrap().collect::<Vec<_>>();
+        std::fs::remove_file(&path).unwrap();
+        let sequential = fast_parse::read(data.as_slice(), &options).collect::<Vec<_>>();
+        assert_eq!(parallel.len(), sequential.len());
+        for (parallel, sequential) in parallel.iter().zip(&sequential) {
+            match (parallel, sequential) {
+                (Ok(parallel), Ok(sequential)) => assert_eq!(parallel, sequential),
+                (Err(parallel), Err(sequential)) => {
+                    assert_eq!(parallel.to_string(), sequential.to_string())
+                }
+                (parallel, sequential) => panic!("{:?} != {:?}", parallel, sequential),
+            }
+        }
+        assert_eq!(
+            parallel.last().unwrap().as_ref().unwrap_err().to_string(),
+            "line 100002: invalid amount"
+        );
+    }
+}
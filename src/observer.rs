@@ -0,0 +1,94 @@
+//! Hooks into the engine, for notifications, metrics and secondary indexes kept alongside it.
+//!
+//! Observers are called synchronously as each transaction is processed, in the order they were added to the engine,
+//! once the transaction has been applied or rejected. Every callback does nothing by default.
+
+use crate::alerts::Alert;
+use crate::engine::Rejection;
+use crate::posting::Postings;
+use crate::risk::Flag;
+use crate::transaction::Transaction;
+
+pub trait EventObserver {
+    /// `postings` are everything the transaction moved, including any fee charged for it
+    fn on_applied(&mut self, _tx: &Transaction, _postings: &Postings) {}
+    fn on_rejected(&mut self, _tx: &Transaction, _rejection: &Rejection) {}
+    /// A risk rule flagged a transaction, which is still applied unless another rule rejects it
+    fn on_flag(&mut self, _flag: &Flag) {}
+    /// A client was locked, by a chargeback or automatically
+    fn on_lock(&mut self, _client_id: u16) {}
+    fn on_dispute_opened(&mut self, _tx: &Transaction) {}
+    /// A dispute was settled by `tx`, a resolve or a chargeback
+    fn on_dispute_closed(&mut self, _tx: &Transaction) {}
+    /// A client's balance crossed one of the engine's alert thresholds
+    fn on_alert(&mut self, _alert: &Alert) {}
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::engine::Engine;
+    use crate::transaction::read_from_csv_reader;
+    use crate::transaction_set::MemoryClient;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// Describes every event it sees
+    struct Events(Rc<RefCell<Vec<String>>>);
+
+    impl EventObserver for Events {
+        fn on_applied(&mut self, tx: &Transaction, _: &Postings) {
+            self.0
+                .borrow_mut()
+                .push(format!("applied {}", tx.transaction_id));
+        }
+        fn on_rejected(&mut self, tx: &Transaction, _: &Rejection) {
+            self.0
+                .borrow_mut()
+                .push(format!("rejected {}", tx.transaction_id));
+        }
+        fn on_lock(&mut self, client_id: u16) {
+            self.0.borrow_mut().push(format!("locked {}", client_id));
+        }
+        fn on_dispute_opened(&mut self, tx: &Transaction) {
+            self.0
+                .borrow_mut()
+                .push(format!("opened {}", tx.transaction_id));
+        }
+        fn on_dispute_closed(&mut self, tx: &Transaction) {
+            self.0
+                .borrow_mut()
+                .push(format!("closed {}", tx.transaction_id));
+        }
+    }
+
+    #[test]
+    fn observe_events() {
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let mut engine = Engine::new(MemoryClient::default()).with_observer(Events(events.clone()));
+        for transaction in read_from_csv_reader(
+            "\
+type,       client,  tx, amount
+deposit,         1,   1,    5.0
+withdrawal,      1,   2,    9.0
+dispute,         1,   1,
+chargeback,      1,   1,
+"
+            .as_bytes(),
+        ) {
+            let _ = engine.process(transaction.unwrap());
+        }
+        assert_eq!(
+            *events.borrow(),
+            vec![
+                "applied 1",
+                "rejected 2",
+                "applied 1",
+                "opened 1",
+                "applied 1",
+                "closed 1",
+                "locked 1",
+            ]
+        );
+    }
+}
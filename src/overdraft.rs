@@ -0,0 +1,52 @@
+//! How far each client may overdraw their available funds
+
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::io::Read;
+
+use crate::decimal::Decimal;
+
+/// A default overdraft limit, and overrides for particular clients
+#[derive(Clone, Debug, Default)]
+pub struct OverdraftLimits {
+    default: Decimal,
+    clients: HashMap<u16, Decimal>,
+}
+
+impl OverdraftLimits {
+    pub fn new(default: Decimal) -> Self {
+        OverdraftLimits {
+            default,
+            clients: HashMap::new(),
+        }
+    }
+
+    pub fn with(mut self, client: u16, limit: Decimal) -> Self {
+        self.clients.insert(client, limit);
+        self
+    }
+
+    /// Reads `client,limit` rows overriding `default`
+    pub fn from_csv_reader<R: Read>(rdr: R, default: Decimal) -> csv::Result<Self> {
+        #[derive(Deserialize)]
+        struct Row {
+            client: u16,
+            limit: Decimal,
+        }
+
+        let mut limits = OverdraftLimits::new(default);
+        for row in csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(rdr)
+            .into_deserialize()
+        {
+            let Row { client, limit } = row?;
+            limits = limits.with(client, limit);
+        }
+        Ok(limits)
+    }
+
+    pub fn limit(&self, client: u16) -> Decimal {
+        self.clients.get(&client).unwrap_or(&self.default).clone()
+    }
+}
@@ -0,0 +1,97 @@
+//! Closing business periods, e.g. at the end of each day.
+//!
+//! Closing a period snapshots every client's balances and the disputes still open with the reasons they were opened
+//! for, which roll forward into the next period, and starts a new segment of the ledger so that each period's postings
+//! are totalled on their own. Periods are keyed by an id such as the business date, so reports can be produced as of
+//! the close of any of them.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::client::ClientOutput;
+use crate::decimal::Decimal;
+use crate::posting::{Account, Postings};
+use crate::transaction::TransactionKind;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Period {
+    pub id: String,
+    /// Every client's balances at the close, in client order
+    pub balances: Vec<ClientOutput>,
+    /// The disputes still open at the close, by transaction id, with the reason given for each
+    pub open_disputes: BTreeMap<u32, Option<String>>,
+    /// The debits (postings into) and credits (postings out of) each account during the period
+    pub ledger: BTreeMap<Account, (Decimal, Decimal)>,
+}
+
+/// A period id which has already been closed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DuplicatePeriod(pub String);
+
+impl fmt::Display for DuplicatePeriod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Period {:?} already closed.", self.0)
+    }
+}
+
+impl std::error::Error for DuplicatePeriod {}
+
+/// The closed periods, in the order they were closed, and the open one's ledger segment
+#[derive(Clone, Debug, Default)]
+pub struct Periods {
+    closed: Vec<Period>,
+    open_disputes: BTreeMap<u32, Option<String>>,
+    ledger: BTreeMap<Account, (Decimal, Decimal)>,
+}
+
+impl Periods {
+    /// Records an applied transaction in the open period
+    pub(crate) fn record(
+        &mut self,
+        kind: TransactionKind,
+        transaction_id: u32,
+        reason: Option<String>,
+        postings: &Postings,
+    ) {
+        match kind {
+            TransactionKind::Dispute => {
+                self.open_disputes.insert(transaction_id, reason);
+            }
+            TransactionKind::Resolve | TransactionKind::Chargeback => {
+                self.open_disputes.remove(&transaction_id);
+            }
+            _ => {}
+        }
+        for posting in postings.iter() {
+            self.ledger.entry(posting.to).or_default().0 += posting.amount.clone();
+            self.ledger.entry(posting.from).or_default().1 += posting.amount.clone();
+        }
+    }
+
+    /// Closes the open period as `id` with the given balances, and opens the next with the same open disputes
+    pub(crate) fn close(
+        &mut self,
+        id: String,
+        mut balances: Vec<ClientOutput>,
+    ) -> Result<&Period, DuplicatePeriod> {
+        if self.get(&id).is_some() {
+            return Err(DuplicatePeriod(id));
+        }
+        balances.sort_by_key(|c| c.client);
+        self.closed.push(Period {
+            id,
+            balances,
+            open_disputes: self.open_disputes.clone(),
+            ledger: std::mem::take(&mut self.ledger),
+        });
+        Ok(self.closed.last().expect("just pushed"))
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Period> {
+        self.closed.iter().find(|p| p.id == id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Period> {
+        self.closed.iter()
+    }
+}
@@ -0,0 +1,321 @@
+//! Protobuf encoding of transactions, mirroring `proto/transaction.proto`.
+//!
+//! Streams are length delimited, each message prefixed by its length as a varint.
+
+use prost::Message;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use crate::transaction::{Adjustment, DisputableType, Transaction, TransactionKind, Type};
+
+/// The generated types, kept in step with `proto/transaction.proto` by hand
+pub mod pb {
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Transaction {
+        #[prost(enumeration = "Type", tag = "1")]
+        pub r#type: i32,
+        #[prost(uint32, tag = "2")]
+        pub client: u32,
+        #[prost(uint32, tag = "3")]
+        pub tx: u32,
+        #[prost(string, optional, tag = "4")]
+        pub amount: Option<String>,
+        #[prost(string, optional, tag = "5")]
+        pub reason: Option<String>,
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+    #[repr(i32)]
+    pub enum Type {
+        Unspecified = 0,
+        Deposit = 1,
+        Withdrawal = 2,
+        Dispute = 3,
+        Resolve = 4,
+        Chargeback = 5,
+        Fee = 6,
+        Interest = 7,
+        Close = 8,
+        Authorize = 9,
+        Capture = 10,
+        Void = 11,
+        Refund = 12,
+        Adjustment = 13,
+        Status = 14,
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Decode(prost::DecodeError),
+    /// A message was decoded, but isn't a valid transaction
+    Invalid(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => e.fmt(f),
+            Error::Decode(e) => e.fmt(f),
+            Error::Invalid(what) => f.write_str(what),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<TransactionKind> for pb::Type {
+    fn from(kind: TransactionKind) -> Self {
+        match kind {
+            TransactionKind::Deposit => pb::Type::Deposit,
+            TransactionKind::Withdrawal => pb::Type::Withdrawal,
+            TransactionKind::Dispute => pb::Type::Dispute,
+            TransactionKind::Resolve => pb::Type::Resolve,
+            TransactionKind::Chargeback => pb::Type::Chargeback,
+            TransactionKind::Fee => pb::Type::Fee,
+            TransactionKind::Interest => pb::Type::Interest,
+            TransactionKind::Close => pb::Type::Close,
+            TransactionKind::Authorize => pb::Type::Authorize,
+            TransactionKind::Capture => pb::Type::Capture,
+            TransactionKind::Void => pb::Type::Void,
+            TransactionKind::Refund => pb::Type::Refund,
+            TransactionKind::Adjustment => pb::Type::Adjustment,
+            TransactionKind::Status => pb::Type::Status,
+        }
+    }
+}
+
+impl TryFrom<pb::Type> for TransactionKind {
+    type Error = Error;
+    fn try_from(type_: pb::Type) -> Result<Self, Error> {
+        Ok(match type_ {
+            pb::Type::Deposit => TransactionKind::Deposit,
+            pb::Type::Withdrawal => TransactionKind::Withdrawal,
+            pb::Type::Dispute => TransactionKind::Dispute,
+            pb::Type::Resolve => TransactionKind::Resolve,
+            pb::Type::Chargeback => TransactionKind::Chargeback,
+            pb::Type::Fee => TransactionKind::Fee,
+            pb::Type::Interest => TransactionKind::Interest,
+            pb::Type::Close => TransactionKind::Close,
+            pb::Type::Authorize => TransactionKind::Authorize,
+            pb::Type::Capture => TransactionKind::Capture,
+            pb::Type::Void => TransactionKind::Void,
+            pb::Type::Refund => TransactionKind::Refund,
+            pb::Type::Adjustment => TransactionKind::Adjustment,
+            pb::Type::Status => TransactionKind::Status,
+            pb::Type::Unspecified => {
+                return Err(Error::Invalid("unspecified transaction type".to_owned()))
+            }
+        })
+    }
+}
+
+impl From<Transaction> for pb::Transaction {
+    fn from(t: Transaction) -> Self {
+        let reason = t.type_.reason().map(str::to_owned);
+        let (type_, amount) = match t.type_ {
+            Type::Disputable(DisputableType::Deposit(amount)) => {
+                (pb::Type::Deposit, Some(amount.to_string()))
+            }
+            Type::Disputable(DisputableType::Withdrawal(amount)) => {
+                (pb::Type::Withdrawal, Some(amount.to_string()))
+            }
+            Type::Dispute(_) => (pb::Type::Dispute, None),
+            Type::Resolve(_) => (pb::Type::Resolve, None),
+            Type::Chargeback(_) => (pb::Type::Chargeback, None),
+            Type::Fee(amount) => (pb::Type::Fee, Some(amount.to_string())),
+            Type::Interest(amount) => (pb::Type::Interest, Some(amount.to_string())),
+            Type::Close => (pb::Type::Close, None),
+            Type::Authorize(amount) => (pb::Type::Authorize, Some(amount.to_string())),
+            Type::Capture => (pb::Type::Capture, None),
+            Type::Void => (pb::Type::Void, None),
+            Type::Refund(amount) => (pb::Type::Refund, Some(amount.to_string())),
+            Type::Adjustment(adjustment) => {
+                let sign = if adjustment.debit { "-" } else { "" };
+                (
+                    pb::Type::Adjustment,
+                    Some(format!("{}{}", sign, adjustment.amount)),
+                )
+            }
+            Type::Status(_) => (pb::Type::Status, None),
+        };
+        pb::Transaction {
+            r#type: type_ as i32,
+            client: t.client_id.into(),
+            tx: t.transaction_id,
+            amount,
+            reason,
+        }
+    }
+}
+
+impl TryFrom<pb::Transaction> for Transaction {
+    type Error = Error;
+    fn try_from(t: pb::Transaction) -> Result<Self, Error> {
+        let amount = || match &t.amount {
+            Some(amount) => amount
+                .parse()
+                .map_err(|_| Error::Invalid(format!("invalid amount {:?}", amount))),
+            None => Err(Error::Invalid("Missing amount".to_owned())),
+        };
+        let type_ = match pb::Type::try_from(t.r#type) {
+            Ok(pb::Type::Deposit) => Type::Disputable(DisputableType::Deposit(amount()?)),
+            Ok(pb::Type::Withdrawal) => Type::Disputable(DisputableType::Withdrawal(amount()?)),
+            Ok(pb::Type::Dispute) => Type::Dispute(t.reason.clone()),
+            Ok(pb::Type::Resolve) => Type::Resolve(t.reason.clone()),
+            Ok(pb::Type::Chargeback) => Type::Chargeback(t.reason.clone()),
+            Ok(pb::Type::Fee) => Type::Fee(amount()?),
+            Ok(pb::Type::Interest) => Type::Interest(amount()?),
+            Ok(pb::Type::Close) => Type::Close,
+            Ok(pb::Type::Authorize) => Type::Authorize(amount()?),
+            Ok(pb::Type::Capture) => Type::Capture,
+            Ok(pb::Type::Void) => Type::Void,
+            Ok(pb::Type::Refund) => Type::Refund(amount()?),
+            Ok(pb::Type::Adjustment) => {
+                let amount = t
+                    .amount
+                    .as_deref()
+                    .ok_or_else(|| Error::Invalid("Missing amount".to_owned()))?;
+                let (debit, magnitude) = match amount.strip_prefix('-') {
+                    Some(magnitude) => (true, magnitude),
+                    None => (false, amount),
+                };
+                Type::Adjustment(Adjustment {
+                    debit,
+                    amount: magnitude
+                        .parse()
+                        .map_err(|_| Error::Invalid(format!("invalid amount {:?}", amount)))?,
+                    reason: t
+                        .reason
+                        .clone()
+                        .ok_or_else(|| Error::Invalid("Missing reason".to_owned()))?,
+                })
+            }
+            Ok(pb::Type::Status) => Type::Status(
+                t.reason
+                    .as_deref()
+                    .and_then(|s| s.parse().ok())
+                    .ok_or_else(|| Error::Invalid("Missing or unknown status".to_owned()))?,
+            ),
+            Ok(pb::Type::Unspecified) | Err(_) => {
+                return Err(Error::Invalid(format!(
+                    "unknown transaction type {}",
+                    t.r#type
+                )))
+            }
+        };
+        Ok(Transaction {
+            client_id: u16::try_from(t.client)
+                .map_err(|_| Error::Invalid(format!("client {} out of range", t.client)))?,
+            transaction_id: t.tx,
+            type_,
+            metadata: HashMap::new(),
+        })
+    }
+}
+
+/// Transactions from a length delimited stream
+pub struct Reader<R: Read> {
+    rdr: R,
+    buf: Vec<u8>,
+    failed: bool,
+}
+
+impl<R: Read> Reader<R> {
+    pub fn new(rdr: R) -> Self {
+        Reader {
+            rdr,
+            buf: Vec::new(),
+            failed: false,
+        }
+    }
+
+    /// Reads the next length prefix, or `None` at a clean end of stream
+    fn read_length(&mut self) -> Result<Option<usize>, Error> {
+        let mut length = 0u64;
+        for (i, shift) in (0..64).step_by(7).enumerate() {
+            let mut byte = [0];
+            if self.rdr.read(&mut byte)? == 0 {
+                return match i {
+                    0 => Ok(None),
+                    _ => Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+                };
+            }
+            length |= u64::from(byte[0] & 0x7f) << shift;
+            if byte[0] & 0x80 == 0 {
+                return Ok(Some(length as usize));
+            }
+        }
+        Err(Error::Invalid("length prefix too long".to_owned()))
+    }
+}
+
+impl<R: Read> Iterator for Reader<R> {
+    type Item = Result<Transaction, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // A broken length prefix leaves us nowhere to resume from
+        if self.failed {
+            return None;
+        }
+        let length = match self.read_length() {
+            Ok(Some(length)) => length,
+            Ok(None) => return None,
+            Err(e) => {
+                self.failed = true;
+                return Some(Err(e));
+            }
+        };
+        self.buf.resize(length, 0);
+        if let Err(e) = self.rdr.read_exact(&mut self.buf) {
+            self.failed = true;
+            return Some(Err(e.into()));
+        }
+        Some(
+            pb::Transaction::decode(&self.buf[..])
+                .map_err(Error::Decode)
+                .and_then(Transaction::try_from),
+        )
+    }
+}
+
+/// Writes a single transaction, prefixed by its length
+pub fn write_length_delimited<W: Write>(mut w: W, transaction: Transaction) -> io::Result<()> {
+    w.write_all(&pb::Transaction::from(transaction).encode_length_delimited_to_vec())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::generate::{Generator, Options};
+
+    #[test]
+    fn round_trip_stream() {
+        let transactions = Generator::new(Options::default())
+            .take(1000)
+            .collect::<Vec<_>>();
+        let mut stream = Vec::new();
+        for transaction in transactions.iter().cloned() {
+            write_length_delimited(&mut stream, transaction).unwrap();
+        }
+        let read = Reader::new(&stream[..])
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(read, transactions);
+
+        // Truncated mid-message
+        let mut read = Reader::new(&stream[..stream.len() - 1]);
+        assert!(read.by_ref().take(999).all(|t| t.is_ok()));
+        assert!(matches!(read.next(), Some(Err(Error::Io(_)))));
+        assert!(read.next().is_none());
+    }
+}
@@ -0,0 +1,174 @@
+//! A read-only HTTP endpoint for querying a batch while it runs, so long reprocessing jobs can be watched mid-flight.
+//!
+//! The engine isn't shared with the server. Instead the batch publishes a `View` of it between transactions every so
+//! often, which only copies the clients changed since the last, and each request is answered from the latest, so every
+//! answer is consistent with one point in the run. Only `GET` is served, one connection at a time, with JSON bodies:
+//!
+//! - `/status`: how many transactions had been read when the view was published
+//! - `/clients`: every client's report row
+//! - `/clients/<id>`: one client's report row and open disputes
+
+use serde::Serialize;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, RwLock};
+
+use crate::client::ClientOutput;
+use crate::view::{Dispute, View};
+
+/// The latest view, and how many transactions had been read when it was taken, which the batch publishes to and the
+/// server answers from. Clones share it.
+#[derive(Clone, Default)]
+pub struct Published {
+    latest: Arc<RwLock<(u64, View)>>,
+}
+
+impl Published {
+    /// Replaces the view answered from, without waiting for requests answered from the last one
+    pub fn publish(&self, transactions: u64, view: View) {
+        *self.latest.write().expect("publishing panicked") = (transactions, view);
+    }
+
+    pub fn current(&self) -> (u64, View) {
+        self.latest.read().expect("publishing panicked").clone()
+    }
+}
+
+#[derive(Serialize)]
+struct Status {
+    transactions: u64,
+}
+
+#[derive(Serialize)]
+struct ClientView<'a> {
+    #[serde(flatten)]
+    client: &'a ClientOutput,
+    disputes: &'a [Dispute],
+}
+
+/// The status and JSON body answering a `GET` of `path`
+fn answer(transactions: u64, view: &View, path: &str) -> (&'static str, String) {
+    let json =
+        |value: serde_json::Result<String>| ("200 OK", value.expect("views always serialize"));
+    let not_found = || ("404 Not Found", "{\"error\":\"not found\"}".to_owned());
+    match path.trim_end_matches('/') {
+        "/status" => json(serde_json::to_string(&Status { transactions })),
+        "/clients" => json(serde_json::to_string(&view.report().collect::<Vec<_>>())),
+        path => match path.strip_prefix("/clients/").map(str::parse::<u16>) {
+            Some(Ok(id)) => match view.client(id) {
+                Some(client) => json(serde_json::to_string(&ClientView {
+                    client,
+                    disputes: view.open_disputes(id),
+                })),
+                None => not_found(),
+            },
+            _ => not_found(),
+        },
+    }
+}
+
+fn respond(stream: TcpStream, published: &Published) -> io::Result<()> {
+    let mut reader = BufReader::new(&stream);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    // The headers aren't needed, but are read so the client isn't reset mid-request
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+    let mut parts = request.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some(path)) => {
+            let (transactions, view) = published.current();
+            answer(transactions, &view, path)
+        }
+        (Some(_), Some(_)) => (
+            "405 Method Not Allowed",
+            "{\"error\":\"only GET is served\"}".to_owned(),
+        ),
+        _ => ("400 Bad Request", "{\"error\":\"bad request\"}".to_owned()),
+    };
+    write!(
+        &stream,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+/// Answers requests on `listener` from whatever view is published, until the process exits. A connection which fails
+/// is dropped without stopping the server.
+pub fn serve(listener: TcpListener, published: Published) {
+    for stream in listener.incoming().flatten() {
+        let _ = respond(stream, &published);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::engine::Engine;
+    use crate::transaction::read_from_csv_reader;
+    use crate::transaction_set::MemoryClient;
+    use std::io::Read;
+
+    #[test]
+    fn queries() {
+        let mut engine = Engine::new(MemoryClient::default());
+        for transaction in read_from_csv_reader(
+            "\
+type,       client,  tx, amount, reason
+deposit,         1,   1,    5.0,
+deposit,         2,   2,    3.0,
+dispute,         2,   2,       , not mine
+"
+            .as_bytes(),
+        ) {
+            engine.process(transaction.unwrap()).unwrap();
+        }
+        let published = Published::default();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = published.clone();
+        std::thread::spawn(move || serve(listener, server));
+
+        let get = |request: &str| {
+            let mut stream = TcpStream::connect(address).unwrap();
+            stream.write_all(request.as_bytes()).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            let (head, body) = response.split_once("\r\n\r\n").unwrap();
+            (head.lines().next().unwrap().to_owned(), body.to_owned())
+        };
+        // Nothing's been published yet
+        assert_eq!(
+            get("GET /status HTTP/1.1\r\n\r\n").1,
+            "{\"transactions\":0}"
+        );
+        published.publish(3, engine.view());
+        assert_eq!(
+            get("GET /status HTTP/1.1\r\nHost: localhost\r\n\r\n").1,
+            "{\"transactions\":3}"
+        );
+        let (status, body) = get("GET /clients/2 HTTP/1.1\r\n\r\n");
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        let client: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(client["held"], "3.0000");
+        assert_eq!(
+            client["disputes"],
+            serde_json::json!([{"tx": 2, "reason": "not mine"}])
+        );
+        let clients: serde_json::Value =
+            serde_json::from_str(&get("GET /clients HTTP/1.1\r\n\r\n").1).unwrap();
+        assert_eq!(clients.as_array().unwrap().len(), 2);
+        assert_eq!(
+            get("GET /clients/9 HTTP/1.1\r\n\r\n").0,
+            "HTTP/1.1 404 Not Found"
+        );
+        assert_eq!(
+            get("POST /clients HTTP/1.1\r\n\r\n").0,
+            "HTTP/1.1 405 Method Not Allowed"
+        );
+    }
+}
@@ -0,0 +1,179 @@
+//! A bounded queue between reading transactions and applying them, so input arriving faster than the engine can keep
+//! up is pushed back on, or shed, rather than buffered without limit.
+//!
+//! The queue keeps count of how deep it is, how deep it's been and how much it's shed, for monitoring.
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+
+/// What to do with an item sent to a full queue
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Overflow {
+    /// Wait for room
+    #[default]
+    Block,
+    /// Hand it straight back, to be rejected
+    Shed,
+}
+
+impl FromStr for Overflow {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "block" => Ok(Overflow::Block),
+            "shed" => Ok(Overflow::Shed),
+            _ => Err(format!("unknown overflow policy {:?}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Metrics {
+    depth: AtomicUsize,
+    peak: AtomicUsize,
+    shed: AtomicU64,
+}
+
+impl Metrics {
+    /// How many items are waiting, counting any a sender is blocked on
+    pub fn depth(&self) -> usize {
+        self.depth.load(Ordering::Relaxed)
+    }
+
+    /// The most items that have been waiting at once
+    pub fn peak(&self) -> usize {
+        self.peak.load(Ordering::Relaxed)
+    }
+
+    /// How many items have been shed
+    pub fn shed(&self) -> u64 {
+        self.shed.load(Ordering::Relaxed)
+    }
+}
+
+/// Why an item wasn't queued, with the item
+#[derive(Debug, PartialEq, Eq)]
+pub enum SendError<T> {
+    /// The queue was full and sheds on overflow
+    Full(T),
+    /// The queue has been dropped
+    Closed(T),
+}
+
+impl<T> fmt::Display for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendError::Full(_) => write!(f, "shed, ingestion queue full"),
+            SendError::Closed(_) => write!(f, "ingestion queue closed"),
+        }
+    }
+}
+
+pub struct Sender<T> {
+    sender: SyncSender<T>,
+    overflow: Overflow,
+    metrics: Arc<Metrics>,
+}
+
+pub struct Queue<T> {
+    receiver: Receiver<T>,
+    metrics: Arc<Metrics>,
+}
+
+/// A queue holding up to `capacity` items
+pub fn bounded<T>(capacity: usize, overflow: Overflow) -> (Sender<T>, Queue<T>) {
+    let (sender, receiver) = mpsc::sync_channel(capacity);
+    let metrics = Arc::new(Metrics::default());
+    (
+        Sender {
+            sender,
+            overflow,
+            metrics: metrics.clone(),
+        },
+        Queue { receiver, metrics },
+    )
+}
+
+impl<T> Sender<T> {
+    pub fn send(&self, item: T) -> Result<(), SendError<T>> {
+        // Counted first, so the receiver never takes it below zero
+        let depth = self.metrics.depth.fetch_add(1, Ordering::Relaxed) + 1;
+        let sent = match self.overflow {
+            Overflow::Block => self.sender.send(item).map_err(|e| SendError::Closed(e.0)),
+            Overflow::Shed => self.sender.try_send(item).map_err(|e| match e {
+                TrySendError::Full(item) => SendError::Full(item),
+                TrySendError::Disconnected(item) => SendError::Closed(item),
+            }),
+        };
+        match &sent {
+            Ok(()) => {
+                self.metrics.peak.fetch_max(depth, Ordering::Relaxed);
+            }
+            Err(e) => {
+                self.metrics.depth.fetch_sub(1, Ordering::Relaxed);
+                if let SendError::Full(_) = e {
+                    self.metrics.shed.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+        sent
+    }
+}
+
+impl<T> Queue<T> {
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.metrics.clone()
+    }
+}
+
+impl<T> Iterator for Queue<T> {
+    type Item = T;
+
+    /// Waits for the next item, until every sender has been dropped
+    fn next(&mut self) -> Option<T> {
+        let item = self.receiver.recv().ok()?;
+        self.metrics.depth.fetch_sub(1, Ordering::Relaxed);
+        Some(item)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn overflow() {
+        let (sender, queue) = bounded(2, Overflow::Shed);
+        let metrics = queue.metrics();
+        assert_eq!(
+            (0..4).map(|i| sender.send(i)).collect::<Vec<_>>(),
+            [
+                Ok(()),
+                Ok(()),
+                Err(SendError::Full(2)),
+                Err(SendError::Full(3))
+            ]
+        );
+        assert_eq!((metrics.depth(), metrics.peak(), metrics.shed()), (2, 2, 2));
+        drop(sender);
+        assert_eq!(queue.collect::<Vec<_>>(), [0, 1]);
+        assert_eq!(metrics.depth(), 0);
+
+        let (sender, queue) = bounded(2, Overflow::Block);
+        let metrics = queue.metrics();
+        let producer = thread::spawn(move || (0..100).map(|i| sender.send(i)).all(|s| s.is_ok()));
+        assert_eq!(queue.collect::<Vec<_>>(), (0..100).collect::<Vec<_>>());
+        assert!(producer.join().unwrap());
+        assert_eq!(metrics.shed(), 0);
+        // Two queued, one blocked on, and one just taken which hasn't been counted off yet
+        assert!(metrics.peak() <= 4);
+
+        let (sender, queue) = bounded(2, Overflow::Block);
+        drop(queue);
+        assert_eq!(sender.send(0), Err(SendError::Closed(0)));
+    }
+}
@@ -0,0 +1,89 @@
+//! Limiting how many transactions each client can make a second, to protect the engine from runaway upstream retries.
+//!
+//! Each client has a token bucket holding up to `burst` tokens, which refills at `rate` tokens a second. Every
+//! transaction for the client takes a token, or is rejected if there are none left. Transactions aren't dated, so
+//! buckets are refilled by a clock, the system clock by default.
+
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+pub struct RateLimit {
+    /// Tokens added a second
+    pub rate: f64,
+    /// How many tokens a bucket holds, and starts with
+    pub burst: u32,
+    clock: Box<dyn Fn() -> u64>,
+    /// Each client's tokens, and when they were last refilled
+    buckets: HashMap<u16, (f64, u64)>,
+}
+
+impl RateLimit {
+    /// At most `rate` transactions a second per client, after a burst of up to `burst`
+    pub fn new(rate: f64, burst: u32) -> Self {
+        RateLimit {
+            rate,
+            burst,
+            clock: Box::new(now_millis),
+            buckets: HashMap::new(),
+        }
+    }
+
+    /// Refills buckets by `clock`, in unix milliseconds, instead of the system clock
+    pub fn with_clock<C: Fn() -> u64 + 'static>(mut self, clock: C) -> Self {
+        self.clock = Box::new(clock);
+        self
+    }
+
+    /// Takes a token from the client's bucket, returning whether there was one
+    pub(crate) fn take(&mut self, client_id: u16) -> bool {
+        let now = (self.clock)();
+        let burst = f64::from(self.burst);
+        let (tokens, refilled) = self.buckets.entry(client_id).or_insert((burst, now));
+        let elapsed = now.saturating_sub(*refilled) as f64 / 1000.0;
+        *tokens = (*tokens + elapsed * self.rate).min(burst);
+        *refilled = now;
+        if *tokens < 1.0 {
+            return false;
+        }
+        *tokens -= 1.0;
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    #[test]
+    fn token_buckets() {
+        let clock = Rc::new(Cell::new(1_000_000));
+        let mut limit = RateLimit::new(2.0, 3).with_clock({
+            let clock = clock.clone();
+            move || clock.get()
+        });
+        // The burst, then nothing until the bucket refills
+        assert_eq!(
+            (0..4).map(|_| limit.take(1)).collect::<Vec<_>>(),
+            [true, true, true, false]
+        );
+        assert!(limit.take(2));
+
+        clock.set(clock.get() + 499);
+        assert!(!limit.take(1));
+        clock.set(clock.get() + 1);
+        assert!(limit.take(1));
+        assert!(!limit.take(1));
+
+        // Never refills past the burst
+        clock.set(clock.get() + 60_000);
+        assert_eq!((0..4).filter(|_| limit.take(1)).count(), 3);
+    }
+}
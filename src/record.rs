@@ -0,0 +1,701 @@
+//! The binary encoding of transactions and clients kept on disk, shared by every store which persists them.
+//!
+//! A record is a version byte followed by the record in that version's layout, encoded with postcard. Records are only
+//! ever written in `FORMAT_VERSION`, but older versions are still read: each keeps its layout here, and decoding one
+//! upgrades it to the current types. Enums are encoded by their position, so new variants go at the end.
+//!
+//! Stores and checkpoints kept in a single file use a file of records: `STMR`, then every record as its kind, its
+//! little-endian `u32` length and itself. `migrate` rewrites one with every record in the current version.
+//!
+//! A record in a file may be compressed on its own, which the upper bits of its kind say how, so stores can still seek
+//! to any one of them. It's only kept compressed if that made it smaller, and is decompressed as it's read.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use serde::{Deserialize, Serialize};
+
+use crate::client::{Client, ClientRecord, Lifetime};
+use crate::compression::Compression;
+use crate::decimal::Decimal;
+use crate::transaction::{DisputableTransaction, DisputableType, TransactionKind};
+use crate::transaction_set::State;
+
+/// The version records are written in
+pub const FORMAT_VERSION: u8 = 2;
+
+/// Why a record couldn't be read
+#[derive(Debug)]
+pub enum Error {
+    /// Not even a version byte
+    Empty,
+    /// Written by a newer version of us, or not a record at all
+    UnknownVersion(u8),
+    Malformed(postcard::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Empty => write!(f, "empty record"),
+            Error::UnknownVersion(version) => write!(f, "unknown record version {}", version),
+            Error::Malformed(e) => write!(f, "malformed record: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<postcard::Error> for Error {
+    fn from(e: postcard::Error) -> Self {
+        Error::Malformed(e)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct TransactionV1<'a> {
+    client_id: u16,
+    transaction_id: u32,
+    withdrawal: bool,
+    amount: (u64, u16),
+    metadata: Cow<'a, HashMap<String, String>>,
+    history: Cow<'a, [(TransactionKind, Option<String>)]>,
+    state: State,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ClientV1 {
+    id: u16,
+    available: (u64, u16),
+    held: (u64, u16),
+    held_reserve: (u64, u16),
+    reserve: (u64, u16),
+    overdraft: (u64, u16),
+    overdraft_limit: (u64, u16),
+    pending: (u64, u16),
+    locked: bool,
+    closed: bool,
+    disputes: u32,
+    chargebacks: u32,
+}
+
+/// Adds lifetime statistics, with transactions and disputes unchanged
+#[derive(Serialize, Deserialize)]
+struct ClientV2 {
+    id: u16,
+    available: (u64, u16),
+    held: (u64, u16),
+    held_reserve: (u64, u16),
+    reserve: (u64, u16),
+    overdraft: (u64, u16),
+    overdraft_limit: (u64, u16),
+    pending: (u64, u16),
+    locked: bool,
+    closed: bool,
+    disputes: u32,
+    chargebacks: u32,
+    deposits: u32,
+    deposited: (u64, u16),
+    withdrawals: u32,
+    withdrawn: (u64, u16),
+    disputed: (u64, u16),
+    charged_back: (u64, u16),
+    first_activity: Option<u64>,
+    last_activity: Option<u64>,
+}
+
+impl From<ClientV1> for ClientV2 {
+    fn from(c: ClientV1) -> Self {
+        ClientV2 {
+            id: c.id,
+            available: c.available,
+            held: c.held,
+            held_reserve: c.held_reserve,
+            reserve: c.reserve,
+            overdraft: c.overdraft,
+            overdraft_limit: c.overdraft_limit,
+            pending: c.pending,
+            locked: c.locked,
+            closed: c.closed,
+            disputes: c.disputes,
+            chargebacks: c.chargebacks,
+            deposits: 0,
+            deposited: (0, 0),
+            withdrawals: 0,
+            withdrawn: (0, 0),
+            disputed: (0, 0),
+            charged_back: (0, 0),
+            first_activity: None,
+            last_activity: None,
+        }
+    }
+}
+
+/// A dispute still open against one of a client's transactions
+#[derive(Serialize, Deserialize)]
+struct DisputeV1<'a> {
+    client_id: u16,
+    transaction_id: u32,
+    reason: Option<Cow<'a, str>>,
+}
+
+/// The version a record was written in
+pub fn version(bytes: &[u8]) -> Result<u8, Error> {
+    bytes.first().copied().ok_or(Error::Empty)
+}
+
+fn encode<T: Serialize>(record: &T) -> Vec<u8> {
+    postcard::to_extend(record, vec![FORMAT_VERSION]).expect("records always serialize")
+}
+
+pub fn encode_transaction(transaction: &DisputableTransaction, state: State) -> Vec<u8> {
+    let (withdrawal, amount) = match &transaction.type_ {
+        DisputableType::Deposit(amount) => (false, amount),
+        DisputableType::Withdrawal(amount) => (true, amount),
+    };
+    encode(&TransactionV1 {
+        client_id: transaction.client_id,
+        transaction_id: transaction.transaction_id,
+        withdrawal,
+        amount: amount.parts(),
+        metadata: Cow::Borrowed(&transaction.metadata),
+        history: Cow::Borrowed(&transaction.history),
+        state,
+    })
+}
+
+pub fn decode_transaction(bytes: &[u8]) -> Result<(DisputableTransaction, State), Error> {
+    let record: TransactionV1 = match version(bytes)? {
+        1 | 2 => postcard::from_bytes(&bytes[1..])?,
+        version => return Err(Error::UnknownVersion(version)),
+    };
+    let amount = Decimal::new(record.amount.0, record.amount.1);
+    let transaction = DisputableTransaction {
+        client_id: record.client_id,
+        transaction_id: record.transaction_id,
+        type_: match record.withdrawal {
+            false => DisputableType::Deposit(amount),
+            true => DisputableType::Withdrawal(amount),
+        },
+        metadata: record.metadata.into_owned(),
+        history: record.history.into_owned(),
+    };
+    Ok((transaction, record.state))
+}
+
+pub fn encode_client(client: &Client) -> Vec<u8> {
+    let c = ClientRecord::from(client.clone());
+    let lifetime = c.lifetime;
+    encode(&ClientV2 {
+        id: c.id,
+        available: c.available.parts(),
+        held: c.held.parts(),
+        held_reserve: c.held_reserve.parts(),
+        reserve: c.reserve.parts(),
+        overdraft: c.overdraft.parts(),
+        overdraft_limit: c.overdraft_limit.parts(),
+        pending: c.pending.parts(),
+        locked: c.locked,
+        closed: c.closed,
+        disputes: c.disputes,
+        chargebacks: c.chargebacks,
+        deposits: lifetime.deposits,
+        deposited: lifetime.deposited.parts(),
+        withdrawals: lifetime.withdrawals,
+        withdrawn: lifetime.withdrawn.parts(),
+        disputed: lifetime.disputed.parts(),
+        charged_back: lifetime.charged_back.parts(),
+        first_activity: lifetime.first_activity,
+        last_activity: lifetime.last_activity,
+    })
+}
+
+pub fn decode_client(bytes: &[u8]) -> Result<Client, Error> {
+    let c: ClientV2 = match version(bytes)? {
+        1 => postcard::from_bytes::<ClientV1>(&bytes[1..])?.into(),
+        2 => postcard::from_bytes(&bytes[1..])?,
+        version => return Err(Error::UnknownVersion(version)),
+    };
+    let decimal = |(dollars, cents)| Decimal::new(dollars, cents);
+    Ok(Client::from(ClientRecord {
+        id: c.id,
+        available: decimal(c.available),
+        held: decimal(c.held),
+        held_reserve: decimal(c.held_reserve),
+        reserve: decimal(c.reserve),
+        overdraft: decimal(c.overdraft),
+        overdraft_limit: decimal(c.overdraft_limit),
+        pending: decimal(c.pending),
+        locked: c.locked,
+        closed: c.closed,
+        disputes: c.disputes,
+        chargebacks: c.chargebacks,
+        lifetime: Lifetime {
+            deposits: c.deposits,
+            deposited: decimal(c.deposited),
+            withdrawals: c.withdrawals,
+            withdrawn: decimal(c.withdrawn),
+            disputed: decimal(c.disputed),
+            charged_back: decimal(c.charged_back),
+            first_activity: c.first_activity,
+            last_activity: c.last_activity,
+        },
+    }))
+}
+
+/// What a record in a file holds
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    Transaction,
+    Client,
+    Dispute,
+}
+
+impl Kind {
+    fn tag(self) -> u8 {
+        match self {
+            Kind::Transaction => 0,
+            Kind::Client => 1,
+            Kind::Dispute => 2,
+        }
+    }
+
+    fn from_tag(tag: u8) -> Option<Self> {
+        match tag {
+            0 => Some(Kind::Transaction),
+            1 => Some(Kind::Client),
+            2 => Some(Kind::Dispute),
+            _ => None,
+        }
+    }
+}
+
+/// The upper bits of a record's kind byte, saying how it's compressed
+fn compression_tag(compression: Compression) -> u8 {
+    match compression {
+        Compression::None => 0,
+        Compression::Gzip => 1 << 4,
+        Compression::Zstd => 2 << 4,
+    }
+}
+
+/// The kind of a record and how it's compressed, from its kind byte
+fn split_tag(tag: u8) -> Option<(Kind, Compression)> {
+    let compression = match tag >> 4 {
+        0 => Compression::None,
+        1 => Compression::Gzip,
+        2 => Compression::Zstd,
+        _ => return None,
+    };
+    Some((Kind::from_tag(tag & 0xf)?, compression))
+}
+
+/// Starts every file of records
+const MAGIC: &[u8; 4] = b"STMR";
+
+fn invalid<E: Into<Box<dyn std::error::Error + Send + Sync>>>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+pub struct Writer<W: Write> {
+    w: W,
+}
+
+impl<W: Write> Writer<W> {
+    pub fn new(mut w: W) -> io::Result<Self> {
+        w.write_all(MAGIC)?;
+        Ok(Writer { w })
+    }
+
+    pub fn write(&mut self, kind: Kind, record: &[u8]) -> io::Result<()> {
+        write_record(&mut self.w, kind, record)
+    }
+
+    pub fn into_inner(self) -> W {
+        self.w
+    }
+}
+
+/// Writes a single record, without the `STMR` which starts a file of them
+pub fn write_record<W: Write>(w: W, kind: Kind, record: &[u8]) -> io::Result<()> {
+    write_tagged(w, kind.tag(), record)
+}
+
+/// Writes a single record compressed with `compression`, unless that doesn't make it any smaller
+pub fn write_compressed_record<W: Write>(
+    w: W,
+    kind: Kind,
+    record: &[u8],
+    compression: Compression,
+) -> io::Result<()> {
+    let compressed = match compression {
+        Compression::None => None,
+        _ => Some(compression.compress(record)?),
+    };
+    match compressed {
+        Some(compressed) if compressed.len() < record.len() => {
+            write_tagged(w, kind.tag() | compression_tag(compression), &compressed)
+        }
+        _ => write_record(w, kind, record),
+    }
+}
+
+fn write_tagged<W: Write>(mut w: W, tag: u8, record: &[u8]) -> io::Result<()> {
+    let len = u32::try_from(record.len()).map_err(|_| invalid("record too long"))?;
+    w.write_all(&[tag])?;
+    w.write_all(&len.to_le_bytes())?;
+    w.write_all(record)
+}
+
+/// Reads a single record, as written by `write_record`, or `None` at the end of the file
+pub fn read_record<R: Read>(mut r: R) -> io::Result<Option<(Kind, Vec<u8>)>> {
+    let mut tag = [0];
+    if r.read(&mut tag)? == 0 {
+        return Ok(None);
+    }
+    let (kind, compression) = split_tag(tag[0]).ok_or_else(|| invalid("unknown kind of record"))?;
+    let mut len = [0; 4];
+    r.read_exact(&mut len)?;
+    let len = u32::from_le_bytes(len);
+    // Read rather than allocated up front, so a corrupt length can't ask for gigabytes
+    let mut record = Vec::new();
+    r.take(len.into()).read_to_end(&mut record)?;
+    if record.len() != len as usize {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    if compression != Compression::None {
+        let mut decompressed = Vec::new();
+        compression
+            .decoder(record.as_slice())?
+            .read_to_end(&mut decompressed)?;
+        record = decompressed;
+    }
+    Ok(Some((kind, record)))
+}
+
+pub struct Reader<R: Read> {
+    r: R,
+    position: u64,
+}
+
+impl<R: Read> Reader<R> {
+    pub fn new(mut r: R) -> io::Result<Self> {
+        let mut magic = [0; 4];
+        r.read_exact(&mut magic)?;
+        match &magic == MAGIC {
+            true => Ok(Reader {
+                r,
+                position: MAGIC.len() as u64,
+            }),
+            false => Err(invalid("not a file of records")),
+        }
+    }
+
+    /// How far into the file the next record starts
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    fn read(&mut self) -> io::Result<Option<(Kind, Vec<u8>)>> {
+        let mut counted = Counted {
+            r: &mut self.r,
+            read: 0,
+        };
+        let record = read_record(&mut counted)?;
+        self.position += counted.read;
+        Ok(record)
+    }
+}
+
+/// Counts the bytes read through it, since a compressed record's length in the file isn't its length once read
+struct Counted<R> {
+    r: R,
+    read: u64,
+}
+
+impl<R: Read> Read for Counted<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.r.read(buf)?;
+        self.read += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: Read> Iterator for Reader<R> {
+    type Item = io::Result<(Kind, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read().transpose()
+    }
+}
+
+/// A record in the current version, decoding and encoding it again if it was written in an older one
+pub fn upgrade(kind: Kind, record: Vec<u8>) -> Result<Vec<u8>, Error> {
+    if version(&record)? == FORMAT_VERSION {
+        return Ok(record);
+    }
+    Ok(match kind {
+        Kind::Transaction => {
+            let (transaction, state) = decode_transaction(&record)?;
+            encode_transaction(&transaction, state)
+        }
+        Kind::Client => encode_client(&decode_client(&record)?),
+        Kind::Dispute => {
+            let (client_id, transaction_id, reason) = decode_dispute(&record)?;
+            encode_dispute(client_id, transaction_id, reason.as_deref())
+        }
+    })
+}
+
+/// Copies a file of records with every record upgraded to the current version, returning how many needed it
+pub fn migrate<R: Read, W: Write>(from: R, to: W) -> io::Result<u64> {
+    let mut writer = Writer::new(to)?;
+    let mut upgraded = 0;
+    for record in Reader::new(from)? {
+        let (kind, record) = record?;
+        let old = version(&record).map_err(invalid)?;
+        let record = upgrade(kind, record).map_err(invalid)?;
+        if old != FORMAT_VERSION {
+            upgraded += 1;
+        }
+        writer.write(kind, &record)?;
+    }
+    writer.into_inner().flush()?;
+    Ok(upgraded)
+}
+
+pub fn encode_dispute(client_id: u16, transaction_id: u32, reason: Option<&str>) -> Vec<u8> {
+    encode(&DisputeV1 {
+        client_id,
+        transaction_id,
+        reason: reason.map(Cow::Borrowed),
+    })
+}
+
+/// The client, the disputed transaction and the reason given
+pub fn decode_dispute(bytes: &[u8]) -> Result<(u16, u32, Option<String>), Error> {
+    let dispute: DisputeV1 = match version(bytes)? {
+        1 | 2 => postcard::from_bytes(&bytes[1..])?,
+        version => return Err(Error::UnknownVersion(version)),
+    };
+    Ok((
+        dispute.client_id,
+        dispute.transaction_id,
+        dispute.reason.map(Cow::into_owned),
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let transaction = DisputableTransaction {
+            client_id: 3,
+            transaction_id: 70_000,
+            type_: DisputableType::Withdrawal(Decimal::new(12, 3400)),
+            metadata: HashMap::from([("memo".to_owned(), "rent".to_owned())]),
+            history: vec![
+                (TransactionKind::Dispute, Some("fraud".to_owned())),
+                (TransactionKind::Resolve, None),
+            ],
+        };
+        let bytes = encode_transaction(&transaction, State::Resolved);
+        assert_eq!(version(&bytes).unwrap(), FORMAT_VERSION);
+        assert_eq!(
+            decode_transaction(&bytes).unwrap(),
+            (transaction, State::Resolved)
+        );
+
+        let mut client = Client::new(3).with_overdraft_limit(Decimal::new(50, 0));
+        client.deposit(Decimal::new(7, 0));
+        client.record_activity(1_600_000_000);
+        client.lock();
+        let bytes = encode_client(&client);
+        assert_eq!(
+            ClientRecord::from(decode_client(&bytes).unwrap()),
+            ClientRecord::from(client)
+        );
+
+        assert!(matches!(decode_client(&[]), Err(Error::Empty)));
+        assert!(matches!(
+            decode_client(&[FORMAT_VERSION + 1]),
+            Err(Error::UnknownVersion(_))
+        ));
+        assert!(matches!(
+            decode_client(&bytes[..bytes.len() - 1]),
+            Err(Error::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn client_v1_read_as_v2() {
+        let v1 = ClientV1 {
+            id: 9,
+            available: (5, 0),
+            held: (2, 5000),
+            held_reserve: (1, 0),
+            reserve: (3, 0),
+            overdraft: (0, 0),
+            overdraft_limit: (20, 0),
+            pending: (4, 0),
+            locked: true,
+            closed: false,
+            disputes: 2,
+            chargebacks: 1,
+        };
+        let client = decode_client(&postcard::to_extend(&v1, vec![1]).unwrap()).unwrap();
+        // Everything is kept, and the lifetime statistics start empty
+        assert_eq!(
+            ClientRecord::from(client.clone()),
+            ClientRecord {
+                id: 9,
+                available: Decimal::new(5, 0),
+                held: Decimal::new(2, 5000),
+                held_reserve: Decimal::new(1, 0),
+                reserve: Decimal::new(3, 0),
+                overdraft: Decimal::zero(),
+                overdraft_limit: Decimal::new(20, 0),
+                pending: Decimal::new(4, 0),
+                locked: true,
+                closed: false,
+                disputes: 2,
+                chargebacks: 1,
+                lifetime: Lifetime::default(),
+            }
+        );
+        // Written again it's a version 2 record, which reads back the same
+        let bytes = encode_client(&client);
+        assert_eq!(version(&bytes).unwrap(), 2);
+        assert_eq!(
+            ClientRecord::from(decode_client(&bytes).unwrap()),
+            ClientRecord::from(client)
+        );
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn compressed_records() {
+        let transaction = DisputableTransaction {
+            client_id: 1,
+            transaction_id: 1,
+            type_: DisputableType::Deposit(Decimal::new(1, 0)),
+            metadata: HashMap::from([("memo".to_owned(), "rent ".repeat(20))]),
+            history: Vec::new(),
+        };
+        let record = encode_transaction(&transaction, State::Committed);
+        let mut file = Writer::new(Vec::new()).unwrap().into_inner();
+        write_compressed_record(&mut file, Kind::Transaction, &record, Compression::Zstd).unwrap();
+        let compressed = file.len();
+        assert!(compressed < MAGIC.len() + 5 + record.len());
+        // Too small to shrink, so kept as it is
+        write_compressed_record(
+            &mut file,
+            Kind::Client,
+            &[FORMAT_VERSION],
+            Compression::Zstd,
+        )
+        .unwrap();
+        assert_eq!(
+            &file[compressed..],
+            [Kind::Client.tag(), 1, 0, 0, 0, FORMAT_VERSION]
+        );
+
+        let mut reader = Reader::new(file.as_slice()).unwrap();
+        assert_eq!(reader.next().unwrap().unwrap(), (Kind::Transaction, record));
+        assert_eq!(reader.position(), compressed as u64);
+        assert_eq!(
+            reader.next().unwrap().unwrap(),
+            (Kind::Client, vec![FORMAT_VERSION])
+        );
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn migrate_file() {
+        let transaction = DisputableTransaction {
+            client_id: 1,
+            transaction_id: 1,
+            type_: DisputableType::Deposit(Decimal::new(1, 0)),
+            metadata: HashMap::new(),
+            history: Vec::new(),
+        };
+        let mut writer = Writer::new(Vec::new()).unwrap();
+        writer
+            .write(
+                Kind::Transaction,
+                &encode_transaction(&transaction, State::Disputed),
+            )
+            .unwrap();
+        writer
+            .write(Kind::Client, &encode_client(&Client::new(1)))
+            .unwrap();
+        let file = writer.into_inner();
+
+        let mut migrated = Vec::new();
+        assert_eq!(migrate(file.as_slice(), &mut migrated).unwrap(), 0);
+        assert_eq!(migrated, file);
+        let records = Reader::new(migrated.as_slice())
+            .unwrap()
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(
+            records.iter().map(|(kind, _)| *kind).collect::<Vec<_>>(),
+            [Kind::Transaction, Kind::Client]
+        );
+        assert_eq!(
+            decode_transaction(&records[0].1).unwrap(),
+            (transaction, State::Disputed)
+        );
+
+        // Truncated part way through the last record
+        assert!(migrate(&file[..file.len() - 1], Vec::new()).is_err());
+        let mut future = Writer::new(Vec::new()).unwrap();
+        future.write(Kind::Client, &[FORMAT_VERSION + 1]).unwrap();
+        assert!(migrate(future.into_inner().as_slice(), Vec::new()).is_err());
+        assert!(Reader::new("type,client".as_bytes()).is_err());
+
+        // Clients written before lifetime statistics start without any
+        let mut v1 = Writer::new(Vec::new()).unwrap();
+        let client = ClientV1 {
+            id: 4,
+            available: (5, 0),
+            held: (0, 0),
+            held_reserve: (0, 0),
+            reserve: (0, 0),
+            overdraft: (0, 0),
+            overdraft_limit: (0, 0),
+            pending: (0, 0),
+            locked: true,
+            closed: false,
+            disputes: 1,
+            chargebacks: 1,
+        };
+        v1.write(
+            Kind::Client,
+            &postcard::to_extend(&client, vec![1]).unwrap(),
+        )
+        .unwrap();
+        let mut migrated = Vec::new();
+        assert_eq!(
+            migrate(v1.into_inner().as_slice(), &mut migrated).unwrap(),
+            1
+        );
+        let (_, record) = Reader::new(migrated.as_slice())
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!(version(&record).unwrap(), FORMAT_VERSION);
+        let client = decode_client(&record).unwrap();
+        assert_eq!(
+            (client.available(), client.chargebacks()),
+            (Decimal::new(5, 0), 1)
+        );
+        assert_eq!(client.lifetime(), &Lifetime::default());
+    }
+}
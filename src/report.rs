@@ -0,0 +1,498 @@
+use csv::{ReaderBuilder, Trim};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+#[cfg(not(target_arch = "wasm32"))]
+use std::{io, path::Path};
+
+use crate::client::{ClientOutput, ClientState};
+use crate::decimal::Decimal;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn read_from_csv_file<P: AsRef<Path>>(
+    path: P,
+) -> io::Result<impl Iterator<Item = csv::Result<ClientOutput>>> {
+    Ok(ReaderBuilder::new()
+        .trim(Trim::All)
+        .from_path(path)?
+        .into_deserialize())
+}
+
+/// A row of the accounts report as written. Only the `client,available,held,total,locked` columns are written unless
+/// asked for all of them, so consumers of the original report aren't given the overdraft, closed and pending columns
+/// of features they don't use.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Row {
+    pub client: u16,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub overdraft: Option<Decimal>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub closed: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pending: Option<Decimal>,
+}
+
+impl Row {
+    pub fn new(row: ClientOutput, all_columns: bool) -> Self {
+        Row {
+            client: row.client,
+            available: row.available,
+            held: row.held,
+            total: row.total,
+            locked: row.locked,
+            overdraft: all_columns.then_some(row.overdraft),
+            closed: all_columns.then_some(row.closed),
+            pending: all_columns.then_some(row.pending),
+        }
+    }
+}
+
+/// Leaves out the columns a report written without all of them wouldn't have, so the row compares equal to one read
+/// back from it
+pub fn baseline(row: ClientOutput) -> ClientOutput {
+    ClientOutput {
+        overdraft: Decimal::zero(),
+        closed: false,
+        pending: Decimal::zero(),
+        ..row
+    }
+}
+
+/// A row of the accounts report with the client's lifetime statistics after the usual columns
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct LifetimeRow {
+    pub client: u16,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+    pub overdraft: Decimal,
+    pub closed: bool,
+    pub pending: Decimal,
+    pub deposits: u32,
+    pub deposited: Decimal,
+    pub withdrawals: u32,
+    pub withdrawn: Decimal,
+    pub disputes: u32,
+    pub disputed: Decimal,
+    pub chargebacks: u32,
+    pub charged_back: Decimal,
+    pub first_activity: Option<u64>,
+    pub last_activity: Option<u64>,
+}
+
+impl From<ClientState> for LifetimeRow {
+    fn from(c: ClientState) -> Self {
+        LifetimeRow {
+            client: c.id,
+            available: c.available,
+            held: c.held,
+            total: c.total,
+            locked: c.locked,
+            overdraft: c.overdraft,
+            closed: c.closed,
+            pending: c.pending,
+            deposits: c.lifetime.deposits,
+            deposited: c.lifetime.deposited,
+            withdrawals: c.lifetime.withdrawals,
+            withdrawn: c.lifetime.withdrawn,
+            disputes: c.disputes,
+            disputed: c.lifetime.disputed,
+            chargebacks: c.chargebacks,
+            charged_back: c.lifetime.charged_back,
+            first_activity: c.lifetime.first_activity,
+            last_activity: c.lifetime.last_activity,
+        }
+    }
+}
+
+/// A single disagreement between an expected and an actual report
+#[derive(Debug, PartialEq, Eq)]
+pub enum Mismatch {
+    /// Present in the expected report, but never produced
+    Missing(ClientOutput),
+    /// Produced, but absent from the expected report
+    Unexpected(ClientOutput),
+    Differs {
+        expected: ClientOutput,
+        actual: ClientOutput,
+    },
+}
+
+impl Mismatch {
+    /// The client disagreed about
+    pub fn client(&self) -> u16 {
+        match self {
+            Mismatch::Missing(row) | Mismatch::Unexpected(row) => row.client,
+            Mismatch::Differs { expected, .. } => expected.client,
+        }
+    }
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mismatch::Missing(expected) => {
+                write!(f, "client {}: missing from output", expected.client)
+            }
+            Mismatch::Unexpected(actual) => {
+                write!(f, "client {}: missing from report", actual.client)
+            }
+            Mismatch::Differs { expected, actual } => {
+                let mut fields = Vec::new();
+                if expected.available != actual.available {
+                    fields.push(format!(
+                        "available expected {}, found {}",
+                        expected.available, actual.available
+                    ));
+                }
+                if expected.held != actual.held {
+                    fields.push(format!(
+                        "held expected {}, found {}",
+                        expected.held, actual.held
+                    ));
+                }
+                if expected.total != actual.total {
+                    fields.push(format!(
+                        "total expected {}, found {}",
+                        expected.total, actual.total
+                    ));
+                }
+                if expected.locked != actual.locked {
+                    fields.push(format!(
+                        "locked expected {}, found {}",
+                        expected.locked, actual.locked
+                    ));
+                }
+                if expected.overdraft != actual.overdraft {
+                    fields.push(format!(
+                        "overdraft expected {}, found {}",
+                        expected.overdraft, actual.overdraft
+                    ));
+                }
+                if expected.closed != actual.closed {
+                    fields.push(format!(
+                        "closed expected {}, found {}",
+                        expected.closed, actual.closed
+                    ));
+                }
+                if expected.pending != actual.pending {
+                    fields.push(format!(
+                        "pending expected {}, found {}",
+                        expected.pending, actual.pending
+                    ));
+                }
+                write!(f, "client {}: {}", expected.client, fields.join("; "))
+            }
+        }
+    }
+}
+
+/// Describes a mismatch between an earlier and a later report as a change to the account
+pub struct Change<'a>(pub &'a Mismatch);
+
+impl fmt::Display for Change<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Mismatch::Missing(before) => write!(f, "client {}: removed", before.client),
+            Mismatch::Unexpected(after) => write!(
+                f,
+                "client {}: new account with available {}, held {}, total {}{}",
+                after.client,
+                after.available,
+                after.held,
+                after.total,
+                if after.locked { ", locked" } else { "" }
+            ),
+            Mismatch::Differs { expected, actual } => {
+                let mut fields = Vec::new();
+                if expected.available != actual.available {
+                    fields.push(format!(
+                        "available {} -> {}",
+                        expected.available, actual.available
+                    ));
+                }
+                if expected.held != actual.held {
+                    fields.push(format!("held {} -> {}", expected.held, actual.held));
+                }
+                if expected.total != actual.total {
+                    fields.push(format!("total {} -> {}", expected.total, actual.total));
+                }
+                if expected.overdraft != actual.overdraft {
+                    fields.push(format!(
+                        "overdraft {} -> {}",
+                        expected.overdraft, actual.overdraft
+                    ));
+                }
+                if expected.pending != actual.pending {
+                    fields.push(format!(
+                        "pending {} -> {}",
+                        expected.pending, actual.pending
+                    ));
+                }
+                if !expected.locked && actual.locked {
+                    fields.push("becomes locked".to_owned());
+                }
+                if !expected.closed && actual.closed {
+                    fields.push("closed".to_owned());
+                }
+                write!(f, "client {}: {}", expected.client, fields.join("; "))
+            }
+        }
+    }
+}
+
+/// Compares two reports by client id, returning every mismatch ordered by client id.
+///
+/// Row order within either report is irrelevant.
+pub fn diff<E, A>(expected: E, actual: A) -> Vec<Mismatch>
+where
+    E: IntoIterator<Item = ClientOutput>,
+    A: IntoIterator<Item = ClientOutput>,
+{
+    let mut rows: BTreeMap<u16, (Option<ClientOutput>, Option<ClientOutput>)> = BTreeMap::new();
+    for row in expected {
+        let entry = rows.entry(row.client).or_default();
+        entry.0 = Some(row);
+    }
+    for row in actual {
+        let entry = rows.entry(row.client).or_default();
+        entry.1 = Some(row);
+    }
+
+    rows.into_values()
+        .filter_map(|rows| match rows {
+            (Some(expected), None) => Some(Mismatch::Missing(expected)),
+            (None, Some(actual)) => Some(Mismatch::Unexpected(actual)),
+            (Some(expected), Some(actual)) if expected != actual => {
+                Some(Mismatch::Differs { expected, actual })
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// A client found in more than one of the reports being merged
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DuplicateClient(pub u16);
+
+impl fmt::Display for DuplicateClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "client {} is in more than one report", self.0)
+    }
+}
+
+impl std::error::Error for DuplicateClient {}
+
+/// Combines the reports of shards which processed their own clients into one, ordered by client id.
+///
+/// The shards must be disjoint by client id unless `sum_duplicates` is set, in which case a client's rows are summed
+/// into one, which is locked if any of them are and closed only if all of them are.
+pub fn merge<I>(rows: I, sum_duplicates: bool) -> Result<Vec<ClientOutput>, DuplicateClient>
+where
+    I: IntoIterator<Item = ClientOutput>,
+{
+    let mut merged: BTreeMap<u16, ClientOutput> = BTreeMap::new();
+    for row in rows {
+        match merged.get_mut(&row.client) {
+            None => {
+                merged.insert(row.client, row);
+            }
+            Some(_) if !sum_duplicates => return Err(DuplicateClient(row.client)),
+            Some(client) => {
+                client.available += row.available;
+                client.held += row.held;
+                client.total += row.total;
+                client.locked |= row.locked;
+                client.overdraft += row.overdraft;
+                client.closed &= row.closed;
+                client.pending += row.pending;
+            }
+        }
+    }
+    Ok(merged.into_values().collect())
+}
+
+/// A digest of a report which is stable across builds and platforms, and independent of row order
+pub fn digest<I: IntoIterator<Item = ClientOutput>>(rows: I) -> u64 {
+    // FNV-1a, which is trivial and (unlike `DefaultHasher`) guaranteed never to change
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    let mut rows = rows.into_iter().collect::<Vec<_>>();
+    rows.sort_by_key(|row| row.client);
+    rows.iter()
+        .flat_map(|row| {
+            format!(
+                "{},{},{},{},{}\n",
+                row.client, row.available, row.held, row.total, row.locked
+            )
+            .into_bytes()
+        })
+        .fold(OFFSET_BASIS, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(PRIME)
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::client::Client;
+    use crate::decimal::Decimal;
+
+    fn row(client: u16, available: u64, locked: bool) -> ClientOutput {
+        ClientOutput {
+            client,
+            available: Decimal::new(available, 0),
+            held: Decimal::zero(),
+            total: Decimal::new(available, 0),
+            locked,
+            overdraft: Decimal::zero(),
+            closed: false,
+            pending: Decimal::zero(),
+        }
+    }
+
+    #[test]
+    fn diff_reports() {
+        assert_eq!(
+            diff(
+                vec![row(1, 1, false), row(2, 2, false)],
+                vec![row(2, 2, false), row(1, 1, false)]
+            ),
+            vec![]
+        );
+
+        let mismatches = diff(
+            vec![row(1, 1, false), row(2, 2, false)],
+            vec![row(1, 1, true), row(3, 3, false)],
+        );
+        assert_eq!(
+            mismatches.iter().map(Mismatch::client).collect::<Vec<_>>(),
+            [1, 2, 3]
+        );
+        assert_eq!(
+            mismatches,
+            vec![
+                Mismatch::Differs {
+                    expected: row(1, 1, false),
+                    actual: row(1, 1, true)
+                },
+                Mismatch::Missing(row(2, 2, false)),
+                Mismatch::Unexpected(row(3, 3, false)),
+            ]
+        );
+    }
+
+    #[test]
+    fn merge_reports() {
+        let shards = || vec![row(2, 2, false), row(1, 1, false), row(2, 3, true)];
+        assert_eq!(merge(shards(), false), Err(DuplicateClient(2)));
+        assert_eq!(
+            merge(shards(), true),
+            Ok(vec![row(1, 1, false), row(2, 5, true)])
+        );
+        assert_eq!(
+            merge(vec![row(3, 3, false), row(1, 1, false)], false),
+            Ok(vec![row(1, 1, false), row(3, 3, false)])
+        );
+    }
+
+    #[test]
+    fn digest_reports() {
+        assert_eq!(digest(vec![]), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(
+            digest(vec![row(1, 1, false), row(2, 2, false)]),
+            digest(vec![row(2, 2, false), row(1, 1, false)])
+        );
+        assert_ne!(
+            digest(vec![row(1, 1, false), row(2, 2, false)]),
+            digest(vec![row(1, 1, false), row(2, 2, true)])
+        );
+    }
+
+    #[test]
+    fn write_rows() {
+        let write = |all_columns| {
+            let mut writer = csv::Writer::from_writer(Vec::new());
+            let mut overdrawn = row(2, 0, false);
+            overdrawn.overdraft = Decimal::new(5, 0);
+            for row in [row(1, 1, false), overdrawn] {
+                writer.serialize(Row::new(row, all_columns)).unwrap();
+            }
+            String::from_utf8(writer.into_inner().unwrap()).unwrap()
+        };
+        assert_eq!(
+            write(false),
+            "\
+client,available,held,total,locked
+1,1.0000,0.0000,1.0000,false
+2,0.0000,0.0000,0.0000,false
+"
+        );
+        assert_eq!(
+            write(true),
+            "\
+client,available,held,total,locked,overdraft,closed,pending
+1,1.0000,0.0000,1.0000,false,0.0000,false,0.0000
+2,0.0000,0.0000,0.0000,false,5.0000,false,0.0000
+"
+        );
+    }
+
+    #[test]
+    fn lifetime_rows() {
+        let mut client = Client::new(3);
+        client.deposit(Decimal::new(10, 0));
+        client.withdraw(Decimal::new(2, 0)).unwrap();
+        client.dispute_deposit(Decimal::new(8, 0));
+        client.chargeback_deposit(Decimal::new(8, 0)).unwrap();
+        client.record_activity(1_600_000_000);
+
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        writer.serialize(LifetimeRow::from(client.state())).unwrap();
+        // A client never timestamped leaves its activity empty
+        writer
+            .serialize(LifetimeRow::from(Client::new(4).state()))
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(writer.into_inner().unwrap()).unwrap(),
+            "\
+client,available,held,total,locked,overdraft,closed,pending,deposits,deposited,withdrawals,withdrawn,disputes,disputed,chargebacks,charged_back,first_activity,last_activity
+3,0.0000,0.0000,0.0000,true,0.0000,false,0.0000,1,10.0000,1,2.0000,1,8.0000,1,8.0000,1600000000,1600000000
+4,0.0000,0.0000,0.0000,false,0.0000,false,0.0000,0,0.0000,0,0.0000,0,0.0000,0,0.0000,,
+"
+        );
+    }
+
+    #[test]
+    fn read_written_report() {
+        let data = "\
+client,available,held,total,locked
+1,   1.5000,  0.0000,  1.5000,false
+";
+        let rows = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes())
+            .into_deserialize()
+            .collect::<csv::Result<Vec<ClientOutput>>>()
+            .unwrap();
+        assert_eq!(
+            rows,
+            vec![ClientOutput {
+                client: 1,
+                available: Decimal::new(1, 5000),
+                held: Decimal::zero(),
+                total: Decimal::new(1, 5000),
+                locked: false,
+                overdraft: Decimal::zero(),
+                closed: false,
+                pending: Decimal::zero(),
+            }]
+        );
+    }
+}
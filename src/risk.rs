@@ -0,0 +1,48 @@
+//! Risk rules, which vet every transaction before the engine applies it.
+//!
+//! Rules are evaluated in the order they were added to the engine, and the first rejection stops the transaction
+//! without evaluating the rest. Flagged transactions are still applied, and the flags kept for review.
+
+use std::fmt;
+
+use crate::client::Client;
+use crate::transaction::Transaction;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Accept,
+    /// Apply the transaction, but flag it for review
+    Flag(String),
+    /// Don't apply the transaction
+    Reject(String),
+}
+
+/// Custom accept, flag or reject logic. Closures taking a transaction and its client are rules too.
+pub trait RiskRule {
+    /// `client` is the client before the transaction, which is a new empty one for a client's first transaction
+    fn evaluate(&self, tx: &Transaction, client: &Client) -> Verdict;
+}
+
+impl<F: Fn(&Transaction, &Client) -> Verdict> RiskRule for F {
+    fn evaluate(&self, tx: &Transaction, client: &Client) -> Verdict {
+        self(tx, client)
+    }
+}
+
+/// A transaction which a rule flagged
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Flag {
+    pub client_id: u16,
+    pub transaction_id: u32,
+    pub reason: String,
+}
+
+impl fmt::Display for Flag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "tx {} for client {} flagged: {}",
+            self.transaction_id, self.client_id, self.reason
+        )
+    }
+}
@@ -0,0 +1,257 @@
+//! Standing orders: deposits and withdrawals which recur on a schedule.
+//!
+//! Each order is made first at its `start`, a unix timestamp, and then every interval after that, either forever or
+//! for a number of times. Intervals are a number of days, weeks or calendar months, written like `1d`, `2w` or `1m`.
+//! Monthly orders keep the day of the month they started on, or are made on the last day of shorter months.
+
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io::Read;
+use std::str::FromStr;
+
+use crate::decimal::Decimal;
+use crate::transaction::{DisputableType, Transaction, TransactionKind, Type};
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interval {
+    Days(u32),
+    Weeks(u32),
+    Months(u32),
+}
+
+impl FromStr for Interval {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid interval {:?}, expected e.g. 1d, 2w or 1m", s);
+        let unit = s.len().checked_sub(1).ok_or_else(invalid)?;
+        let n = match s[..unit].parse::<u32>() {
+            Ok(n) if n > 0 => n,
+            _ => return Err(invalid()),
+        };
+        match &s[unit..] {
+            "d" => Ok(Interval::Days(n)),
+            "w" => Ok(Interval::Weeks(n)),
+            "m" => Ok(Interval::Months(n)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl Interval {
+    /// `start` moved on by `n` of these intervals, or `None` if that's past the end of time
+    fn advance(self, start: u64, n: u32) -> Option<u64> {
+        let days = match self {
+            Interval::Days(days) => u64::from(days) * u64::from(n),
+            Interval::Weeks(weeks) => u64::from(weeks) * 7 * u64::from(n),
+            Interval::Months(months) => {
+                let day = i64::try_from(start / SECONDS_PER_DAY).ok()?;
+                let (year, month, day_of_month) = civil_from_days(day);
+                let months = year * 12 + i64::from(month - 1) + i64::from(months) * i64::from(n);
+                let (year, month) = (months.div_euclid(12), months.rem_euclid(12) as u32 + 1);
+                let moved =
+                    days_from_civil(year, month, day_of_month.min(days_in_month(year, month)));
+                u64::try_from(moved - day).ok()?
+            }
+        };
+        start.checked_add(days.checked_mul(SECONDS_PER_DAY)?)
+    }
+}
+
+/// A deposit or withdrawal made every `every` from `start`, `count` times or forever
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "Row")]
+pub struct StandingOrder {
+    pub client: u16,
+    pub type_: DisputableType,
+    pub start: u64,
+    pub every: Interval,
+    pub count: Option<u32>,
+}
+
+/// A row of a schedule file
+#[derive(Deserialize)]
+struct Row {
+    client: u16,
+    #[serde(rename = "type")]
+    kind: TransactionKind,
+    amount: Decimal,
+    start: u64,
+    every: String,
+    count: Option<u32>,
+}
+
+impl TryFrom<Row> for StandingOrder {
+    type Error = String;
+    fn try_from(row: Row) -> Result<Self, Self::Error> {
+        let type_ = match row.kind {
+            TransactionKind::Deposit => DisputableType::Deposit(row.amount),
+            TransactionKind::Withdrawal => DisputableType::Withdrawal(row.amount),
+            kind => return Err(format!("can't schedule a {}", kind)),
+        };
+        Ok(StandingOrder {
+            client: row.client,
+            type_,
+            start: row.start,
+            every: row.every.parse()?,
+            count: row.count,
+        })
+    }
+}
+
+impl StandingOrder {
+    /// When the order is made between two unix timestamps, including `from` but not `to`
+    pub fn occurrences(&self, from: u64, to: u64) -> impl Iterator<Item = u64> + '_ {
+        (0..self.count.unwrap_or(u32::MAX))
+            .map_while(move |n| self.every.advance(self.start, n))
+            .take_while(move |&at| at < to)
+            .filter(move |&at| at >= from)
+    }
+}
+
+/// Every standing order, in the order they were given
+#[derive(Clone, Debug, Default)]
+pub struct Schedule {
+    orders: Vec<StandingOrder>,
+}
+
+impl Schedule {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, order: StandingOrder) -> Self {
+        self.orders.push(order);
+        self
+    }
+
+    /// Reads `client,type,amount,start,every,count` rows, where `count` may be left empty
+    pub fn from_csv_reader<R: Read>(rdr: R) -> csv::Result<Self> {
+        let mut schedule = Schedule::new();
+        for order in csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(rdr)
+            .into_deserialize()
+        {
+            schedule = schedule.with(order?);
+        }
+        Ok(schedule)
+    }
+
+    /// The transactions due between two unix timestamps, in the order they're due, numbered from `first_id`. Orders
+    /// due at the same time are made in schedule order.
+    pub fn transactions(&self, from: u64, to: u64, first_id: u32) -> Vec<Transaction> {
+        let mut due = self
+            .orders
+            .iter()
+            .enumerate()
+            .flat_map(|(i, order)| order.occurrences(from, to).map(move |at| (at, i, order)))
+            .collect::<Vec<_>>();
+        due.sort_by_key(|&(at, i, _)| (at, i));
+        due.into_iter()
+            .zip(first_id..)
+            .map(|((_, _, order), transaction_id)| Transaction {
+                client_id: order.client,
+                transaction_id,
+                type_: Type::Disputable(order.type_.clone()),
+                metadata: HashMap::new(),
+            })
+            .collect()
+    }
+}
+
+/// Howard Hinnant's `days_from_civil`
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = i64::from((month + 9) % 12);
+    let doy = (153 * mp + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// Howard Hinnant's `civil_from_days`
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    (yoe + era * 400 + i64::from(month <= 2), month, day)
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    let (next_year, next_month) = if month == 12 {
+        (year + 1, 1)
+    } else {
+        (year, month + 1)
+    };
+    (days_from_civil(next_year, next_month, 1) - days_from_civil(year, month, 1)) as u32
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn expand_schedule() {
+        // 2021-01-31 and 2021-06-01, both at noon
+        let (jan_31, jun_1) = (1_612_094_400, 1_622_548_800);
+        let schedule = Schedule::from_csv_reader(
+            format!(
+                "\
+client, type,       amount, start, every, count
+1,      deposit,     100.0, {},    1m,
+2,      withdrawal,    5.0, {},    2w,    2
+",
+                jan_31, jan_31
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+
+        let ids = |t: &Transaction| (t.transaction_id, t.client_id);
+        let transactions = schedule.transactions(jan_31, jun_1, 10);
+        assert_eq!(
+            transactions.iter().map(ids).collect::<Vec<_>>(),
+            vec![
+                (10, 1),
+                (11, 2),
+                (12, 2),
+                (13, 1),
+                (14, 1),
+                (15, 1),
+                (16, 1)
+            ]
+        );
+        assert_eq!(
+            schedule.orders[0]
+                .occurrences(jan_31, jun_1)
+                .map(|at| civil_from_days((at / SECONDS_PER_DAY) as i64))
+                .collect::<Vec<_>>(),
+            vec![
+                (2021, 1, 31),
+                (2021, 2, 28),
+                (2021, 3, 31),
+                (2021, 4, 30),
+                (2021, 5, 31)
+            ]
+        );
+        // Only what's due in the period
+        assert_eq!(schedule.transactions(jun_1, jun_1 + 1, 0).len(), 0);
+
+        assert!(Schedule::from_csv_reader(
+            "client,type,amount,start,every,count\n1,dispute,1.0,0,1d,\n".as_bytes()
+        )
+        .is_err());
+        assert!("0d".parse::<Interval>().is_err());
+        assert!("m".parse::<Interval>().is_err());
+        assert_eq!("3w".parse(), Ok(Interval::Weeks(3)));
+    }
+}
@@ -0,0 +1,136 @@
+//! Verifying Ed25519 signed transactions, for feeds which cross a trust boundary.
+//!
+//! Transactions are signed over their canonical CSV row, see `Transaction::to_csv_row`, with the signature given as 128
+//! hex digits in a `signature` column. A transaction is accepted if any of the trusted keys verifies its signature.
+//! Unsigned transactions are rejected, including every transaction read from a format without extra columns.
+
+use ed25519_dalek::{Signature, VerifyingKey};
+use serde::Deserialize;
+use std::convert::TryFrom;
+use std::io::Read;
+
+use crate::chain::from_hex;
+use crate::client::Client;
+use crate::risk::{RiskRule, Verdict};
+use crate::transaction::Transaction;
+
+/// A public key trusted to sign transactions
+#[derive(Deserialize)]
+#[serde(try_from = "Row")]
+struct TrustedKey(VerifyingKey);
+
+/// A row of a trusted keys file
+#[derive(Deserialize)]
+struct Row {
+    key: String,
+}
+
+impl TryFrom<Row> for TrustedKey {
+    type Error = String;
+    fn try_from(row: Row) -> Result<Self, Self::Error> {
+        from_hex(&row.key)
+            .and_then(|key| VerifyingKey::from_bytes(&key).ok())
+            .map(TrustedKey)
+            .ok_or_else(|| format!("invalid public key {:?}, expected 64 hex digits", row.key))
+    }
+}
+
+/// A risk rule rejecting every transaction which isn't signed by a trusted key
+#[derive(Clone, Debug, Default)]
+pub struct Signatures {
+    keys: Vec<VerifyingKey>,
+}
+
+impl Signatures {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, key: VerifyingKey) -> Self {
+        self.keys.push(key);
+        self
+    }
+
+    /// Reads `key` rows, each an Ed25519 public key as 64 hex digits
+    pub fn from_csv_reader<R: Read>(rdr: R) -> csv::Result<Self> {
+        let mut signatures = Signatures::new();
+        for key in csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(rdr)
+            .into_deserialize()
+        {
+            let TrustedKey(key) = key?;
+            signatures = signatures.with(key);
+        }
+        Ok(signatures)
+    }
+}
+
+impl RiskRule for Signatures {
+    fn evaluate(&self, tx: &Transaction, _: &Client) -> Verdict {
+        let signature = match tx.metadata.get("signature") {
+            Some(signature) => signature,
+            None => return Verdict::Reject("unsigned".to_owned()),
+        };
+        let signature = match from_hex(signature) {
+            Some(signature) => Signature::from_bytes(&signature),
+            None => return Verdict::Reject("malformed signature".to_owned()),
+        };
+        let row = tx.to_csv_row();
+        match self
+            .keys
+            .iter()
+            .any(|key| key.verify_strict(&row, &signature).is_ok())
+        {
+            true => Verdict::Accept,
+            false => Verdict::Reject("invalid signature".to_owned()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::chain::Digest;
+    use crate::transaction::read_from_csv_reader;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    #[test]
+    fn verify_signatures() {
+        let trusted = SigningKey::from_bytes(&[1; 32]);
+        let untrusted = SigningKey::from_bytes(&[2; 32]);
+        let signatures = Signatures::from_csv_reader(
+            format!("key\n{}\n", Digest(trusted.verifying_key().to_bytes())).as_bytes(),
+        )
+        .unwrap();
+
+        let sign = |key: &SigningKey, row: &str| {
+            let signature = key.sign(row.as_bytes()).to_bytes();
+            let hex = signature
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect::<String>();
+            format!("{},{}\n", row, hex)
+        };
+        let data = format!(
+            "type,client,tx,amount,reason,signature\n{}{}{}deposit,1,4,1.0,,\n",
+            sign(&trusted, "deposit,1,1,1.0000,"),
+            sign(&untrusted, "deposit,1,2,1.0000,"),
+            // Signed for another amount
+            sign(&trusted, "deposit,1,3,1.0000,").replacen("1.0000", "2.0", 1),
+        );
+        let verdicts = read_from_csv_reader(data.as_bytes())
+            .map(|t| signatures.evaluate(&t.unwrap(), &Client::new(1)))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            verdicts,
+            vec![
+                Verdict::Accept,
+                Verdict::Reject("invalid signature".to_owned()),
+                Verdict::Reject("invalid signature".to_owned()),
+                Verdict::Reject("unsigned".to_owned()),
+            ]
+        );
+        assert!(Signatures::from_csv_reader("key\nnot-a-key\n".as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,192 @@
+//! Importing OFX and QIF bank statements, as exported by most banks and personal finance tools.
+//!
+//! A statement covers a single account, so the client is given by the caller. Credits become deposits and debits
+//! become withdrawals. OFX entries are keyed by their `FITID`, which is used as the transaction id directly if it's a
+//! number that fits, and hashed otherwise. QIF has no such id, so entries are keyed by a hash of their contents
+//! instead, which is stable across re-imports of the same file.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::decimal::{Decimal, Locale};
+use crate::transaction::{DisputableType, Transaction, Type};
+
+/// A single statement entry which couldn't be imported
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    Missing(&'static str),
+    BadAmount(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Missing(field) => write!(f, "entry has no {}", field),
+            Error::BadAmount(amount) => write!(f, "invalid amount {:?}", amount),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// The `<STMTTRN>` entries of an OFX statement, either SGML (OFX 1.x) or XML (OFX 2.x)
+pub fn read_ofx(s: &str, client_id: u16) -> Vec<Result<Transaction, Error>> {
+    let mut entries = Vec::new();
+    let mut rest = s;
+    while let Some(start) = rest.find("<STMTTRN>") {
+        rest = &rest[start + "<STMTTRN>".len()..];
+        let end = rest.find("</STMTTRN>").unwrap_or(rest.len());
+        let entry = &rest[..end];
+        rest = &rest[end..];
+
+        entries.push((|| {
+            let amount = ofx_field(entry, "TRNAMT").ok_or(Error::Missing("TRNAMT"))?;
+            let fitid = ofx_field(entry, "FITID").ok_or(Error::Missing("FITID"))?;
+            Ok(Transaction {
+                client_id,
+                transaction_id: transaction_id(fitid),
+                type_: Type::Disputable(parse_amount(amount)?),
+                metadata: HashMap::new(),
+            })
+        })());
+    }
+    entries
+}
+
+/// The value of `<name>` within an entry, which runs to the next tag or line break as SGML elements needn't be closed
+fn ofx_field<'a>(entry: &'a str, name: &str) -> Option<&'a str> {
+    let tag = format!("<{}>", name);
+    let value = &entry[entry.find(&tag)? + tag.len()..];
+    let end = value.find(['<', '\r', '\n']).unwrap_or(value.len());
+    Some(value[..end].trim()).filter(|v| !v.is_empty())
+}
+
+/// The entries of a QIF bank or cash account, each a series of lines ending with `^`
+pub fn read_qif(s: &str, client_id: u16) -> Vec<Result<Transaction, Error>> {
+    // Identical entries on one statement (two coffees in a day) are still distinct
+    let mut seen = HashMap::<String, u32>::new();
+    let mut entries = Vec::new();
+    let mut start = 0;
+    for (end, _) in s.match_indices('^') {
+        let entry = s[start..end].trim();
+        start = end + 1;
+        let lines = entry
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('!'));
+        let contents = lines.clone().collect::<Vec<_>>().join("\n");
+        if contents.is_empty() {
+            continue;
+        }
+
+        let occurrence = seen.entry(contents.clone()).or_default();
+        *occurrence += 1;
+        let key = format!("{}\n{}", contents, occurrence);
+        // `U` is the same amount as `T`, but some exporters only write one of them
+        let amount = lines
+            .clone()
+            .find(|line| line.starts_with('T') || line.starts_with('U'))
+            .map(|line| &line[1..]);
+        entries.push(match amount {
+            Some(amount) => parse_amount(amount).map(|type_| Transaction {
+                client_id,
+                transaction_id: transaction_id(&key),
+                type_: Type::Disputable(type_),
+                metadata: HashMap::new(),
+            }),
+            None => Err(Error::Missing("amount")),
+        });
+    }
+    entries
+}
+
+/// A signed amount, negative for debits
+fn parse_amount(amount: &str) -> Result<DisputableType, Error> {
+    let bad_amount = || Error::BadAmount(amount.to_owned());
+    let (debit, magnitude) = match amount.trim().strip_prefix('-') {
+        Some(magnitude) => (true, magnitude),
+        None => (false, amount.trim().trim_start_matches('+')),
+    };
+    let magnitude =
+        Decimal::parse_lenient(magnitude, Locale::DECIMAL_POINT).map_err(|_| bad_amount())?;
+    Ok(match debit {
+        true => DisputableType::Withdrawal(magnitude),
+        false => DisputableType::Deposit(magnitude),
+    })
+}
+
+/// The id itself if it's a small enough number, otherwise its 32 bit FNV-1a hash
+pub(crate) fn transaction_id(id: &str) -> u32 {
+    id.parse().unwrap_or_else(|_| {
+        id.bytes().fold(0x811c9dc5, |hash: u32, byte| {
+            (hash ^ u32::from(byte)).wrapping_mul(0x01000193)
+        })
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn read_statements() {
+        let ofx = "\
+OFXHEADER:100
+<OFX><BANKMSGSRSV1><STMTTRNRS><STMTRS><BANKTRANLIST>
+<STMTTRN><TRNTYPE>CREDIT<DTPOSTED>20210601<TRNAMT>1,250.00<FITID>1001<NAME>Salary</STMTTRN>
+<STMTTRN>
+<TRNTYPE>DEBIT
+<TRNAMT>-12.5
+<FITID>2021060100012345
+</STMTTRN>
+<STMTTRN><TRNAMT>4.00</STMTTRN>
+</BANKTRANLIST></STMTRS></STMTTRNRS></BANKMSGSRSV1></OFX>
+";
+        let transactions = read_ofx(ofx, 7);
+        assert_eq!(transactions.len(), 3);
+        assert_eq!(
+            transactions[0],
+            Ok(Transaction {
+                client_id: 7,
+                transaction_id: 1001,
+                type_: Type::Disputable(DisputableType::Deposit(Decimal::new(1250, 0))),
+                metadata: HashMap::new(),
+            })
+        );
+        let withdrawal = transactions[1].as_ref().unwrap();
+        assert_eq!(
+            withdrawal.transaction_id,
+            transaction_id("2021060100012345")
+        );
+        assert_eq!(
+            withdrawal.type_,
+            Type::Disputable(DisputableType::Withdrawal(Decimal::new(12, 5000)))
+        );
+        assert_eq!(transactions[2], Err(Error::Missing("FITID")));
+
+        let qif = "\
+!Type:Bank
+D06/01/2021
+T-4.00
+PCoffee
+^
+D06/01/2021
+T-4.00
+PCoffee
+^
+D06/02/2021
+Tabc
+^
+";
+        let transactions = read_qif(qif, 7);
+        assert_eq!(transactions.len(), 3);
+        let (first, second) = (
+            transactions[0].as_ref().unwrap(),
+            transactions[1].as_ref().unwrap(),
+        );
+        assert_eq!(first.type_, second.type_);
+        assert_ne!(first.transaction_id, second.transaction_id);
+        assert_eq!(read_qif(qif, 7)[0], transactions[0]);
+        assert_eq!(transactions[2], Err(Error::BadAmount("abc".to_owned())));
+    }
+}
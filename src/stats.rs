@@ -0,0 +1,232 @@
+//! Statistics about a run, broken down by transaction type and by client, for a summary at the end of it.
+//!
+//! Each type gets a histogram of how long its transactions took to process, and the clients with the most volume,
+//! disputes and rejections are picked out, since a report of every client would be as long as the accounts report.
+
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+use crate::decimal::Decimal;
+use crate::engine::Rejection;
+use crate::transaction::{DisputableType, Transaction, TransactionKind, Type};
+use crate::transaction_set::StoreStats;
+
+/// The upper bounds of the latency histogram's buckets, in microseconds, before the last which has none
+const BUCKETS: [u64; 6] = [1, 10, 100, 1_000, 10_000, 100_000];
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct Latency {
+    counts: [u64; BUCKETS.len() + 1],
+}
+
+impl Latency {
+    fn record(&mut self, elapsed: Duration) {
+        let micros = elapsed.as_micros();
+        let bucket = BUCKETS
+            .iter()
+            .position(|&le| micros <= u128::from(le))
+            .unwrap_or(BUCKETS.len());
+        self.counts[bucket] += 1;
+    }
+}
+
+/// A bucket of a latency histogram
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+struct Bucket {
+    /// Taking at most this many microseconds, or any longer than the other buckets if `None`
+    le_us: Option<u64>,
+    count: u64,
+}
+
+impl Serialize for Latency {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(
+            BUCKETS
+                .iter()
+                .map(|&le| Some(le))
+                .chain([None])
+                .zip(self.counts)
+                .map(|(le_us, count)| Bucket { le_us, count }),
+        )
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct TypeStats {
+    pub applied: u64,
+    pub rejected: u64,
+    latency: Latency,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct ClientStats {
+    pub client: u16,
+    pub transactions: u64,
+    /// The total of applied deposits and withdrawals
+    pub volume: Decimal,
+    /// Disputes opened
+    pub disputes: u64,
+    pub rejections: u64,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Stats {
+    types: BTreeMap<TransactionKind, TypeStats>,
+    /// How many rejections of each kind, by `Rejection::code`
+    rejections: BTreeMap<&'static str, u64>,
+    clients: HashMap<u16, ClientStats>,
+}
+
+/// The summary written at the end of a run
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Summary<'a> {
+    pub transactions: u64,
+    pub applied: u64,
+    pub rejected: u64,
+    pub rejections: &'a BTreeMap<&'static str, u64>,
+    pub types: &'a BTreeMap<TransactionKind, TypeStats>,
+    pub top_clients: TopClients<'a>,
+    /// What the transaction store held at the end, which the engine rather than `Stats` knows
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub store: Option<StoreStats>,
+}
+
+/// The clients with the most of each, most first, then by client id
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct TopClients<'a> {
+    pub by_volume: Vec<&'a ClientStats>,
+    pub by_disputes: Vec<&'a ClientStats>,
+    pub by_rejections: Vec<&'a ClientStats>,
+}
+
+impl Stats {
+    /// Records a transaction which was processed in `elapsed`, and whether it was rejected
+    pub fn record(
+        &mut self,
+        transaction: &Transaction,
+        result: Result<(), &Rejection>,
+        elapsed: Duration,
+    ) {
+        let kind = transaction.type_.kind();
+        let type_ = self.types.entry(kind).or_default();
+        type_.latency.record(elapsed);
+        let client_id = transaction.client_id;
+        let client = self
+            .clients
+            .entry(client_id)
+            .or_insert_with(|| ClientStats {
+                client: client_id,
+                ..ClientStats::default()
+            });
+        client.transactions += 1;
+        match result {
+            Ok(()) => {
+                type_.applied += 1;
+                match &transaction.type_ {
+                    Type::Disputable(
+                        DisputableType::Deposit(amount) | DisputableType::Withdrawal(amount),
+                    ) => client.volume += amount.clone(),
+                    Type::Dispute(_) => client.disputes += 1,
+                    _ => {}
+                }
+            }
+            Err(rejection) => {
+                type_.rejected += 1;
+                client.rejections += 1;
+                *self.rejections.entry(rejection.code()).or_default() += 1;
+            }
+        }
+    }
+
+    /// Summarises everything recorded, with up to `top` clients for each breakdown
+    pub fn summary(&self, top: usize) -> Summary<'_> {
+        let (applied, rejected) = self
+            .types
+            .values()
+            .fold((0, 0), |(a, r), t| (a + t.applied, r + t.rejected));
+        Summary {
+            transactions: applied + rejected,
+            applied,
+            rejected,
+            rejections: &self.rejections,
+            types: &self.types,
+            top_clients: TopClients {
+                by_volume: self.top_by(top, |c| c.volume.clone()),
+                by_disputes: self.top_by(top, |c| c.disputes),
+                by_rejections: self.top_by(top, |c| c.rejections),
+            },
+            store: None,
+        }
+    }
+
+    /// Up to `top` clients with the most by `key`, leaving out those with none
+    fn top_by<K: Ord + Default>(
+        &self,
+        top: usize,
+        key: impl Fn(&ClientStats) -> K,
+    ) -> Vec<&ClientStats> {
+        let mut clients = self
+            .clients
+            .values()
+            .filter(|c| key(c) > K::default())
+            .collect::<Vec<_>>();
+        clients.sort_by(|a, b| key(b).cmp(&key(a)).then(a.client.cmp(&b.client)));
+        clients.truncate(top);
+        clients
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::engine::Engine;
+    use crate::transaction::read_from_csv_reader;
+    use crate::transaction_set::MemoryClient;
+
+    #[test]
+    fn summary() {
+        let mut engine = Engine::new(MemoryClient::default());
+        let mut stats = Stats::default();
+        for transaction in read_from_csv_reader(
+            "\
+type,       client,  tx, amount
+deposit,         1,   1,    5.0
+deposit,         2,   2,    1.0
+withdrawal,      2,   3,    2.0
+dispute,         2,   2,
+dispute,         2,   4,
+withdrawal,      3,   5,    1.0
+"
+            .as_bytes(),
+        ) {
+            let transaction = transaction.unwrap();
+            let result = engine.process(transaction.clone());
+            stats.record(
+                &transaction,
+                result.as_ref().map(|_| ()),
+                Duration::from_micros(50),
+            );
+        }
+
+        let summary = stats.summary(1);
+        assert_eq!(
+            (summary.transactions, summary.applied, summary.rejected),
+            (6, 3, 3)
+        );
+        assert_eq!(
+            summary.rejections.iter().collect::<Vec<_>>(),
+            [(&"E_INSUFFICIENT_FUNDS", &2), (&"E_TX_NOT_FOUND", &1)]
+        );
+        let withdrawals = &summary.types[&TransactionKind::Withdrawal];
+        assert_eq!((withdrawals.applied, withdrawals.rejected), (0, 2));
+        assert_eq!(withdrawals.latency.counts, [0, 0, 2, 0, 0, 0, 0]);
+
+        let top = |clients: &[&ClientStats]| clients.iter().map(|c| c.client).collect::<Vec<_>>();
+        assert_eq!(top(&summary.top_clients.by_volume), [1]);
+        assert_eq!(top(&summary.top_clients.by_disputes), [2]);
+        // Client 2 has two rejections, and client 3 one
+        assert_eq!(top(&summary.top_clients.by_rejections), [2]);
+        assert_eq!(stats.summary(10).top_clients.by_volume.len(), 2);
+    }
+}
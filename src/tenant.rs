@@ -0,0 +1,202 @@
+//! Processing the feeds of several tenants, like partner banks, in one engine and one transaction store.
+//!
+//! Each tenant has its own client and transaction ids, which are given ids of the engine's own as they're first seen,
+//! so the same ids from two tenants never meet. Tenants share the engine's clients, so there can be at most 65,536
+//! clients across all of them.
+
+use std::collections::{BTreeSet, HashMap};
+use std::convert::TryFrom;
+use std::fmt;
+
+use crate::client::ClientOutput;
+use crate::engine::{Engine, Rejection};
+use crate::transaction::Transaction;
+use crate::transaction_set::Client as TransactionSetClient;
+
+/// Why a tenant's transaction was not applied
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// Rejected by the engine, with the tenant's own client id
+    Rejected(Rejection),
+    /// Every one of the engine's client ids has been given out
+    TooManyClients,
+    /// Every one of the engine's transaction ids has been given out
+    TooManyTransactions,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Rejected(rejection) => rejection.fmt(f),
+            Error::TooManyClients => write!(f, "No client ids left for a new client."),
+            Error::TooManyTransactions => {
+                write!(f, "No transaction ids left for a new transaction.")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl Error {
+    /// A stable code for the error, like `Rejection::code`
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::Rejected(rejection) => rejection.code(),
+            Error::TooManyClients => "E_TOO_MANY_CLIENTS",
+            Error::TooManyTransactions => "E_TOO_MANY_TRANSACTIONS",
+        }
+    }
+}
+
+impl From<Rejection> for Error {
+    fn from(rejection: Rejection) -> Self {
+        Error::Rejected(rejection)
+    }
+}
+
+pub struct Tenants<T: TransactionSetClient> {
+    engine: Engine<T>,
+    clients: HashMap<(String, u16), u16>,
+    // The tenant and tenant's id of each of the engine's clients, indexed by the engine's id
+    owners: Vec<(String, u16)>,
+    transactions: HashMap<(String, u32), u32>,
+}
+
+impl<T: TransactionSetClient> Tenants<T> {
+    /// Shares `engine`, which should have no clients of its own, between tenants
+    pub fn new(engine: Engine<T>) -> Self {
+        Tenants {
+            engine,
+            clients: HashMap::new(),
+            owners: Vec::new(),
+            transactions: HashMap::new(),
+        }
+    }
+
+    /// Processes a transaction of `tenant`'s, with the tenant's own ids
+    pub fn process(&mut self, tenant: &str, mut transaction: Transaction) -> Result<(), Error> {
+        transaction.client_id = self.client_id(tenant, transaction.client_id)?;
+        transaction.transaction_id = self.transaction_id(tenant, transaction.transaction_id)?;
+        self.engine
+            .process(transaction)
+            .map_err(|rejection| Error::Rejected(self.external(rejection)))
+    }
+
+    fn client_id(&mut self, tenant: &str, id: u16) -> Result<u16, Error> {
+        if let Some(&internal) = self.clients.get(&(tenant.to_owned(), id)) {
+            return Ok(internal);
+        }
+        let internal = u16::try_from(self.owners.len()).map_err(|_| Error::TooManyClients)?;
+        self.clients.insert((tenant.to_owned(), id), internal);
+        self.owners.push((tenant.to_owned(), id));
+        Ok(internal)
+    }
+
+    fn transaction_id(&mut self, tenant: &str, id: u32) -> Result<u32, Error> {
+        if let Some(&internal) = self.transactions.get(&(tenant.to_owned(), id)) {
+            return Ok(internal);
+        }
+        let internal =
+            u32::try_from(self.transactions.len()).map_err(|_| Error::TooManyTransactions)?;
+        self.transactions.insert((tenant.to_owned(), id), internal);
+        Ok(internal)
+    }
+
+    /// Gives a rejection the tenant's client id back
+    fn external(&self, mut rejection: Rejection) -> Rejection {
+        match &mut rejection {
+            Rejection::InsufficientFunds { client_id, .. }
+            | Rejection::Frozen { client_id, .. }
+            | Rejection::AccountClosed { client_id }
+            | Rejection::Unsettled { client_id, .. }
+            | Rejection::Risk { client_id, .. }
+            | Rejection::RateLimited { client_id } => {
+                *client_id = self.owners[*client_id as usize].1
+            }
+            _ => {}
+        }
+        rejection
+    }
+
+    /// Every tenant which has had a client, by name
+    pub fn tenants(&self) -> BTreeSet<&str> {
+        self.owners
+            .iter()
+            .map(|(tenant, _)| tenant.as_str())
+            .collect()
+    }
+
+    /// The accounts report for `tenant`'s clients, with their own ids
+    pub fn report<'a>(&'a self, tenant: &'a str) -> impl Iterator<Item = ClientOutput> + 'a {
+        self.engine.report().filter_map(move |mut output| {
+            let (owner, id) = &self.owners[output.client as usize];
+            output.client = *id;
+            (owner == tenant).then_some(output)
+        })
+    }
+
+    pub fn engine(&self) -> &Engine<T> {
+        &self.engine
+    }
+
+    pub fn engine_mut(&mut self) -> &mut Engine<T> {
+        &mut self.engine
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::decimal::Decimal;
+    use crate::transaction::read_from_csv_reader;
+    use crate::transaction_set::MemoryClient;
+
+    #[test]
+    fn separate_tenants() {
+        let mut tenants = Tenants::new(Engine::new(MemoryClient::default()));
+        let results = read_from_csv_reader(
+            "\
+type,       client,  tx, amount, tenant
+deposit,         1,   1,    5.0, first
+deposit,         1,   1,    7.0, second
+withdrawal,      1,   2,    6.0, first
+dispute,         1,   1,       , second
+"
+            .as_bytes(),
+        )
+        .map(|t| {
+            let t = t.unwrap();
+            let tenant = t.metadata["tenant"].clone();
+            tenants.process(&tenant, t)
+        })
+        .collect::<Vec<_>>();
+        assert_eq!(
+            results,
+            vec![
+                Ok(()),
+                Ok(()),
+                Err(Error::Rejected(Rejection::InsufficientFunds {
+                    client_id: 1,
+                    requested: Decimal::new(6, 0),
+                    available: Decimal::new(5, 0),
+                })),
+                Ok(()),
+            ]
+        );
+
+        assert_eq!(tenants.tenants(), BTreeSet::from(["first", "second"]));
+        let first = tenants.report("first").collect::<Vec<_>>();
+        let second = tenants.report("second").collect::<Vec<_>>();
+        assert_eq!(first.len(), 1);
+        assert_eq!(
+            (first[0].client, &first[0].available),
+            (1, &Decimal::new(5, 0))
+        );
+        assert_eq!(second.len(), 1);
+        assert_eq!(
+            (second[0].client, &second[0].held),
+            (1, &Decimal::new(7, 0))
+        );
+    }
+}
@@ -0,0 +1,57 @@
+//! Invariant checking and proptest strategies, for property testing integrations with the engine
+
+use proptest::prelude::*;
+use std::collections::HashMap;
+
+use crate::decimal::Decimal;
+use crate::transaction::{DisputableType::*, Transaction, Type, Type::*};
+
+pub use stm_core::InvariantViolation;
+
+/// Amounts up to a million, at full precision
+pub fn decimal() -> impl Strategy<Value = Decimal> {
+    (0u64..1_000_000, 0u16..10_000).prop_map(|(dollars, cents)| Decimal::new(dollars, cents))
+}
+
+pub fn type_() -> impl Strategy<Value = Type> {
+    prop_oneof![
+        decimal().prop_map(|amount| Disputable(Deposit(amount))),
+        decimal().prop_map(|amount| Disputable(Withdrawal(amount))),
+        Just(Dispute(None)),
+        Just(Resolve(None)),
+        Just(Chargeback(None)),
+    ]
+}
+
+/// Transactions with ids drawn from `0..clients` and `0..transactions`.
+///
+/// Keeping the ranges small means disputes, resolves and chargebacks regularly reference real transactions.
+pub fn transaction(clients: u16, transactions: u32) -> impl Strategy<Value = Transaction> {
+    (0..clients, 0..transactions, type_()).prop_map(|(client_id, transaction_id, type_)| {
+        Transaction {
+            client_id,
+            transaction_id,
+            type_,
+            metadata: HashMap::new(),
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::engine::Engine;
+    use crate::transaction_set::MemoryClient;
+    use proptest::collection::vec;
+
+    proptest! {
+        #[test]
+        fn engine_invariants(transactions in vec(transaction(4, 16), 0..64)) {
+            let mut engine = Engine::new(MemoryClient::default());
+            for transaction in transactions {
+                let _ = engine.process(transaction);
+                prop_assert_eq!(engine.check_invariants(), Ok(()));
+            }
+        }
+    }
+}
@@ -0,0 +1,223 @@
+//! Account tiers, so a retail client with a couple of transactions isn't held to the same limits and policies as an
+//! enterprise merchant.
+//!
+//! Each tier can cap single withdrawals, allow an overdraft and only take disputes within a window. Transactions aren't
+//! dated, so deposits and withdrawals are timestamped by a clock as they're applied, the system clock by default, and
+//! disputes of those applied before then, or to a client without a window, are always in time.
+
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::rc::Rc;
+
+use crate::decimal::Decimal;
+use crate::engine::Rejection;
+use crate::limits::Limit;
+use crate::transaction::{DisputableType, Transaction, TransactionKind, Type};
+use crate::velocity::{now, parse_window};
+
+/// A tier's limits and policies, any of which may be left unset
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Tier {
+    /// The largest single withdrawal
+    pub max_withdrawal: Option<Decimal>,
+    /// How far clients may overdraw, overriding any other overdraft limit
+    pub overdraft: Option<Decimal>,
+    /// How long after a deposit or withdrawal it can be disputed, in seconds
+    pub dispute_window: Option<u64>,
+}
+
+/// A row of a tiers file
+#[derive(Deserialize)]
+struct Row {
+    tier: String,
+    max_withdrawal: Option<Decimal>,
+    overdraft: Option<Decimal>,
+    dispute_window: Option<String>,
+}
+
+impl Row {
+    /// The tier's name, and the tier
+    fn into_tier(self) -> Result<(String, Tier), String> {
+        Ok((
+            self.tier,
+            Tier {
+                max_withdrawal: self.max_withdrawal,
+                overdraft: self.overdraft,
+                dispute_window: self
+                    .dispute_window
+                    .as_deref()
+                    .map(parse_window)
+                    .transpose()?,
+            },
+        ))
+    }
+}
+
+/// The tiers, and which each client is in. Clients without a tier aren't limited by any.
+#[derive(Clone)]
+pub struct Tiers {
+    tiers: HashMap<String, Tier>,
+    clients: HashMap<u16, String>,
+    clock: Rc<dyn Fn() -> u64>,
+    /// When each deposit or withdrawal was applied, for clients whose tier has a dispute window
+    applied: HashMap<u32, u64>,
+}
+
+impl Default for Tiers {
+    fn default() -> Self {
+        Tiers {
+            tiers: HashMap::new(),
+            clients: HashMap::new(),
+            clock: Rc::new(now),
+            applied: HashMap::new(),
+        }
+    }
+}
+
+impl Tiers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_tier(mut self, name: &str, tier: Tier) -> Self {
+        self.tiers.insert(name.to_owned(), tier);
+        self
+    }
+
+    /// Puts `client` in the tier called `name`, which needn't have been added yet
+    pub fn with_client(mut self, client: u16, name: &str) -> Self {
+        self.clients.insert(client, name.to_owned());
+        self
+    }
+
+    /// Timestamps deposits and withdrawals with `clock`, in unix seconds, instead of the system clock
+    pub fn with_clock<C: Fn() -> u64 + 'static>(mut self, clock: C) -> Self {
+        self.clock = Rc::new(clock);
+        self
+    }
+
+    /// Reads `tier,max_withdrawal,overdraft,dispute_window` rows from `tiers`, where all but `tier` may be left empty,
+    /// and `client,tier` rows from `clients`, which may only name tiers in `tiers`
+    pub fn from_csv_readers<R: Read, C: Read>(tiers: R, clients: C) -> csv::Result<Self> {
+        #[derive(Deserialize)]
+        struct ClientRow {
+            client: u16,
+            tier: String,
+        }
+
+        let reader = || {
+            let mut builder = csv::ReaderBuilder::new();
+            builder.trim(csv::Trim::All);
+            builder
+        };
+        let mut result = Tiers::new();
+        for row in reader().from_reader(tiers).into_deserialize::<Row>() {
+            let (name, tier) = row?
+                .into_tier()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            result = result.with_tier(&name, tier);
+        }
+        for row in reader().from_reader(clients).into_deserialize() {
+            let ClientRow { client, tier } = row?;
+            if !result.tiers.contains_key(&tier) {
+                let e = format!("client {} is in unknown tier {:?}", client, tier);
+                return Err(io::Error::new(io::ErrorKind::InvalidData, e).into());
+            }
+            result = result.with_client(client, &tier);
+        }
+        Ok(result)
+    }
+
+    /// The tier `client` is in, if any
+    pub fn tier(&self, client: u16) -> Option<&Tier> {
+        self.tiers.get(self.clients.get(&client)?)
+    }
+
+    /// Rejects withdrawals over the client's tier's limit, and disputes after its window
+    pub(crate) fn check(&self, transaction: &Transaction) -> Result<(), Rejection> {
+        let client_id = transaction.client_id;
+        let tier = match self.tier(client_id) {
+            Some(tier) => tier,
+            None => return Ok(()),
+        };
+        match &transaction.type_ {
+            Type::Disputable(DisputableType::Withdrawal(amount)) => {
+                let limit = Limit {
+                    min: None,
+                    max: tier.max_withdrawal.clone(),
+                };
+                match limit.allows(amount) {
+                    true => Ok(()),
+                    false => Err(Rejection::LimitExceeded {
+                        client_id,
+                        kind: TransactionKind::Withdrawal,
+                        amount: amount.clone(),
+                        limit,
+                    }),
+                }
+            }
+            Type::Dispute(_) => {
+                let transaction_id = transaction.transaction_id;
+                match (tier.dispute_window, self.applied.get(&transaction_id)) {
+                    (Some(window), Some(&at)) if (self.clock)().saturating_sub(at) >= window => {
+                        Err(Rejection::DisputeWindowClosed {
+                            client_id,
+                            transaction_id,
+                        })
+                    }
+                    _ => Ok(()),
+                }
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Records when a transaction which has just been applied was, if it may later be disputed within a window
+    pub(crate) fn record(&mut self, client_id: u16, transaction_id: u32, kind: TransactionKind) {
+        let windowed = self
+            .tier(client_id)
+            .is_some_and(|tier| tier.dispute_window.is_some());
+        if windowed && matches!(kind, TransactionKind::Deposit | TransactionKind::Withdrawal) {
+            self.applied.insert(transaction_id, (self.clock)());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn read_tiers() {
+        let tiers = Tiers::from_csv_readers(
+            "\
+tier,       max_withdrawal, overdraft, dispute_window
+retail,             1000.0,          ,            30d
+enterprise,               ,   50000.0,
+"
+            .as_bytes(),
+            "client, tier\n1, retail\n2, enterprise\n".as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(
+            tiers.tier(1),
+            Some(&Tier {
+                max_withdrawal: Some(Decimal::new(1000, 0)),
+                overdraft: None,
+                dispute_window: Some(30 * 24 * 60 * 60),
+            })
+        );
+        assert_eq!(
+            tiers.tier(2).unwrap().overdraft,
+            Some(Decimal::new(50000, 0))
+        );
+        assert_eq!(tiers.tier(3), None);
+
+        assert!(Tiers::from_csv_readers(
+            "tier,max_withdrawal,overdraft,dispute_window\nretail,,,\n".as_bytes(),
+            "client,tier\n1,gold\n".as_bytes(),
+        )
+        .is_err());
+    }
+}
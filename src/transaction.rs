@@ -1,54 +1,208 @@
-use crate::decimal::Decimal;
-use csv::{ReaderBuilder, Trim};
-use serde::{Deserialize, Serialize};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::compression::Compression;
+use crate::decimal::{Decimal, Locale};
+use crate::kyc::Status;
+use csv::{ReaderBuilder, StringRecord, Trim};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use std::borrow::Borrow;
+use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::fmt;
 use std::hash::{Hash, Hasher};
 use std::io;
+use std::num::ParseIntError;
+#[cfg(not(target_arch = "wasm32"))]
 use std::path::Path;
+use std::str::FromStr;
 
 /// We can't use tags, so use an intermediary
 #[derive(Serialize, Deserialize, Debug)]
-struct CsvTransaction {
+pub(crate) struct CsvTransaction {
     #[serde(rename = "type")]
-    type_: CsvType,
+    pub(crate) type_: CsvType,
+    pub(crate) client: u16,
+    pub(crate) tx: u32,
+    pub(crate) amount: Option<CsvAmount>,
+    /// Required for adjustments, and optional for disputes, resolves and chargebacks
+    #[serde(default)]
+    pub(crate) reason: Option<String>,
+}
+
+/// An amount column, which only adjustments may give a leading `-`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct CsvAmount {
+    pub(crate) negative: bool,
+    pub(crate) magnitude: Decimal,
+}
+
+impl From<Decimal> for CsvAmount {
+    fn from(magnitude: Decimal) -> Self {
+        CsvAmount {
+            negative: false,
+            magnitude,
+        }
+    }
+}
+
+impl FromStr for CsvAmount {
+    type Err = ParseIntError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.strip_prefix('-') {
+            Some(magnitude) => CsvAmount {
+                negative: true,
+                magnitude: magnitude.parse()?,
+            },
+            None => CsvAmount::from(s.strip_prefix('+').unwrap_or(s).parse::<Decimal>()?),
+        })
+    }
+}
+
+impl fmt::Display for CsvAmount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.negative {
+            f.write_str("-")?;
+        }
+        self.magnitude.fmt(f)
+    }
+}
+
+impl Serialize for CsvAmount {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for CsvAmount {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl<'de> de::Visitor<'de> for Visitor {
+            type Value = CsvAmount;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("string containing a decimal, optionally signed")
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<CsvAmount, E> {
+                value.parse().map_err(de::Error::custom)
+            }
+        }
+
+        deserializer.deserialize_str(Visitor)
+    }
+}
+
+/// A row of the debit/credit dialect, where debits are withdrawals and credits are deposits
+#[derive(Deserialize, Debug)]
+struct DebitCreditRow {
     client: u16,
     tx: u32,
-    amount: Option<Decimal>,
+    debit: Option<CsvAmount>,
+    credit: Option<CsvAmount>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(try_from = "DebitCreditRow")]
+struct DebitCredit(Transaction);
+
+impl TryFrom<DebitCreditRow> for DebitCredit {
+    type Error = &'static str;
+    fn try_from(row: DebitCreditRow) -> Result<Self, Self::Error> {
+        // Neither column is signed, since which one is given says which way the money goes
+        let unsigned = |amount: Option<CsvAmount>| match amount {
+            Some(CsvAmount { negative: true, .. }) => Err("Negative amount"),
+            amount => Ok(amount.map(|a| a.magnitude)),
+        };
+        // Bank exports often fill the unused column with zero
+        let zero = Decimal::zero();
+        let type_ = match (unsigned(row.debit)?, unsigned(row.credit)?) {
+            (Some(debit), Some(credit)) if credit == zero => DisputableType::Withdrawal(debit),
+            (Some(debit), Some(credit)) if debit == zero => DisputableType::Deposit(credit),
+            (Some(_), Some(_)) => return Err("both debit and credit given"),
+            (Some(debit), None) => DisputableType::Withdrawal(debit),
+            (None, Some(credit)) => DisputableType::Deposit(credit),
+            (None, None) => return Err("Missing amount"),
+        };
+        Ok(DebitCredit(Transaction {
+            client_id: row.client,
+            transaction_id: row.tx,
+            type_: Type::Disputable(type_),
+            metadata: HashMap::new(),
+        }))
+    }
 }
 
 // TODO: A macro might be useful to generate this as a part of `Type`
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
-enum CsvType {
+pub(crate) enum CsvType {
     Deposit,
     Withdrawal,
     Dispute,
     Resolve,
     Chargeback,
+    Fee,
+    Interest,
+    Close,
+    Authorize,
+    Capture,
+    Void,
+    Refund,
+    Adjustment,
+    Status,
 }
 
 // TODO: Disputes of chargebacks... yay recursion!
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
 pub enum DisputableType {
     Deposit(Decimal),
     Withdrawal(Decimal),
 }
 
+/// An operator's correction to a client's available funds, outside of the dispute flow
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub struct Adjustment {
+    /// Debited from available funds, rather than credited. Written as a negative amount
+    pub debit: bool,
+    pub amount: Decimal,
+    /// Why the correction was made, e.g. `duplicate-deposit`
+    pub reason: String,
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct DisputableTransaction {
     pub client_id: u16,
     pub transaction_id: u32,
     pub type_: DisputableType,
+    /// The metadata of the transaction, kept for when it's disputed
+    pub metadata: HashMap<String, String>,
+    /// The disputes, resolves and chargebacks applied to it so far, with their reasons
+    pub history: Vec<(TransactionKind, Option<String>)>,
 }
 
-#[derive(Deserialize, Debug, PartialEq, Eq, Clone)]
-#[serde(try_from = "CsvTransaction")]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+#[serde(try_from = "CsvTransaction", into = "CsvTransaction")]
 pub struct Transaction {
     pub client_id: u16,
     pub transaction_id: u32,
     pub type_: Type,
+    /// Columns we don't understand, like `memo` or `merchant`, by header. Not written back out to CSV
+    pub metadata: HashMap<String, String>,
+}
+
+impl Transaction {
+    /// The transaction as a headerless CSV row without a line terminator, like `deposit,1,1,1.0000,`, which is the same
+    /// however the transaction was read, for hashing and signing
+    pub fn to_csv_row(&self) -> Vec<u8> {
+        let mut row = csv::WriterBuilder::new()
+            .has_headers(false)
+            .terminator(csv::Terminator::Any(b'\n'))
+            .from_writer(Vec::new());
+        row.serialize(self).expect("transactions always serialize");
+        let mut row = row.into_inner().expect("writing to memory");
+        row.pop();
+        row
+    }
 }
 
 impl Hash for Transaction {
@@ -63,65 +217,533 @@ impl Borrow<u32> for Transaction {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
 pub enum Type {
     Disputable(DisputableType),
+    /// Each step of a dispute may give a reason, e.g. a card network reason code like `10.4`
+    Dispute(Option<String>),
+    Resolve(Option<String>),
+    Chargeback(Option<String>),
+    /// Charged from available funds, even if the client is locked. Can't be disputed
+    Fee(Decimal),
+    /// Paid into available funds by an accrual, even if the client is locked. Can't be disputed
+    Interest(Decimal),
+    /// Closes the client's account once no disputes are outstanding, after which every transaction is rejected
+    Close,
+    /// Sets funds aside for the client until they're captured, when they become a deposit of the same transaction
+    Authorize(Decimal),
+    Capture,
+    /// Releases an authorization which won't be captured, e.g. because it expired
+    Void,
+    /// Pays part of a deposit back out of available funds, referring to the deposit like a dispute does. What's been
+    /// refunded can't be disputed
+    Refund(Decimal),
+    /// Credits or debits available funds, even if the client is locked. Can't be disputed
+    Adjustment(Adjustment),
+    /// Changes the client's KYC status, written as the reason
+    Status(Status),
+}
+
+impl Type {
+    pub fn kind(&self) -> TransactionKind {
+        match self {
+            Type::Disputable(DisputableType::Deposit(_)) => TransactionKind::Deposit,
+            Type::Disputable(DisputableType::Withdrawal(_)) => TransactionKind::Withdrawal,
+            Type::Dispute(_) => TransactionKind::Dispute,
+            Type::Resolve(_) => TransactionKind::Resolve,
+            Type::Chargeback(_) => TransactionKind::Chargeback,
+            Type::Fee(_) => TransactionKind::Fee,
+            Type::Interest(_) => TransactionKind::Interest,
+            Type::Close => TransactionKind::Close,
+            Type::Authorize(_) => TransactionKind::Authorize,
+            Type::Capture => TransactionKind::Capture,
+            Type::Void => TransactionKind::Void,
+            Type::Refund(_) => TransactionKind::Refund,
+            Type::Adjustment(_) => TransactionKind::Adjustment,
+            Type::Status(_) => TransactionKind::Status,
+        }
+    }
+
+    /// The amount the transaction gives, which disputes, captures and the like take from the transaction they refer to
+    /// instead
+    pub fn amount(&self) -> Option<&Decimal> {
+        match self {
+            Type::Disputable(
+                DisputableType::Deposit(amount) | DisputableType::Withdrawal(amount),
+            )
+            | Type::Fee(amount)
+            | Type::Interest(amount)
+            | Type::Authorize(amount)
+            | Type::Refund(amount) => Some(amount),
+            Type::Adjustment(adjustment) => Some(&adjustment.amount),
+            Type::Dispute(_)
+            | Type::Resolve(_)
+            | Type::Chargeback(_)
+            | Type::Close
+            | Type::Capture
+            | Type::Void
+            | Type::Status(_) => None,
+        }
+    }
+
+    /// The reason given for a step of a dispute, or for an adjustment, or the new status
+    pub fn reason(&self) -> Option<&str> {
+        match self {
+            Type::Dispute(reason) | Type::Resolve(reason) | Type::Chargeback(reason) => {
+                reason.as_deref()
+            }
+            Type::Adjustment(adjustment) => Some(&adjustment.reason),
+            Type::Status(status) => Some(status.as_str()),
+            _ => None,
+        }
+    }
+}
+
+/// A `Type` without its data, as written in the `type` column
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum TransactionKind {
+    Deposit,
+    Withdrawal,
     Dispute,
     Resolve,
     Chargeback,
+    Fee,
+    Interest,
+    Close,
+    Authorize,
+    Capture,
+    Void,
+    Refund,
+    Adjustment,
+    Status,
+}
+
+impl fmt::Display for TransactionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TransactionKind::Deposit => "deposit",
+            TransactionKind::Withdrawal => "withdrawal",
+            TransactionKind::Dispute => "dispute",
+            TransactionKind::Resolve => "resolve",
+            TransactionKind::Chargeback => "chargeback",
+            TransactionKind::Fee => "fee",
+            TransactionKind::Interest => "interest",
+            TransactionKind::Close => "close",
+            TransactionKind::Authorize => "authorize",
+            TransactionKind::Capture => "capture",
+            TransactionKind::Void => "void",
+            TransactionKind::Refund => "refund",
+            TransactionKind::Adjustment => "adjustment",
+            TransactionKind::Status => "status",
+        })
+    }
 }
 
-// TODO: improve errors
-pub struct Error;
+#[derive(Debug, PartialEq, Eq)]
+pub enum Error {
+    MissingAmount,
+    /// Only adjustments can be negative
+    NegativeAmount,
+    /// Adjustments must say why they were made
+    MissingReason,
+    /// An amount of zero, which `AmountRules` can allow
+    ZeroAmount,
+    /// An amount over `AmountRules::max`
+    AmountOutOfRange,
+    /// A status transaction without a known status as its reason
+    InvalidStatus,
+}
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        // TODO: improve errors
-        write!(f, "Missing amount")
+        f.write_str(match self {
+            Error::MissingAmount => "Missing amount",
+            Error::NegativeAmount => "Negative amount",
+            Error::MissingReason => "Missing reason",
+            Error::ZeroAmount => "Zero amount",
+            Error::AmountOutOfRange => "Amount out of range",
+            Error::InvalidStatus => "Missing or unknown status",
+        })
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl Error {
+    /// A stable code for the error, like `Rejection::code`
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::MissingAmount => "E_MISSING_AMOUNT",
+            Error::NegativeAmount => "E_NEGATIVE_AMOUNT",
+            Error::MissingReason => "E_MISSING_REASON",
+            Error::ZeroAmount => "E_ZERO_AMOUNT",
+            Error::AmountOutOfRange => "E_AMOUNT_OUT_OF_RANGE",
+            Error::InvalidStatus => "E_INVALID_STATUS",
+        }
     }
 }
 
 impl TryFrom<CsvTransaction> for Transaction {
-    // TODO: improve errors
     type Error = Error;
     fn try_from(t: CsvTransaction) -> Result<Self, Self::Error> {
+        let unsigned = |amount| match amount {
+            Some(CsvAmount {
+                negative: false,
+                magnitude,
+            }) => Ok(magnitude),
+            Some(CsvAmount { negative: true, .. }) => Err(Error::NegativeAmount),
+            None => Err(Error::MissingAmount),
+        };
         Ok(Transaction {
             client_id: t.client,
             transaction_id: t.tx,
-            type_: match (t.type_, t.amount) {
-                (CsvType::Deposit, Some(amount)) => {
-                    Type::Disputable(DisputableType::Deposit(amount))
+            type_: match t.type_ {
+                CsvType::Deposit => Type::Disputable(DisputableType::Deposit(unsigned(t.amount)?)),
+                CsvType::Withdrawal => {
+                    Type::Disputable(DisputableType::Withdrawal(unsigned(t.amount)?))
                 }
-                (CsvType::Deposit, None) => return Err(Error),
-
-                (CsvType::Withdrawal, Some(amount)) => {
-                    Type::Disputable(DisputableType::Withdrawal(amount))
+                CsvType::Fee => Type::Fee(unsigned(t.amount)?),
+                CsvType::Interest => Type::Interest(unsigned(t.amount)?),
+                CsvType::Authorize => Type::Authorize(unsigned(t.amount)?),
+                CsvType::Refund => Type::Refund(unsigned(t.amount)?),
+                CsvType::Adjustment => {
+                    let CsvAmount {
+                        negative,
+                        magnitude,
+                    } = t.amount.ok_or(Error::MissingAmount)?;
+                    Type::Adjustment(Adjustment {
+                        debit: negative,
+                        amount: magnitude,
+                        reason: t.reason.ok_or(Error::MissingReason)?,
+                    })
                 }
-                (CsvType::Withdrawal, None) => return Err(Error),
 
-                (CsvType::Dispute, _) => Type::Dispute,
-                (CsvType::Resolve, _) => Type::Resolve,
-                (CsvType::Chargeback, _) => Type::Chargeback,
+                CsvType::Dispute => Type::Dispute(t.reason),
+                CsvType::Resolve => Type::Resolve(t.reason),
+                CsvType::Chargeback => Type::Chargeback(t.reason),
+                CsvType::Close => Type::Close,
+                CsvType::Capture => Type::Capture,
+                CsvType::Void => Type::Void,
+                CsvType::Status => Type::Status(
+                    t.reason
+                        .as_deref()
+                        .and_then(|s| s.parse().ok())
+                        .ok_or(Error::InvalidStatus)?,
+                ),
             },
+            metadata: HashMap::new(),
         })
     }
 }
 
+impl From<Transaction> for CsvTransaction {
+    fn from(t: Transaction) -> Self {
+        let (type_, amount, reason) = match t.type_ {
+            Type::Disputable(DisputableType::Deposit(amount)) => {
+                (CsvType::Deposit, Some(amount.into()), None)
+            }
+            Type::Disputable(DisputableType::Withdrawal(amount)) => {
+                (CsvType::Withdrawal, Some(amount.into()), None)
+            }
+            Type::Dispute(reason) => (CsvType::Dispute, None, reason),
+            Type::Resolve(reason) => (CsvType::Resolve, None, reason),
+            Type::Chargeback(reason) => (CsvType::Chargeback, None, reason),
+            Type::Fee(amount) => (CsvType::Fee, Some(amount.into()), None),
+            Type::Interest(amount) => (CsvType::Interest, Some(amount.into()), None),
+            Type::Close => (CsvType::Close, None, None),
+            Type::Authorize(amount) => (CsvType::Authorize, Some(amount.into()), None),
+            Type::Capture => (CsvType::Capture, None, None),
+            Type::Void => (CsvType::Void, None, None),
+            Type::Refund(amount) => (CsvType::Refund, Some(amount.into()), None),
+            Type::Adjustment(Adjustment {
+                debit,
+                amount,
+                reason,
+            }) => (
+                CsvType::Adjustment,
+                Some(CsvAmount {
+                    negative: debit,
+                    magnitude: amount,
+                }),
+                Some(reason),
+            ),
+            Type::Status(status) => (CsvType::Status, None, Some(status.to_string())),
+        };
+        CsvTransaction {
+            type_,
+            client: t.client_id,
+            tx: t.transaction_id,
+            amount,
+            reason,
+        }
+    }
+}
+
+/// Decompresses `.gz` and `.zst` files on the fly. Not available on `wasm32`, which has no filesystem
+#[cfg(not(target_arch = "wasm32"))]
 pub fn read_from_csv_file<P: AsRef<Path>>(
     path: P,
 ) -> io::Result<impl Iterator<Item = csv::Result<Transaction>>> {
-    Ok(ReaderBuilder::new()
-        .trim(Trim::All)
-        .from_path(path)?
-        .into_deserialize())
+    let compression = Compression::detect(&path);
+    read_from_compressed_csv_file(path, compression)
+}
+
+/// Like `read_from_csv_file`, but with the compression given rather than guessed from the extension
+#[cfg(not(target_arch = "wasm32"))]
+pub fn read_from_compressed_csv_file<P: AsRef<Path>>(
+    path: P,
+    compression: Compression,
+) -> io::Result<impl Iterator<Item = csv::Result<Transaction>>> {
+    Ok(read_from_csv_reader(
+        compression.decoder(std::fs::File::open(path)?)?,
+    ))
 }
 
-#[allow(dead_code)]
 pub fn read_from_csv_reader<R: io::Read>(rdr: R) -> impl Iterator<Item = csv::Result<Transaction>> {
-    ReaderBuilder::new()
-        .trim(Trim::All)
-        .from_reader(rdr)
-        .into_deserialize()
+    read_from_csv_reader_with(rdr, &CsvOptions::default())
+}
+
+/// How a transaction file is laid out, for files that aren't quite standard CSV
+#[derive(Clone, Debug)]
+pub struct CsvOptions {
+    /// e.g. `b'\t'` for TSV, or `b'|'`
+    pub delimiter: u8,
+    pub quote: u8,
+    /// Parse amounts with `Decimal::parse_lenient` in this locale, rather than strictly
+    pub lenient_amounts: Option<Locale>,
+    pub dialect: Dialect,
+    pub amounts: AmountRules,
+}
+
+/// Which amounts are accepted once they've been parsed, which can differ between deployments
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AmountRules {
+    /// Accept amounts of zero, which otherwise are rejected as almost certainly a mistake upstream
+    pub allow_zero: bool,
+    /// The largest amount of any transaction
+    pub max: Option<Decimal>,
+}
+
+impl AmountRules {
+    /// Checks the transaction's amount, if it has one
+    pub fn check(&self, type_: &Type) -> Result<(), Error> {
+        match type_.amount() {
+            Some(amount) if !self.allow_zero && *amount == Decimal::zero() => {
+                Err(Error::ZeroAmount)
+            }
+            Some(amount) if self.max.as_ref().is_some_and(|max| amount > max) => {
+                Err(Error::AmountOutOfRange)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Which columns describe a transaction
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Dialect {
+    /// `type`, `client`, `tx`, `amount` and `reason`, which adjustments require
+    #[default]
+    Standard,
+    /// `client`, `tx`, `debit` and `credit`, like many bank exports. Debits are withdrawals and credits are deposits,
+    /// so there are no disputes.
+    DebitCredit,
+}
+
+impl Dialect {
+    /// The columns we understand
+    pub(crate) fn columns(self) -> &'static [&'static str] {
+        match self {
+            Dialect::Standard => &["type", "client", "tx", "amount", "reason"],
+            Dialect::DebitCredit => &["client", "tx", "debit", "credit"],
+        }
+    }
+}
+
+impl fmt::Display for Dialect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Dialect::Standard => "standard",
+            Dialect::DebitCredit => "debit-credit",
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct UnknownDialect(String);
+
+impl fmt::Display for UnknownDialect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "unknown dialect {:?}, expected standard or debit-credit",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for UnknownDialect {}
+
+impl FromStr for Dialect {
+    type Err = UnknownDialect;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "standard" => Ok(Dialect::Standard),
+            "debit-credit" => Ok(Dialect::DebitCredit),
+            _ => Err(UnknownDialect(s.to_owned())),
+        }
+    }
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        CsvOptions {
+            delimiter: b',',
+            quote: b'"',
+            lenient_amounts: None,
+            dialect: Dialect::Standard,
+            amounts: AmountRules::default(),
+        }
+    }
+}
+
+impl CsvOptions {
+    pub(crate) fn reader_builder(&self) -> ReaderBuilder {
+        let mut builder = ReaderBuilder::new();
+        builder
+            .trim(Trim::All)
+            // Extra trailing columns are metadata, not an error
+            .flexible(true)
+            .delimiter(self.delimiter)
+            .quote(self.quote);
+        builder
+    }
+
+    /// Rewrites the amount column into the strict format if amounts are lenient. Amounts which can't be parsed even
+    /// leniently are left alone, to fail when deserialized.
+    pub(crate) fn normalize(&self, record: StringRecord, headers: &StringRecord) -> StringRecord {
+        let locale = match self.lenient_amounts {
+            Some(locale) => locale,
+            None => return record,
+        };
+        let is_amount = |i: usize| {
+            headers
+                .get(i)
+                .is_some_and(|h| matches!(h, "amount" | "debit" | "credit"))
+        };
+
+        let mut rewritten = record
+            .iter()
+            .enumerate()
+            .map(|(i, field)| match is_amount(i) && !field.is_empty() {
+                true => Decimal::parse_lenient(field, locale)
+                    .map_or_else(|_| field.to_owned(), |a| a.to_string()),
+                false => field.to_owned(),
+            })
+            .collect::<StringRecord>();
+        rewritten.set_position(record.position().cloned());
+        rewritten
+    }
+
+    /// Deserializes a row in whichever dialect the file is in, rejecting amounts our `AmountRules` don't allow with an
+    /// I/O error wrapping an `Error`
+    pub(crate) fn deserialize(
+        &self,
+        record: &StringRecord,
+        headers: Option<&StringRecord>,
+    ) -> csv::Result<Transaction> {
+        let transaction = match self.dialect {
+            Dialect::Standard => record.deserialize(headers)?,
+            Dialect::DebitCredit => record.deserialize(headers).map(|DebitCredit(t)| t)?,
+        };
+        self.amounts
+            .check(&transaction.type_)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(transaction)
+    }
+
+    /// Like `deserialize`, but leaving any missing amount to be found later
+    pub(crate) fn deserialize_csv(
+        &self,
+        record: &StringRecord,
+        headers: Option<&StringRecord>,
+    ) -> csv::Result<CsvTransaction> {
+        match self.dialect {
+            Dialect::Standard => record.deserialize(headers),
+            Dialect::DebitCredit => record
+                .deserialize(headers)
+                .map(|DebitCredit(t)| CsvTransaction::from(t)),
+        }
+    }
+}
+
+/// Any columns of a row we don't understand, e.g. a trailing `memo`, kept as `(header, value)` pairs in file order.
+/// Columns past the end of the header are named by their (zero based) index.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Metadata(pub Vec<(String, String)>);
+
+impl Metadata {
+    fn from_record(record: &StringRecord, headers: &StringRecord, known: &[&str]) -> Self {
+        Metadata(
+            record
+                .iter()
+                .enumerate()
+                .filter(|&(_, value)| !value.is_empty())
+                .filter_map(|(i, value)| match headers.get(i) {
+                    Some(header) if known.contains(&header) => None,
+                    Some(header) => Some((header.to_owned(), value.to_owned())),
+                    None => Some((i.to_string(), value.to_owned())),
+                })
+                .collect(),
+        )
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl fmt::Display for Metadata {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (header, value)) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{}={:?}", header, value)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn read_from_csv_reader_with<R: io::Read>(
+    rdr: R,
+    options: &CsvOptions,
+) -> impl Iterator<Item = csv::Result<Transaction>> {
+    read_from_csv_reader_with_metadata(rdr, options).map(|t| t.map(|(t, _)| t))
+}
+
+/// Like `read_from_csv_reader_with`, but also passing through the columns we don't understand
+pub fn read_from_csv_reader_with_metadata<R: io::Read>(
+    rdr: R,
+    options: &CsvOptions,
+) -> impl Iterator<Item = csv::Result<(Transaction, Metadata)>> {
+    let mut rdr = options.reader_builder().from_reader(rdr);
+    let headers = rdr.headers().cloned();
+    let options = options.clone();
+    rdr.into_records().map(move |record| {
+        let headers = headers.as_ref().ok();
+        let record = match headers {
+            Some(headers) => options.normalize(record?, headers),
+            None => record?,
+        };
+        let metadata = headers.map_or_else(Metadata::default, |headers| {
+            Metadata::from_record(&record, headers, options.dialect.columns())
+        });
+        let mut transaction = options.deserialize(&record, headers)?;
+        transaction.metadata = metadata.0.iter().cloned().collect();
+        Ok((transaction, metadata))
+    })
 }
 
 #[cfg(test)]
@@ -147,7 +769,7 @@ chargeback,         10,   21,
             assert_eq!(record.type_, CsvType::Deposit);
             assert_eq!(record.client, 1);
             assert_eq!(record.tx, 1);
-            assert_eq!(record.amount, Some(Decimal::new(1, 0)));
+            assert_eq!(record.amount, Some(Decimal::new(1, 0).into()));
         }
 
         {
@@ -159,4 +781,196 @@ chargeback,         10,   21,
             assert_eq!(record.amount, None);
         }
     }
+
+    #[test]
+    fn round_trip_transactions() {
+        let transactions = vec![
+            Transaction {
+                client_id: 1,
+                transaction_id: 1,
+                type_: Type::Disputable(DisputableType::Withdrawal(Decimal::new(1, 5))),
+                metadata: HashMap::new(),
+            },
+            Transaction {
+                client_id: 2,
+                transaction_id: 1,
+                type_: Type::Chargeback(Some("13.1".to_owned())),
+                metadata: HashMap::new(),
+            },
+            Transaction {
+                client_id: 2,
+                transaction_id: 2,
+                type_: Type::Adjustment(Adjustment {
+                    debit: true,
+                    amount: Decimal::new(3, 0),
+                    reason: "duplicate-deposit".to_owned(),
+                }),
+                metadata: HashMap::new(),
+            },
+            Transaction {
+                client_id: 2,
+                transaction_id: 3,
+                type_: Type::Status(Status::Suspended),
+                metadata: HashMap::new(),
+            },
+        ];
+
+        let mut wtr = csv::Writer::from_writer(vec![]);
+        for transaction in &transactions {
+            wtr.serialize(transaction).unwrap();
+        }
+        let data = wtr.into_inner().unwrap();
+        assert_eq!(
+            read_from_csv_reader(&data[..])
+                .collect::<csv::Result<Vec<_>>>()
+                .unwrap(),
+            transactions
+        );
+    }
+
+    #[test]
+    fn read_pipe_delimited() {
+        let data = "type|client|tx|amount\ndeposit|1|1|'1,0'\nwithdrawal|1|2|0.5\n";
+        let options = CsvOptions {
+            delimiter: b'|',
+            quote: b'\'',
+            ..CsvOptions::default()
+        };
+        let transactions = read_from_csv_reader_with(data.as_bytes(), &options)
+            .map(|t| t.map(|t| t.type_))
+            .collect::<Vec<_>>();
+        assert!(transactions[0].is_err());
+        assert_eq!(
+            transactions[1].as_ref().unwrap(),
+            &Type::Disputable(DisputableType::Withdrawal(Decimal::new(0, 5000)))
+        );
+    }
+
+    #[test]
+    fn read_lenient_amounts() {
+        let data =
+            "type;client;tx;amount\ndeposit;1;1;1 234,50 €\ndeposit;1;2;1,2,3\ndispute;1;1;\n";
+        let options = CsvOptions {
+            delimiter: b';',
+            lenient_amounts: Some(Locale::DECIMAL_COMMA),
+            ..CsvOptions::default()
+        };
+        let transactions = read_from_csv_reader_with(data.as_bytes(), &options)
+            .map(|t| t.map(|t| t.type_))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            transactions[0].as_ref().unwrap(),
+            &Type::Disputable(DisputableType::Deposit(Decimal::new(1234, 5000)))
+        );
+        assert!(transactions[1].is_err());
+        assert_eq!(transactions[2].as_ref().unwrap(), &Type::Dispute(None));
+    }
+
+    #[test]
+    fn read_extra_columns() {
+        let data = "\
+tx, memo, type, amount, client
+ 1, rent, deposit, 5.0, 1
+ 2, , withdrawal, 1.0, 1, trailing
+";
+        let transactions =
+            read_from_csv_reader_with_metadata(data.as_bytes(), &CsvOptions::default())
+                .collect::<csv::Result<Vec<_>>>()
+                .unwrap();
+        assert_eq!(
+            transactions[0].0.type_,
+            Type::Disputable(DisputableType::Deposit(Decimal::new(5, 0)))
+        );
+        assert_eq!(
+            transactions[0].1,
+            Metadata(vec![("memo".to_owned(), "rent".to_owned())])
+        );
+        assert_eq!(
+            transactions[0].0.metadata,
+            transactions[0].1 .0.iter().cloned().collect()
+        );
+        assert_eq!(transactions[1].0.transaction_id, 2);
+        assert_eq!(
+            transactions[1].1,
+            Metadata(vec![("5".to_owned(), "trailing".to_owned())])
+        );
+        assert_eq!(transactions[1].1.to_string(), r#"5="trailing""#);
+    }
+
+    #[test]
+    fn read_debit_credit() {
+        let data = "\
+date, client, tx, debit, credit
+2021-06-01, 1, 1, , 10.00
+2021-06-02, 1, 2, 2.50, 0.00
+2021-06-03, 1, 3, 1.00, 1.00
+2021-06-04, 1, 4, ,
+2021-06-05, 1, 5, -1.00,
+";
+        let options = CsvOptions {
+            dialect: Dialect::DebitCredit,
+            ..CsvOptions::default()
+        };
+        let transactions = read_from_csv_reader_with_metadata(data.as_bytes(), &options)
+            .map(|t| t.map(|(t, metadata)| (t.type_, metadata.to_string())))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            transactions[0].as_ref().unwrap(),
+            &(
+                Type::Disputable(DisputableType::Deposit(Decimal::new(10, 0))),
+                r#"date="2021-06-01""#.to_owned()
+            )
+        );
+        assert_eq!(
+            transactions[1].as_ref().unwrap().0,
+            Type::Disputable(DisputableType::Withdrawal(Decimal::new(2, 5000)))
+        );
+        assert!(transactions[2].is_err());
+        assert!(transactions[3].is_err());
+        assert!(transactions[4].is_err());
+    }
+
+    #[test]
+    fn amount_rules() {
+        let data = "\
+type,       client,  tx, amount, reason
+deposit,         1,   1,    0.0,
+adjustment,      1,   2,   -0.0, typo
+withdrawal,      1,   3,  100.0,
+deposit,         1,   4, 100.01,
+dispute,         1,   1,       ,
+";
+        let errors = |amounts| {
+            let options = CsvOptions {
+                amounts,
+                ..CsvOptions::default()
+            };
+            read_from_csv_reader_with(data.as_bytes(), &options)
+                .map(|t| {
+                    t.err().map(|e| match e.kind() {
+                        csv::ErrorKind::Io(e) => e.get_ref().unwrap().to_string(),
+                        _ => panic!("{}", e),
+                    })
+                })
+                .collect::<Vec<_>>()
+        };
+        let zero = Some("Zero amount".to_owned());
+        assert_eq!(
+            errors(AmountRules::default()),
+            [zero.clone(), zero, None, None, None]
+        );
+        assert_eq!(
+            errors(AmountRules {
+                allow_zero: true,
+                max: Some(Decimal::new(100, 0)),
+            }),
+            [
+                None,
+                None,
+                None,
+                Some("Amount out of range".to_owned()),
+                None
+            ]
+        );
+    }
 }
@@ -1,15 +1,46 @@
+#[cfg(feature = "cached")]
 use cached::Cached;
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
 
-use crate::transaction::DisputableTransaction;
+use crate::compression::Compression;
+use crate::decimal::Decimal;
+use crate::record;
+use crate::transaction::{
+    DisputableTransaction, DisputableType, Transaction, TransactionKind, Type,
+};
+use crate::velocity::now;
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub enum State {
     Committed,
     Resolved,
     Disputed,
     ChargedBack,
     ChargedBackFinal,
+    /// An authorization which hasn't been captured, which becomes `Committed` once it is
+    Authorized,
+    Voided,
+}
+
+impl State {
+    /// Whether a transaction in this state can be moved to `to`, as `Client::update` describes
+    pub fn can_become(self, to: State) -> bool {
+        use State::*;
+        matches!(
+            (self, to),
+            (Committed, Disputed)
+                | (Disputed, Resolved | ChargedBack)
+                | (Resolved, Committed)
+                | (ChargedBack, ChargedBackFinal)
+                | (Authorized, Committed | Voided)
+        )
+    }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -18,79 +49,1108 @@ pub enum UpdateFailure {
     WrongState(State),
 }
 
+/// A transaction moved from one state to another by `Client::transition`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Transition {
+    pub transaction: DisputableTransaction,
+    pub from: State,
+    pub to: State,
+}
+
+/// What a store holds and roughly what it takes to hold it, for capacity planning
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct StoreStats {
+    /// How many transactions are in each state, leaving out states none are in
+    pub states: BTreeMap<State, u64>,
+    /// Roughly how much memory the transactions take, in bytes
+    pub memory_bytes: u64,
+    /// How much disk the store takes, in bytes
+    pub disk_bytes: u64,
+    /// The lowest id of a disputed transaction. Transactions aren't dated, so this stands in for the oldest dispute.
+    pub oldest_disputed: Option<u32>,
+}
+
+impl StoreStats {
+    /// The stats of `entries`, without any memory or disk
+    pub fn of<I: IntoIterator<Item = (DisputableTransaction, State)>>(entries: I) -> Self {
+        let mut stats = StoreStats::default();
+        for (t, state) in entries {
+            stats.count(&t, state);
+        }
+        stats
+    }
+
+    /// How many transactions there are in all
+    pub fn entries(&self) -> u64 {
+        self.states.values().sum()
+    }
+
+    /// Adds the stats of another store holding different transactions, like another shard
+    pub fn merge(&mut self, other: StoreStats) {
+        for (state, count) in other.states {
+            *self.states.entry(state).or_default() += count;
+        }
+        self.memory_bytes += other.memory_bytes;
+        self.disk_bytes += other.disk_bytes;
+        self.oldest_disputed = match (self.oldest_disputed, other.oldest_disputed) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+    }
+
+    pub(crate) fn count(&mut self, t: &DisputableTransaction, state: State) {
+        *self.states.entry(state).or_default() += 1;
+        if state == State::Disputed {
+            let id = t.transaction_id;
+            self.oldest_disputed = Some(self.oldest_disputed.map_or(id, |oldest| oldest.min(id)));
+        }
+    }
+}
+
+/// Roughly how much memory a stored transaction takes, with its metadata and history
+fn memory_size(t: &DisputableTransaction) -> u64 {
+    let metadata = t.metadata.iter().map(|(k, v)| k.len() + v.len());
+    let history = t.history.iter().map(|(_, reason)| {
+        std::mem::size_of::<(TransactionKind, Option<String>)>()
+            + reason.as_ref().map_or(0, String::len)
+    });
+    (std::mem::size_of::<(DisputableTransaction, State)>() + metadata.chain(history).sum::<usize>())
+        as u64
+}
+
 pub trait Client {
     fn store(&mut self, t: DisputableTransaction);
     fn access(&mut self, id: u32) -> Option<(DisputableTransaction, State)>;
-    //
+    /// Moves a transaction to `state`, returning it. A committed transaction can be disputed, a disputed one resolved
+    /// or charged back, and then committed again or charged back for good, and an authorized one committed or voided.
+    /// Anything else fails with the state the transaction is in, leaving it there.
     fn update(&mut self, id: u32, state: State) -> Result<DisputableTransaction, UpdateFailure>;
+    /// Like `update`, but also says which state the transaction moved from, as some states can be reached from more
+    /// than one. By default that's read before updating, so stores shared between engines should answer both at once.
+    fn transition(&mut self, id: u32, state: State) -> Result<Transition, UpdateFailure> {
+        let (_, from) = self.access(id).ok_or(UpdateFailure::NotFound)?;
+        let transaction = self.update(id, state)?;
+        Ok(Transition {
+            transaction,
+            from,
+            to: state,
+        })
+    }
+    /// Moves every transaction of client `from` to client `to`
+    fn reassign(&mut self, from: u16, to: u16);
+    /// Records a refund of part of a committed deposit, which stays committed with the refund taken off its amount, so
+    /// what's left can still be disputed or refunded. Returns the deposit with what's left of it. The caller checks the
+    /// refund is no more than the deposit.
+    fn refund(&mut self, id: u32, amount: Decimal) -> Result<DisputableTransaction, UpdateFailure>;
+    /// Adds a dispute, resolve or chargeback which was applied to the transaction to its history
+    fn record(&mut self, id: u32, kind: TransactionKind, reason: Option<String>);
+    /// Stores a transaction which is already in `state`, like one from a snapshot, replacing any with the same id
+    fn restore(&mut self, t: DisputableTransaction, state: State);
+    /// Every transaction stored, in no particular order
+    fn entries(&self) -> Box<dyn Iterator<Item = (DisputableTransaction, State)> + '_>;
+    /// What the store holds, counted from `entries` unless the store knows better, and what it takes to hold it if it
+    /// knows
+    fn stats(&self) -> StoreStats {
+        StoreStats::of(self.entries())
+    }
 }
 
+/// Input transactions which would leave a store holding what `store` does, for moving it to another backend or
+/// re-deriving the clients' state from it: every deposit and withdrawal in transaction id order, with authorizations
+/// which haven't been captured and the voids of those which were voided, and then every dispute, resolve and
+/// chargeback in each transaction's history.
+///
+/// Stores don't keep the order in which different transactions were applied, so disputes all come after the
+/// transactions themselves, which also keeps a chargeback from locking a client before what was applied to it earlier.
+/// Refunded deposits are given as what's left of them, and captured authorizations as deposits.
+pub fn journal<C: Client + ?Sized>(store: &C) -> Vec<Transaction> {
+    let mut entries = store.entries().collect::<Vec<_>>();
+    entries.sort_by_key(|(t, _)| t.transaction_id);
+    let transaction = |t: &DisputableTransaction, type_| Transaction {
+        client_id: t.client_id,
+        transaction_id: t.transaction_id,
+        type_,
+        metadata: t.metadata.clone(),
+    };
+
+    let mut journal = Vec::new();
+    for (t, state) in &entries {
+        let (DisputableType::Deposit(amount) | DisputableType::Withdrawal(amount)) = &t.type_;
+        match state {
+            State::Authorized => journal.push(transaction(t, Type::Authorize(amount.clone()))),
+            State::Voided => {
+                journal.push(transaction(t, Type::Authorize(amount.clone())));
+                journal.push(transaction(t, Type::Void));
+            }
+            _ => journal.push(transaction(t, Type::Disputable(t.type_.clone()))),
+        }
+    }
+    for (t, _) in &entries {
+        for (kind, reason) in &t.history {
+            let type_ = match kind {
+                TransactionKind::Dispute => Type::Dispute(reason.clone()),
+                TransactionKind::Resolve => Type::Resolve(reason.clone()),
+                TransactionKind::Chargeback => Type::Chargeback(reason.clone()),
+                _ => continue,
+            };
+            journal.push(transaction(t, type_));
+        }
+    }
+    journal
+}
+
+/// Transactions are kept together in a `Vec` in the order they were stored, indexed by id, so the hash table only
+/// holds small fixed size entries and a million transactions don't mean a million scattered lookups
 #[derive(Default)]
-pub struct MemoryClient(HashMap<u32, (DisputableTransaction, State)>);
+pub struct MemoryClient {
+    index: HashMap<u32, usize>,
+    entries: Vec<(DisputableTransaction, State)>,
+}
+
+impl MemoryClient {
+    /// Takes every transaction `other` has, replacing any with the same id
+    pub(crate) fn absorb(&mut self, other: MemoryClient) {
+        for entry in other.entries {
+            self.insert(entry);
+        }
+    }
+
+    fn insert(&mut self, entry: (DisputableTransaction, State)) {
+        match self.index.get(&entry.0.transaction_id) {
+            Some(&i) => self.entries[i] = entry,
+            None => {
+                self.index
+                    .insert(entry.0.transaction_id, self.entries.len());
+                self.entries.push(entry);
+            }
+        }
+    }
+
+    fn get_mut(&mut self, id: u32) -> Option<&mut (DisputableTransaction, State)> {
+        let &i = self.index.get(&id)?;
+        Some(&mut self.entries[i])
+    }
+}
 
 impl Client for MemoryClient {
     fn store(&mut self, t: DisputableTransaction) {
-        self.0.insert(t.transaction_id, (t, State::Committed));
+        self.insert((t, State::Committed));
+    }
+    fn restore(&mut self, t: DisputableTransaction, state: State) {
+        self.insert((t, state));
+    }
+    fn entries(&self) -> Box<dyn Iterator<Item = (DisputableTransaction, State)> + '_> {
+        Box::new(self.entries.iter().cloned())
     }
     fn access(&mut self, id: u32) -> Option<(DisputableTransaction, State)> {
-        self.0.get_key_value(&id).map(|(_, (t, s))| (t.clone(), *s))
+        self.get_mut(id).map(|(t, s)| (t.clone(), *s))
     }
     fn update(&mut self, id: u32, state: State) -> Result<DisputableTransaction, UpdateFailure> {
-        use State::*;
-        match (self.0.get_mut(&id), state) {
-            (None, _) => Err(UpdateFailure::NotFound),
-            (Some(&mut (ref t, ref mut s @ Resolved)), Committed)
-            | (Some(&mut (ref t, ref mut s @ ChargedBack)), ChargedBackFinal) => {
-                *s = state;
-                Ok(t.clone())
+        self.transition(id, state).map(|t| t.transaction)
+    }
+    fn transition(&mut self, id: u32, state: State) -> Result<Transition, UpdateFailure> {
+        transition_entry(self.get_mut(id), state)
+    }
+    fn reassign(&mut self, from: u16, to: u16) {
+        for (t, _) in &mut self.entries {
+            if t.client_id == from {
+                t.client_id = to;
             }
+        }
+    }
+    fn refund(&mut self, id: u32, amount: Decimal) -> Result<DisputableTransaction, UpdateFailure> {
+        refund_entry(self.get_mut(id), amount)
+    }
+    fn record(&mut self, id: u32, kind: TransactionKind, reason: Option<String>) {
+        if let Some((t, _)) = self.get_mut(id) {
+            t.history.push((kind, reason));
+        }
+    }
+    fn stats(&self) -> StoreStats {
+        let mut stats = StoreStats::default();
+        for (t, state) in &self.entries {
+            stats.count(t, *state);
+            stats.memory_bytes += memory_size(t) + std::mem::size_of::<(u32, usize)>() as u64;
+        }
+        stats
+    }
+}
+
+/// `Client::transition` for stores which can get at an entry in place
+fn transition_entry(
+    entry: Option<&mut (DisputableTransaction, State)>,
+    state: State,
+) -> Result<Transition, UpdateFailure> {
+    match entry {
+        None => Err(UpdateFailure::NotFound),
+        Some((t, s)) if s.can_become(state) => {
+            let from = std::mem::replace(s, state);
+            Ok(Transition {
+                transaction: t.clone(),
+                from,
+                to: state,
+            })
+        }
+        Some((_, s)) => Err(UpdateFailure::WrongState(*s)),
+    }
+}
 
-            (Some(&mut (_, s @ ChargedBackFinal)), _)
-            | (Some(&mut (_, s)), ChargedBackFinal)
-            | (Some(&mut (_, s @ ChargedBack)), _)
-            | (Some(&mut (_, s @ Committed)), ChargedBack)
-            | (Some(&mut (_, s @ Committed)), Committed)
-            | (Some(&mut (_, s @ Resolved)), _) => Err(UpdateFailure::WrongState(s)),
-            (Some(&mut (ref t, ref mut s)), state) => {
-                *s = state;
+/// `Client::refund` for stores which can get at an entry in place
+fn refund_entry(
+    entry: Option<&mut (DisputableTransaction, State)>,
+    amount: Decimal,
+) -> Result<DisputableTransaction, UpdateFailure> {
+    match entry {
+        None => Err(UpdateFailure::NotFound),
+        Some((t, State::Committed)) => match &mut t.type_ {
+            DisputableType::Deposit(deposit) => {
+                *deposit = (deposit.clone() - amount).unwrap_or_default();
                 Ok(t.clone())
             }
+            DisputableType::Withdrawal(_) => Err(UpdateFailure::WrongState(State::Committed)),
+        },
+        Some((_, s)) => Err(UpdateFailure::WrongState(*s)),
+    }
+}
+
+/// Transactions kept in a file of records, with only where each one is in the file kept in memory, for more
+/// transactions than fit there.
+///
+/// Every change appends the transaction's new record rather than rewriting the old one, so the file grows with every
+/// update until `compact_to` copies only the latest of each. The file is the store, so failing to read or write it
+/// panics like running out of memory would for `MemoryClient`.
+pub struct FileClient {
+    file: File,
+    // Where each transaction's latest record starts
+    index: HashMap<u32, u64>,
+    compression: Compression,
+}
+
+impl FileClient {
+    /// Starts an empty store at `path`, replacing any file there
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        let file = record::Writer::new(file)?.into_inner();
+        Ok(FileClient {
+            file,
+            index: HashMap::new(),
+            compression: Compression::None,
+        })
+    }
+
+    /// Opens a store kept at `path`, or any other file of records, finding the latest record of each transaction in it
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        let mut index = HashMap::new();
+        let mut reader = record::Reader::new(BufReader::new(&file))?;
+        loop {
+            let offset = reader.position();
+            match reader.next().transpose()? {
+                Some((record::Kind::Transaction, bytes)) => {
+                    let (t, _) = record::decode_transaction(&bytes)
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                    index.insert(t.transaction_id, offset);
+                }
+                Some(_) => {}
+                None => break,
+            }
+        }
+        Ok(FileClient {
+            file,
+            index,
+            compression: Compression::None,
+        })
+    }
+
+    /// Compresses each record written from now on on its own, which pays off for transactions with metadata or a
+    /// history. Records already in the file are read either way.
+    pub fn compressing(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Copies the latest record of each transaction to a new store at `path`, compressed the same way, leaving this
+    /// one's file to be removed
+    pub fn compact_to<P: AsRef<Path>>(&self, path: P) -> io::Result<FileClient> {
+        let mut compacted = FileClient::create(path)?.compressing(self.compression);
+        for (t, s) in self.entries() {
+            compacted.write(&t, s);
+        }
+        Ok(compacted)
+    }
+
+    fn read(&self, offset: u64) -> (DisputableTransaction, State) {
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(offset))
+            .and_then(|_| record::read_record(BufReader::new(file)))
+            .and_then(|record| match record {
+                Some((record::Kind::Transaction, bytes)) => record::decode_transaction(&bytes)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+                _ => Err(io::ErrorKind::InvalidData.into()),
+            })
+            .unwrap_or_else(|e| panic!("reading transaction store failed: {}", e))
+    }
+
+    fn get(&self, id: u32) -> Option<(DisputableTransaction, State)> {
+        self.index.get(&id).map(|&offset| self.read(offset))
+    }
+
+    fn write(&mut self, t: &DisputableTransaction, state: State) {
+        let mut bytes = Vec::new();
+        record::write_compressed_record(
+            &mut bytes,
+            record::Kind::Transaction,
+            &record::encode_transaction(t, state),
+            self.compression,
+        )
+        .and_then(|()| {
+            let offset = self.file.seek(SeekFrom::End(0))?;
+            self.file.write_all(&bytes)?;
+            Ok(offset)
+        })
+        .map(|offset| self.index.insert(t.transaction_id, offset))
+        .unwrap_or_else(|e| panic!("writing transaction store failed: {}", e));
+    }
+
+    /// Changes a transaction with `change`, writing it back if it succeeds
+    fn change<T, F>(&mut self, id: u32, change: F) -> Result<T, UpdateFailure>
+    where
+        F: FnOnce(Option<&mut (DisputableTransaction, State)>) -> Result<T, UpdateFailure>,
+    {
+        let mut entry = self.get(id);
+        let result = change(entry.as_mut())?;
+        if let Some((t, s)) = entry {
+            self.write(&t, s);
         }
+        Ok(result)
     }
 }
 
-#[derive(Default)]
-pub struct CachedClient<Cl: Client, Ca: Cached<u32, (DisputableTransaction, State)>> {
+impl Client for FileClient {
+    fn store(&mut self, t: DisputableTransaction) {
+        self.write(&t, State::Committed);
+    }
+    fn restore(&mut self, t: DisputableTransaction, state: State) {
+        self.write(&t, state);
+    }
+    fn entries(&self) -> Box<dyn Iterator<Item = (DisputableTransaction, State)> + '_> {
+        Box::new(self.index.values().map(move |&offset| self.read(offset)))
+    }
+    fn access(&mut self, id: u32) -> Option<(DisputableTransaction, State)> {
+        self.get(id)
+    }
+    fn update(&mut self, id: u32, state: State) -> Result<DisputableTransaction, UpdateFailure> {
+        self.transition(id, state).map(|t| t.transaction)
+    }
+    fn transition(&mut self, id: u32, state: State) -> Result<Transition, UpdateFailure> {
+        self.change(id, |entry| transition_entry(entry, state))
+    }
+    fn reassign(&mut self, from: u16, to: u16) {
+        let moved = self
+            .entries()
+            .filter(|(t, _)| t.client_id == from)
+            .collect::<Vec<_>>();
+        for (mut t, s) in moved {
+            t.client_id = to;
+            self.write(&t, s);
+        }
+    }
+    fn refund(&mut self, id: u32, amount: Decimal) -> Result<DisputableTransaction, UpdateFailure> {
+        self.change(id, |entry| refund_entry(entry, amount))
+    }
+    fn record(&mut self, id: u32, kind: TransactionKind, reason: Option<String>) {
+        let _ = self.change(id, |entry| match entry {
+            Some((t, _)) => {
+                t.history.push((kind, reason));
+                Ok(())
+            }
+            None => Err(UpdateFailure::NotFound),
+        });
+    }
+    fn stats(&self) -> StoreStats {
+        let file = self
+            .file
+            .metadata()
+            .unwrap_or_else(|e| panic!("reading transaction store failed: {}", e));
+        StoreStats {
+            memory_bytes: (self.index.len() * std::mem::size_of::<(u32, u64)>()) as u64,
+            disk_bytes: file.len(),
+            ..StoreStats::of(self.entries())
+        }
+    }
+}
+
+/// Keeps recently used and unsettled transactions in memory in front of a slower store, like a `FileClient`, demoting
+/// the least recently used settled ones to it once there are more than `capacity`, and promoting them back when
+/// they're used. Disputed, charged back and authorized transactions are never demoted, so they don't count towards
+/// memory staying bounded, but old transactions can still be disputed.
+pub struct TieredClient<C: Client> {
+    cold: C,
+    capacity: usize,
+    // Each transaction in memory along with when it was last used
+    hot: HashMap<u32, ((DisputableTransaction, State), u64)>,
+    // Ids by when they were last used
+    recency: BTreeMap<u64, u32>,
+    clock: u64,
+}
+
+impl<C: Client> TieredClient<C> {
+    pub fn new(cold: C, capacity: usize) -> Self {
+        TieredClient {
+            cold,
+            capacity,
+            hot: HashMap::new(),
+            recency: BTreeMap::new(),
+            clock: 0,
+        }
+    }
+
+    /// How many transactions are in memory
+    pub fn hot_len(&self) -> usize {
+        self.hot.len()
+    }
+
+    /// Puts a transaction in memory as just used, demoting others to make room
+    fn insert(&mut self, entry: (DisputableTransaction, State)) {
+        let id = entry.0.transaction_id;
+        self.clock += 1;
+        if let Some((_, used)) = self.hot.insert(id, (entry, self.clock)) {
+            self.recency.remove(&used);
+        }
+        self.recency.insert(self.clock, id);
+        self.demote(id);
+    }
+
+    /// Demotes the least recently used settled transactions, other than `keep`, until at most `capacity` are in memory
+    fn demote(&mut self, keep: u32) {
+        while self.hot.len() > self.capacity {
+            let settled = self.recency.values().copied().find(|&id| {
+                let (_, state) = self.hot[&id].0;
+                id != keep
+                    && !matches!(
+                        state,
+                        State::Disputed | State::ChargedBack | State::Authorized
+                    )
+            });
+            let Some(id) = settled else { break };
+            let ((t, s), used) = self.hot.remove(&id).expect("found above");
+            self.recency.remove(&used);
+            self.cold.restore(t, s);
+        }
+    }
+
+    /// A transaction in memory, promoting it if it was demoted, marked as just used
+    fn promote(&mut self, id: u32) -> Option<&mut (DisputableTransaction, State)> {
+        if !self.hot.contains_key(&id) {
+            let entry = self.cold.access(id)?;
+            self.insert(entry);
+        }
+        let (entry, used) = self.hot.get_mut(&id)?;
+        self.recency.remove(used);
+        self.clock += 1;
+        *used = self.clock;
+        self.recency.insert(self.clock, id);
+        Some(entry)
+    }
+}
+
+impl<C: Client> Client for TieredClient<C> {
+    fn store(&mut self, t: DisputableTransaction) {
+        self.insert((t, State::Committed));
+    }
+    fn restore(&mut self, t: DisputableTransaction, state: State) {
+        self.insert((t, state));
+    }
+    // A promoted transaction's copy in the cold store is stale, so only the one in memory is listed
+    fn entries(&self) -> Box<dyn Iterator<Item = (DisputableTransaction, State)> + '_> {
+        let hot = self.hot.values().map(|(entry, _)| entry.clone());
+        let cold = self
+            .cold
+            .entries()
+            .filter(move |(t, _)| !self.hot.contains_key(&t.transaction_id));
+        Box::new(hot.chain(cold))
+    }
+    fn access(&mut self, id: u32) -> Option<(DisputableTransaction, State)> {
+        self.promote(id).map(|entry| entry.clone())
+    }
+    fn update(&mut self, id: u32, state: State) -> Result<DisputableTransaction, UpdateFailure> {
+        self.transition(id, state).map(|t| t.transaction)
+    }
+    fn transition(&mut self, id: u32, state: State) -> Result<Transition, UpdateFailure> {
+        transition_entry(self.promote(id), state)
+    }
+    fn reassign(&mut self, from: u16, to: u16) {
+        for ((t, _), _) in self.hot.values_mut() {
+            if t.client_id == from {
+                t.client_id = to;
+            }
+        }
+        self.cold.reassign(from, to);
+    }
+    fn refund(&mut self, id: u32, amount: Decimal) -> Result<DisputableTransaction, UpdateFailure> {
+        refund_entry(self.promote(id), amount)
+    }
+    fn record(&mut self, id: u32, kind: TransactionKind, reason: Option<String>) {
+        if let Some((t, _)) = self.promote(id) {
+            t.history.push((kind, reason));
+        }
+    }
+    // Counted like `entries`, since a promoted transaction's stale copy is still in the cold store
+    fn stats(&self) -> StoreStats {
+        let cold = self.cold.stats();
+        let mut stats = StoreStats::default();
+        for ((t, state), _) in self.hot.values() {
+            stats.count(t, *state);
+            stats.memory_bytes += memory_size(t) + std::mem::size_of::<(u64, u32)>() as u64;
+        }
+        for (t, state) in self.cold.entries() {
+            if !self.hot.contains_key(&t.transaction_id) {
+                stats.count(&t, state);
+            }
+        }
+        stats.memory_bytes += cold.memory_bytes;
+        stats.disk_bytes = cold.disk_bytes;
+        stats
+    }
+}
+
+/// The part of a cache `CachedClient` needs, keyed by transaction id
+pub trait TxCache {
+    fn get(&mut self, id: u32) -> Option<&(DisputableTransaction, State)>;
+    fn get_mut(&mut self, id: u32) -> Option<&mut (DisputableTransaction, State)>;
+    fn insert(&mut self, id: u32, entry: (DisputableTransaction, State));
+    fn clear(&mut self);
+}
+
+/// Any cache from the `cached` crate
+#[cfg(feature = "cached")]
+impl<C: Cached<u32, (DisputableTransaction, State)>> TxCache for C {
+    fn get(&mut self, id: u32) -> Option<&(DisputableTransaction, State)> {
+        self.cache_get(&id)
+    }
+    fn get_mut(&mut self, id: u32) -> Option<&mut (DisputableTransaction, State)> {
+        self.cache_get_mut(&id)
+    }
+    fn insert(&mut self, id: u32, entry: (DisputableTransaction, State)) {
+        self.cache_set(id, entry);
+    }
+    fn clear(&mut self) {
+        self.cache_clear();
+    }
+}
+
+/// Keeps the most recently used entries, up to a fixed capacity
+pub struct LruCache {
+    capacity: usize,
+    // Each entry along with when it was last used
+    entries: HashMap<u32, ((DisputableTransaction, State), u64)>,
+    // Ids by when they were last used
+    recency: BTreeMap<u64, u32>,
+    clock: u64,
+}
+
+impl LruCache {
+    pub fn with_capacity(capacity: usize) -> Self {
+        LruCache {
+            capacity,
+            entries: HashMap::with_capacity(capacity),
+            recency: BTreeMap::new(),
+            clock: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Marks an entry as just used, returning it if present
+    fn touch(&mut self, id: u32) -> Option<&mut (DisputableTransaction, State)> {
+        let (entry, used) = self.entries.get_mut(&id)?;
+        self.recency.remove(used);
+        self.clock += 1;
+        *used = self.clock;
+        self.recency.insert(self.clock, id);
+        Some(entry)
+    }
+}
+
+impl TxCache for LruCache {
+    fn get(&mut self, id: u32) -> Option<&(DisputableTransaction, State)> {
+        self.touch(id).map(|entry| &*entry)
+    }
+    fn get_mut(&mut self, id: u32) -> Option<&mut (DisputableTransaction, State)> {
+        self.touch(id)
+    }
+    fn insert(&mut self, id: u32, entry: (DisputableTransaction, State)) {
+        if self.capacity == 0 {
+            return;
+        }
+        if let Some(cached) = self.touch(id) {
+            *cached = entry;
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            if let Some((_, evicted)) = self.recency.pop_first() {
+                self.entries.remove(&evicted);
+            }
+        }
+        self.clock += 1;
+        self.entries.insert(id, (entry, self.clock));
+        self.recency.insert(self.clock, id);
+    }
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.recency.clear();
+    }
+}
+
+/// Keeps entries for a fixed time after they're inserted, however many that is, since disputes mostly come soon after
+/// the transaction they're for
+pub struct TtlCache {
+    ttl: u64,
+    clock: Box<dyn Fn() -> u64>,
+    // Each entry along with when it was inserted
+    entries: HashMap<u32, ((DisputableTransaction, State), u64)>,
+    // Ids by when they were inserted, oldest first, including those inserted again since
+    inserted: VecDeque<(u64, u32)>,
+}
+
+impl TtlCache {
+    /// Keeps entries for `ttl` seconds
+    pub fn new(ttl: u64) -> Self {
+        TtlCache {
+            ttl,
+            clock: Box::new(now),
+            entries: HashMap::new(),
+            inserted: VecDeque::new(),
+        }
+    }
+
+    /// Times entries with `clock`, in unix seconds, instead of the system clock
+    pub fn with_clock<C: Fn() -> u64 + 'static>(mut self, clock: C) -> Self {
+        self.clock = Box::new(clock);
+        self
+    }
+
+    /// How many entries there are, including any which expired since they were last looked for
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Drops the entries which have been there `ttl` or longer
+    fn expire(&mut self) {
+        let now = (self.clock)();
+        while let Some(&(at, id)) = self.inserted.front() {
+            if now.saturating_sub(at) < self.ttl {
+                break;
+            }
+            self.inserted.pop_front();
+            // Unless it was inserted again since
+            if self
+                .entries
+                .get(&id)
+                .is_some_and(|&(_, inserted)| inserted == at)
+            {
+                self.entries.remove(&id);
+            }
+        }
+    }
+}
+
+impl TxCache for TtlCache {
+    fn get(&mut self, id: u32) -> Option<&(DisputableTransaction, State)> {
+        self.expire();
+        self.entries.get(&id).map(|(entry, _)| entry)
+    }
+    fn get_mut(&mut self, id: u32) -> Option<&mut (DisputableTransaction, State)> {
+        self.expire();
+        self.entries.get_mut(&id).map(|(entry, _)| entry)
+    }
+    fn insert(&mut self, id: u32, entry: (DisputableTransaction, State)) {
+        self.expire();
+        let at = (self.clock)();
+        self.entries.insert(id, (entry, at));
+        self.inserted.push_back((at, id));
+    }
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.inserted.clear();
+    }
+}
+
+/// Serves reads from a cache in front of another client, filling it on a miss
+pub struct CachedClient<Cl: Client, Ca: TxCache> {
     client: Cl,
     cache: Ca,
 }
 
-impl<Cl: Client, Ca: Cached<u32, (DisputableTransaction, State)>> CachedClient<Cl, Ca> {
+impl<Cl: Client, Ca: TxCache> CachedClient<Cl, Ca> {
     pub fn new(client: Cl, cache: Ca) -> Self {
         CachedClient { client, cache }
     }
+
+    /// Fills the cache with up to `count` of the client's transactions, so a run over a store which already has some
+    /// doesn't start with every access missing. Disputed ones come first, as they're the likeliest to be resolved or
+    /// charged back soon, then the most recently stored, taking higher ids as later. Returns how many were loaded.
+    ///
+    /// Every transaction is read once to pick them, but only the ids of those picked are kept meanwhile.
+    pub fn warm(&mut self, count: usize) -> usize {
+        // The least likely to be used of those picked so far on top
+        let mut picked = BinaryHeap::with_capacity(count + 1);
+        for (t, state) in self.client.entries() {
+            picked.push(Reverse((state == State::Disputed, t.transaction_id)));
+            if picked.len() > count {
+                picked.pop();
+            }
+        }
+        // Loaded least likely first, so the likeliest are the most recently used
+        let mut loaded = 0;
+        while let Some(Reverse((_, id))) = picked.pop() {
+            if let Some(entry) = self.client.access(id) {
+                self.cache.insert(id, entry);
+                loaded += 1;
+            }
+        }
+        loaded
+    }
 }
 
-impl<Cl: Client, Ca: Cached<u32, (DisputableTransaction, State)>> Client for CachedClient<Cl, Ca> {
+impl<Cl: Client, Ca: TxCache> Client for CachedClient<Cl, Ca> {
     fn store(&mut self, t: DisputableTransaction) {
+        let id = t.transaction_id;
         self.client.store(t);
+        if let Some(cached) = self.cache.get_mut(id) {
+            // Stored again, so whatever was cached is stale
+            if let Some(entry) = self.client.access(id) {
+                *cached = entry;
+            }
+        }
     }
 
     fn access(&mut self, id: u32) -> Option<(DisputableTransaction, State)> {
-        self.cache
-            .cache_get(&id)
-            .map(|(t, s)| (t.clone(), *s))
-            .or_else(|| self.client.access(id))
+        if let Some((t, s)) = self.cache.get(id) {
+            return Some((t.clone(), *s));
+        }
+        let entry = self.client.access(id)?;
+        self.cache.insert(id, entry.clone());
+        Some(entry)
     }
 
     fn update(&mut self, id: u32, state: State) -> Result<DisputableTransaction, UpdateFailure> {
-        let transaction = self.client.update(id, state)?;
-        if let Some(cached) = self.cache.cache_get_mut(&id) {
-            cached.1 = state;
-            // TODO check that cache matches
+        self.transition(id, state).map(|t| t.transaction)
+    }
+
+    fn transition(&mut self, id: u32, state: State) -> Result<Transition, UpdateFailure> {
+        let transition = self.client.transition(id, state)?;
+        if let Some(cached) = self.cache.get_mut(id) {
+            // Whatever was cached, the client has the transaction as it now is
+            *cached = (transition.transaction.clone(), state);
+        }
+        Ok(transition)
+    }
+
+    fn refund(&mut self, id: u32, amount: Decimal) -> Result<DisputableTransaction, UpdateFailure> {
+        let transaction = self.client.refund(id, amount)?;
+        if let Some(cached) = self.cache.get_mut(id) {
+            cached.0 = transaction.clone();
         }
         Ok(transaction)
     }
+
+    fn reassign(&mut self, from: u16, to: u16) {
+        self.client.reassign(from, to);
+        // Rare enough that it's not worth finding the affected entries
+        self.cache.clear();
+    }
+
+    fn record(&mut self, id: u32, kind: TransactionKind, reason: Option<String>) {
+        if let Some((cached, _)) = self.cache.get_mut(id) {
+            cached.history.push((kind, reason.clone()));
+        }
+        self.client.record(id, kind, reason);
+    }
+
+    fn restore(&mut self, t: DisputableTransaction, state: State) {
+        if let Some(cached) = self.cache.get_mut(t.transaction_id) {
+            *cached = (t.clone(), state);
+        }
+        self.client.restore(t, state);
+    }
+
+    // Every write goes through to the client, so it has everything the cache does
+    fn entries(&self) -> Box<dyn Iterator<Item = (DisputableTransaction, State)> + '_> {
+        self.client.entries()
+    }
+
+    fn stats(&self) -> StoreStats {
+        self.client.stats()
+    }
+}
+
+/// Any store behind one lock, so threads can share it. Clones share the same store, and each call holds the lock for
+/// its duration, so calls from different threads take turns. `ShardedClient` lets them overlap.
+pub struct Locked<C> {
+    client: Arc<Mutex<C>>,
+}
+
+impl<C> Locked<C> {
+    pub fn new(client: C) -> Self {
+        Locked {
+            client: Arc::new(Mutex::new(client)),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, C> {
+        self.client.lock().expect("a store call panicked")
+    }
+}
+
+impl<C> Clone for Locked<C> {
+    fn clone(&self) -> Self {
+        Locked {
+            client: self.client.clone(),
+        }
+    }
+}
+
+impl<C: Client> Client for Locked<C> {
+    fn store(&mut self, t: DisputableTransaction) {
+        self.lock().store(t);
+    }
+    fn access(&mut self, id: u32) -> Option<(DisputableTransaction, State)> {
+        self.lock().access(id)
+    }
+    fn update(&mut self, id: u32, state: State) -> Result<DisputableTransaction, UpdateFailure> {
+        self.lock().update(id, state)
+    }
+    fn transition(&mut self, id: u32, state: State) -> Result<Transition, UpdateFailure> {
+        self.lock().transition(id, state)
+    }
+    fn reassign(&mut self, from: u16, to: u16) {
+        self.lock().reassign(from, to);
+    }
+    fn refund(&mut self, id: u32, amount: Decimal) -> Result<DisputableTransaction, UpdateFailure> {
+        self.lock().refund(id, amount)
+    }
+    fn record(&mut self, id: u32, kind: TransactionKind, reason: Option<String>) {
+        self.lock().record(id, kind, reason);
+    }
+    fn restore(&mut self, t: DisputableTransaction, state: State) {
+        self.lock().restore(t, state);
+    }
+    // Collected, as the lock can't be held by the iterator
+    fn entries(&self) -> Box<dyn Iterator<Item = (DisputableTransaction, State)> + '_> {
+        Box::new(self.lock().entries().collect::<Vec<_>>().into_iter())
+    }
+    fn stats(&self) -> StoreStats {
+        self.lock().stats()
+    }
+}
+
+/// Transactions in memory split by id across shards, each behind its own lock, so threads can share them and only
+/// take turns when they're after transactions in the same shard. Clones share the same transactions.
+#[derive(Clone)]
+pub struct ShardedClient {
+    shards: Arc<[Mutex<MemoryClient>]>,
+}
+
+impl ShardedClient {
+    /// # Panics
+    /// If `shards` is zero.
+    pub fn new(shards: usize) -> Self {
+        assert!(shards > 0, "can't store transactions in zero shards");
+        ShardedClient {
+            shards: (0..shards).map(|_| Mutex::default()).collect(),
+        }
+    }
+
+    fn shard(&self, id: u32) -> MutexGuard<'_, MemoryClient> {
+        self.shards[id as usize % self.shards.len()]
+            .lock()
+            .expect("a store call panicked")
+    }
+
+    fn shards(&self) -> impl Iterator<Item = MutexGuard<'_, MemoryClient>> {
+        self.shards
+            .iter()
+            .map(|shard| shard.lock().expect("a store call panicked"))
+    }
+}
+
+impl Client for ShardedClient {
+    fn store(&mut self, t: DisputableTransaction) {
+        self.shard(t.transaction_id).store(t);
+    }
+    fn access(&mut self, id: u32) -> Option<(DisputableTransaction, State)> {
+        self.shard(id).access(id)
+    }
+    fn update(&mut self, id: u32, state: State) -> Result<DisputableTransaction, UpdateFailure> {
+        self.shard(id).update(id, state)
+    }
+    fn transition(&mut self, id: u32, state: State) -> Result<Transition, UpdateFailure> {
+        self.shard(id).transition(id, state)
+    }
+    // One shard at a time, so other threads may see some shards reassigned and others not yet
+    fn reassign(&mut self, from: u16, to: u16) {
+        for mut shard in self.shards() {
+            shard.reassign(from, to);
+        }
+    }
+    fn refund(&mut self, id: u32, amount: Decimal) -> Result<DisputableTransaction, UpdateFailure> {
+        self.shard(id).refund(id, amount)
+    }
+    fn record(&mut self, id: u32, kind: TransactionKind, reason: Option<String>) {
+        self.shard(id).record(id, kind, reason);
+    }
+    fn restore(&mut self, t: DisputableTransaction, state: State) {
+        self.shard(t.transaction_id).restore(t, state);
+    }
+    // Each shard's collected in turn, as its lock can't be held by the iterator
+    fn entries(&self) -> Box<dyn Iterator<Item = (DisputableTransaction, State)> + '_> {
+        Box::new(
+            self.shards()
+                .flat_map(|shard| shard.entries().collect::<Vec<_>>()),
+        )
+    }
+    fn stats(&self) -> StoreStats {
+        let mut stats = StoreStats::default();
+        for shard in self.shards() {
+            stats.merge(shard.stats());
+        }
+        stats
+    }
+}
+
+/// A set of transaction ids which can give false positives, but never false negatives
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    hashes: u32,
+}
+
+impl BloomFilter {
+    /// Sized for `capacity` ids at about a 1% false positive rate, which grows as more are inserted
+    pub fn with_capacity(capacity: usize) -> Self {
+        // 9.6 bits and 7 hashes per id is optimal for 1%
+        let words = (capacity.max(1) * 96 / 10).div_ceil(64);
+        BloomFilter {
+            bits: vec![0; words],
+            hashes: 7,
+        }
+    }
+
+    pub fn insert(&mut self, id: u32) {
+        for i in bloom_indices(self.bits.len(), self.hashes, id) {
+            self.bits[i / 64] |= 1 << (i % 64);
+        }
+    }
+
+    pub fn may_contain(&self, id: u32) -> bool {
+        bloom_indices(self.bits.len(), self.hashes, id)
+            .all(|i| self.bits[i / 64] & (1 << (i % 64)) != 0)
+    }
+}
+
+/// The bits of a filter `words` long which `id` sets, by double hashing
+fn bloom_indices(words: usize, hashes: u32, id: u32) -> impl Iterator<Item = usize> {
+    // splitmix64's finalizer, split into two hashes
+    let mut h = u64::from(id).wrapping_add(0x9e37_79b9_7f4a_7c15);
+    h = (h ^ (h >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    h = (h ^ (h >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    h ^= h >> 31;
+    let (h1, h2) = (h & 0xffff_ffff, (h >> 32) | 1);
+    let len = words as u64 * 64;
+    (0..u64::from(hashes)).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % len) as usize)
+}
+
+/// Rejects ids which were never stored without asking the client behind it, for stores where a miss is slow.
+///
+/// The filter only knows about transactions stored through it, so it should start in front of an empty store.
+pub struct BloomClient<Cl: Client> {
+    client: Cl,
+    filter: BloomFilter,
+}
+
+impl<Cl: Client> BloomClient<Cl> {
+    /// Sized for about `capacity` transactions
+    pub fn new(client: Cl, capacity: usize) -> Self {
+        BloomClient {
+            client,
+            filter: BloomFilter::with_capacity(capacity),
+        }
+    }
+}
+
+impl<Cl: Client> Client for BloomClient<Cl> {
+    fn store(&mut self, t: DisputableTransaction) {
+        self.filter.insert(t.transaction_id);
+        self.client.store(t);
+    }
+
+    fn access(&mut self, id: u32) -> Option<(DisputableTransaction, State)> {
+        if !self.filter.may_contain(id) {
+            return None;
+        }
+        self.client.access(id)
+    }
+
+    fn update(&mut self, id: u32, state: State) -> Result<DisputableTransaction, UpdateFailure> {
+        if !self.filter.may_contain(id) {
+            return Err(UpdateFailure::NotFound);
+        }
+        self.client.update(id, state)
+    }
+
+    fn transition(&mut self, id: u32, state: State) -> Result<Transition, UpdateFailure> {
+        if !self.filter.may_contain(id) {
+            return Err(UpdateFailure::NotFound);
+        }
+        self.client.transition(id, state)
+    }
+
+    fn refund(&mut self, id: u32, amount: Decimal) -> Result<DisputableTransaction, UpdateFailure> {
+        if !self.filter.may_contain(id) {
+            return Err(UpdateFailure::NotFound);
+        }
+        self.client.refund(id, amount)
+    }
+
+    fn reassign(&mut self, from: u16, to: u16) {
+        self.client.reassign(from, to);
+    }
+
+    fn restore(&mut self, t: DisputableTransaction, state: State) {
+        self.filter.insert(t.transaction_id);
+        self.client.restore(t, state);
+    }
+
+    fn entries(&self) -> Box<dyn Iterator<Item = (DisputableTransaction, State)> + '_> {
+        self.client.entries()
+    }
+
+    fn stats(&self) -> StoreStats {
+        let mut stats = self.client.stats();
+        stats.memory_bytes += (self.filter.bits.len() * std::mem::size_of::<u64>()) as u64;
+        stats
+    }
+
+    fn record(&mut self, id: u32, kind: TransactionKind, reason: Option<String>) {
+        if self.filter.may_contain(id) {
+            self.client.record(id, kind, reason);
+        }
+    }
 }
 
 #[cfg(test)]
@@ -108,8 +1168,422 @@ mod test {
             client_id: 501,
             transaction_id: 16,
             type_: DisputableType::Withdrawal(Decimal::zero()),
+            metadata: HashMap::new(),
+            history: Vec::new(),
         });
         assert_eq!(client.access(0), None);
-        assert_eq!(client.access(16).is_some(), true);
+        assert!(client.access(16).is_some());
+    }
+
+    #[test]
+    fn memory_replaces_stored_ids() {
+        let transaction = |transaction_id, amount| DisputableTransaction {
+            client_id: 1,
+            transaction_id,
+            type_: DisputableType::Deposit(Decimal::new(amount, 0)),
+            metadata: HashMap::new(),
+            history: Vec::new(),
+        };
+        let mut client = MemoryClient::default();
+        client.store(transaction(1, 1));
+        client.store(transaction(2, 2));
+        assert!(client.update(1, State::Disputed).is_ok());
+        client.store(transaction(1, 3));
+
+        assert_eq!(client.entries.len(), 2);
+        assert_eq!(
+            client.access(1),
+            Some((transaction(1, 3), State::Committed))
+        );
+        assert_eq!(
+            client.access(2),
+            Some((transaction(2, 2), State::Committed))
+        );
+    }
+
+    #[test]
+    fn memory_transitions() {
+        let mut client = MemoryClient::default();
+        client.store(DisputableTransaction {
+            client_id: 1,
+            transaction_id: 1,
+            type_: DisputableType::Deposit(Decimal::new(5, 0)),
+            metadata: HashMap::new(),
+            history: Vec::new(),
+        });
+        let mut update = |state| client.update(1, state).map(drop);
+        // Only a disputed transaction can be resolved or charged back, and only once
+        assert_eq!(
+            update(State::Resolved),
+            Err(UpdateFailure::WrongState(State::Committed))
+        );
+        assert_eq!(update(State::Disputed), Ok(()));
+        assert_eq!(
+            update(State::Disputed),
+            Err(UpdateFailure::WrongState(State::Disputed))
+        );
+        assert_eq!(update(State::Resolved), Ok(()));
+        assert_eq!(
+            update(State::Disputed),
+            Err(UpdateFailure::WrongState(State::Resolved))
+        );
+        assert_eq!(update(State::Committed), Ok(()));
+        assert_eq!(update(State::Disputed), Ok(()));
+        assert_eq!(update(State::ChargedBack), Ok(()));
+        assert_eq!(
+            update(State::Disputed),
+            Err(UpdateFailure::WrongState(State::ChargedBack))
+        );
+        assert_eq!(update(State::ChargedBackFinal), Ok(()));
+        assert_eq!(
+            update(State::Disputed),
+            Err(UpdateFailure::WrongState(State::ChargedBackFinal))
+        );
+
+        // Committed from either a resolved deposit or an authorization
+        let deposit = client.access(1).unwrap().0;
+        for from in [State::Resolved, State::Authorized] {
+            client.restore(deposit.clone(), from);
+            assert_eq!(
+                client.transition(1, State::Committed),
+                Ok(Transition {
+                    transaction: deposit.clone(),
+                    from,
+                    to: State::Committed,
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn lru_eviction() {
+        let entry = |transaction_id| {
+            (
+                DisputableTransaction {
+                    client_id: 1,
+                    transaction_id,
+                    type_: DisputableType::Deposit(Decimal::zero()),
+                    metadata: HashMap::new(),
+                    history: Vec::new(),
+                },
+                State::Committed,
+            )
+        };
+        let mut cache = LruCache::with_capacity(2);
+        cache.insert(1, entry(1));
+        cache.insert(2, entry(2));
+        assert!(cache.get(1).is_some());
+        cache.insert(3, entry(3));
+
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get(2).is_none());
+        assert!(cache.get(1).is_some());
+        assert!(cache.get(3).is_some());
+    }
+
+    #[test]
+    fn ttl_expiry() {
+        use std::sync::atomic::{AtomicU64, Ordering};
+        use std::sync::Arc;
+
+        let entry = |transaction_id| {
+            (
+                DisputableTransaction {
+                    client_id: 1,
+                    transaction_id,
+                    type_: DisputableType::Deposit(Decimal::zero()),
+                    metadata: HashMap::new(),
+                    history: Vec::new(),
+                },
+                State::Committed,
+            )
+        };
+        let time = Arc::new(AtomicU64::new(1000));
+        let clock = time.clone();
+        let mut cache = TtlCache::new(60).with_clock(move || clock.load(Ordering::Relaxed));
+        cache.insert(1, entry(1));
+        time.store(1030, Ordering::Relaxed);
+        cache.insert(2, entry(2));
+        cache.insert(3, entry(3));
+        // Inserted again, so it lasts from now
+        cache.insert(1, entry(1));
+        assert!(cache.get(2).is_some());
+
+        time.store(1089, Ordering::Relaxed);
+        assert!(cache.get(1).is_some());
+        assert!(cache.get(2).is_some());
+        time.store(1090, Ordering::Relaxed);
+        assert!(cache.get(2).is_none());
+        assert_eq!(cache.len(), 0);
+    }
+
+    #[test]
+    fn cache_warming() {
+        let mut store = MemoryClient::default();
+        for transaction_id in 1..=5 {
+            store.store(DisputableTransaction {
+                client_id: 1,
+                transaction_id,
+                type_: DisputableType::Deposit(Decimal::zero()),
+                metadata: HashMap::new(),
+                history: Vec::new(),
+            });
+        }
+        assert!(store.update(2, State::Disputed).is_ok());
+        let mut client = CachedClient::new(store, LruCache::with_capacity(2));
+        assert_eq!(client.warm(2), 2);
+        assert_eq!(client.cache.len(), 2);
+        assert!(client.cache.get(2).is_some());
+        assert!(client.cache.get(5).is_some());
+        assert_eq!(client.warm(10), 5);
+    }
+
+    #[test]
+    fn cache_refreshed_on_store() {
+        let deposit = |amount| DisputableTransaction {
+            client_id: 1,
+            transaction_id: 1,
+            type_: DisputableType::Deposit(Decimal::new(amount, 0)),
+            metadata: HashMap::new(),
+            history: Vec::new(),
+        };
+        let mut client = CachedClient::new(MemoryClient::default(), LruCache::with_capacity(2));
+        client.store(deposit(5));
+        assert_eq!(client.access(1), Some((deposit(5), State::Committed)));
+        client.store(deposit(7));
+        assert_eq!(client.access(1), Some((deposit(7), State::Committed)));
+    }
+
+    #[test]
+    fn journal_rederives_store() {
+        use crate::engine::Engine;
+        use crate::report;
+        use crate::transaction::read_from_csv_reader;
+
+        let (original, rederived) = (
+            Locked::new(MemoryClient::default()),
+            Locked::new(MemoryClient::default()),
+        );
+        let mut engine = Engine::new(original.clone());
+        for transaction in read_from_csv_reader(
+            "\
+type,       client,  tx, amount, reason
+deposit,         1,   1,    5.0,
+deposit,         1,   2,    3.0,
+withdrawal,      1,   3,    1.0,
+dispute,         1,   2,       , fraud
+resolve,         1,   2,       ,
+dispute,         1,   2,       ,
+deposit,         2,   4,    2.0,
+authorize,       2,   5,    1.0,
+authorize,       2,   6,    4.0,
+void,            2,   6,       ,
+refund,          2,   4,    0.5,
+dispute,         2,   4,       ,
+chargeback,      2,   4,       , 10.4
+"
+            .as_bytes(),
+        ) {
+            engine.process(transaction.unwrap()).unwrap();
+        }
+        let exported = journal(&original);
+        assert_eq!(exported.len(), 12);
+
+        let mut replayed = Engine::new(rederived.clone());
+        for transaction in exported.clone() {
+            replayed.process(transaction).unwrap();
+        }
+        assert_eq!(report::diff(engine.report(), replayed.report()), []);
+        assert_eq!(journal(&rederived), exported);
+        assert_eq!(rederived.stats(), original.stats());
+    }
+
+    #[test]
+    fn shared_across_threads() {
+        fn share<C: Client + Clone + Send + Sync + 'static>(client: C) -> StoreStats {
+            let threads = (0..4u32)
+                .map(|thread| {
+                    let mut client = client.clone();
+                    std::thread::spawn(move || {
+                        for transaction_id in (0..100).map(|i| i * 4 + thread) {
+                            client.store(DisputableTransaction {
+                                client_id: 1,
+                                transaction_id,
+                                type_: DisputableType::Deposit(Decimal::new(1, 0)),
+                                metadata: HashMap::new(),
+                                history: Vec::new(),
+                            });
+                            if transaction_id % 10 == 3 {
+                                client.update(transaction_id, State::Disputed).unwrap();
+                            }
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+            for thread in threads {
+                thread.join().unwrap();
+            }
+            client.stats()
+        }
+
+        let expected = BTreeMap::from([(State::Committed, 360), (State::Disputed, 40)]);
+        let sharded = share(ShardedClient::new(8));
+        assert_eq!(sharded.states, expected);
+        assert_eq!(sharded.oldest_disputed, Some(3));
+        let locked = share(Locked::new(MemoryClient::default()));
+        assert_eq!(locked, sharded);
+    }
+
+    #[test]
+    fn bloom_rejects_unknown_ids() {
+        let mut client = BloomClient::new(MemoryClient::default(), 1000);
+        for transaction_id in (0..2000).step_by(2) {
+            client.store(DisputableTransaction {
+                client_id: 1,
+                transaction_id,
+                type_: DisputableType::Deposit(Decimal::zero()),
+                metadata: HashMap::new(),
+                history: Vec::new(),
+            });
+        }
+        assert!((0..2000).step_by(2).all(|id| client.filter.may_contain(id)));
+        let false_positives = (1..2000)
+            .step_by(2)
+            .filter(|&id| client.filter.may_contain(id))
+            .count();
+        assert!(false_positives < 50, "{} false positives", false_positives);
+
+        assert!(client.access(4).is_some());
+        assert_eq!(client.access(5), None);
+        assert_eq!(
+            client.update(5, State::Disputed),
+            Err(UpdateFailure::NotFound)
+        );
+    }
+
+    #[test]
+    fn file_reopens() {
+        let path = std::env::temp_dir().join(format!("stm-store-{}.stmr", std::process::id()));
+        let transaction = |transaction_id| DisputableTransaction {
+            client_id: 1,
+            transaction_id,
+            type_: DisputableType::Deposit(Decimal::new(10, 0)),
+            metadata: HashMap::new(),
+            history: Vec::new(),
+        };
+        let mut client = FileClient::create(&path).unwrap();
+        client.store(transaction(1));
+        client.store(transaction(2));
+        assert!(client.update(1, State::Disputed).is_ok());
+        client.record(1, TransactionKind::Dispute, Some("fraud".to_owned()));
+        assert_eq!(
+            client.refund(1, Decimal::new(4, 0)),
+            Err(UpdateFailure::WrongState(State::Disputed))
+        );
+        assert!(client.refund(2, Decimal::new(4, 0)).is_ok());
+        client.reassign(1, 3);
+        drop(client);
+
+        let mut compacted = path.clone();
+        compacted.set_extension("compacted");
+        let reopened = FileClient::open(&path).unwrap();
+        let mut client = reopened.compact_to(&compacted).unwrap();
+        let lengths = [&path, &compacted].map(|p| std::fs::metadata(p).unwrap().len());
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&compacted).unwrap();
+        assert!(lengths[1] < lengths[0]);
+        let mut disputed = transaction(1);
+        disputed.client_id = 3;
+        disputed.history = vec![(TransactionKind::Dispute, Some("fraud".to_owned()))];
+        assert_eq!(client.access(1), Some((disputed, State::Disputed)));
+        let mut refunded = transaction(2);
+        refunded.client_id = 3;
+        refunded.type_ = DisputableType::Deposit(Decimal::new(6, 0));
+        assert_eq!(client.access(2), Some((refunded, State::Committed)));
+        assert_eq!(client.access(3), None);
+        assert_eq!(client.entries().count(), 2);
+    }
+
+    #[test]
+    fn tiered_demotes_and_promotes() {
+        let transaction = |transaction_id| DisputableTransaction {
+            client_id: 1,
+            transaction_id,
+            type_: DisputableType::Deposit(Decimal::new(1, 0)),
+            metadata: HashMap::new(),
+            history: Vec::new(),
+        };
+        let mut client = TieredClient::new(MemoryClient::default(), 2);
+        client.store(transaction(1));
+        assert!(client.update(1, State::Disputed).is_ok());
+        for id in 2..=5 {
+            client.store(transaction(id));
+        }
+        // The disputed transaction stays, with room for one more
+        assert_eq!(client.hot_len(), 2);
+        assert!(client.hot.contains_key(&1) && client.hot.contains_key(&5));
+        assert_eq!(client.cold.entries.len(), 3);
+
+        // Promoted, demoting the other
+        assert!(client.update(2, State::Disputed).is_ok());
+        assert_eq!(client.hot_len(), 2);
+        assert!(!client.hot.contains_key(&5));
+        // Both disputed, so neither makes room
+        client.store(transaction(6));
+        assert_eq!(client.hot_len(), 3);
+        assert!(client.update(1, State::Resolved).is_ok());
+        assert!(client.update(2, State::ChargedBack).is_ok());
+
+        let mut entries = client.entries().collect::<Vec<_>>();
+        entries.sort_by_key(|(t, _)| t.transaction_id);
+        assert_eq!(
+            entries
+                .iter()
+                .map(|&(ref t, s)| (t.transaction_id, s))
+                .collect::<Vec<_>>(),
+            [
+                (1, State::Resolved),
+                (2, State::ChargedBack),
+                (3, State::Committed),
+                (4, State::Committed),
+                (5, State::Committed),
+                (6, State::Committed),
+            ]
+        );
+        // Promoted transactions aren't counted again from their stale copies
+        let stats = client.stats();
+        assert_eq!(stats.entries(), 6);
+        assert_eq!(stats.states[&State::Committed], 4);
+    }
+
+    #[test]
+    fn store_stats() {
+        let mut client = MemoryClient::default();
+        for transaction_id in 1..=3 {
+            client.store(DisputableTransaction {
+                client_id: 1,
+                transaction_id,
+                type_: DisputableType::Deposit(Decimal::new(1, 0)),
+                metadata: HashMap::new(),
+                history: Vec::new(),
+            });
+        }
+        assert!(client.update(3, State::Disputed).is_ok());
+        assert!(client.update(2, State::Disputed).is_ok());
+        let stats = client.stats();
+        assert_eq!(
+            stats.states,
+            BTreeMap::from([(State::Committed, 1), (State::Disputed, 2)])
+        );
+        assert_eq!(stats.oldest_disputed, Some(2));
+        assert!(stats.memory_bytes > 0);
+        assert_eq!(stats.disk_bytes, 0);
+        assert_eq!(
+            StoreStats::of(client.entries()),
+            StoreStats {
+                memory_bytes: 0,
+                ..stats
+            }
+        );
     }
 }
@@ -0,0 +1,236 @@
+//! A live terminal dashboard of an [`Engine`] while it processes transactions
+
+use ratatui::backend::CrosstermBackend;
+use ratatui::crossterm::execute;
+use ratatui::crossterm::terminal::{EnterAlternateScreen, LeaveAlternateScreen};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::widgets::{Block, List, Paragraph};
+use ratatui::{Frame, Terminal};
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Stderr};
+use std::time::{Duration, Instant};
+
+use crate::client::{Client, ClientOutput};
+use crate::engine::{Engine, Rejection};
+use crate::transaction::{Transaction, Type, Type::*};
+use crate::transaction_set::Client as TransactionSetClient;
+
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+// How many entries each list keeps
+const RECENT: usize = 10;
+
+/// Tracks what the engine has been doing and draws it to `STDERR`, leaving `STDOUT` free for the report.
+///
+/// Rejections are shown on the dashboard rather than printed, since that would garble the screen.
+pub struct Dashboard {
+    terminal: Terminal<CrosstermBackend<Stderr>>,
+    started: Instant,
+    last_draw: Option<Instant>,
+    processed: u64,
+    rejected: u64,
+    // Transaction ids currently under dispute, with the reason given
+    open_disputes: HashMap<u32, Option<String>>,
+    // Most recent first
+    recently_locked: VecDeque<u16>,
+    recent_rejections: VecDeque<String>,
+}
+
+/// A transaction as it was before being processed
+pub struct Before {
+    transaction_id: u32,
+    client_id: u16,
+    type_: Type,
+    was_locked: bool,
+}
+
+impl Dashboard {
+    /// Takes over the terminal until [`Dashboard::finish`]
+    pub fn new() -> io::Result<Self> {
+        let mut stderr = io::stderr();
+        execute!(stderr, EnterAlternateScreen)?;
+        Ok(Dashboard {
+            terminal: Terminal::new(CrosstermBackend::new(stderr))?,
+            started: Instant::now(),
+            last_draw: None,
+            processed: 0,
+            rejected: 0,
+            open_disputes: HashMap::new(),
+            recently_locked: VecDeque::new(),
+            recent_rejections: VecDeque::new(),
+        })
+    }
+
+    /// Notes what a transaction is about to change, for [`Dashboard::record`] to compare against once it's processed
+    pub fn before<T: TransactionSetClient>(
+        &self,
+        engine: &Engine<T>,
+        transaction: &Transaction,
+    ) -> Before {
+        Before {
+            transaction_id: transaction.transaction_id,
+            client_id: transaction.client_id,
+            type_: transaction.type_.clone(),
+            was_locked: engine
+                .client(transaction.client_id)
+                .is_some_and(Client::is_locked),
+        }
+    }
+
+    /// Records the outcome of processing the transaction noted by [`Dashboard::before`]
+    pub fn record<T: TransactionSetClient>(
+        &mut self,
+        engine: &Engine<T>,
+        before: Before,
+        result: Result<(), &Rejection>,
+    ) {
+        let Before {
+            transaction_id,
+            client_id,
+            type_,
+            was_locked,
+        } = before;
+        self.processed += 1;
+        match result {
+            Ok(()) => match type_ {
+                Dispute(reason) => {
+                    self.open_disputes.insert(transaction_id, reason);
+                }
+                Resolve(_) | Chargeback(_) => {
+                    self.open_disputes.remove(&transaction_id);
+                }
+                Disputable(_) | Fee(_) | Interest(_) | Close | Authorize(_) | Capture | Void
+                | Refund(_) | Adjustment(_) | Status(_) => {}
+            },
+            Err(e) => {
+                self.rejected += 1;
+                push_recent(
+                    &mut self.recent_rejections,
+                    format!("tx {}: {}: {}", transaction_id, e.code(), e),
+                );
+            }
+        }
+        if !was_locked && engine.client(client_id).is_some_and(Client::is_locked) {
+            push_recent(&mut self.recently_locked, client_id);
+        }
+    }
+
+    /// Redraws the dashboard, unless it was drawn very recently
+    pub fn draw<T: TransactionSetClient>(&mut self, engine: &Engine<T>) -> io::Result<()> {
+        if self
+            .last_draw
+            .is_some_and(|last| last.elapsed() < REDRAW_INTERVAL)
+        {
+            return Ok(());
+        }
+        self.last_draw = Some(Instant::now());
+
+        let mut top = engine.report().collect::<Vec<_>>();
+        top.sort_by(|a, b| b.held.cmp(&a.held).then(a.client.cmp(&b.client)));
+        top.truncate(RECENT);
+
+        let status = self.status();
+        let Dashboard {
+            terminal,
+            open_disputes,
+            recently_locked,
+            recent_rejections,
+            ..
+        } = self;
+        terminal.draw(|frame| {
+            render(
+                frame,
+                status,
+                &top,
+                open_disputes,
+                recently_locked,
+                recent_rejections,
+            )
+        })?;
+        Ok(())
+    }
+
+    /// Draws the final state and gives the terminal back
+    pub fn finish<T: TransactionSetClient>(mut self, engine: &Engine<T>) -> io::Result<()> {
+        self.last_draw = None;
+        self.draw(engine)?;
+        execute!(self.terminal.backend_mut(), LeaveAlternateScreen)?;
+        eprintln!("{}", self.status());
+        Ok(())
+    }
+
+    fn status(&self) -> String {
+        let elapsed = self.started.elapsed().as_secs_f64();
+        format!(
+            "{} processed, {} rejected, {:.0} tx/s",
+            self.processed,
+            self.rejected,
+            self.processed as f64 / elapsed.max(f64::EPSILON)
+        )
+    }
+}
+
+fn push_recent<T>(list: &mut VecDeque<T>, item: T) {
+    list.push_front(item);
+    list.truncate(RECENT);
+}
+
+fn render(
+    frame: &mut Frame,
+    status: String,
+    top: &[ClientOutput],
+    open_disputes: &HashMap<u32, Option<String>>,
+    recently_locked: &VecDeque<u16>,
+    recent_rejections: &VecDeque<String>,
+) {
+    let [status_area, main_area, rejections_area] = Layout::vertical([
+        Constraint::Length(3),
+        Constraint::Min(0),
+        Constraint::Length(RECENT as u16 + 2),
+    ])
+    .areas(frame.area());
+    let [top_area, disputes_area, locked_area] = Layout::horizontal([
+        Constraint::Percentage(50),
+        Constraint::Percentage(25),
+        Constraint::Percentage(25),
+    ])
+    .areas(main_area);
+
+    frame.render_widget(
+        Paragraph::new(status).block(Block::bordered().title("Throughput")),
+        status_area,
+    );
+    frame.render_widget(
+        List::new(top.iter().map(|c| {
+            format!(
+                "client {:>5}  held {:>12}  total {:>12}{}",
+                c.client,
+                c.held.to_string(),
+                c.total.to_string(),
+                if c.locked { "  locked" } else { "" }
+            )
+        }))
+        .block(Block::bordered().title("Top clients by held funds")),
+        top_area,
+    );
+
+    let mut disputes = open_disputes.iter().collect::<Vec<_>>();
+    disputes.sort_unstable();
+    frame.render_widget(
+        List::new(disputes.iter().map(|(tx, reason)| match reason {
+            Some(reason) => format!("tx {} ({})", tx, reason),
+            None => format!("tx {}", tx),
+        }))
+        .block(Block::bordered().title(format!("Open disputes ({})", open_disputes.len()))),
+        disputes_area,
+    );
+    frame.render_widget(
+        List::new(recently_locked.iter().map(|c| format!("client {}", c)))
+            .block(Block::bordered().title("Recently locked")),
+        locked_area,
+    );
+    frame.render_widget(
+        List::new(recent_rejections.iter().map(String::as_str))
+            .block(Block::bordered().title("Recent rejections")),
+        rejections_area,
+    );
+}
@@ -0,0 +1,253 @@
+use csv::StringRecord;
+use serde::de::{self, value::StrDeserializer};
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::io;
+
+use crate::decimal::Decimal;
+use crate::transaction::{
+    CsvOptions, CsvType, DisputableTransaction, DisputableType, Transaction, Type::*,
+};
+use crate::transaction_set::{Client, MemoryClient, State::*, UpdateFailure::*};
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+    /// The row couldn't be read at all (e.g. missing fields)
+    Malformed,
+    BadType,
+    BadClient,
+    BadTx,
+    BadAmount,
+    MissingAmount,
+    /// A deposit, withdrawal or authorization reusing an existing transaction id
+    DuplicateTx,
+    /// A dispute step, capture, void or refund of a transaction that doesn't exist
+    NotFound,
+    /// A dispute step, capture, void or refund of a transaction that can't make that transition
+    WrongState,
+    /// A dispute step, capture, void or refund naming a different client than the original transaction
+    ClientMismatch,
+}
+
+/// A single problem found in a transaction file
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    pub line: u64,
+    pub tx: Option<u32>,
+    pub client: Option<u16>,
+    pub kind: Kind,
+    pub detail: String,
+}
+
+/// Checks every row of a transaction file, returning all the problems found in file order.
+///
+/// Dispute state transitions are simulated, but balances are not, so insufficient funds are never reported. Resolves
+/// and chargebacks are assumed to succeed.
+pub fn validate_reader<R: io::Read>(rdr: R) -> csv::Result<Vec<Problem>> {
+    validate_reader_with(rdr, &CsvOptions::default())
+}
+
+pub fn validate_reader_with<R: io::Read>(
+    rdr: R,
+    options: &CsvOptions,
+) -> csv::Result<Vec<Problem>> {
+    let mut rdr = options.reader_builder().from_reader(rdr);
+    let headers = rdr.headers()?.clone();
+    let mut tx_record = MemoryClient::default();
+    let mut problems = Vec::new();
+
+    let mut record = StringRecord::new();
+    loop {
+        let line = rdr.position().line();
+        match rdr.read_record(&mut record) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(e) => {
+                problems.push(Problem {
+                    line,
+                    tx: None,
+                    client: None,
+                    kind: Kind::Malformed,
+                    detail: e.to_string(),
+                });
+                continue;
+            }
+        }
+        let line = record.position().map_or(line, |p| p.line());
+        let record = options.normalize(record.clone(), &headers);
+        if let Err(problem) = validate_record(&record, &headers, options, &mut tx_record) {
+            problems.push(Problem { line, ..problem });
+        }
+    }
+    Ok(problems)
+}
+
+fn validate_record(
+    record: &StringRecord,
+    headers: &StringRecord,
+    options: &CsvOptions,
+    tx_record: &mut MemoryClient,
+) -> Result<(), Problem> {
+    let problem = |tx, client, kind, detail: String| Problem {
+        line: 0,
+        tx,
+        client,
+        kind,
+        detail,
+    };
+
+    let csv = options
+        .deserialize_csv(record, Some(headers))
+        .map_err(|e| problem(None, None, locate(record, headers), e.to_string()))?;
+    let (tx, client) = (Some(csv.tx), Some(csv.client));
+    let transaction = Transaction::try_from(csv)
+        .map_err(|e| problem(tx, client, Kind::MissingAmount, e.to_string()))?;
+
+    let state = match transaction.type_ {
+        Disputable(_) | Authorize(_) => {
+            if tx_record.access(transaction.transaction_id).is_some() {
+                return Err(problem(
+                    tx,
+                    client,
+                    Kind::DuplicateTx,
+                    "transaction id already used".to_owned(),
+                ));
+            }
+            // Authorizations become deposits once they're captured
+            let (type_, state) = match transaction.type_ {
+                Authorize(amount) => (DisputableType::Deposit(amount), Authorized),
+                Disputable(type_) => (type_, Committed),
+                _ => unreachable!("matched above"),
+            };
+            tx_record.restore(
+                DisputableTransaction {
+                    transaction_id: transaction.transaction_id,
+                    client_id: transaction.client_id,
+                    type_,
+                    metadata: transaction.metadata.clone(),
+                    history: Vec::new(),
+                },
+                state,
+            );
+            return Ok(());
+        }
+        // Balances aren't simulated, so there's nothing to check
+        Fee(_) | Interest(_) | Close | Adjustment(_) | Status(_) => return Ok(()),
+        Dispute(_) => Disputed,
+        Resolve(_) => Resolved,
+        Chargeback(_) => ChargedBack,
+        Capture => Committed,
+        Void => Voided,
+        // Refunds don't change the deposit's state, only what's left of it
+        Refund(_) => Committed,
+    };
+
+    let updated = match transaction.type_ {
+        Refund(amount) => tx_record.refund(transaction.transaction_id, amount),
+        _ => tx_record.update(transaction.transaction_id, state),
+    };
+    let disputed = match updated {
+        Err(NotFound) => {
+            return Err(problem(
+                tx,
+                client,
+                Kind::NotFound,
+                "transaction not found".to_owned(),
+            ))
+        }
+        Err(WrongState(s)) => {
+            return Err(problem(
+                tx,
+                client,
+                Kind::WrongState,
+                format!("transaction is {:?}", s),
+            ))
+        }
+        Ok(disputed) => disputed,
+    };
+
+    // Mirror the follow-up transition `process_transaction` makes on success
+    match state {
+        Resolved => {
+            let _ = tx_record.update(transaction.transaction_id, Committed);
+        }
+        ChargedBack => {
+            let _ = tx_record.update(transaction.transaction_id, ChargedBackFinal);
+        }
+        _ => {}
+    }
+
+    if disputed.client_id != transaction.client_id {
+        return Err(problem(
+            tx,
+            client,
+            Kind::ClientMismatch,
+            format!("transaction belongs to client {}", disputed.client_id),
+        ));
+    }
+    Ok(())
+}
+
+/// csv only attributes some deserialization errors to a field, so figure out which column is actually bad
+fn locate(record: &StringRecord, headers: &StringRecord) -> Kind {
+    for (header, field) in headers.iter().zip(record.iter()) {
+        let bad = match header {
+            "type" => {
+                CsvType::deserialize(StrDeserializer::<de::value::Error>::new(field)).is_err()
+            }
+            "client" => field.parse::<u16>().is_err(),
+            "tx" => field.parse::<u32>().is_err(),
+            "amount" | "debit" | "credit" => !field.is_empty() && field.parse::<Decimal>().is_err(),
+            _ => false,
+        };
+        if bad {
+            return match header {
+                "type" => Kind::BadType,
+                "client" => Kind::BadClient,
+                "tx" => Kind::BadTx,
+                _ => Kind::BadAmount,
+            };
+        }
+    }
+    Kind::Malformed
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn validate_basic_problems() {
+        let data = "\
+type,       client,  tx, amount
+deposit,         1,   1,    1.0
+deposit,         1,   1,    1.0
+deposit,         1,   2,    1.x
+withdrawal,      1,   3,
+deposit,     70000,   4,    1.0
+transfer,        1,   5,    1.0
+dispute,         1,   9,
+dispute,         2,   1,
+chargeback,      1,   1,
+resolve,         1,   1,
+";
+        let problems = validate_reader(data.as_bytes()).unwrap();
+        assert_eq!(
+            problems
+                .iter()
+                .map(|p| (p.line, p.kind))
+                .collect::<Vec<_>>(),
+            vec![
+                (3, Kind::DuplicateTx),
+                (4, Kind::BadAmount),
+                (5, Kind::MissingAmount),
+                (6, Kind::BadClient),
+                (7, Kind::BadType),
+                (8, Kind::NotFound),
+                (9, Kind::ClientMismatch),
+                (11, Kind::WrongState),
+            ]
+        );
+    }
+}
@@ -0,0 +1,238 @@
+//! A risk rule limiting how many deposits or withdrawals each client makes, and for how much, within rolling windows.
+//!
+//! Transactions aren't dated, so they're timestamped by a clock as they're evaluated, the system clock by default.
+//! Every attempt the rule doesn't reject counts towards the windows, even if the engine goes on to reject it.
+
+use serde::Deserialize;
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryFrom;
+use std::io::Read;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::client::Client;
+use crate::decimal::Decimal;
+use crate::risk::{RiskRule, Verdict};
+use crate::transaction::{DisputableType, Transaction, TransactionKind, Type};
+
+/// What to do with a transaction over a limit
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Flag,
+    Reject,
+}
+
+/// At most `count` transactions of a kind, or `volume` in total, within `window` seconds
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "Row")]
+pub struct Limit {
+    pub kind: TransactionKind,
+    pub window: u64,
+    pub count: Option<u32>,
+    pub volume: Option<Decimal>,
+    pub action: Action,
+}
+
+/// A row of a limits file
+#[derive(Deserialize)]
+struct Row {
+    #[serde(rename = "type")]
+    kind: TransactionKind,
+    window: String,
+    count: Option<u32>,
+    volume: Option<Decimal>,
+    action: Action,
+}
+
+impl TryFrom<Row> for Limit {
+    type Error = String;
+    fn try_from(row: Row) -> Result<Self, Self::Error> {
+        if !matches!(
+            row.kind,
+            TransactionKind::Deposit | TransactionKind::Withdrawal
+        ) {
+            return Err(format!("can't limit the velocity of a {}", row.kind));
+        }
+        Ok(Limit {
+            kind: row.kind,
+            window: parse_window(&row.window)?,
+            count: row.count,
+            volume: row.volume,
+            action: row.action,
+        })
+    }
+}
+
+/// A number of hours or days, like `1h` or `7d`, in seconds
+pub fn parse_window(s: &str) -> Result<u64, String> {
+    let invalid = || format!("invalid window {:?}, expected e.g. 1h or 7d", s);
+    let unit = s.len().checked_sub(1).ok_or_else(invalid)?;
+    let n = u64::from_str(&s[..unit]).map_err(|_| invalid())?;
+    let seconds = match &s[unit..] {
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+    n.checked_mul(seconds).ok_or_else(invalid)
+}
+
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// A client's recent deposits and withdrawals with their timestamps, oldest first
+type History = VecDeque<(u64, TransactionKind, Decimal)>;
+
+pub struct Velocity {
+    limits: Vec<Limit>,
+    clock: Box<dyn Fn() -> u64>,
+    history: RefCell<HashMap<u16, History>>,
+}
+
+impl Default for Velocity {
+    fn default() -> Self {
+        Velocity {
+            limits: Vec::new(),
+            clock: Box::new(now),
+            history: RefCell::default(),
+        }
+    }
+}
+
+impl Velocity {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, limit: Limit) -> Self {
+        self.limits.push(limit);
+        self
+    }
+
+    /// Timestamps transactions with `clock`, in unix seconds, instead of the system clock
+    pub fn with_clock<C: Fn() -> u64 + 'static>(mut self, clock: C) -> Self {
+        self.clock = Box::new(clock);
+        self
+    }
+
+    /// Reads `type,window,count,volume,action` rows, where either `count` or `volume` may be left empty
+    pub fn from_csv_reader<R: Read>(rdr: R) -> csv::Result<Self> {
+        let mut velocity = Velocity::new();
+        for limit in csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(rdr)
+            .into_deserialize()
+        {
+            velocity = velocity.with(limit?);
+        }
+        Ok(velocity)
+    }
+}
+
+impl RiskRule for Velocity {
+    fn evaluate(&self, tx: &Transaction, _: &Client) -> Verdict {
+        let (kind, amount) = match &tx.type_ {
+            Type::Disputable(DisputableType::Deposit(amount)) => (TransactionKind::Deposit, amount),
+            Type::Disputable(DisputableType::Withdrawal(amount)) => {
+                (TransactionKind::Withdrawal, amount)
+            }
+            _ => return Verdict::Accept,
+        };
+        let now = (self.clock)();
+        let longest = self.limits.iter().map(|l| l.window).max().unwrap_or(0);
+        let mut history = self.history.borrow_mut();
+        let recent = history.entry(tx.client_id).or_default();
+        while recent
+            .front()
+            .is_some_and(|&(at, _, _)| now.saturating_sub(at) >= longest)
+        {
+            recent.pop_front();
+        }
+
+        let mut verdict = Verdict::Accept;
+        for limit in self.limits.iter().filter(|l| l.kind == kind) {
+            let (count, volume) = recent
+                .iter()
+                .filter(|&&(at, k, _)| k == kind && now.saturating_sub(at) < limit.window)
+                .fold((1, amount.clone()), |(count, volume), (_, _, amount)| {
+                    (count + 1, volume + amount.clone())
+                });
+            let exceeded = match (limit.count, &limit.volume) {
+                (Some(max), _) if count > max => {
+                    format!("{} {}s within {}s", count, kind, limit.window)
+                }
+                (_, Some(max)) if volume > *max => {
+                    format!("{} of {}s within {}s", volume, kind, limit.window)
+                }
+                _ => continue,
+            };
+            match limit.action {
+                Action::Reject => return Verdict::Reject(exceeded),
+                Action::Flag if verdict == Verdict::Accept => verdict = Verdict::Flag(exceeded),
+                Action::Flag => {}
+            }
+        }
+        recent.push_back((now, kind, amount.clone()));
+        verdict
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    #[test]
+    fn velocity_limits() {
+        let clock = Rc::new(Cell::new(1_000_000));
+        let velocity = Velocity::from_csv_reader(
+            "\
+type,       window, count, volume, action
+withdrawal,     1h,     2,       , reject
+withdrawal,     1d,      ,  100.0, flag
+"
+            .as_bytes(),
+        )
+        .unwrap()
+        .with_clock({
+            let clock = clock.clone();
+            move || clock.get()
+        });
+        let client = Client::new(1);
+        let evaluate = |tx, amount| {
+            let verdict = velocity.evaluate(
+                &Transaction {
+                    client_id: 1,
+                    transaction_id: tx,
+                    type_: Type::Disputable(DisputableType::Withdrawal(Decimal::new(amount, 0))),
+                    metadata: HashMap::new(),
+                },
+                &client,
+            );
+            clock.set(clock.get() + 60);
+            verdict
+        };
+        assert_eq!(evaluate(1, 40), Verdict::Accept);
+        assert_eq!(evaluate(2, 40), Verdict::Accept);
+        assert_eq!(
+            evaluate(3, 1),
+            Verdict::Reject("3 withdrawals within 3600s".to_owned())
+        );
+        clock.set(clock.get() + 60 * 60);
+        assert_eq!(
+            evaluate(4, 30),
+            Verdict::Flag("110.0000 of withdrawals within 86400s".to_owned())
+        );
+
+        assert!(Velocity::from_csv_reader(
+            "type,window,count,volume,action\ndispute,1h,1,,flag\n".as_bytes()
+        )
+        .is_err());
+        assert!(parse_window("5m").is_err());
+    }
+}
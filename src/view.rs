@@ -0,0 +1,154 @@
+//! Point-in-time views of an engine's clients and open disputes, for reading on other threads while transactions keep
+//! being applied.
+//!
+//! Views are copy-on-write: each client's row and disputes are shared between every view they haven't changed in, so
+//! refreshing a view only copies the clients changed since the last one. The map of clients itself is only copied if
+//! an older view is still being read.
+
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+
+use crate::client::{Client, ClientOutput};
+
+/// A disputed transaction and why it was disputed
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Dispute {
+    pub tx: u32,
+    pub reason: Option<String>,
+}
+
+/// Every client's report row and open disputes as of one point in processing. Cloning is cheap.
+#[derive(Clone, Debug, Default)]
+pub struct View {
+    epoch: u64,
+    clients: Arc<BTreeMap<u16, Arc<ClientOutput>>>,
+    disputes: Arc<BTreeMap<u16, Arc<[Dispute]>>>,
+}
+
+impl View {
+    /// How many views the engine had given before this one
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    pub fn client(&self, client_id: u16) -> Option<&ClientOutput> {
+        self.clients.get(&client_id).map(Arc::as_ref)
+    }
+
+    /// The accounts report as of the view, in client order
+    pub fn report(&self) -> impl Iterator<Item = &ClientOutput> {
+        self.clients.values().map(Arc::as_ref)
+    }
+
+    /// The client's open disputes in transaction id order
+    pub fn open_disputes(&self, client_id: u16) -> &[Dispute] {
+        self.disputes.get(&client_id).map_or(&[], Arc::as_ref)
+    }
+}
+
+/// The latest view an engine gave, and the clients changed since
+#[derive(Debug)]
+pub(crate) struct Views {
+    latest: View,
+    changed: HashSet<u16>,
+}
+
+impl Views {
+    /// Starts from every client, as none are in a view yet
+    pub(crate) fn new(clients: &HashMap<u16, Client>) -> Self {
+        Views {
+            latest: View::default(),
+            changed: clients.keys().copied().collect(),
+        }
+    }
+
+    pub(crate) fn change(&mut self, client_id: u16) {
+        self.changed.insert(client_id);
+    }
+
+    /// Brings the latest view up to date with the changed clients, and gives it
+    pub(crate) fn refresh(
+        &mut self,
+        clients: &HashMap<u16, Client>,
+        open_disputes: &HashMap<u16, BTreeMap<u32, Option<String>>>,
+    ) -> View {
+        if !self.changed.is_empty() {
+            let rows = Arc::make_mut(&mut self.latest.clients);
+            let disputes = Arc::make_mut(&mut self.latest.disputes);
+            for id in self.changed.drain() {
+                match clients.get(&id) {
+                    Some(client) => rows.insert(id, Arc::new(ClientOutput::from(client.clone()))),
+                    None => rows.remove(&id),
+                };
+                match open_disputes.get(&id) {
+                    Some(open) => disputes.insert(
+                        id,
+                        open.iter()
+                            .map(|(&tx, reason)| Dispute {
+                                tx,
+                                reason: reason.clone(),
+                            })
+                            .collect(),
+                    ),
+                    None => disputes.remove(&id),
+                };
+            }
+        }
+        let view = self.latest.clone();
+        self.latest.epoch += 1;
+        view
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::engine::Engine;
+    use crate::transaction::read_from_csv_reader;
+    use crate::transaction_set::MemoryClient;
+    use std::sync::Arc;
+
+    #[test]
+    fn views_are_isolated() {
+        let mut engine = Engine::new(MemoryClient::default());
+        let process = |engine: &mut Engine<MemoryClient>, csv: &str| {
+            for transaction in read_from_csv_reader(csv.as_bytes()) {
+                let _ = engine.process(transaction.unwrap());
+            }
+        };
+        process(
+            &mut engine,
+            "\
+type,       client,  tx, amount, reason
+deposit,         1,   1,    5.0,
+deposit,         2,   2,    3.0,
+",
+        );
+        let before = engine.view();
+        process(
+            &mut engine,
+            "\
+type,       client,  tx, amount, reason
+dispute,         2,   2,       , not mine
+deposit,         3,   3,    1.0,
+",
+        );
+        let after = engine.view();
+
+        // The earlier view is unaffected by what was applied after it
+        assert_eq!((before.epoch(), after.epoch()), (0, 1));
+        assert_eq!(before.report().count(), 2);
+        assert_eq!(before.client(2).unwrap().held.to_string(), "0.0000");
+        assert!(before.open_disputes(2).is_empty());
+
+        assert_eq!(after.report().count(), 3);
+        assert_eq!(after.client(2).unwrap().held.to_string(), "3.0000");
+        assert_eq!(
+            after.open_disputes(2)[0].reason.as_deref(),
+            Some("not mine")
+        );
+        // Client 1 didn't change, so both views share its row
+        assert!(Arc::ptr_eq(&before.clients[&1], &after.clients[&1]));
+        assert!(!Arc::ptr_eq(&before.clients[&2], &after.clients[&2]));
+    }
+}
@@ -0,0 +1,88 @@
+//! JavaScript bindings, so the engine can run client-side on uploaded files.
+//!
+//! ```js
+//! const engine = new Engine();
+//! for (const rejection of engine.processCsv(await file.text())) console.warn(rejection);
+//! download(engine.reportCsv());
+//! ```
+
+use serde::de::{self, value::StrDeserializer};
+use serde::Deserialize;
+use std::convert::TryFrom;
+use wasm_bindgen::prelude::*;
+
+use crate::client::ClientOutput;
+use crate::engine;
+use crate::report::Row;
+use crate::transaction::{read_from_csv_reader, CsvAmount, CsvTransaction, CsvType, Transaction};
+use crate::transaction_set::MemoryClient;
+
+#[wasm_bindgen(js_name = Engine)]
+pub struct WasmEngine {
+    engine: engine::Engine<MemoryClient>,
+}
+
+#[wasm_bindgen(js_class = Engine)]
+impl WasmEngine {
+    #[wasm_bindgen(constructor)]
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        WasmEngine {
+            engine: engine::Engine::new(MemoryClient::default()),
+        }
+    }
+
+    /// Processes every row of a transactions CSV, returning a message for each row which couldn't be parsed or was
+    /// rejected, in file order
+    #[wasm_bindgen(js_name = processCsv)]
+    pub fn process_csv(&mut self, csv: &str) -> Vec<String> {
+        let mut messages = Vec::new();
+        for transaction in read_from_csv_reader(csv.as_bytes()) {
+            let transaction = match transaction {
+                Ok(transaction) => transaction,
+                Err(e) => {
+                    messages.push(format!("failed to parse transaction: E_PARSE: {}", e));
+                    continue;
+                }
+            };
+            let transaction_id = transaction.transaction_id;
+            if let Err(e) = self.engine.process(transaction) {
+                messages.push(format!("tx {}: {}: {}", transaction_id, e.code(), e));
+            }
+        }
+        messages
+    }
+
+    /// Processes a single transaction, with the same columns as a CSV row. Throws if it is invalid or rejected.
+    #[wasm_bindgen(js_name = processTransaction)]
+    pub fn process_transaction(
+        &mut self,
+        type_: &str,
+        client: u16,
+        tx: u32,
+        amount: Option<String>,
+        reason: Option<String>,
+    ) -> Result<(), JsError> {
+        let csv = CsvTransaction {
+            type_: CsvType::deserialize(StrDeserializer::<de::value::Error>::new(type_))?,
+            client,
+            tx,
+            amount: amount.map(|a| a.parse::<CsvAmount>()).transpose()?,
+            reason,
+        };
+        Ok(self.engine.process(Transaction::try_from(csv)?)?)
+    }
+
+    /// The accounts report as CSV, ordered by client id
+    #[wasm_bindgen(js_name = reportCsv)]
+    pub fn report_csv(&self) -> Result<String, JsError> {
+        let mut report = self.engine.report().collect::<Vec<ClientOutput>>();
+        report.sort_by_key(|c| c.client);
+
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        for client in report {
+            writer.serialize(Row::new(client, false))?;
+        }
+        Ok(String::from_utf8(writer.into_inner()?)?)
+    }
+}
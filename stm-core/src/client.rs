@@ -0,0 +1,998 @@
+// `Ok({ ... })` is used throughout to apply a mutation and report success in one expression
+#![allow(clippy::unit_arg)]
+
+use core::convert::TryFrom;
+use core::fmt;
+use core::hash::{Hash, Hasher};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::decimal::Decimal;
+use crate::posting::{Account, Posting, Postings};
+#[cfg(feature = "testing")]
+use crate::InvariantViolation;
+#[cfg(feature = "testing")]
+use alloc::format;
+
+/// Written and read as a report row, which loses some of its state. `ClientRecord` keeps all of it.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(into = "ClientOutput", from = "ClientOutput"))]
+pub struct Client {
+    id: u16,
+    available: Decimal,
+    held: Decimal,
+    // An amount we're trying to hold, but was withdrawn before we got the chance
+    // If any ever comes back, we will hold it.
+    held_reserve: Decimal,
+    // A tenative amount from disputed withdrawal
+    reserve: Decimal,
+    // Credit extended by withdrawals of more than was available, which deposits pay back first
+    overdraft: Decimal,
+    overdraft_limit: Decimal,
+    // Authorized funds waiting to be captured
+    pending: Decimal,
+    locked: bool,
+    closed: bool,
+    // Every dispute and chargeback ever applied, which aren't in the report
+    disputes: u32,
+    chargebacks: u32,
+    lifetime: Lifetime,
+    // Whether disputed withdrawals are held, counting towards the total, rather than reserved. This is configuration,
+    // so isn't kept in a `ClientRecord`.
+    hold_disputed_withdrawals: bool,
+}
+
+impl Hash for Client {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
+impl Client {
+    pub fn new(id: u16) -> Self {
+        Client {
+            id,
+            available: Decimal::zero(),
+            held: Decimal::zero(),
+            held_reserve: Decimal::zero(),
+            reserve: Decimal::zero(),
+            overdraft: Decimal::zero(),
+            overdraft_limit: Decimal::zero(),
+            pending: Decimal::zero(),
+            locked: false,
+            closed: false,
+            disputes: 0,
+            chargebacks: 0,
+            lifetime: Lifetime::default(),
+            hold_disputed_withdrawals: false,
+        }
+    }
+
+    /// Allows withdrawals to overdraw available funds by up to `limit` in total
+    pub fn with_overdraft_limit(mut self, limit: Decimal) -> Self {
+        self.overdraft_limit = limit;
+        self
+    }
+
+    /// Holds the amount of a disputed withdrawal, so it's reported in held funds and the total until the dispute is
+    /// settled, instead of reserving it out of sight. A resolve releases it and a chargeback makes it available, either
+    /// way from wherever the dispute put it, so this must be the same for as long as any withdrawal is disputed.
+    pub fn with_disputed_withdrawals_held(mut self) -> Self {
+        self.hold_disputed_withdrawals = true;
+        self
+    }
+
+    pub fn id(&self) -> u16 {
+        self.id
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    /// Funds free to withdraw, not counting any overdraft
+    pub fn available(&self) -> Decimal {
+        self.available.clone()
+    }
+
+    /// Funds held for open disputes, including any still to be held once they come back after being withdrawn
+    pub fn held(&self) -> Decimal {
+        self.held.clone() + self.held_reserve.clone()
+    }
+
+    /// Available and held funds together, as reported
+    pub fn total(&self) -> Decimal {
+        self.available() + self.held()
+    }
+
+    /// Credit in use, which isn't taken off the total
+    pub fn overdraft(&self) -> Decimal {
+        self.overdraft.clone()
+    }
+
+    /// How far the client may overdraw
+    pub fn overdraft_limit(&self) -> Decimal {
+        self.overdraft_limit.clone()
+    }
+
+    /// Authorized funds which haven't been captured, which aren't part of the total
+    pub fn pending(&self) -> Decimal {
+        self.pending.clone()
+    }
+
+    /// A snapshot of the client's balances and status
+    pub fn state(&self) -> ClientState {
+        ClientState {
+            id: self.id,
+            available: self.available(),
+            held: self.held(),
+            total: self.total(),
+            overdraft: self.overdraft(),
+            overdraft_limit: self.overdraft_limit(),
+            pending: self.pending(),
+            locked: self.locked,
+            closed: self.closed,
+            disputes: self.disputes,
+            chargebacks: self.chargebacks,
+            lifetime: self.lifetime.clone(),
+        }
+    }
+
+    /// Counts and volumes of everything applied to the client, and when it was active
+    pub fn lifetime(&self) -> &Lifetime {
+        &self.lifetime
+    }
+
+    /// Notes the client was active at `at`, e.g. a unix timestamp
+    pub fn record_activity(&mut self, at: u64) {
+        let lifetime = &mut self.lifetime;
+        lifetime.first_activity = Some(lifetime.first_activity.map_or(at, |first| first.min(at)));
+        lifetime.last_activity = Some(lifetime.last_activity.map_or(at, |last| last.max(at)));
+    }
+
+    /// How many disputes have been applied to the client
+    pub fn disputes(&self) -> u32 {
+        self.disputes
+    }
+
+    /// How many chargebacks have been applied to the client, whether or not there were enough funds held for them
+    pub fn chargebacks(&self) -> u32 {
+        self.chargebacks
+    }
+
+    /// Locks the account, as a chargeback would, e.g. because of too many disputes
+    pub fn lock(&mut self) {
+        self.locked = true;
+    }
+
+    /// Moves all of `other`'s funds into this account, including any held for disputes, and closes `other`. Either
+    /// being locked locks the merged account.
+    pub fn merge(&mut self, other: &mut Client) {
+        let closed = Client {
+            closed: true,
+            ..Client::new(other.id)
+        };
+        let other = core::mem::replace(other, closed);
+        self.available += other.available;
+        self.held += other.held;
+        self.held_reserve += other.held_reserve;
+        self.reserve += other.reserve;
+        self.overdraft += other.overdraft;
+        self.pending += other.pending;
+        self.locked |= other.locked;
+        self.disputes = self.disputes.saturating_add(other.disputes);
+        self.chargebacks = self.chargebacks.saturating_add(other.chargebacks);
+        self.lifetime.merge(other.lifetime);
+    }
+
+    /// Closes the account, unless funds are still held or reserved for a dispute or pending capture, which is returned
+    /// instead
+    pub fn close(&mut self) -> Result<(), Decimal> {
+        let unsettled = self.held.clone()
+            + self.held_reserve.clone()
+            + self.reserve.clone()
+            + self.pending.clone();
+        match unsettled == Decimal::zero() {
+            true => Ok(self.closed = true),
+            false => Err(unsettled),
+        }
+    }
+
+    /// The balance of one of the client's own accounts
+    fn balance_mut(&mut self, account: Account) -> Option<&mut Decimal> {
+        match account {
+            Account::Available => Some(&mut self.available),
+            Account::Held => Some(&mut self.held),
+            Account::HeldReserve => Some(&mut self.held_reserve),
+            Account::Reserve => Some(&mut self.reserve),
+            Account::Pending => Some(&mut self.pending),
+            // The overdraft is credit the client owes, so it grows as funds are posted from it
+            Account::External
+            | Account::ChargebackWriteoff
+            | Account::Fees
+            | Account::Interest
+            | Account::Overdraft
+            | Account::Adjustments => None,
+        }
+    }
+
+    /// Moves funds between accounts, which the caller has already checked `from` has
+    fn post(&mut self, postings: &mut Postings, from: Account, to: Account, amount: Decimal) {
+        if let Some(balance) = self.balance_mut(from) {
+            *balance =
+                (balance.clone() - amount.clone()).expect("postings never overdraw an account");
+        }
+        if let Some(balance) = self.balance_mut(to) {
+            *balance += amount.clone();
+        }
+        postings.push(Posting { from, to, amount });
+    }
+
+    pub fn deposit(&mut self, amount: Decimal) -> Postings {
+        self.lifetime.deposits = self.lifetime.deposits.saturating_add(1);
+        self.lifetime.deposited += amount.clone();
+        let mut postings = Postings::default();
+        // Anything still owed for a dispute is held first
+        let owed = match self.held_reserve.clone() - amount.clone() {
+            Ok(_) => amount.clone(),
+            Err(_) => self.held_reserve.clone(),
+        };
+        self.post(
+            &mut postings,
+            Account::External,
+            Account::Held,
+            owed.clone(),
+        );
+        self.post(
+            &mut postings,
+            Account::HeldReserve,
+            Account::External,
+            owed.clone(),
+        );
+        if let Ok(rest) = amount - owed {
+            // Then any overdraft is paid back
+            let repaid = match self.overdraft.clone() - rest.clone() {
+                Ok(_) => rest.clone(),
+                Err(_) => self.overdraft.clone(),
+            };
+            self.overdraft = (self.overdraft.clone() - repaid.clone()).unwrap_or_default();
+            self.post(
+                &mut postings,
+                Account::External,
+                Account::Overdraft,
+                repaid.clone(),
+            );
+            if let Ok(rest) = rest - repaid {
+                self.post(&mut postings, Account::External, Account::Available, rest);
+            }
+        }
+        postings
+    }
+
+    /// Withdraws from available funds, overdrawing them if there isn't enough. Fails with the funds available including
+    /// the remaining overdraft, or `None` if the client is locked.
+    pub fn withdraw(&mut self, amount: Decimal) -> Result<Postings, Option<Decimal>> {
+        let postings = self.pay_out(amount.clone())?;
+        self.lifetime.withdrawals = self.lifetime.withdrawals.saturating_add(1);
+        self.lifetime.withdrawn += amount;
+        Ok(postings)
+    }
+
+    /// Pays part of a deposit back, as a withdrawal which isn't counted as one
+    pub fn refund(&mut self, amount: Decimal) -> Result<Postings, Option<Decimal>> {
+        self.pay_out(amount)
+    }
+
+    fn pay_out(&mut self, amount: Decimal) -> Result<Postings, Option<Decimal>> {
+        let mut postings = Postings::default();
+        let credit = (self.overdraft_limit.clone() - self.overdraft.clone()).unwrap_or_default();
+        match (self.locked, self.available.clone() - amount.clone()) {
+            (true, _) => Err(None),
+            (false, Ok(_)) => Ok({
+                self.post(&mut postings, Account::Available, Account::External, amount);
+                postings
+            }),
+            (false, Err(shortfall)) if shortfall <= credit => Ok({
+                let available = self.available.clone();
+                self.post(
+                    &mut postings,
+                    Account::Available,
+                    Account::External,
+                    available,
+                );
+                self.overdraft += shortfall.clone();
+                self.post(
+                    &mut postings,
+                    Account::Overdraft,
+                    Account::External,
+                    shortfall,
+                );
+                postings
+            }),
+            (false, Err(_)) => Err(Some(self.available.clone() + credit)),
+        }
+    }
+
+    /// Charges a fee from available funds, even if the client is locked
+    pub fn charge_fee(&mut self, amount: Decimal) -> Result<Postings, Decimal> {
+        let mut postings = Postings::default();
+        match self.available.clone() - amount.clone() {
+            Ok(_) => Ok({
+                self.post(&mut postings, Account::Available, Account::Fees, amount);
+                postings
+            }),
+            Err(_) => Err(self.available.clone()),
+        }
+    }
+
+    /// Charges a fee from available funds, overdrawing them past any overdraft limit if there isn't enough, even if the
+    /// client is locked
+    pub fn charge_fee_overdrawing(&mut self, amount: Decimal) -> Postings {
+        match self.charge_fee(amount.clone()) {
+            Ok(postings) => postings,
+            Err(available) => {
+                let mut postings = Postings::default();
+                let shortfall = (amount - available.clone()).unwrap_or_default();
+                self.post(&mut postings, Account::Available, Account::Fees, available);
+                self.overdraft += shortfall.clone();
+                self.post(&mut postings, Account::Overdraft, Account::Fees, shortfall);
+                postings
+            }
+        }
+    }
+
+    /// Pays interest into available funds, even if the client is locked
+    pub fn accrue_interest(&mut self, amount: Decimal) -> Postings {
+        let mut postings = Postings::default();
+        self.post(&mut postings, Account::Interest, Account::Available, amount);
+        postings
+    }
+
+    /// Corrects available funds by crediting or debiting them, even if the client is locked
+    pub fn adjust(&mut self, debit: bool, amount: Decimal) -> Result<Postings, Decimal> {
+        let mut postings = Postings::default();
+        match (debit, self.available.clone() - amount.clone()) {
+            (false, _) => Ok({
+                self.post(
+                    &mut postings,
+                    Account::Adjustments,
+                    Account::Available,
+                    amount,
+                );
+                postings
+            }),
+            (true, Ok(_)) => Ok({
+                self.post(
+                    &mut postings,
+                    Account::Available,
+                    Account::Adjustments,
+                    amount,
+                );
+                postings
+            }),
+            (true, Err(_)) => Err(self.available.clone()),
+        }
+    }
+
+    /// Sets authorized funds aside until they're captured or voided
+    pub fn authorize(&mut self, amount: Decimal) -> Postings {
+        let mut postings = Postings::default();
+        self.post(&mut postings, Account::External, Account::Pending, amount);
+        postings
+    }
+
+    /// Makes authorized funds available, as if they had been deposited
+    pub fn capture(&mut self, amount: Decimal) -> Result<Postings, Decimal> {
+        let mut postings = Postings::default();
+        match self.pending.clone() - amount.clone() {
+            Ok(_) => Ok({
+                self.post(&mut postings, Account::Pending, Account::Available, amount);
+                postings
+            }),
+            Err(_) => Err(self.pending.clone()),
+        }
+    }
+
+    /// Releases authorized funds back to where they came from
+    pub fn void(&mut self, amount: Decimal) -> Result<Postings, Decimal> {
+        let mut postings = Postings::default();
+        match self.pending.clone() - amount.clone() {
+            Ok(_) => Ok({
+                self.post(&mut postings, Account::Pending, Account::External, amount);
+                postings
+            }),
+            Err(_) => Err(self.pending.clone()),
+        }
+    }
+
+    pub fn dispute_deposit(&mut self, amount: Decimal) -> Postings {
+        self.disputes = self.disputes.saturating_add(1);
+        self.lifetime.disputed += amount.clone();
+        let mut postings = Postings::default();
+        match self.available.clone() - amount.clone() {
+            Ok(_) => self.post(&mut postings, Account::Available, Account::Held, amount),
+            Err(shortfall) => {
+                let available = self.available.clone();
+                self.post(&mut postings, Account::Available, Account::Held, available);
+                self.post(
+                    &mut postings,
+                    Account::External,
+                    Account::HeldReserve,
+                    shortfall,
+                );
+            }
+        }
+        postings
+    }
+
+    /// Where the amounts of disputed withdrawals are kept, and how much is there
+    fn withdrawal_disputes(&self) -> (Account, Decimal) {
+        match self.hold_disputed_withdrawals {
+            true => (Account::Held, self.held.clone()),
+            false => (Account::Reserve, self.reserve.clone()),
+        }
+    }
+
+    pub fn dispute_withdrawal(&mut self, amount: Decimal) -> Postings {
+        self.disputes = self.disputes.saturating_add(1);
+        self.lifetime.disputed += amount.clone();
+        let mut postings = Postings::default();
+        let (disputed, _) = self.withdrawal_disputes();
+        self.post(&mut postings, Account::External, disputed, amount);
+        postings
+    }
+
+    pub fn resolve_deposit(&mut self, amount: Decimal) -> Result<Postings, Decimal> {
+        let mut postings = Postings::default();
+        match self.held_reserve.clone() - amount.clone() {
+            // Relieved some of the reserve burden
+            Ok(_) => Ok({
+                self.post(
+                    &mut postings,
+                    Account::HeldReserve,
+                    Account::External,
+                    amount,
+                );
+                postings
+            }),
+            // No reserve burden remains, apply to actual held
+            Err(rest) => match self.held.clone() - rest.clone() {
+                // Some held relieved
+                Ok(_) => Ok({
+                    let held_reserve = self.held_reserve.clone();
+                    self.post(
+                        &mut postings,
+                        Account::HeldReserve,
+                        Account::External,
+                        held_reserve,
+                    );
+                    self.post(&mut postings, Account::Held, Account::Available, rest);
+                    postings
+                }),
+                // You can't resolve more than is being held
+                Err(_) => Err(self.held.clone() + self.held_reserve.clone()),
+            },
+        }
+    }
+    /// Lets a disputed withdrawal stand, posting its amount back out to where it was withdrawn to. Fails with how much
+    /// is held or reserved for disputed withdrawals, changing nothing, if that's less than `amount`.
+    pub fn resolve_withdrawal(&mut self, amount: Decimal) -> Result<Postings, Decimal> {
+        let mut postings = Postings::default();
+        let (disputed, balance) = self.withdrawal_disputes();
+        match balance.clone() - amount.clone() {
+            Ok(_) => Ok({
+                self.post(&mut postings, disputed, Account::External, amount);
+                postings
+            }),
+            Err(_) => Err(balance),
+        }
+    }
+
+    pub fn chargeback_deposit(&mut self, amount: Decimal) -> Result<Postings, Decimal> {
+        self.locked = true;
+        self.chargebacks = self.chargebacks.saturating_add(1);
+        let charged_back = amount.clone();
+        let mut postings = Postings::default();
+        let result = match self.held_reserve.clone() - amount.clone() {
+            // Relieved some of the reserve burden
+            Ok(_) => Ok({
+                self.post(
+                    &mut postings,
+                    Account::HeldReserve,
+                    Account::ChargebackWriteoff,
+                    amount,
+                );
+                postings
+            }),
+            // No reserve burden remains, apply to actual held
+            Err(rest) => match self.held.clone() - rest.clone() {
+                // Some held relieved
+                Ok(_) => Ok({
+                    let held_reserve = self.held_reserve.clone();
+                    self.post(
+                        &mut postings,
+                        Account::HeldReserve,
+                        Account::ChargebackWriteoff,
+                        held_reserve,
+                    );
+                    self.post(
+                        &mut postings,
+                        Account::Held,
+                        Account::ChargebackWriteoff,
+                        rest,
+                    );
+                    postings
+                }),
+                // You can't chargeback more than is being held
+                Err(_) => Err(self.held.clone() + self.held_reserve.clone()),
+            },
+        };
+        if result.is_ok() {
+            self.lifetime.charged_back += charged_back;
+        }
+        result
+    }
+
+    /// Reverses a disputed withdrawal, making its amount available again, and locks the client. Fails like
+    /// `resolve_withdrawal`, except that the client is still locked.
+    pub fn chargeback_withdrawal(&mut self, amount: Decimal) -> Result<Postings, Decimal> {
+        self.locked = true;
+        self.chargebacks = self.chargebacks.saturating_add(1);
+        let mut postings = Postings::default();
+        let (disputed, balance) = self.withdrawal_disputes();
+        match balance.clone() - amount.clone() {
+            Ok(_) => Ok({
+                self.lifetime.charged_back += amount.clone();
+                self.post(&mut postings, disputed, Account::Available, amount);
+                postings
+            }),
+            Err(_) => Err(balance),
+        }
+    }
+}
+
+#[cfg(feature = "testing")]
+impl Client {
+    /// Checks that every balance is well formed, and that the reported total is consistent with the other balances
+    pub fn check_invariants(&self) -> Result<(), InvariantViolation> {
+        let violation = |description| {
+            Err(InvariantViolation {
+                client: self.id,
+                description,
+            })
+        };
+
+        for (name, balance) in &[
+            ("available", &self.available),
+            ("held", &self.held),
+            ("held_reserve", &self.held_reserve),
+            ("reserve", &self.reserve),
+            ("overdraft", &self.overdraft),
+            ("pending", &self.pending),
+        ] {
+            if !balance.is_normalized() {
+                return violation(format!("{} is malformed: {:?}", name, balance));
+            }
+        }
+
+        let output = ClientOutput::from(self.clone());
+        if output.total != output.available.clone() + output.held.clone() {
+            return violation(format!(
+                "total {} is not available {} + held {}",
+                output.total, output.available, output.held
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// A single row of the accounts report
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ClientOutput {
+    pub client: u16,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+    /// Credit in use, which isn't taken off the total. Missing from reports written before overdrafts
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub overdraft: Decimal,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub closed: bool,
+    /// Authorized funds which haven't been captured, which aren't part of the total
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub pending: Decimal,
+}
+
+impl From<Client> for ClientOutput {
+    fn from(c: Client) -> Self {
+        ClientOutput {
+            client: c.id,
+            total: c.available.clone() + c.held.clone() + c.held_reserve.clone(),
+            available: c.available,
+            held: c.held + c.held_reserve,
+            locked: c.locked,
+            overdraft: c.overdraft,
+            closed: c.closed,
+            pending: c.pending,
+        }
+    }
+}
+
+/// Everything applied to a client over its lifetime, besides the counts of disputes and chargebacks which are
+/// `Client::disputes` and `Client::chargebacks`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Lifetime {
+    pub deposits: u32,
+    pub deposited: Decimal,
+    /// Not counting refunds
+    pub withdrawals: u32,
+    pub withdrawn: Decimal,
+    /// The amounts of every transaction disputed, however the disputes were settled
+    pub disputed: Decimal,
+    /// The amounts of every chargeback applied
+    pub charged_back: Decimal,
+    /// When the client was first and last active, as given to `Client::record_activity`
+    pub first_activity: Option<u64>,
+    pub last_activity: Option<u64>,
+}
+
+impl Lifetime {
+    fn merge(&mut self, other: Lifetime) {
+        self.deposits = self.deposits.saturating_add(other.deposits);
+        self.deposited += other.deposited;
+        self.withdrawals = self.withdrawals.saturating_add(other.withdrawals);
+        self.withdrawn += other.withdrawn;
+        self.disputed += other.disputed;
+        self.charged_back += other.charged_back;
+        self.first_activity = match (self.first_activity, other.first_activity) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        self.last_activity = self.last_activity.max(other.last_activity);
+    }
+}
+
+/// A client's balances and status at a point in time, from `Client::state`, for inspecting accounts without going
+/// through the report.
+///
+/// More may be added, so it can only be built by a client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[non_exhaustive]
+pub struct ClientState {
+    pub id: u16,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub overdraft: Decimal,
+    pub overdraft_limit: Decimal,
+    pub pending: Decimal,
+    pub locked: bool,
+    pub closed: bool,
+    pub disputes: u32,
+    pub chargebacks: u32,
+    pub lifetime: Lifetime,
+}
+
+/// Every field of a client, for storing it and rebuilding it exactly.
+///
+/// Unlike a report row, nothing is folded together or left out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ClientRecord {
+    pub id: u16,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub held_reserve: Decimal,
+    pub reserve: Decimal,
+    pub overdraft: Decimal,
+    pub overdraft_limit: Decimal,
+    pub pending: Decimal,
+    pub locked: bool,
+    pub closed: bool,
+    pub disputes: u32,
+    pub chargebacks: u32,
+    pub lifetime: Lifetime,
+}
+
+impl From<Client> for ClientRecord {
+    fn from(c: Client) -> Self {
+        ClientRecord {
+            id: c.id,
+            available: c.available,
+            held: c.held,
+            held_reserve: c.held_reserve,
+            reserve: c.reserve,
+            overdraft: c.overdraft,
+            overdraft_limit: c.overdraft_limit,
+            pending: c.pending,
+            locked: c.locked,
+            closed: c.closed,
+            disputes: c.disputes,
+            chargebacks: c.chargebacks,
+            lifetime: c.lifetime,
+        }
+    }
+}
+
+impl From<ClientRecord> for Client {
+    fn from(c: ClientRecord) -> Self {
+        Client {
+            id: c.id,
+            available: c.available,
+            held: c.held,
+            held_reserve: c.held_reserve,
+            reserve: c.reserve,
+            overdraft: c.overdraft,
+            overdraft_limit: c.overdraft_limit,
+            pending: c.pending,
+            locked: c.locked,
+            closed: c.closed,
+            disputes: c.disputes,
+            chargebacks: c.chargebacks,
+            lifetime: c.lifetime,
+            hold_disputed_withdrawals: false,
+        }
+    }
+}
+
+impl From<ClientRecord> for ClientOutput {
+    fn from(c: ClientRecord) -> Self {
+        ClientOutput::from(Client::from(c))
+    }
+}
+
+/// A report row whose total isn't its available and held funds added up, so can't have come from a client
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InconsistentTotal(pub ClientOutput);
+
+impl fmt::Display for InconsistentTotal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let row = &self.0;
+        write!(
+            f,
+            "client {}: total {} isn't available {} plus held {}",
+            row.client, row.total, row.available, row.held
+        )
+    }
+}
+
+/// Rebuilds as much of a client as a report row has, as `From<ClientOutput> for Client` does, after checking the row
+/// adds up
+impl TryFrom<ClientOutput> for ClientRecord {
+    type Error = InconsistentTotal;
+    fn try_from(c: ClientOutput) -> Result<Self, Self::Error> {
+        if c.available.clone() + c.held.clone() != c.total {
+            return Err(InconsistentTotal(c));
+        }
+        Ok(ClientRecord::from(Client::from(c)))
+    }
+}
+
+/// Rebuilds a client from a report row.
+///
+/// This is lossy: the report folds `held_reserve` into `held`, and doesn't include `reserve`, the overdraft limit or the
+/// dispute and chargeback counts at all.
+impl From<ClientOutput> for Client {
+    fn from(c: ClientOutput) -> Self {
+        Client {
+            id: c.client,
+            available: c.available,
+            held: c.held,
+            held_reserve: Decimal::zero(),
+            reserve: Decimal::zero(),
+            overdraft: c.overdraft,
+            overdraft_limit: Decimal::zero(),
+            pending: c.pending,
+            locked: c.locked,
+            closed: c.closed,
+            disputes: 0,
+            chargebacks: 0,
+            lifetime: Lifetime::default(),
+            hold_disputed_withdrawals: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::decimal::Decimal;
+
+    #[test]
+    fn test_freeze() {
+        let mut c = Client::new(100);
+        assert_eq!(
+            c.chargeback_deposit(Decimal::zero()),
+            Ok(Postings::default())
+        );
+
+        assert_eq!(c.withdraw(Decimal::zero()), Err(None));
+        c.deposit(Decimal::new(10, 1));
+        assert_eq!(c.withdraw(Decimal::zero()), Err(None));
+        assert_eq!(c.withdraw(Decimal::new(1, 1)), Err(None));
+
+        let mut c = Client::new(100);
+        c.deposit(Decimal::new(10, 1));
+
+        assert_eq!(c.withdraw(Decimal::zero()), Ok(Postings::default()));
+    }
+
+    #[test]
+    fn state() {
+        let mut c = Client::new(7).with_overdraft_limit(Decimal::new(5, 0));
+        c.deposit(Decimal::new(10, 0));
+        c.dispute_deposit(Decimal::new(4, 0));
+        c.authorize(Decimal::new(1, 0));
+        assert_eq!(
+            (c.available(), c.held(), c.total(), c.is_locked()),
+            (
+                Decimal::new(6, 0),
+                Decimal::new(4, 0),
+                Decimal::new(10, 0),
+                false
+            )
+        );
+
+        c.refund(Decimal::new(1, 0)).unwrap();
+        c.chargeback_deposit(Decimal::new(4, 0)).unwrap();
+        let state = c.state();
+        assert_eq!(state.id, 7);
+        assert_eq!(state.total, Decimal::new(5, 0));
+        assert_eq!(state.pending, Decimal::new(1, 0));
+        assert_eq!(state.overdraft_limit, Decimal::new(5, 0));
+        assert!(state.locked);
+        assert_eq!((state.disputes, state.chargebacks), (1, 1));
+
+        c.record_activity(20);
+        c.record_activity(10);
+        assert_eq!(
+            c.lifetime(),
+            &Lifetime {
+                deposits: 1,
+                deposited: Decimal::new(10, 0),
+                withdrawals: 0,
+                withdrawn: Decimal::zero(),
+                disputed: Decimal::new(4, 0),
+                charged_back: Decimal::new(4, 0),
+                first_activity: Some(10),
+                last_activity: Some(20),
+            }
+        );
+    }
+
+    #[test]
+    fn lifetime() {
+        let mut c = Client::new(1);
+        c.deposit(Decimal::new(10, 0));
+        c.deposit(Decimal::new(5, 0));
+        c.withdraw(Decimal::new(3, 0)).unwrap();
+        // Neither refunds nor failed withdrawals are counted as withdrawals
+        c.refund(Decimal::new(1, 0)).unwrap();
+        assert!(c.withdraw(Decimal::new(100, 0)).is_err());
+        c.dispute_deposit(Decimal::new(5, 0));
+        c.chargeback_deposit(Decimal::new(5, 0)).unwrap();
+        // Resolved disputes are still part of the volume disputed
+        c.dispute_withdrawal(Decimal::new(3, 0));
+        c.resolve_withdrawal(Decimal::new(3, 0)).unwrap();
+        assert_eq!((c.disputes(), c.chargebacks()), (2, 1));
+        assert_eq!(
+            c.lifetime(),
+            &Lifetime {
+                deposits: 2,
+                deposited: Decimal::new(15, 0),
+                withdrawals: 1,
+                withdrawn: Decimal::new(3, 0),
+                disputed: Decimal::new(8, 0),
+                charged_back: Decimal::new(5, 0),
+                first_activity: None,
+                last_activity: None,
+            }
+        );
+
+        c.record_activity(30);
+        c.record_activity(10);
+        c.record_activity(20);
+        assert_eq!(
+            (c.lifetime().first_activity, c.lifetime().last_activity),
+            (Some(10), Some(30))
+        );
+
+        // Merged clients' statistics are combined, and the activity spans both
+        let mut other = Client::new(2);
+        other.deposit(Decimal::new(1, 0));
+        other.record_activity(5);
+        c.merge(&mut other);
+        assert_eq!(other.lifetime(), &Lifetime::default());
+        let lifetime = c.lifetime();
+        assert_eq!(
+            (lifetime.deposits, lifetime.deposited.clone()),
+            (3, Decimal::new(16, 0))
+        );
+        assert_eq!(
+            (lifetime.first_activity, lifetime.last_activity),
+            (Some(5), Some(30))
+        );
+    }
+
+    #[test]
+    fn disputed_withdrawals_held() {
+        let balances = |c: &Client| (c.available(), c.held(), c.total());
+        for held in [false, true] {
+            let mut c = Client::new(1);
+            if held {
+                c = c.with_disputed_withdrawals_held();
+            }
+            c.deposit(Decimal::new(10, 0));
+            c.withdraw(Decimal::new(4, 0)).unwrap();
+            c.dispute_withdrawal(Decimal::new(4, 0));
+            let disputed = match held {
+                true => Decimal::new(4, 0),
+                false => Decimal::zero(),
+            };
+            assert_eq!(
+                balances(&c),
+                (
+                    Decimal::new(6, 0),
+                    disputed.clone(),
+                    Decimal::new(6, 0) + disputed
+                )
+            );
+            // Only what was disputed can be resolved, wherever it is
+            assert_eq!(
+                c.resolve_withdrawal(Decimal::new(5, 0)),
+                Err(Decimal::new(4, 0))
+            );
+
+            let mut resolved = c.clone();
+            resolved.resolve_withdrawal(Decimal::new(4, 0)).unwrap();
+            assert_eq!(
+                balances(&resolved),
+                (Decimal::new(6, 0), Decimal::zero(), Decimal::new(6, 0))
+            );
+            // Either way a chargeback gives the withdrawal back
+            c.chargeback_withdrawal(Decimal::new(4, 0)).unwrap();
+            assert_eq!(
+                balances(&c),
+                (Decimal::new(10, 0), Decimal::zero(), Decimal::new(10, 0))
+            );
+        }
+    }
+
+    #[test]
+    fn record_from_report() {
+        let mut c = Client::new(3);
+        c.deposit(Decimal::new(5, 0));
+        c.dispute_deposit(Decimal::new(2, 0));
+        let mut output = ClientOutput::from(c.clone());
+        let record = ClientRecord::try_from(output.clone()).unwrap();
+        assert_eq!(ClientOutput::from(record), output);
+
+        output.total = Decimal::new(4, 0);
+        assert_eq!(
+            ClientRecord::try_from(output.clone()),
+            Err(InconsistentTotal(output))
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn record_round_trip() {
+        let mut c = Client::new(3).with_overdraft_limit(Decimal::new(1, 0));
+        c.deposit(Decimal::new(5, 0));
+        c.withdraw(Decimal::new(5, 0)).unwrap();
+        // Held once it comes back
+        c.dispute_deposit(Decimal::new(5, 0));
+        c.dispute_withdrawal(Decimal::new(1, 0));
+        let record = ClientRecord::from(c);
+        let json = serde_json::to_string(&record).unwrap();
+        assert_eq!(serde_json::from_str::<ClientRecord>(&json).unwrap(), record);
+    }
+}
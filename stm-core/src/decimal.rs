@@ -1,17 +1,21 @@
-use std::fmt::{self, Formatter};
-use std::num::ParseIntError;
-use std::ops::{Add, AddAssign, Sub};
-use std::str::{from_utf8, FromStr};
-
+//! A lot taken from rust_decimal. Originally was thinking about just using that crate, but it seemed to have a large number of dependencies
+//! which I don't have time to audit, and also it seems to be a bit overkill. Should be reasonably drop-in able though.
+
+use core::convert::TryFrom;
+use core::fmt;
+use core::num::ParseIntError;
+use core::ops::{Add, AddAssign, Sub};
+use core::str::FromStr;
+
+#[cfg(feature = "serde")]
+use core::{fmt::Formatter, str::from_utf8};
+#[cfg(feature = "serde")]
 use serde::{
     de::{self, Unexpected},
     ser, Deserialize, Deserializer, Serialize, Serializer,
 };
 
-///! A lot taken from rust_decimal. Originally was thinking about just using that crate, but it seemed to have a large number of dependencies
-///! which I don't have time to audit, and also it seems to be a bit overkill. Should be reasonably drop-in able though.
-
-#[derive(Default, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Default, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Decimal {
     dollars: u64,
     cents: u16,
@@ -26,6 +30,44 @@ impl Decimal {
     pub fn zero() -> Self {
         Default::default()
     }
+
+    /// The whole and fractional parts, as given to `new`
+    pub fn parts(&self) -> (u64, u16) {
+        (self.dollars, self.cents)
+    }
+
+    /// The product, truncated to our precision like parsing, or `None` if it's too large
+    pub fn checked_mul(&self, rhs: &Self) -> Option<Self> {
+        Self::from_units(self.units().checked_mul(rhs.units())? / UNIT)
+    }
+
+    /// `percent`% of this amount, truncated to our precision
+    pub fn checked_percent(&self, percent: &Self) -> Option<Self> {
+        Self::from_units(self.units().checked_mul(percent.units())? / UNIT / 100)
+    }
+
+    /// `numerator / denominator` of this amount, truncated to our precision
+    pub fn checked_ratio(&self, numerator: u64, denominator: u64) -> Option<Self> {
+        Self::from_units(self.units().checked_mul(u128::from(numerator))? / u128::from(denominator))
+    }
+
+    /// The amount in ten-thousandths
+    fn units(&self) -> u128 {
+        u128::from(self.dollars) * UNIT + u128::from(self.cents)
+    }
+
+    fn from_units(units: u128) -> Option<Self> {
+        Some(Decimal {
+            dollars: u64::try_from(units / UNIT).ok()?,
+            cents: (units % UNIT) as u16,
+        })
+    }
+
+    /// Whether the fractional part is within `PRECISION` digits, as every operation should leave it
+    #[cfg(feature = "testing")]
+    pub fn is_normalized(&self) -> bool {
+        self.cents < 10u16.pow(PRECISION as u32)
+    }
 }
 
 impl fmt::Display for Decimal {
@@ -63,11 +105,11 @@ impl Sub for Decimal {
                 cents: rhs.cents - self.cents,
             }),
 
-            // Rhs dollars are greater, lhs cents are greater
-            (None, true) => Err(Decimal {
-                dollars: rhs.dollars - self.dollars - 1,
-                cents: 10u16.pow(PRECISION as u32) + rhs.cents - self.cents,
-            }),
+            // Rhs dollars are greater, lhs cents are greater or equal (borrow, unless equal)
+            (None, true) => Err(Decimal::new(
+                rhs.dollars - self.dollars - 1,
+                10u16.pow(PRECISION as u32) + rhs.cents - self.cents,
+            )),
 
             // Both parts of lhs are greater or equal
             (Some(dollars), true) => Ok(Decimal {
@@ -92,6 +134,91 @@ impl Sub for Decimal {
 
 const DIGITS: usize = 20; // Above decimal
 const PRECISION: usize = 4; // Below decimal
+const UNIT: u128 = 10u128.pow(PRECISION as u32);
+
+/// How a locale writes amounts, for `Decimal::parse_lenient`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Locale {
+    pub decimal_separator: char,
+}
+
+impl Locale {
+    /// `1,234.50`
+    pub const DECIMAL_POINT: Locale = Locale {
+        decimal_separator: '.',
+    };
+    /// `1.234,50` or `1 234,50`
+    pub const DECIMAL_COMMA: Locale = Locale {
+        decimal_separator: ',',
+    };
+}
+
+impl Default for Locale {
+    fn default() -> Self {
+        Locale::DECIMAL_POINT
+    }
+}
+
+impl Decimal {
+    /// Parses an amount the way people write them, e.g. `$1,234.50` or `1 234,50 €`.
+    ///
+    /// Currency symbols and whitespace are ignored, as are group separators provided they split the whole part into
+    /// groups of three digits. The decimal separator comes from `locale`. Signs and letters are still errors, so
+    /// negative amounts and currency codes are rejected rather than silently misread.
+    pub fn parse_lenient(s: &str, locale: Locale) -> Result<Self, ParseIntError> {
+        let invalid_digit = || u64::from_str("x").unwrap_err();
+
+        // Just the digits and a `.`, to be parsed as normal
+        let mut buf = [0u8; DIGITS + 1 + 2 * PRECISION];
+        let mut len = 0;
+        let mut fraction = false;
+        // Digits since the last group separator, if there has been one
+        let (mut run, mut grouped, mut separator) = (0, false, false);
+        for c in s.chars() {
+            let b = match c {
+                '0'..='9' => {
+                    if separator {
+                        // Fractions aren't grouped
+                        if fraction || (grouped && run != 3) || run > 3 {
+                            return Err(invalid_digit());
+                        }
+                        grouped = true;
+                        run = 0;
+                        separator = false;
+                    }
+                    run += 1;
+                    c as u8
+                }
+                c if c == locale.decimal_separator && !fraction => {
+                    if grouped && run != 3 {
+                        return Err(invalid_digit());
+                    }
+                    fraction = true;
+                    b'.'
+                }
+                c if c.is_alphanumeric() || matches!(c, '-' | '+' | '(' | ')') => {
+                    return Err(invalid_digit())
+                }
+                _ => {
+                    separator = run > 0;
+                    continue;
+                }
+            };
+            if len == buf.len() {
+                // More digits than can possibly fit
+                return Err(u8::from_str("256").unwrap_err());
+            }
+            buf[len] = b;
+            len += 1;
+        }
+        if !fraction && grouped && run != 3 {
+            return Err(invalid_digit());
+        }
+        core::str::from_utf8(&buf[..len])
+            .expect("only ASCII was written")
+            .parse()
+    }
+}
 
 impl FromStr for Decimal {
     type Err = ParseIntError;
@@ -113,18 +240,19 @@ impl FromStr for Decimal {
         } else {
             dollars.parse::<u64>()?
         };
-        let cents = (0..PRECISION).fold(Ok(0), |total, cent_index| {
-            total.and_then(|total| {
-                Ok(total * 10
+        let cents = (0..PRECISION).try_fold(0, |total, cent_index| {
+            Ok::<_, ParseIntError>(
+                total * 10
                     + cents
                         .get(cent_index..cent_index + 1)
-                        .map_or(Ok(0), |c| u16::from_str(c))?)
-            })
+                        .map_or(Ok(0), u16::from_str)?,
+            )
         })?;
         Ok(Decimal { dollars, cents })
     }
 }
 
+#[cfg(feature = "serde")]
 impl<'de> Deserialize<'de> for Decimal {
     fn deserialize<D>(deserializer: D) -> Result<Decimal, D::Error>
     where
@@ -189,19 +317,20 @@ impl<'de> Deserialize<'de> for Decimal {
     }
 }
 
+#[cfg(feature = "serde")]
 impl Serialize for Decimal {
     fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
         use fmt::Write;
         struct Buffer {
             buf: [u8; DIGITS + 1 + PRECISION],
             len: usize,
-        };
+        }
         impl Write for Buffer {
             fn write_str(&mut self, s: &str) -> Result<(), fmt::Error> {
                 if self.len + s.len() > self.buf.len() {
                     return Err(fmt::Error);
                 }
-                (&mut self.buf[self.len..self.len + s.len()]).copy_from_slice(s.as_bytes());
+                self.buf[self.len..self.len + s.len()].copy_from_slice(s.as_bytes());
                 self.len += s.len();
                 Ok(())
             }
@@ -220,6 +349,7 @@ mod test {
     use super::*;
 
     #[test]
+    #[cfg(feature = "serde")]
     fn deserialize_basic_decimal() {
         let d: Decimal = serde_json::from_str("\"10.1234\"").unwrap();
         assert_eq!(d.dollars, 10);
@@ -238,6 +368,31 @@ mod test {
         assert_eq!(d.cents, 20);
     }
 
+    #[test]
+    fn parse_lenient_amounts() {
+        let point = Locale::DECIMAL_POINT;
+        let comma = Locale::DECIMAL_COMMA;
+        assert_eq!(
+            Decimal::parse_lenient("$1,234.50", point),
+            Ok(Decimal::new(1234, 5000))
+        );
+        assert_eq!(
+            Decimal::parse_lenient("1 234,50", comma),
+            Ok(Decimal::new(1234, 5000))
+        );
+        assert_eq!(
+            Decimal::parse_lenient("€ 1.234.567,8 ", comma),
+            Ok(Decimal::new(1234567, 8000))
+        );
+        assert!(Decimal::parse_lenient("12.5 EUR", point).is_err());
+        // Misread locales and signs are errors rather than a different amount
+        assert!(Decimal::parse_lenient("1,5", point).is_err());
+        assert!(Decimal::parse_lenient("1,2345.0", point).is_err());
+        assert!(Decimal::parse_lenient("1,2,3", comma).is_err());
+        assert!(Decimal::parse_lenient("-$5.00", point).is_err());
+        assert!(Decimal::parse_lenient("$", point).is_err());
+    }
+
     #[test]
     fn deserialize_basic_math() {
         assert_eq!(
@@ -265,5 +420,25 @@ mod test {
             Decimal::new(1, 5000) - Decimal::new(5, 0),
             Err(Decimal::new(3, 5000))
         );
+        assert_eq!(
+            Decimal::zero() - Decimal::new(5, 0),
+            Err(Decimal::new(5, 0))
+        );
+        assert_eq!(
+            Decimal::new(12, 5000).checked_mul(&Decimal::new(0, 3333)),
+            Some(Decimal::new(4, 1662))
+        );
+        assert_eq!(
+            Decimal::new(200, 0).checked_percent(&Decimal::new(2, 5000)),
+            Some(Decimal::new(5, 0))
+        );
+        assert_eq!(
+            Decimal::new(10, 0).checked_ratio(1, 3),
+            Some(Decimal::new(3, 3333))
+        );
+        assert_eq!(
+            Decimal::new(u64::MAX, 0).checked_mul(&Decimal::new(2, 0)),
+            None
+        );
     }
 }
@@ -0,0 +1,31 @@
+//! The balance math behind the transaction manager, usable without `std` so the same dispute accounting can run on
+//! embedded targets.
+
+#![no_std]
+
+#[cfg(feature = "testing")]
+extern crate alloc;
+
+#[cfg(feature = "testing")]
+use alloc::string::String;
+#[cfg(feature = "testing")]
+use core::fmt;
+
+pub mod client;
+pub mod decimal;
+pub mod posting;
+
+/// A broken internal invariant, which always indicates a bug in the engine
+#[cfg(feature = "testing")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvariantViolation {
+    pub client: u16,
+    pub description: String,
+}
+
+#[cfg(feature = "testing")]
+impl fmt::Display for InvariantViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "client {}: {}", self.client, self.description)
+    }
+}
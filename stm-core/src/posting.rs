@@ -0,0 +1,104 @@
+//! Every change to a client's balances as a double-entry posting between named accounts, so each movement of funds can
+//! be audited and always balances.
+
+use crate::decimal::Decimal;
+
+/// Where a client's funds can be
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Account {
+    /// Outside the system, where deposits come from and withdrawals go
+    External,
+    Available,
+    Held,
+    /// Held funds the client didn't have when the dispute came in, which they still owe
+    HeldReserve,
+    /// Funds from a disputed withdrawal, which come back if it's charged back. Not part of the client's total
+    Reserve,
+    /// Where charged back deposits go
+    ChargebackWriteoff,
+    /// Where fees go
+    Fees,
+    /// Where interest is paid from
+    Interest,
+    /// Credit extended to the client by overdrawn withdrawals, and paid back by deposits
+    Overdraft,
+    /// Authorized funds waiting to be captured. Not part of the client's total
+    Pending,
+    /// Where operators' corrections to available funds come from and go
+    Adjustments,
+}
+
+impl Account {
+    pub const ALL: [Account; 11] = [
+        Account::External,
+        Account::Available,
+        Account::Held,
+        Account::HeldReserve,
+        Account::Reserve,
+        Account::ChargebackWriteoff,
+        Account::Fees,
+        Account::Interest,
+        Account::Overdraft,
+        Account::Pending,
+        Account::Adjustments,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Account::External => "external",
+            Account::Available => "available",
+            Account::Held => "held",
+            Account::HeldReserve => "held_reserve",
+            Account::Reserve => "reserve",
+            Account::ChargebackWriteoff => "chargeback_writeoff",
+            Account::Fees => "fees",
+            Account::Interest => "interest",
+            Account::Overdraft => "overdraft",
+            Account::Pending => "pending",
+            Account::Adjustments => "adjustments",
+        }
+    }
+}
+
+/// `amount` moving from one account to another
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Posting {
+    pub from: Account,
+    pub to: Account,
+    pub amount: Decimal,
+}
+
+/// The postings made by a single transaction, of which there are never more than four plus a fee
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Postings {
+    postings: [Option<Posting>; 5],
+}
+
+impl Postings {
+    /// Adds a posting, leaving out zero amounts
+    pub fn push(&mut self, posting: Posting) {
+        if posting.amount == Decimal::zero() {
+            return;
+        }
+        let slot = self
+            .postings
+            .iter_mut()
+            .find(|p| p.is_none())
+            .expect("no transaction makes more than five postings");
+        *slot = Some(posting);
+    }
+
+    pub fn extend(&mut self, postings: Postings) {
+        for posting in IntoIterator::into_iter(postings.postings).flatten() {
+            self.push(posting);
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Posting> {
+        self.postings.iter().flatten()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+}